@@ -0,0 +1,42 @@
+/* The C API of randomlib, built with the `ffi` feature. See src/ffi.rs. */
+#ifndef RANDOMLIB_H
+#define RANDOMLIB_H
+
+#include <stdbool.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define RANDOMLIB_OK 0
+#define RANDOMLIB_INVALID_ARGUMENT -1
+#define RANDOMLIB_REJECTED 1
+#define RANDOMLIB_NOT_FOUND 2
+#define RANDOMLIB_OVERFLOW 3
+
+typedef struct FfiEngine RandomlibEngine;
+
+/* Amounts are in ten-thousandths. */
+typedef struct RandomlibAccount {
+    uint16_t client;
+    int64_t available;
+    int64_t held;
+    int64_t total;
+    bool locked;
+    bool closed;
+} RandomlibAccount;
+
+RandomlibEngine *randomlib_engine_new(void);
+void randomlib_engine_free(RandomlibEngine *engine);
+int randomlib_engine_insert(RandomlibEngine *engine, const char *variant, uint16_t client,
+                            uint32_t tx, const char *amount);
+int randomlib_engine_account(const RandomlibEngine *engine, uint16_t client,
+                             RandomlibAccount *account);
+const char *randomlib_engine_last_error(const RandomlibEngine *engine);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
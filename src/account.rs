@@ -1,21 +1,102 @@
-use crate::{amount::Amount, error::TransactionError, TransactionVariant};
-use serde::Serialize;
+use crate::{
+    amount::{deserialize_unchecked, Amount},
+    config::{EngineConfig, LockingBehavior, WithdrawalDisputes, WithdrawalLimit},
+    error::TransactionError,
+    TransactionVariant,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
-#[derive(Debug, Serialize, Clone)]
+/// Descriptive details of a client, to make outputs keyed by client id
+/// easier to consume, see
+/// [`PaymentEngine::set_metadata`](crate::PaymentEngine::set_metadata). The
+/// tier of the client is kept on the account, see [`Account::tier`].
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct ClientMetadata {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub kyc_status: Option<String>,
+}
+
+/// The funds of an [`Account`] in one currency.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct Balance {
+    #[serde(deserialize_with = "deserialize_unchecked")]
+    pub available: Amount,
+    #[serde(deserialize_with = "deserialize_unchecked")]
+    pub held: Amount,
+    #[serde(deserialize_with = "deserialize_unchecked")]
+    pub total: Amount,
+}
+
+impl Default for Balance {
+    fn default() -> Self {
+        Self {
+            available: Amount::zero(),
+            held: Amount::zero(),
+            total: Amount::zero(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Account {
     /// A unique client id
     client: u16,
     /// The total funds that are available for trading, staking, withdrawal, etc.
     /// This should be equal to the `total` - `held` amounts
+    #[serde(deserialize_with = "deserialize_unchecked")]
     available: Amount,
     /// The total funds that are held for dispute.
     /// This should be equal to `total` - `available` amounts
+    #[serde(deserialize_with = "deserialize_unchecked")]
     held: Amount,
     /// The total funds that are available or held.
     /// This should be equal to `available` + `held`
+    #[serde(deserialize_with = "deserialize_unchecked")]
     total: Amount,
     /// Whether the account is locked. An account is locked if a chargeback occurs
     locked: bool,
+    /// Whether the account is closed. A closed account rejects every transaction
+    #[serde(default)]
+    closed: bool,
+    /// Whether the account is frozen, which pauses every transaction. Not
+    /// written with the account but kept in snapshots
+    #[serde(skip)]
+    frozen: bool,
+    /// The funds in currencies given by the `currency` column, by currency
+    /// code. The fields above hold the funds of transactions without one.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    balances: BTreeMap<String, Balance>,
+    /// The total withdrawn, to enforce [`WithdrawalLimit::max_total`]. Not
+    /// written with the account but kept in snapshots.
+    #[serde(skip, default = "Amount::zero")]
+    withdrawn: Amount,
+    /// Selects the [`TierRules`](crate::TierRules) that apply to the account.
+    #[serde(skip)]
+    tier: Option<String>,
+    /// Not written with the account unless requested, see
+    /// [`OutputOptions::metadata`](crate::OutputOptions::metadata), but kept
+    /// in snapshots.
+    #[serde(skip)]
+    metadata: Option<ClientMetadata>,
+}
+
+/// Accounts are equal if they would be written the same and are both frozen
+/// or not, regardless of what has been withdrawn to get there and of their
+/// tier and metadata.
+impl PartialEq for Account {
+    fn eq(&self, other: &Self) -> bool {
+        self.client == other.client
+            && self.available == other.available
+            && self.held == other.held
+            && self.total == other.total
+            && self.locked == other.locked
+            && self.closed == other.closed
+            && self.frozen == other.frozen
+            && self.balances == other.balances
+    }
 }
 
 impl Account {
@@ -26,9 +107,62 @@ impl Account {
             held: Amount::zero(),
             total: Amount::zero(),
             locked: false,
+            closed: false,
+            frozen: false,
+            balances: BTreeMap::new(),
+            withdrawn: Amount::zero(),
+            tier: None,
+            metadata: None,
         }
     }
 
+    /// Recreates an account from previously stored balances.
+    #[cfg(feature = "sqlite")]
+    pub(crate) fn restore(
+        client: u16,
+        available: Amount,
+        held: Amount,
+        total: Amount,
+        locked: bool,
+        closed: bool,
+    ) -> Self {
+        Self {
+            client,
+            available,
+            held,
+            total,
+            locked,
+            closed,
+            frozen: false,
+            balances: BTreeMap::new(),
+            withdrawn: Amount::zero(),
+            tier: None,
+            metadata: None,
+        }
+    }
+
+    /// Restores the previously stored funds in `currency`.
+    #[cfg(feature = "sqlite")]
+    pub(crate) fn restore_balance(&mut self, currency: String, balance: Balance) {
+        self.balances.insert(currency, balance);
+    }
+
+    pub fn client(&self) -> u16 {
+        self.client
+    }
+
+    /// The total ever withdrawn from the account in any currency, including
+    /// transfers, fees and conversions. Only tracked since the account was
+    /// created or restored from a [`Snapshot`](crate::Snapshot), and not kept
+    /// in SQLite storage.
+    pub fn withdrawn(&self) -> Amount {
+        self.withdrawn
+    }
+
+    pub(crate) fn set_withdrawn(&mut self, withdrawn: Amount) {
+        self.withdrawn = withdrawn;
+    }
+
     pub fn available(&self) -> Amount {
         self.available
     }
@@ -45,46 +179,279 @@ impl Account {
         self.locked
     }
 
-    fn deposit(&mut self, amount: Amount) {
-        self.available += amount;
-        self.total += amount;
+    pub fn closed(&self) -> bool {
+        self.closed
     }
 
-    fn withdraw(&mut self, amount: Amount) -> Result<(), TransactionError> {
-        if self.available < amount {
+    /// Whether the account is frozen, see
+    /// [`PaymentEngine::freeze`](crate::PaymentEngine::freeze).
+    pub fn frozen(&self) -> bool {
+        self.frozen
+    }
+
+    /// The tier of the account, see
+    /// [`PaymentEngine::set_tier`](crate::PaymentEngine::set_tier).
+    pub fn tier(&self) -> Option<&str> {
+        self.tier.as_deref()
+    }
+
+    pub(crate) fn set_tier(&mut self, tier: String) {
+        self.tier = Some(tier);
+    }
+
+    pub fn metadata(&self) -> Option<&ClientMetadata> {
+        self.metadata.as_ref()
+    }
+
+    pub(crate) fn set_metadata(&mut self, metadata: ClientMetadata) {
+        self.metadata = Some(metadata);
+    }
+
+    /// The funds in every currency other than the one of transactions without
+    /// a currency, which are [`Account::available`], [`Account::held`] and
+    /// [`Account::total`].
+    pub fn balances(&self) -> &BTreeMap<String, Balance> {
+        &self.balances
+    }
+
+    /// The funds in `currency`, or of transactions without a currency if
+    /// `None`.
+    pub fn balance(&self, currency: Option<&str>) -> Balance {
+        match currency {
+            Some(currency) => self.balances.get(currency).copied().unwrap_or_default(),
+            None => Balance {
+                available: self.available,
+                held: self.held,
+                total: self.total,
+            },
+        }
+    }
+
+    /// Adds the funds of `other` in every currency, and keeps the account
+    /// locked or closed if either is. Leaves the account unchanged if any sum
+    /// overflows.
+    pub(crate) fn merge(&mut self, other: &Account) -> Result<(), TransactionError> {
+        fn add(a: Balance, b: Balance) -> Result<Balance, TransactionError> {
+            Ok(Balance {
+                available: a.available.checked_add(b.available)?,
+                held: a.held.checked_add(b.held)?,
+                total: a.total.checked_add(b.total)?,
+            })
+        }
+        let base = add(self.balance(None), other.balance(None))?;
+        let mut balances = self.balances.clone();
+        for (currency, balance) in &other.balances {
+            let sum = add(self.balance(Some(currency)), *balance)?;
+            balances.insert(currency.clone(), sum);
+        }
+        self.swap_balance(base);
+        self.balances = balances;
+        self.withdrawn = self.withdrawn.checked_add(other.withdrawn)?;
+        self.locked |= other.locked;
+        self.closed |= other.closed;
+        if self.tier.is_none() {
+            self.tier = other.tier.clone();
+        }
+        if self.metadata.is_none() {
+            self.metadata = other.metadata.clone();
+        }
+        Ok(())
+    }
+
+    /// Runs `f` with the funds in `currency` in place of the funds without a
+    /// currency, so that all operations apply to `currency`.
+    pub(crate) fn in_currency<R>(
+        &mut self,
+        currency: Option<&str>,
+        f: impl FnOnce(&mut Self) -> R,
+    ) -> R {
+        let currency = match currency {
+            Some(currency) => currency,
+            None => return f(self),
+        };
+        let balance = self.balances.remove(currency).unwrap_or_default();
+        let base = self.swap_balance(balance);
+        let result = f(self);
+        let balance = self.swap_balance(base);
+        if balance != Balance::default() {
+            self.balances.insert(currency.to_string(), balance);
+        }
+        result
+    }
+
+    fn swap_balance(&mut self, balance: Balance) -> Balance {
+        let previous = self.balance(None);
+        self.available = balance.available;
+        self.held = balance.held;
+        self.total = balance.total;
+        previous
+    }
+
+    /// Sets the funds to the results of `available`, `held` and `total`, or
+    /// leaves them unchanged if any of them overflowed.
+    fn update(
+        &mut self,
+        available: Result<Amount, TransactionError>,
+        held: Result<Amount, TransactionError>,
+        total: Result<Amount, TransactionError>,
+    ) -> Result<(), TransactionError> {
+        let (available, held, total) = (available?, held?, total?);
+        self.available = available;
+        self.held = held;
+        self.total = total;
+        Ok(())
+    }
+
+    fn deposit(&mut self, amount: Amount) -> Result<(), TransactionError> {
+        self.update(
+            self.available.checked_add(amount),
+            Ok(self.held),
+            self.total.checked_add(amount),
+        )
+    }
+
+    fn withdraw(
+        &mut self,
+        amount: Amount,
+        overdraft: Amount,
+        limit: WithdrawalLimit,
+    ) -> Result<(), TransactionError> {
+        let withdrawn = self.withdrawn.checked_add(amount)?;
+        let exceeded = [
+            limit.max_amount.filter(|max| amount > *max),
+            limit.max_total.filter(|max| withdrawn > *max),
+        ];
+        if let Some(limit) = exceeded.iter().flatten().next().copied() {
+            return Err(TransactionError::LimitExceeded {
+                client: self.client,
+                limit,
+                amount_attempted: amount,
+            });
+        }
+        if self.available.checked_add(overdraft)? < amount {
             return Err(TransactionError::InsufficientFunds {
                 client: self.client,
                 available: self.available,
                 amount_attempted: amount,
             });
         }
-        self.available -= amount;
-        self.total -= amount;
+        self.update(
+            self.available.checked_sub(amount),
+            Ok(self.held),
+            self.total.checked_sub(amount),
+        )?;
+        self.withdrawn = withdrawn;
         Ok(())
     }
 
-    fn dispute(&mut self, amount: Amount) {
-        self.available -= amount;
-        self.held += amount;
+    fn dispute(&mut self, amount: Amount) -> Result<(), TransactionError> {
+        self.update(
+            self.available.checked_sub(amount),
+            self.held.checked_add(amount),
+            Ok(self.total),
+        )
     }
 
-    fn resolve(&mut self, amount: Amount) {
-        self.available += amount;
-        self.held -= amount;
+    fn resolve(&mut self, amount: Amount) -> Result<(), TransactionError> {
+        self.update(
+            self.available.checked_add(amount),
+            self.held.checked_sub(amount),
+            Ok(self.total),
+        )
     }
 
-    fn chargeback(&mut self, amount: Amount) {
-        self.total -= amount;
-        self.held -= amount;
-        self.locked = true;
+    fn chargeback(
+        &mut self,
+        amount: Amount,
+        locking: LockingBehavior,
+    ) -> Result<(), TransactionError> {
+        self.update(
+            Ok(self.available),
+            self.held.checked_sub(amount),
+            self.total.checked_sub(amount),
+        )?;
+        self.lock(locking);
+        Ok(())
     }
 
-    pub(crate) fn transaction(
+    /// Moves `amount` from the available to the held funds for a
+    /// reservation. Unlike a withdrawal it cannot overdraw the account.
+    pub(crate) fn reserve(
         &mut self,
+        amount: Amount,
+        config: &EngineConfig,
+    ) -> Result<(), TransactionError> {
+        self.check(&TransactionVariant::Withdrawal, amount, config)?;
+        if self.available < amount {
+            return Err(TransactionError::InsufficientFunds {
+                client: self.client,
+                available: self.available,
+                amount_attempted: amount,
+            });
+        }
+        self.dispute(amount)
+    }
+
+    /// Takes the reserved `amount` out of the account.
+    pub(crate) fn commit_reservation(&mut self, amount: Amount) -> Result<(), TransactionError> {
+        self.update(
+            Ok(self.available),
+            self.held.checked_sub(amount),
+            self.total.checked_sub(amount),
+        )
+    }
+
+    /// Makes the reserved `amount` available again.
+    pub(crate) fn release_reservation(&mut self, amount: Amount) -> Result<(), TransactionError> {
+        self.resolve(amount)
+    }
+
+    pub(crate) fn unlock(&mut self) {
+        self.locked = false;
+    }
+
+    pub(crate) fn set_frozen(&mut self, frozen: bool) {
+        self.frozen = frozen;
+    }
+
+    /// Closes the account, which requires all of its funds to be withdrawn.
+    fn close(&mut self) -> Result<(), TransactionError> {
+        let balances = std::iter::once(self.balance(None)).chain(self.balances.values().copied());
+        for balance in balances {
+            if balance.total != Amount::zero() || balance.held != Amount::zero() {
+                return Err(TransactionError::NonZeroBalance {
+                    client: self.client,
+                    total: balance.total,
+                });
+            }
+        }
+        self.closed = true;
+        Ok(())
+    }
+
+    pub(crate) fn lock(&mut self, locking: LockingBehavior) {
+        if locking != LockingBehavior::Never {
+            self.locked = true;
+        }
+    }
+
+    /// Check that the account accepts a transaction of `variant` with `amount`.
+    pub(crate) fn check(
+        &self,
         variant: &TransactionVariant,
         amount: Amount,
+        config: &EngineConfig,
     ) -> Result<(), TransactionError> {
-        if self.locked {
+        if self.closed {
+            return Err(TransactionError::ClosedAccount);
+        }
+        if self.frozen {
+            return Err(TransactionError::FrozenAccount);
+        }
+
+        let deposit_allowed = config.locking == LockingBehavior::AllowDeposits
+            && *variant == TransactionVariant::Deposit;
+        if self.locked && !deposit_allowed {
             return Err(TransactionError::LockedAccount);
         }
 
@@ -92,25 +459,136 @@ impl Account {
             return Err(TransactionError::NegativeAmount);
         }
 
+        Ok(())
+    }
+
+    /// Applies a transaction of `variant` with `amount` according to `config`.
+    pub(crate) fn transaction(
+        &mut self,
+        variant: &TransactionVariant,
+        amount: Amount,
+        config: &EngineConfig,
+    ) -> Result<(), TransactionError> {
+        // Unlocking is the only thing allowed on a locked account
+        if *variant != TransactionVariant::Unlock {
+            self.check(variant, amount, config)?;
+        }
+
         match variant {
-            TransactionVariant::Deposit => {
-                self.deposit(amount);
+            TransactionVariant::Deposit => self.deposit(amount),
+            TransactionVariant::Withdrawal => self.withdraw(
+                amount,
+                config.overdraft_of(self.client, self.tier()),
+                config.withdrawal_limit_of(self.client, self.tier()),
+            ),
+            TransactionVariant::Dispute => self.dispute(amount),
+            TransactionVariant::Resolve => self.resolve(amount),
+            TransactionVariant::Chargeback => self.chargeback(amount, config.locking),
+            TransactionVariant::Unlock => {
+                self.unlock();
                 Ok(())
             }
-            TransactionVariant::Withdrawal => self.withdraw(amount),
-            TransactionVariant::Dispute => {
-                self.dispute(amount);
-                Ok(())
+            TransactionVariant::Close => self.close(),
+            TransactionVariant::Freeze | TransactionVariant::Unfreeze => {
+                unreachable!("Handled by `PaymentEngine::freeze` and `PaymentEngine::unfreeze`")
             }
-            TransactionVariant::Resolve => {
-                self.resolve(amount);
-                Ok(())
+            TransactionVariant::Transfer
+            | TransactionVariant::Fee
+            | TransactionVariant::Convert => unreachable!(
+                "Transfers, fees and conversions are applied as a withdrawal and a deposit"
+            ),
+            TransactionVariant::Authorize
+            | TransactionVariant::Capture
+            | TransactionVariant::Void => {
+                unreachable!("Authorizations are applied as reservations")
             }
+            TransactionVariant::Representment => {
+                unreachable!("Applied by `Account::represent`")
+            }
+        }
+    }
+
+    /// Reverses the chargeback of a transaction of `disputed` with `amount`
+    /// according to `config`, which is allowed on an account locked by it.
+    pub(crate) fn represent(
+        &mut self,
+        disputed: &TransactionVariant,
+        amount: Amount,
+        config: &EngineConfig,
+    ) -> Result<(), TransactionError> {
+        if self.closed {
+            return Err(TransactionError::ClosedAccount);
+        }
+        if self.frozen {
+            return Err(TransactionError::FrozenAccount);
+        }
+        // The chargeback of a credited withdrawal released the funds instead
+        // of removing them
+        if *disputed == TransactionVariant::Withdrawal
+            && config.withdrawal_disputes == WithdrawalDisputes::Credit
+        {
+            self.update(
+                self.available.checked_sub(amount),
+                Ok(self.held),
+                self.total.checked_sub(amount),
+            )
+        } else {
+            self.deposit(amount)
+        }
+    }
+
+    /// Applies a dispute, resolve or chargeback (`variant`) of a transaction
+    /// of `disputed` with `amount` according to `config`.
+    pub(crate) fn dispute_transaction(
+        &mut self,
+        variant: &TransactionVariant,
+        disputed: &TransactionVariant,
+        amount: Amount,
+        config: &EngineConfig,
+    ) -> Result<(), TransactionError> {
+        if *disputed != TransactionVariant::Withdrawal
+            || config.withdrawal_disputes != WithdrawalDisputes::Credit
+        {
+            return self.transaction(variant, amount, config);
+        }
+
+        self.check(variant, amount, config)?;
+        match variant {
+            TransactionVariant::Dispute => self.update(
+                Ok(self.available),
+                self.held.checked_add(amount),
+                self.total.checked_add(amount),
+            )?,
+            TransactionVariant::Resolve => self.update(
+                Ok(self.available),
+                self.held.checked_sub(amount),
+                self.total.checked_sub(amount),
+            )?,
             TransactionVariant::Chargeback => {
-                self.chargeback(amount);
-                Ok(())
+                self.update(
+                    self.available.checked_add(amount),
+                    self.held.checked_sub(amount),
+                    Ok(self.total),
+                )?;
+                self.lock(config.locking);
+            }
+            TransactionVariant::Deposit
+            | TransactionVariant::Withdrawal
+            | TransactionVariant::Unlock
+            | TransactionVariant::Close
+            | TransactionVariant::Freeze
+            | TransactionVariant::Unfreeze
+            | TransactionVariant::Transfer
+            | TransactionVariant::Fee
+            | TransactionVariant::Convert
+            | TransactionVariant::Authorize
+            | TransactionVariant::Capture
+            | TransactionVariant::Void
+            | TransactionVariant::Representment => {
+                return self.transaction(variant, amount, config)
             }
         }
+        Ok(())
     }
 }
 
@@ -126,8 +604,18 @@ mod tests {
             total: Amount::new(10, 1).unwrap(),
             held: Amount::zero(),
             locked: false,
+            closed: false,
+            frozen: false,
+            balances: BTreeMap::new(),
+            withdrawn: Amount::zero(),
+            tier: None,
+            metadata: None,
         };
-        let res = account.transaction(&TransactionVariant::Chargeback, Amount::new(10, 1).unwrap());
+        let res = account.transaction(
+            &TransactionVariant::Chargeback,
+            Amount::new(10, 1).unwrap(),
+            &EngineConfig::default(),
+        );
         assert!(res.is_ok());
         assert!(account.locked);
     }
@@ -140,8 +628,18 @@ mod tests {
             total: Amount::new(10, 1).unwrap(),
             held: Amount::zero(),
             locked: true,
+            closed: false,
+            frozen: false,
+            balances: BTreeMap::new(),
+            withdrawn: Amount::zero(),
+            tier: None,
+            metadata: None,
         };
-        let res = account.transaction(&TransactionVariant::Withdrawal, Amount::new(10, 1).unwrap());
+        let res = account.transaction(
+            &TransactionVariant::Withdrawal,
+            Amount::new(10, 1).unwrap(),
+            &EngineConfig::default(),
+        );
         assert!(res.is_err());
         assert_eq!(res.unwrap_err(), TransactionError::LockedAccount);
     }
@@ -154,11 +652,232 @@ mod tests {
             total: Amount::new(10, 1).unwrap(),
             held: Amount::zero(),
             locked: false,
+            closed: false,
+            frozen: false,
+            balances: BTreeMap::new(),
+            withdrawn: Amount::zero(),
+            tier: None,
+            metadata: None,
         };
         let mut amount = Amount::zero();
         amount -= Amount::new(1, 0).unwrap();
-        let res = account.transaction(&TransactionVariant::Withdrawal, amount);
+        let res = account.transaction(
+            &TransactionVariant::Withdrawal,
+            amount,
+            &EngineConfig::default(),
+        );
         assert!(res.is_err());
         assert_eq!(res.unwrap_err(), TransactionError::NegativeAmount);
     }
+
+    #[test]
+    fn locked_account_allows_deposits_if_configured() {
+        let config = EngineConfig {
+            locking: LockingBehavior::AllowDeposits,
+            ..EngineConfig::default()
+        };
+        let mut account = Account::new(1);
+        account.locked = true;
+        let amount = Amount::new(10, 1).unwrap();
+        assert!(account
+            .transaction(&TransactionVariant::Deposit, amount, &config)
+            .is_ok());
+        assert_eq!(
+            account.transaction(&TransactionVariant::Withdrawal, amount, &config),
+            Err(TransactionError::LockedAccount)
+        );
+    }
+
+    #[test]
+    fn withdrawal_within_overdraft() {
+        let config = EngineConfig {
+            overdraft: Amount::new(5, 0).unwrap(),
+            ..EngineConfig::default()
+        };
+        let mut account = Account::new(1);
+        assert!(account
+            .transaction(
+                &TransactionVariant::Withdrawal,
+                Amount::new(5, 0).unwrap(),
+                &config
+            )
+            .is_ok());
+        assert!(account.available().is_sign_negative());
+        assert!(account
+            .transaction(
+                &TransactionVariant::Withdrawal,
+                Amount::new(1, 0).unwrap(),
+                &config
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn client_overdraft_replaces_default() {
+        let config = EngineConfig {
+            overdraft: Amount::new(5, 0).unwrap(),
+            client_overdrafts: BTreeMap::from([(2, Amount::zero())]),
+            ..EngineConfig::default()
+        };
+        let amount = Amount::new(3, 0).unwrap();
+        let mut account = Account::new(1);
+        assert!(account
+            .transaction(&TransactionVariant::Withdrawal, amount, &config)
+            .is_ok());
+        let mut account = Account::new(2);
+        assert!(matches!(
+            account.transaction(&TransactionVariant::Withdrawal, amount, &config),
+            Err(TransactionError::InsufficientFunds { .. })
+        ));
+    }
+
+    #[test]
+    fn withdrawals_within_limits() {
+        let limit = WithdrawalLimit {
+            max_amount: Some(Amount::new(5, 0).unwrap()),
+            max_total: Some(Amount::new(8, 0).unwrap()),
+        };
+        let config = EngineConfig {
+            withdrawal_limit: limit,
+            client_withdrawal_limits: BTreeMap::from([(2, WithdrawalLimit::default())]),
+            ..EngineConfig::default()
+        };
+        let amount = |amount| Amount::new(amount, 0).unwrap();
+        let mut account = Account::new(1);
+        account
+            .transaction(&TransactionVariant::Deposit, amount(20), &config)
+            .unwrap();
+        assert_eq!(
+            account.transaction(&TransactionVariant::Withdrawal, amount(6), &config),
+            Err(TransactionError::LimitExceeded {
+                client: 1,
+                limit: amount(5),
+                amount_attempted: amount(6),
+            })
+        );
+        account
+            .transaction(&TransactionVariant::Withdrawal, amount(5), &config)
+            .unwrap();
+        assert_eq!(
+            account.transaction(&TransactionVariant::Withdrawal, amount(4), &config),
+            Err(TransactionError::LimitExceeded {
+                client: 1,
+                limit: amount(8),
+                amount_attempted: amount(4),
+            })
+        );
+        account
+            .transaction(&TransactionVariant::Withdrawal, amount(3), &config)
+            .unwrap();
+        assert_eq!(account.withdrawn(), amount(8));
+        assert_eq!(account.available(), amount(12));
+
+        let mut account = Account::new(2);
+        assert!(account
+            .transaction(&TransactionVariant::Withdrawal, amount(6), &config)
+            .is_err_and(|e| matches!(e, TransactionError::InsufficientFunds { .. })));
+    }
+
+    #[test]
+    fn credited_withdrawal_dispute() {
+        let config = EngineConfig {
+            withdrawal_disputes: WithdrawalDisputes::Credit,
+            ..EngineConfig::default()
+        };
+        let amount = Amount::new(4, 0).unwrap();
+        let mut account = Account::new(1);
+        account
+            .transaction(
+                &TransactionVariant::Deposit,
+                Amount::new(10, 0).unwrap(),
+                &config,
+            )
+            .unwrap();
+        account
+            .transaction(&TransactionVariant::Withdrawal, amount, &config)
+            .unwrap();
+
+        let withdrawal = TransactionVariant::Withdrawal;
+        account
+            .dispute_transaction(&TransactionVariant::Dispute, &withdrawal, amount, &config)
+            .unwrap();
+        assert_eq!(account.available(), Amount::new(6, 0).unwrap());
+        assert_eq!(account.held(), amount);
+        assert_eq!(account.total(), Amount::new(10, 0).unwrap());
+
+        account
+            .dispute_transaction(
+                &TransactionVariant::Chargeback,
+                &withdrawal,
+                amount,
+                &config,
+            )
+            .unwrap();
+        assert_eq!(account.available(), Amount::new(10, 0).unwrap());
+        assert_eq!(account.held(), Amount::zero());
+        assert_eq!(account.total(), Amount::new(10, 0).unwrap());
+        assert!(account.locked());
+    }
+
+    #[test]
+    fn close_requires_zero_balance() {
+        let config = EngineConfig::default();
+        let amount = Amount::new(10, 1).unwrap();
+        let mut account = Account::new(1);
+        account
+            .transaction(&TransactionVariant::Deposit, amount, &config)
+            .unwrap();
+        assert_eq!(
+            account.transaction(&TransactionVariant::Close, Amount::zero(), &config),
+            Err(TransactionError::NonZeroBalance {
+                client: 1,
+                total: amount
+            })
+        );
+
+        account
+            .transaction(&TransactionVariant::Withdrawal, amount, &config)
+            .unwrap();
+        account
+            .transaction(&TransactionVariant::Close, Amount::zero(), &config)
+            .unwrap();
+        assert!(account.closed());
+        assert_eq!(
+            account.transaction(&TransactionVariant::Deposit, amount, &config),
+            Err(TransactionError::ClosedAccount)
+        );
+    }
+
+    #[test]
+    fn currencies_have_separate_balances() {
+        let config = EngineConfig::default();
+        let mut account = Account::new(1);
+        let amount = Amount::new(5, 0).unwrap();
+        account
+            .in_currency(Some("EUR"), |a| {
+                a.transaction(&TransactionVariant::Deposit, amount, &config)
+            })
+            .unwrap();
+        assert_eq!(account.total(), Amount::zero());
+        assert_eq!(account.balance(Some("EUR")).available, amount);
+        assert!(matches!(
+            account.transaction(&TransactionVariant::Withdrawal, amount, &config),
+            Err(TransactionError::InsufficientFunds { .. })
+        ));
+
+        // Every currency has to be empty to close the account
+        assert_eq!(
+            account.transaction(&TransactionVariant::Close, amount, &config),
+            Err(TransactionError::NonZeroBalance {
+                client: 1,
+                total: amount
+            })
+        );
+        account
+            .in_currency(Some("EUR"), |a| {
+                a.transaction(&TransactionVariant::Withdrawal, amount, &config)
+            })
+            .unwrap();
+        assert!(account.balances().is_empty());
+    }
 }
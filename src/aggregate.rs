@@ -0,0 +1,274 @@
+//! User defined aggregations over the final accounts.
+//!
+//! Aggregations are written as `<name>=<function>[ where <filter>]`, where the
+//! function is one of `count`, `sum(<field>)`, `min(<field>)` or
+//! `max(<field>)` over the fields `client`, `available`, `held` or `total`,
+//! and the optional filter is a [`Filter`] selecting the accounts to include.
+//! For example `low_clients=sum(available) where client >= 1000 && client <= 1999`.
+
+use std::{fmt::Display, str::FromStr};
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::{
+    account::Account,
+    filter::{Filter, FilterError},
+};
+
+#[derive(Debug, PartialEq, Error)]
+pub enum AggregationError {
+    #[error("Aggregation `{0}` is missing a `<name>=`")]
+    MissingName(String),
+    #[error("Unknown aggregate function `{0}`")]
+    UnknownFunction(String),
+    #[error("Unknown field `{0}` in aggregation")]
+    UnknownField(String),
+    #[error(transparent)]
+    Filter(#[from] FilterError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AggregateField {
+    Client,
+    Available,
+    Held,
+    Total,
+}
+
+impl AggregateField {
+    fn value(&self, account: &Account) -> Decimal {
+        match self {
+            AggregateField::Client => Decimal::from(account.client()),
+            AggregateField::Available => account.available().into(),
+            AggregateField::Held => account.held().into(),
+            AggregateField::Total => account.total().into(),
+        }
+    }
+}
+
+impl FromStr for AggregateField {
+    type Err = AggregationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "client" => Ok(AggregateField::Client),
+            "available" => Ok(AggregateField::Available),
+            "held" => Ok(AggregateField::Held),
+            "total" => Ok(AggregateField::Total),
+            field => Err(AggregationError::UnknownField(field.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AggregateFunction {
+    Count,
+    Sum(AggregateField),
+    Min(AggregateField),
+    Max(AggregateField),
+}
+
+impl FromStr for AggregateFunction {
+    type Err = AggregationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s == "count" {
+            return Ok(AggregateFunction::Count);
+        }
+        let (function, field) = s
+            .strip_suffix(')')
+            .and_then(|s| s.split_once('('))
+            .ok_or_else(|| AggregationError::UnknownFunction(s.to_string()))?;
+        let field = field.parse()?;
+        match function.trim() {
+            "sum" => Ok(AggregateFunction::Sum(field)),
+            "min" => Ok(AggregateFunction::Min(field)),
+            "max" => Ok(AggregateFunction::Max(field)),
+            _ => Err(AggregationError::UnknownFunction(s.to_string())),
+        }
+    }
+}
+
+/// A named aggregation over the accounts matching an optional [`Filter`].
+///
+/// # Examples
+///
+/// ```
+/// use randomlib::Aggregation;
+///
+/// let aggregation: Aggregation = "locked=count where locked".parse().unwrap();
+/// assert_eq!(aggregation.name, "locked");
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Aggregation {
+    pub name: String,
+    pub function: AggregateFunction,
+    pub filter: Option<Filter>,
+    source: String,
+}
+
+impl FromStr for Aggregation {
+    type Err = AggregationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, rest) = s
+            .split_once('=')
+            .filter(|(name, _)| !name.trim().is_empty())
+            .ok_or_else(|| AggregationError::MissingName(s.to_string()))?;
+        let (function, filter) = match rest.split_once(" where ") {
+            Some((function, filter)) => (function, Some(filter.parse()?)),
+            None => (rest, None),
+        };
+        Ok(Self {
+            name: name.trim().to_string(),
+            function: function.parse()?,
+            filter,
+            source: s.to_string(),
+        })
+    }
+}
+
+impl Display for Aggregation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+/// The result of an [`Aggregation`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AggregateValue {
+    pub name: String,
+    /// `None` for `min` and `max` when no account matched
+    pub value: Option<Decimal>,
+}
+
+/// Computes [`Aggregation`]s one account at a time.
+#[derive(Debug)]
+pub(crate) struct Aggregator<'a> {
+    aggregations: &'a [Aggregation],
+    values: Vec<Option<Decimal>>,
+}
+
+impl<'a> Aggregator<'a> {
+    pub(crate) fn new(aggregations: &'a [Aggregation]) -> Self {
+        let values = aggregations
+            .iter()
+            .map(|aggregation| match aggregation.function {
+                AggregateFunction::Count | AggregateFunction::Sum(_) => Some(Decimal::ZERO),
+                AggregateFunction::Min(_) | AggregateFunction::Max(_) => None,
+            })
+            .collect();
+        Self {
+            aggregations,
+            values,
+        }
+    }
+
+    pub(crate) fn add(&mut self, account: &Account) {
+        for (aggregation, value) in self.aggregations.iter().zip(self.values.iter_mut()) {
+            if let Some(filter) = &aggregation.filter {
+                if !filter.matches(account) {
+                    continue;
+                }
+            }
+            *value = match (aggregation.function, *value) {
+                (AggregateFunction::Count, v) => v.map(|v| v.saturating_add(Decimal::ONE)),
+                (AggregateFunction::Sum(field), v) => {
+                    v.map(|v| v.saturating_add(field.value(account)))
+                }
+                (AggregateFunction::Min(field), v) => Some(match v {
+                    Some(v) => v.min(field.value(account)),
+                    None => field.value(account),
+                }),
+                (AggregateFunction::Max(field), v) => Some(match v {
+                    Some(v) => v.max(field.value(account)),
+                    None => field.value(account),
+                }),
+            };
+        }
+    }
+
+    pub(crate) fn finish(self) -> Vec<AggregateValue> {
+        self.aggregations
+            .iter()
+            .zip(self.values)
+            .map(|(aggregation, value)| AggregateValue {
+                name: aggregation.name.clone(),
+                value,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Amount, EngineConfig, TransactionVariant};
+
+    fn account(client: u16, available: i64) -> Account {
+        let mut account = Account::new(client);
+        account
+            .transaction(
+                &TransactionVariant::Deposit,
+                Amount::new(available, 0).unwrap(),
+                &EngineConfig::default(),
+            )
+            .unwrap();
+        account
+    }
+
+    #[test]
+    fn computes_aggregations() {
+        let aggregations = [
+            "low=sum(available) where client >= 1000 && client <= 1999",
+            "all=count",
+            "smallest=min(available)",
+            "largest_locked=max(total) where locked",
+        ]
+        .iter()
+        .map(|s| s.parse().unwrap())
+        .collect::<Vec<Aggregation>>();
+
+        let mut aggregator = Aggregator::new(&aggregations);
+        for account in [account(1000, 5), account(1999, 7), account(2000, 3)] {
+            aggregator.add(&account);
+        }
+        let values = aggregator
+            .finish()
+            .into_iter()
+            .map(|v| v.value)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            values,
+            vec![
+                Some(Decimal::from(12)),
+                Some(Decimal::from(3)),
+                Some(Decimal::from(3)),
+                None
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_aggregations() {
+        assert_eq!(
+            "sum(available)".parse::<Aggregation>().unwrap_err(),
+            AggregationError::MissingName("sum(available)".to_string())
+        );
+        assert_eq!(
+            "a=avg(available)".parse::<Aggregation>().unwrap_err(),
+            AggregationError::UnknownFunction("avg(available)".to_string())
+        );
+        assert_eq!(
+            "a=sum(locked)".parse::<Aggregation>().unwrap_err(),
+            AggregationError::UnknownField("locked".to_string())
+        );
+        assert!(matches!(
+            "a=count where held >".parse::<Aggregation>().unwrap_err(),
+            AggregationError::Filter(_)
+        ));
+    }
+}
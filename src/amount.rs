@@ -1,12 +1,16 @@
 use std::{
     convert::TryFrom,
-    fmt::Display,
-    ops::{AddAssign, SubAssign},
+    fmt::{Display, Write},
+    iter::Sum,
+    ops::{Add, AddAssign, Mul, Neg, Sub, SubAssign},
+    str::FromStr,
 };
 
 use rust_decimal::prelude::*;
 use serde::{de, Deserialize, Deserializer, Serialize};
 
+use crate::error::TransactionError;
+
 /// A wrapper type for `rust_decimal::Decimal` to add additional constraints:
 /// - The scale is no more than 4
 /// - The value is nonnegative when created
@@ -24,14 +28,36 @@ use serde::{de, Deserialize, Deserializer, Serialize};
 ///
 /// # Panics
 ///
-/// The user of [`Amount`] must ensure that any mutable operation does not
-/// make the resulting value overflow, because then it will panic.
+/// The arithmetic operators panic if the result overflows. Use
+/// [`Amount::checked_add`], [`Amount::checked_sub`] and
+/// [`Amount::checked_mul`] for amounts read from an input.
 #[derive(Debug, Serialize, Clone, Copy)]
 pub struct Amount(Decimal);
 
+/// Always writes four decimal places, e.g. `1.5000`, which [`Amount::from_str`]
+/// parses back to the same amount.
 impl Display for Amount {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.0)
+        // Padded by hand, as formatting with a precision overflows for values
+        // with 29 digits
+        let value = self.0.round_dp(4);
+        write!(f, "{}", value)?;
+        if value.scale() == 0 {
+            f.write_char('.')?;
+        }
+        (value.scale()..4).try_for_each(|_| f.write_char('0'))
+    }
+}
+
+/// Parses an [`Amount`] with the same checks as deserializing one, so it must
+/// be nonnegative with no more than four decimal places.
+impl FromStr for Amount {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let value = Decimal::from_str(s.trim())
+            .map_err(|e| format!("`{}` is not a valid amount: {}", s, e))?;
+        Self::try_from(value)
     }
 }
 
@@ -44,9 +70,130 @@ impl Amount {
         Self(Decimal::zero())
     }
 
+    /// Creates an [`Amount`] from `value` rounded to four decimal places with
+    /// `mode`. Unlike [`Amount::try_from`] it accepts more precise values, but
+    /// still rejects negative ones, even if they round to zero.
+    pub fn rounded(value: Decimal, mode: RoundingMode) -> Result<Self, String> {
+        if value.is_sign_negative() {
+            return Self::try_from(value);
+        }
+        Self::try_from(mode.round(value))
+    }
+
     pub fn is_sign_negative(&self) -> bool {
         self.0.is_sign_negative()
     }
+
+    /// Adds `rhs`, or fails with [`TransactionError::Overflow`].
+    pub fn checked_add(self, rhs: Self) -> Result<Self, TransactionError> {
+        self.0
+            .checked_add(rhs.0)
+            .map(Self)
+            .ok_or(TransactionError::Overflow)
+    }
+
+    /// Subtracts `rhs`, or fails with [`TransactionError::Overflow`]. The
+    /// result can be negative.
+    pub fn checked_sub(self, rhs: Self) -> Result<Self, TransactionError> {
+        self.0
+            .checked_sub(rhs.0)
+            .map(Self)
+            .ok_or(TransactionError::Overflow)
+    }
+
+    /// Multiplies by `rhs`, rounded to four decimal places with banker's
+    /// rounding, or fails with [`TransactionError::Overflow`].
+    pub fn checked_mul(self, rhs: Decimal) -> Result<Self, TransactionError> {
+        self.0
+            .checked_mul(rhs)
+            .map(|product| Self(product.round_dp(4)))
+            .ok_or(TransactionError::Overflow)
+    }
+
+    /// The absolute value, e.g. of a negative balance.
+    pub fn abs(self) -> Self {
+        Self(self.0.abs())
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+
+    /// A fixed size binary representation, see [`Amount::from_bytes`].
+    pub(crate) fn to_bytes(self) -> [u8; 16] {
+        self.0.serialize()
+    }
+
+    /// The inverse of [`Amount::to_bytes`].
+    pub(crate) fn from_bytes(bytes: [u8; 16]) -> Self {
+        Self(Decimal::deserialize(bytes))
+    }
+
+    /// Parses an [`Amount`] without the nonnegative and precision checks.
+    ///
+    /// See [`deserialize_unchecked`].
+    #[cfg(feature = "sqlite")]
+    pub(crate) fn parse_unchecked(value: &str) -> Result<Self, String> {
+        Decimal::from_str(value)
+            .map(Amount)
+            .map_err(|e| format!("`{}` is not a valid amount: {}", value, e))
+    }
+}
+
+/// How amounts with more than four decimal places are rounded, see
+/// [`EngineConfig::rounding`](crate::EngineConfig::rounding).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RoundingMode {
+    /// Round half to even, e.g. `0.00125` to `0.0012`.
+    #[default]
+    Bankers,
+    /// Round half away from zero, e.g. `0.00125` to `0.0013`.
+    HalfUp,
+    /// Drop the extra decimal places, e.g. `0.00129` to `0.0012`.
+    Truncate,
+}
+
+impl RoundingMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RoundingMode::Bankers => "bankers",
+            RoundingMode::HalfUp => "half-up",
+            RoundingMode::Truncate => "truncate",
+        }
+    }
+
+    /// Rounds `value` to four decimal places. Values that are precise enough
+    /// are returned as they are.
+    pub fn round(self, value: Decimal) -> Decimal {
+        let strategy = match self {
+            RoundingMode::Bankers => RoundingStrategy::MidpointNearestEven,
+            RoundingMode::HalfUp => RoundingStrategy::MidpointAwayFromZero,
+            RoundingMode::Truncate => RoundingStrategy::ToZero,
+        };
+        value.round_dp_with_strategy(4, strategy)
+    }
+}
+
+impl FromStr for RoundingMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bankers" => Ok(RoundingMode::Bankers),
+            "half-up" => Ok(RoundingMode::HalfUp),
+            "truncate" => Ok(RoundingMode::Truncate),
+            _ => Err(format!(
+                "Unknown rounding mode `{}`, expected `bankers`, `half-up` or `truncate`",
+                s
+            )),
+        }
+    }
+}
+
+impl Display for RoundingMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
 }
 
 impl TryFrom<Decimal> for Amount {
@@ -71,6 +218,12 @@ impl TryFrom<Decimal> for Amount {
     }
 }
 
+impl From<Amount> for Decimal {
+    fn from(amount: Amount) -> Self {
+        amount.0
+    }
+}
+
 impl AddAssign for Amount {
     fn add_assign(&mut self, rhs: Self) {
         // Assume no overflow
@@ -85,6 +238,54 @@ impl SubAssign for Amount {
     }
 }
 
+impl Add for Amount {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        Self(self.0 + rhs.0)
+    }
+}
+
+impl Sub for Amount {
+    type Output = Self;
+
+    /// The result can be negative.
+    fn sub(self, rhs: Self) -> Self {
+        Self(self.0 - rhs.0)
+    }
+}
+
+/// Rounds like [`Amount::checked_mul`].
+impl Mul<Decimal> for Amount {
+    type Output = Self;
+
+    fn mul(self, rhs: Decimal) -> Self {
+        Self((self.0 * rhs).round_dp(4))
+    }
+}
+
+impl Mul<u64> for Amount {
+    type Output = Self;
+
+    fn mul(self, rhs: u64) -> Self {
+        Self(self.0 * Decimal::from(rhs))
+    }
+}
+
+impl Neg for Amount {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Self(-self.0)
+    }
+}
+
+impl Sum for Amount {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::zero(), Add::add)
+    }
+}
+
 impl PartialEq for Amount {
     fn eq(&self, other: &Self) -> bool {
         self.0 == other.0
@@ -108,6 +309,18 @@ impl<'de> Deserialize<'de> for Amount {
     }
 }
 
+/// Deserializes an [`Amount`] without the nonnegative check.
+///
+/// Only meant for balances that are restored from a trusted source, such as a
+/// snapshot, as balances can legitimately become negative.
+pub(crate) fn deserialize_unchecked<'de, D>(deserializer: D) -> Result<Amount, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let val: Decimal = Deserialize::deserialize(deserializer)?;
+    Ok(Amount(val))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -143,4 +356,68 @@ mod tests {
             assert!(Amount::try_from(value).is_err());
         }
     }
+
+    #[test]
+    fn parses_and_formats_four_decimal_places() {
+        let amount: Amount = "1.5".parse().unwrap();
+        assert_eq!(amount, Amount::new(15, 1).unwrap());
+        assert_eq!(amount.to_string(), "1.5000");
+        assert_eq!(amount.to_string().parse(), Ok(amount));
+        assert_eq!(Amount::zero().to_string(), "0.0000");
+        assert_eq!(" 2 ".parse::<Amount>().unwrap().to_string(), "2.0000");
+        assert!("abc".parse::<Amount>().is_err());
+        assert!("-1.0".parse::<Amount>().is_err());
+        assert!("1.00001".parse::<Amount>().is_err());
+    }
+
+    #[test]
+    fn rounds_precise_values() {
+        let rounded = |num, mode| Amount::rounded(Decimal::new(num, 6), mode);
+        let amount = |num| Ok(Amount::new(num, 4).unwrap());
+        assert_eq!(rounded(1_234_250, RoundingMode::Bankers), amount(12342));
+        assert_eq!(rounded(1_234_350, RoundingMode::Bankers), amount(12344));
+        assert_eq!(rounded(1_234_250, RoundingMode::HalfUp), amount(12343));
+        assert_eq!(rounded(1_234_299, RoundingMode::Truncate), amount(12342));
+        assert_eq!(rounded(1_200_000, RoundingMode::Truncate), amount(12000));
+        assert!(rounded(-1_234_250, RoundingMode::HalfUp).is_err());
+        assert!(rounded(-1, RoundingMode::Truncate).is_err());
+        assert_eq!("half-up".parse(), Ok(RoundingMode::HalfUp));
+        assert!("up".parse::<RoundingMode>().is_err());
+    }
+
+    #[test]
+    fn operators_keep_the_scale() {
+        let amount = |num, scale| Amount::new(num, scale).unwrap();
+        assert_eq!(amount(15, 1) + amount(25, 2), amount(175, 2));
+        assert_eq!((amount(1, 0) - amount(25, 1)).to_string(), "-1.5000");
+        assert_eq!(-(amount(1, 0) - amount(25, 1)), amount(15, 1));
+        assert_eq!((amount(1, 0) - amount(25, 1)).abs(), amount(15, 1));
+        assert_eq!(amount(15, 1) * 3, amount(45, 1));
+        // 1.2345 * 0.5 = 0.61725, rounded to even
+        assert_eq!(amount(12345, 4) * Decimal::new(5, 1), amount(6172, 4));
+        assert_eq!(
+            vec![amount(1, 0), amount(2, 1)].into_iter().sum::<Amount>(),
+            amount(12, 1)
+        );
+        assert_eq!(
+            Amount(Decimal::MAX).checked_mul(Decimal::TWO),
+            Err(TransactionError::Overflow)
+        );
+    }
+
+    #[test]
+    fn checked_arithmetic_reports_overflow() {
+        let max = Amount(Decimal::MAX);
+        let one = Amount::new(1, 0).unwrap();
+        assert_eq!(max.checked_add(one), Err(TransactionError::Overflow));
+        assert_eq!(
+            Amount(Decimal::MIN).checked_sub(one),
+            Err(TransactionError::Overflow)
+        );
+        assert_eq!(
+            Amount::zero().checked_sub(one).unwrap().to_string(),
+            "-1.0000"
+        );
+        assert_eq!(one.checked_add(one), Ok(Amount::new(2, 0).unwrap()));
+    }
 }
@@ -0,0 +1,96 @@
+use std::error::Error;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use tempfile::NamedTempFile;
+
+/// Writes a file at `path` with `write`, replacing it only once `write`
+/// succeeded.
+///
+/// The content is written to a temporary file in the same directory, which is
+/// synced and renamed to `path` afterwards. A crash or an error while writing
+/// therefore never leaves a truncated file at `path`.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::Write;
+///
+/// let dir = tempfile::tempdir().unwrap();
+/// let path = dir.path().join("accounts.csv");
+/// randomlib::write_atomically(&path, |file| {
+///     file.write_all(b"client,available,held,total,locked,closed\n")?;
+///     Ok(())
+/// })
+/// .unwrap();
+/// assert!(path.exists());
+/// ```
+pub fn write_atomically<P, F>(path: P, write: F) -> Result<(), Box<dyn Error>>
+where
+    P: AsRef<Path>,
+    F: FnOnce(&mut File) -> Result<(), Box<dyn Error>>,
+{
+    let mut file = AtomicFile::create(path)?;
+    write(file.as_file_mut())?;
+    file.commit()
+}
+
+/// A file that only appears at its path once [`AtomicFile::commit`] is
+/// called, see [`write_atomically`]. Dropping it without committing discards
+/// everything written.
+#[derive(Debug)]
+pub(crate) struct AtomicFile {
+    path: PathBuf,
+    file: NamedTempFile,
+}
+
+impl AtomicFile {
+    pub(crate) fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: NamedTempFile::new_in(dir)?,
+        })
+    }
+
+    pub(crate) fn as_file_mut(&mut self) -> &mut File {
+        self.file.as_file_mut()
+    }
+
+    pub(crate) fn commit(self) -> Result<(), Box<dyn Error>> {
+        self.file.as_file().sync_all()?;
+        self.file.persist(&self.path).map_err(|e| e.error)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Write;
+
+    use super::*;
+
+    #[test]
+    fn keeps_existing_file_on_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("output.csv");
+        fs::write(&path, "complete").unwrap();
+
+        let result = write_atomically(&path, |file| {
+            file.write_all(b"trunc")?;
+            Err("crash".into())
+        });
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "complete");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        write_atomically(&path, |file| Ok(file.write_all(b"new")?)).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+    }
+}
@@ -0,0 +1,115 @@
+use std::{fmt, io};
+
+use serde::Serialize;
+
+use crate::{Account, Amount, TransactionError, TransactionVariant};
+
+/// The balances of an account before and after a transaction changed it,
+/// see [`AuditWriter`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AuditRecord {
+    pub tx: u32,
+    #[serde(rename = "type")]
+    pub variant: TransactionVariant,
+    /// The client of the account, which is the destination for the second
+    /// record of a transfer or a fee
+    pub client: u16,
+    pub available_before: Amount,
+    pub held_before: Amount,
+    pub total_before: Amount,
+    pub available_after: Amount,
+    pub held_after: Amount,
+    pub total_after: Amount,
+}
+
+impl AuditRecord {
+    /// A record of `tx` changing `before` into `after`, where `before` is
+    /// `None` for an account created by the transaction.
+    pub(crate) fn new(
+        tx: u32,
+        variant: TransactionVariant,
+        before: Option<&Account>,
+        after: &Account,
+    ) -> Self {
+        Self {
+            tx,
+            variant,
+            client: after.client(),
+            available_before: before.map_or_else(Amount::zero, Account::available),
+            held_before: before.map_or_else(Amount::zero, Account::held),
+            total_before: before.map_or_else(Amount::zero, Account::total),
+            available_after: after.available(),
+            held_after: after.held(),
+            total_after: after.total(),
+        }
+    }
+}
+
+/// Writes an [`AuditRecord`] as CSV for every account changed by an accepted
+/// transaction, registered with
+/// [`PaymentEngine::set_audit_writer`](crate::PaymentEngine::set_audit_writer).
+///
+/// A transfer or a fee credited to a fee account writes a record for both
+/// accounts. Unlocking and closing accounts does not change balances and is
+/// not recorded.
+pub struct AuditWriter {
+    writer: csv::Writer<Box<dyn io::Write>>,
+}
+
+impl AuditWriter {
+    pub fn new<W: io::Write + 'static>(writer: W) -> Self {
+        Self {
+            writer: csv::Writer::from_writer(Box::new(writer)),
+        }
+    }
+
+    pub(crate) fn write(&mut self, record: &AuditRecord) -> Result<(), TransactionError> {
+        self.writer.serialize(record).map_err(audit_error)
+    }
+
+    pub(crate) fn flush(&mut self) -> Result<(), TransactionError> {
+        self.writer.flush().map_err(audit_error)
+    }
+}
+
+impl fmt::Debug for AuditWriter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuditWriter").finish_non_exhaustive()
+    }
+}
+
+/// Failing to write the audit trail stops processing like a failing storage.
+fn audit_error(e: impl fmt::Display) -> TransactionError {
+    TransactionError::Storage(format!("Failed to write the audit log: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+
+    use super::*;
+    use crate::{process_with_policy, PaymentEngine, ProcessingPolicy};
+
+    #[test]
+    fn records_balances_before_and_after() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.csv");
+        let mut engine = PaymentEngine::default();
+        engine.set_audit_writer(AuditWriter::new(File::create(&path).unwrap()));
+        let input = "type,client,tx,amount,destination\n\
+                     deposit,1,1,5.0,\n\
+                     withdrawal,1,2,9.0,\n\
+                     transfer,1,3,2.0,2\n\
+                     dispute,1,1,,\n";
+        process_with_policy(input.as_bytes(), &mut engine, ProcessingPolicy::SkipInvalid).unwrap();
+        engine.flush().unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "tx,type,client,available_before,held_before,total_before,available_after,held_after,total_after\n\
+             1,deposit,1,0,0,0,5.0,0,5.0\n\
+             3,transfer,1,5.0,0,5.0,3.0,0,3.0\n\
+             3,transfer,2,0,0,0,2.0,0,2.0\n\
+             1,dispute,1,3.0,0,3.0,-2.0,5.0,3.0\n"
+        );
+    }
+}
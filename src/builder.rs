@@ -0,0 +1,314 @@
+use std::collections::{BTreeMap, HashMap};
+
+use uuid::Uuid;
+
+use crate::{
+    config::{
+        DailyLimit, DisputeExpiry, EngineConfig, IdCollision, LockingBehavior, Retention,
+        TierRules, WithdrawalDisputes, WithdrawalLimit,
+    },
+    hot::HotAccountConfig,
+    id::IdGenerator,
+    interest::{HeldFundsConfig, InterestConfig},
+    observer::EngineObserver,
+    store::{AccountStore, TransactionStore},
+    validator::Validator,
+    velocity::VelocityConfig,
+    Account, Amount, PaymentEngine, RoundingMode,
+};
+
+/// Configures a new [`PaymentEngine`], see [`PaymentEngine::builder`].
+#[derive(Debug, Default)]
+pub struct PaymentEngineBuilder {
+    config: EngineConfig,
+    accounts_capacity: usize,
+    transactions_capacity: usize,
+    hot_accounts: Option<HotAccountConfig>,
+    history: bool,
+    timeline: bool,
+    dispute_log: bool,
+    track_changes: bool,
+    max_transactions_per_client: Option<u64>,
+    velocity: Option<VelocityConfig>,
+    observers: Vec<Box<dyn EngineObserver>>,
+    validators: Vec<Box<dyn Validator>>,
+    ids: Option<Box<dyn IdGenerator>>,
+    run_id: Option<Uuid>,
+    interest: Option<InterestConfig>,
+}
+
+impl PaymentEngineBuilder {
+    /// Replaces the whole configuration.
+    pub fn config(mut self, config: EngineConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn withdrawal_disputes(mut self, withdrawal_disputes: WithdrawalDisputes) -> Self {
+        self.config.withdrawal_disputes = withdrawal_disputes;
+        self
+    }
+
+    /// Allows withdrawals to take the available funds below zero by at most
+    /// `overdraft`.
+    pub fn overdraft(mut self, overdraft: Amount) -> Self {
+        self.config.overdraft = overdraft;
+        self
+    }
+
+    /// Allows the withdrawals of `client` to take its available funds below
+    /// zero by at most `overdraft`, instead of the limit of every client.
+    pub fn client_overdraft(mut self, client: u16, overdraft: Amount) -> Self {
+        self.config.client_overdrafts.insert(client, overdraft);
+        self
+    }
+
+    /// Limits the withdrawals of every client, see [`WithdrawalLimit`].
+    pub fn withdrawal_limit(mut self, limit: WithdrawalLimit) -> Self {
+        self.config.withdrawal_limit = limit;
+        self
+    }
+
+    /// Limits the withdrawals of `client`, instead of the limit of every
+    /// client.
+    pub fn client_withdrawal_limit(mut self, client: u16, limit: WithdrawalLimit) -> Self {
+        self.config.client_withdrawal_limits.insert(client, limit);
+        self
+    }
+
+    /// Caps what every client can deposit and withdraw per day, see
+    /// [`DailyLimit`].
+    pub fn daily_limit(mut self, limit: DailyLimit) -> Self {
+        self.config.daily_limit = limit;
+        self
+    }
+
+    /// Caps what `client` can deposit and withdraw per day, instead of the
+    /// limit of every client.
+    pub fn client_daily_limit(mut self, client: u16, limit: DailyLimit) -> Self {
+        self.config.client_daily_limits.insert(client, limit);
+        self
+    }
+
+    /// Applies `rules` to the accounts in `tier`, see
+    /// [`PaymentEngine::set_tier`](crate::PaymentEngine::set_tier).
+    pub fn tier(mut self, tier: &str, rules: TierRules) -> Self {
+        self.config.tiers.insert(tier.to_string(), rules);
+        self
+    }
+
+    pub fn id_collision(mut self, id_collision: IdCollision) -> Self {
+        self.config.id_collision = id_collision;
+        self
+    }
+
+    pub fn locking(mut self, locking: LockingBehavior) -> Self {
+        self.config.locking = locking;
+        self
+    }
+
+    pub fn retention(mut self, retention: Retention) -> Self {
+        self.config.retention = retention;
+        self
+    }
+
+    /// Unlocks accounts when a chargeback is reversed, see
+    /// [`EngineConfig::unlock_on_representment`].
+    pub fn unlock_on_representment(mut self) -> Self {
+        self.config.unlock_on_representment = true;
+        self
+    }
+
+    /// Settles disputes open for longer than `expiry`, see
+    /// [`PaymentEngine::expire_disputes`].
+    pub fn dispute_expiry(mut self, expiry: DisputeExpiry) -> Self {
+        self.config.dispute_expiry = Some(expiry);
+        self
+    }
+
+    /// Charges or credits the funds held by disputes, see
+    /// [`PaymentEngine::accrue_held_funds`](crate::PaymentEngine::accrue_held_funds).
+    pub fn held_funds(mut self, config: HeldFundsConfig) -> Self {
+        self.config.held_funds = Some(config);
+        self
+    }
+
+    /// Credits fees to the account of `client`, see
+    /// [`EngineConfig::fee_account`].
+    pub fn fee_account(mut self, client: u16) -> Self {
+        self.config.fee_account = Some(client);
+        self
+    }
+
+    /// Rounds amounts with more than four decimal places with `mode`, see
+    /// [`EngineConfig::rounding`].
+    pub fn rounding(mut self, mode: RoundingMode) -> Self {
+        self.config.rounding = Some(mode);
+        self
+    }
+
+    /// The number of clients to reserve memory for up front.
+    pub fn accounts_capacity(mut self, capacity: usize) -> Self {
+        self.accounts_capacity = capacity;
+        self
+    }
+
+    /// The number of transactions to reserve memory for up front.
+    pub fn transactions_capacity(mut self, capacity: usize) -> Self {
+        self.transactions_capacity = capacity;
+        self
+    }
+
+    /// Detects hot clients, see [`PaymentEngine::with_hot_accounts`].
+    pub fn hot_accounts(mut self, config: HotAccountConfig) -> Self {
+        self.hot_accounts = Some(config);
+        self
+    }
+
+    /// Accrues interest, see [`PaymentEngine::advance_time`].
+    pub fn interest(mut self, config: InterestConfig) -> Self {
+        self.interest = Some(config);
+        self
+    }
+
+    /// Uses `run_id` instead of a random one, see [`PaymentEngine::run_id`].
+    pub fn run_id(mut self, run_id: Uuid) -> Self {
+        self.run_id = Some(run_id);
+        self
+    }
+
+    /// Keeps an index of the transactions of every client, see
+    /// [`PaymentEngine::history`].
+    pub fn history(mut self) -> Self {
+        self.history = true;
+        self
+    }
+
+    /// Records the balance of every client over time, see
+    /// [`PaymentEngine::balance_at`].
+    pub fn timeline(mut self) -> Self {
+        self.timeline = true;
+        self
+    }
+
+    /// Keeps a log of every dispute and its outcome, see
+    /// [`PaymentEngine::dispute_outcomes`].
+    pub fn dispute_log(mut self) -> Self {
+        self.dispute_log = true;
+        self
+    }
+
+    /// Tracks which accounts change, see [`PaymentEngine::take_changes`].
+    pub fn track_changes(mut self) -> Self {
+        self.track_changes = true;
+        self
+    }
+
+    /// Processes at most `max` transactions per client, see
+    /// [`PaymentEngine::quarantined`].
+    pub fn max_transactions_per_client(mut self, max: u64) -> Self {
+        self.max_transactions_per_client = Some(max);
+        self
+    }
+
+    /// Generates the ids of transactions originated by the engine with `ids`
+    /// instead of counting up from 1, see [`PaymentEngine::next_tx_id`].
+    pub fn id_generator(mut self, ids: Box<dyn IdGenerator>) -> Self {
+        self.ids = Some(ids);
+        self
+    }
+
+    /// Limits how many transactions, and how much, each client can move
+    /// within a sliding window, see [`VelocityConfig`].
+    pub fn velocity(mut self, config: VelocityConfig) -> Self {
+        self.velocity = Some(config);
+        self
+    }
+
+    /// Registers `observer`, see [`PaymentEngine::add_observer`].
+    pub fn observer(mut self, observer: Box<dyn EngineObserver>) -> Self {
+        self.observers.push(observer);
+        self
+    }
+
+    /// Registers `validator`, see [`PaymentEngine::add_validator`].
+    pub fn validator(mut self, validator: Box<dyn Validator>) -> Self {
+        self.validators.push(validator);
+        self
+    }
+
+    /// Builds an engine with the default [`HashMap`] stores.
+    pub fn build(self) -> PaymentEngine {
+        let accounts = HashMap::with_capacity(self.accounts_capacity);
+        let transactions = HashMap::with_capacity(self.transactions_capacity);
+        self.build_with_stores(accounts, transactions)
+    }
+
+    /// Builds an engine that keeps accounts in a [`BTreeMap`], so that they
+    /// are iterated and written in order of client id without sorting them.
+    /// The accounts capacity hint is ignored.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use randomlib::{process, Account, PaymentEngine};
+    ///
+    /// let mut engine = PaymentEngine::builder().build_ordered();
+    /// process("type,client,tx,amount\ndeposit,2,1,1.0\ndeposit,1,2,1.0".as_bytes(), &mut engine)
+    ///     .unwrap();
+    /// let clients = engine.accounts_iter(false).map(Account::client).collect::<Vec<_>>();
+    /// assert_eq!(clients, vec![1, 2]);
+    /// ```
+    pub fn build_ordered(self) -> PaymentEngine<BTreeMap<u16, Account>> {
+        let transactions = HashMap::with_capacity(self.transactions_capacity);
+        self.build_with_stores(BTreeMap::new(), transactions)
+    }
+
+    /// Builds an engine with custom stores, see
+    /// [`PaymentEngine::with_stores`]. The capacity hints are ignored.
+    pub fn build_with_stores<A, T>(self, accounts: A, transactions: T) -> PaymentEngine<A, T>
+    where
+        A: AccountStore,
+        T: TransactionStore,
+    {
+        let mut engine = PaymentEngine::with_stores(accounts, transactions);
+        engine.set_config(self.config);
+        if let Some(config) = self.hot_accounts {
+            engine.set_hot_accounts(config);
+        }
+        if self.history {
+            engine.set_history();
+        }
+        if self.timeline {
+            engine.set_timeline();
+        }
+        if self.dispute_log {
+            engine.set_dispute_log();
+        }
+        if self.track_changes {
+            engine.track_changes();
+        }
+        if let Some(run_id) = self.run_id {
+            engine.set_run_id(run_id);
+        }
+        if let Some(config) = self.interest {
+            engine.set_interest(config);
+        }
+        if let Some(max) = self.max_transactions_per_client {
+            engine.set_client_limit(max);
+        }
+        if let Some(config) = self.velocity {
+            engine.set_velocity(config);
+        }
+        if let Some(ids) = self.ids {
+            engine.set_id_generator(ids);
+        }
+        for observer in self.observers {
+            engine.add_observer(observer);
+        }
+        for validator in self.validators {
+            engine.add_validator(validator);
+        }
+        engine
+    }
+}
@@ -0,0 +1,55 @@
+use serde::{Deserialize, Serialize};
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+/// A token to stop a run from another thread, see
+/// [`RunOptions::cancel`](crate::RunOptions::cancel).
+///
+/// Clones share the same state, so cancelling any of them cancels all.
+///
+/// # Examples
+///
+/// ```
+/// use randomlib::{run_with_options, CancellationToken, RunOptions};
+///
+/// let token = CancellationToken::new();
+/// let options = RunOptions {
+///     cancel: Some(token.clone()),
+///     ..RunOptions::default()
+/// };
+/// token.cancel();
+///
+/// let input = "type,client,tx,amount\ndeposit,1,1,1.0\n";
+/// let report = run_with_options(input.as_bytes(), std::io::sink(), &options).unwrap();
+/// assert_eq!(report.resume.unwrap().line, 2);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stops processing at the next record.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Where a cancelled run stopped in the input.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ResumePoint {
+    /// The byte offset of the first record that was not processed
+    pub byte: u64,
+    /// The line of the first record that was not processed, starting at 1 for
+    /// the header
+    pub line: u64,
+}
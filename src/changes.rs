@@ -0,0 +1,34 @@
+use std::collections::BTreeSet;
+
+use crate::Account;
+
+/// The accounts that changed since the previous
+/// [`PaymentEngine::take_changes`](crate::PaymentEngine::take_changes).
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccountChanges {
+    /// Counts up from 1 with every call, so that a consumer can detect
+    /// missed changes
+    pub sequence: u64,
+    /// The changed accounts as they are now, ordered by client
+    pub accounts: Vec<Account>,
+}
+
+/// The clients whose accounts changed since the changes were last taken.
+#[derive(Debug, Default)]
+pub(crate) struct ChangeTracker {
+    clients: BTreeSet<u16>,
+    sequence: u64,
+}
+
+impl ChangeTracker {
+    pub(crate) fn mark(&mut self, client: u16) {
+        self.clients.insert(client);
+    }
+
+    /// Returns the changed clients with the next sequence number and starts
+    /// over.
+    pub(crate) fn take(&mut self) -> (u64, BTreeSet<u16>) {
+        self.sequence += 1;
+        (self.sequence, std::mem::take(&mut self.clients))
+    }
+}
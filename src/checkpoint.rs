@@ -0,0 +1,162 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufRead, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    atomic::write_atomically,
+    cancel::ResumePoint,
+    compression::Compression,
+    metrics::{EngineStats, RunMetrics},
+    snapshot::Snapshot,
+};
+
+/// The state of a [`PaymentEngine`](crate::PaymentEngine) part way through a
+/// run, see [`PaymentEngine::checkpoint`](crate::PaymentEngine::checkpoint)
+/// and [`PaymentEngine::resume`](crate::PaymentEngine::resume).
+///
+/// Unlike a [`Snapshot`] this includes the [`RunMetrics`] and [`EngineStats`]
+/// so far, so that a resumed run reports the same totals as one that was
+/// never interrupted.
+/// Hot account detection and client limits start over on resume.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub snapshot: Snapshot,
+    pub metrics: RunMetrics,
+    #[serde(default)]
+    pub stats: EngineStats,
+    /// Where the next row starts in the input, for checkpoints written
+    /// during a run, see [`CheckpointOptions`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position: Option<ResumePoint>,
+}
+
+/// Writes a [`Checkpoint`] during
+/// [`run_with_options`](crate::run_with_options), see
+/// [`RunOptions::checkpoint`](crate::RunOptions::checkpoint), so that a run
+/// that crashes can continue from the last one instead of from the start of
+/// the input.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CheckpointOptions {
+    /// Write a checkpoint after every this many accepted transactions.
+    pub every: u64,
+    /// Replaced atomically with every checkpoint, compressed according to its
+    /// extension, see [`Compression::from_path`].
+    pub path: PathBuf,
+}
+
+impl Checkpoint {
+    pub fn read<R: io::Read>(reader: R) -> Result<Self, Box<dyn Error>> {
+        Ok(serde_json::from_reader(io::BufReader::new(reader))?)
+    }
+
+    pub fn write<W: io::Write>(&self, writer: W) -> Result<(), Box<dyn Error>> {
+        let mut writer = io::BufWriter::new(writer);
+        serde_json::to_writer(&mut writer, self)?;
+        io::Write::flush(&mut writer)?;
+        Ok(())
+    }
+
+    /// Reads a checkpoint from the file at `path`, decompressing it according
+    /// to its extension.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let file = File::open(path)?;
+        Self::read(Compression::from_path(path).decoder(file)?)
+    }
+
+    /// Writes the checkpoint to `path` atomically, compressing it according
+    /// to its extension.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        let compression = Compression::from_path(path.as_ref());
+        write_atomically(path, |file| {
+            let mut encoder = compression.encoder(file)?;
+            self.write(&mut encoder)?;
+            encoder.finish()?;
+            Ok(())
+        })
+    }
+
+    /// The part of the CSV or TSV `input` after [`Checkpoint::position`],
+    /// preceded by its header, to continue the run with
+    /// [`PaymentEngine::resume`](crate::PaymentEngine::resume). The whole
+    /// input if the checkpoint has no position.
+    pub fn remaining<R: Read + Seek>(&self, mut input: R) -> io::Result<impl Read> {
+        let mut header = Vec::new();
+        if let Some(position) = self.position {
+            input.seek(SeekFrom::Start(0))?;
+            io::BufReader::new(&mut input).read_until(b'\n', &mut header)?;
+            input.seek(SeekFrom::Start(position.byte))?;
+        }
+        Ok(io::Cursor::new(header).chain(input))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{process, run_with_options, PaymentEngine, RunOptions};
+
+    const HEADER: &str = "type,client,tx,amount\n";
+
+    #[test]
+    fn resumed_run_matches_uninterrupted_run() {
+        let first = "deposit,1,1,5.0\ndeposit,2,2,3.0\n";
+        let second = "dispute,1,1,\nwithdrawal,2,3,1.0\n";
+
+        let mut uninterrupted = PaymentEngine::default();
+        let input = format!("{}{}{}", HEADER, first, second);
+        process(input.as_bytes(), &mut uninterrupted).unwrap();
+
+        let mut interrupted = PaymentEngine::default();
+        let input = format!("{}{}", HEADER, first);
+        process(input.as_bytes(), &mut interrupted).unwrap();
+        let mut stored = Vec::new();
+        interrupted.checkpoint().write(&mut stored).unwrap();
+
+        let checkpoint = Checkpoint::read(stored.as_slice()).unwrap();
+        let remaining = format!("{}{}", HEADER, second);
+        let resumed = PaymentEngine::resume(checkpoint, remaining.as_bytes()).unwrap();
+
+        assert_eq!(resumed.run_id(), interrupted.run_id());
+        assert_eq!(resumed.metrics(), uninterrupted.metrics());
+        let mut accounts = resumed.snapshot().accounts;
+        let mut expected = uninterrupted.snapshot().accounts;
+        accounts.sort_by_key(|account| account.client());
+        expected.sort_by_key(|account| account.client());
+        assert_eq!(accounts, expected);
+    }
+
+    #[test]
+    fn run_writes_checkpoints_to_continue_from() {
+        let input = format!(
+            "{}deposit,1,1,5.0\ndeposit,2,2,3.0\nwithdrawal,1,3,1.0\n",
+            HEADER
+        );
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("checkpoint.json");
+        let options = RunOptions {
+            checkpoint: Some(CheckpointOptions {
+                every: 2,
+                path: path.clone(),
+            }),
+            ..RunOptions::default()
+        };
+        let report = run_with_options(input.as_bytes(), io::sink(), &options).unwrap();
+
+        // Continue after the second row as if the run had crashed there
+        let checkpoint = Checkpoint::load(&path).unwrap();
+        assert_eq!(checkpoint.metrics.rows, 2);
+        assert_eq!(checkpoint.position.unwrap().line, 4);
+        let remaining = checkpoint
+            .remaining(io::Cursor::new(input.into_bytes()))
+            .unwrap();
+        let resumed = PaymentEngine::resume(checkpoint, remaining).unwrap();
+        assert_eq!(resumed.run_id(), report.run_id);
+        assert_eq!(resumed.metrics(), &report.metrics);
+        let account = resumed.accounts().get(&1).unwrap();
+        assert_eq!(account.total(), crate::Amount::new(4, 0).unwrap());
+    }
+}
@@ -0,0 +1,170 @@
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+/// The compression of an output file.
+///
+/// Gzip requires the `gzip` feature and zstd the `zstd` feature. Using them
+/// without the feature fails with an [`io::ErrorKind::Unsupported`] error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Selects the compression by the extension of `path`: `.gz` for gzip and
+    /// `.zst` for zstd.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Self {
+        match path.as_ref().extension().and_then(|e| e.to_str()) {
+            Some("gz") => Compression::Gzip,
+            Some("zst") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+
+    /// Wraps `writer` to compress everything written to it. Call
+    /// [`Encoder::finish`] when done.
+    pub(crate) fn encoder<W: Write>(self, writer: W) -> io::Result<Encoder<W>> {
+        match self {
+            Compression::None => Ok(Encoder::Plain(writer)),
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => Ok(Encoder::Gzip(flate2::write::GzEncoder::new(
+                writer,
+                flate2::Compression::default(),
+            ))),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Ok(Encoder::Zstd(zstd::Encoder::new(writer, 0)?)),
+            #[allow(unreachable_patterns)]
+            _ => Err(self.unsupported()),
+        }
+    }
+
+    /// Wraps `reader` to decompress everything read from it.
+    pub(crate) fn decoder<'a, R: Read + 'a>(self, reader: R) -> io::Result<Box<dyn Read + 'a>> {
+        match self {
+            Compression::None => Ok(Box::new(reader)),
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => Ok(Box::new(flate2::read::MultiGzDecoder::new(reader))),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Ok(Box::new(zstd::Decoder::new(reader)?)),
+            #[allow(unreachable_patterns)]
+            _ => Err(self.unsupported()),
+        }
+    }
+
+    #[allow(dead_code)]
+    fn unsupported(self) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!(
+                "{:?} compression requires the `{}` feature",
+                self,
+                self.as_str()
+            ),
+        )
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Compression::None),
+            "gzip" => Ok(Compression::Gzip),
+            "zstd" => Ok(Compression::Zstd),
+            _ => Err(format!(
+                "Unknown compression `{}`, expected `none`, `gzip` or `zstd`",
+                s
+            )),
+        }
+    }
+}
+
+/// A writer compressing with a [`Compression`].
+pub(crate) enum Encoder<W: Write> {
+    Plain(W),
+    #[cfg(feature = "gzip")]
+    Gzip(flate2::write::GzEncoder<W>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::Encoder<'static, W>),
+}
+
+impl<W: Write> Encoder<W> {
+    /// Writes the end of the compressed stream and returns the inner writer.
+    pub(crate) fn finish(self) -> io::Result<W> {
+        match self {
+            Encoder::Plain(writer) => Ok(writer),
+            #[cfg(feature = "gzip")]
+            Encoder::Gzip(encoder) => encoder.finish(),
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Encoder::Plain(writer) => writer.write(buf),
+            #[cfg(feature = "gzip")]
+            Encoder::Gzip(encoder) => encoder.write(buf),
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Encoder::Plain(writer) => writer.flush(),
+            #[cfg(feature = "gzip")]
+            Encoder::Gzip(encoder) => encoder.flush(),
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(compression: Compression) -> io::Result<Vec<u8>> {
+        let mut encoder = compression.encoder(Vec::new())?;
+        encoder.write_all(b"client,available\n1,1.0\n")?;
+        let compressed = encoder.finish()?;
+        let mut decompressed = Vec::new();
+        compression
+            .decoder(compressed.as_slice())?
+            .read_to_end(&mut decompressed)?;
+        Ok(decompressed)
+    }
+
+    #[test]
+    fn selects_compression_by_extension() {
+        assert_eq!(Compression::from_path("out.csv.gz"), Compression::Gzip);
+        assert_eq!(Compression::from_path("out.csv.zst"), Compression::Zstd);
+        assert_eq!(Compression::from_path("out.csv"), Compression::None);
+    }
+
+    #[test]
+    fn round_trips() {
+        for compression in [Compression::None, Compression::Gzip, Compression::Zstd] {
+            match round_trip(compression) {
+                Ok(decompressed) => assert_eq!(decompressed, b"client,available\n1,1.0\n"),
+                Err(e) => assert_eq!(e.kind(), io::ErrorKind::Unsupported),
+            }
+        }
+    }
+}
@@ -0,0 +1,261 @@
+use std::collections::BTreeMap;
+
+use crate::{interest::HeldFundsConfig, Amount, RoundingMode, TransactionVariant};
+
+/// Behaviors of a [`PaymentEngine`](crate::PaymentEngine) that can be
+/// configured with [`PaymentEngine::builder`](crate::PaymentEngine::builder).
+///
+/// The default matches the behavior of an engine created with
+/// [`PaymentEngine::default`](crate::PaymentEngine::default).
+#[derive(Debug, Clone, PartialEq)]
+pub struct EngineConfig {
+    pub withdrawal_disputes: WithdrawalDisputes,
+    /// How far the available funds of an account may go below zero by
+    /// withdrawals.
+    pub overdraft: Amount,
+    /// Overdraft limits of individual clients, which replace `overdraft` for
+    /// them.
+    pub client_overdrafts: BTreeMap<u16, Amount>,
+    pub withdrawal_limit: WithdrawalLimit,
+    /// Withdrawal limits of individual clients, which replace
+    /// `withdrawal_limit` for them.
+    pub client_withdrawal_limits: BTreeMap<u16, WithdrawalLimit>,
+    pub daily_limit: DailyLimit,
+    /// Daily limits of individual clients, which replace `daily_limit` for
+    /// them.
+    pub client_daily_limits: BTreeMap<u16, DailyLimit>,
+    pub id_collision: IdCollision,
+    pub locking: LockingBehavior,
+    /// The system account that [`TransactionVariant::Fee`]s are credited to.
+    /// Fees leave the system if not set.
+    ///
+    /// [`TransactionVariant::Fee`]: crate::TransactionVariant::Fee
+    pub fee_account: Option<u16>,
+    /// Rounds amounts read from an input with more than four decimal places
+    /// instead of rejecting them.
+    pub rounding: Option<RoundingMode>,
+    pub retention: Retention,
+    /// Settles disputes that stay open for too long, see
+    /// [`PaymentEngine::expire_disputes`](crate::PaymentEngine::expire_disputes).
+    pub dispute_expiry: Option<DisputeExpiry>,
+    /// Unlocks the account when a chargeback is reversed by a
+    /// [`TransactionVariant::Representment`].
+    pub unlock_on_representment: bool,
+    /// The rules of the accounts in each tier, see
+    /// [`Account::tier`](crate::Account::tier).
+    pub tiers: BTreeMap<String, TierRules>,
+    /// Charges or credits the funds held by disputes, see
+    /// [`PaymentEngine::accrue_held_funds`](crate::PaymentEngine::accrue_held_funds).
+    pub held_funds: Option<HeldFundsConfig>,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        Self {
+            withdrawal_disputes: WithdrawalDisputes::default(),
+            overdraft: Amount::zero(),
+            client_overdrafts: BTreeMap::new(),
+            withdrawal_limit: WithdrawalLimit::default(),
+            client_withdrawal_limits: BTreeMap::new(),
+            daily_limit: DailyLimit::default(),
+            client_daily_limits: BTreeMap::new(),
+            id_collision: IdCollision::default(),
+            locking: LockingBehavior::default(),
+            fee_account: None,
+            rounding: None,
+            retention: Retention::default(),
+            dispute_expiry: None,
+            unlock_on_representment: false,
+            tiers: BTreeMap::new(),
+            held_funds: None,
+        }
+    }
+}
+
+impl EngineConfig {
+    /// The rules of `tier`, if it has any.
+    pub fn tier_rules(&self, tier: Option<&str>) -> Option<&TierRules> {
+        self.tiers.get(tier?)
+    }
+
+    /// The overdraft limit of `client` in `tier`.
+    pub fn overdraft_of(&self, client: u16, tier: Option<&str>) -> Amount {
+        self.client_overdrafts
+            .get(&client)
+            .copied()
+            .or_else(|| self.tier_rules(tier)?.overdraft)
+            .unwrap_or(self.overdraft)
+    }
+
+    /// The withdrawal limit of `client` in `tier`.
+    pub fn withdrawal_limit_of(&self, client: u16, tier: Option<&str>) -> WithdrawalLimit {
+        self.client_withdrawal_limits
+            .get(&client)
+            .copied()
+            .or_else(|| self.tier_rules(tier)?.withdrawal_limit)
+            .unwrap_or(self.withdrawal_limit)
+    }
+
+    /// The daily limit of `client` in `tier`.
+    pub fn daily_limit_of(&self, client: u16, tier: Option<&str>) -> DailyLimit {
+        self.client_daily_limits
+            .get(&client)
+            .copied()
+            .or_else(|| self.tier_rules(tier)?.daily_limit)
+            .unwrap_or(self.daily_limit)
+    }
+}
+
+/// Rules for the accounts of a tier, for products with different terms that
+/// share the same transaction feed. Each limit that is set replaces the one
+/// of every client, while the limits of individual clients still take
+/// precedence.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TierRules {
+    pub overdraft: Option<Amount>,
+    pub withdrawal_limit: Option<WithdrawalLimit>,
+    pub daily_limit: Option<DailyLimit>,
+    /// Disputes of the transactions of the account fail with
+    /// [`TransactionError::NotDisputable`](crate::TransactionError::NotDisputable).
+    pub deny_disputes: bool,
+}
+
+/// Limits on the withdrawals of an account, which fail with
+/// [`TransactionError::LimitExceeded`](crate::TransactionError::LimitExceeded)
+/// beyond them. Unlimited by default.
+///
+/// The outgoing side of transfers, fees and conversions counts as a
+/// withdrawal, and amounts in every currency count towards the same total.
+/// Disputes of withdrawals do not give back any of the total.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct WithdrawalLimit {
+    /// The largest amount of a single withdrawal
+    pub max_amount: Option<Amount>,
+    /// The largest amount withdrawn over the lifetime of the account, see
+    /// [`Account::withdrawn`](crate::Account::withdrawn)
+    pub max_total: Option<Amount>,
+}
+
+/// Caps on the amounts an account can deposit and withdraw per day, in UTC,
+/// which fail with
+/// [`TransactionError::DailyLimitExceeded`](crate::TransactionError::DailyLimitExceeded)
+/// beyond them. Unlimited by default.
+///
+/// Only transactions with a `timestamp`, and without a currency, count.
+/// Transfers and fees count as withdrawals of their client. The totals of
+/// the current day are not kept in snapshots.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct DailyLimit {
+    pub max_deposits: Option<Amount>,
+    pub max_withdrawals: Option<Amount>,
+}
+
+/// How disputes of withdrawals affect the account. Payment processors differ
+/// on this.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WithdrawalDisputes {
+    /// Treat the withdrawal like a deposit: the amount is moved from the
+    /// available to the held funds, which can make the available funds
+    /// negative. A chargeback removes it from the account.
+    #[default]
+    Hold,
+    /// Credit the withdrawn amount back to the held funds while disputed. A
+    /// resolve removes it again, as the withdrawal stands, while a chargeback
+    /// releases it to the available funds.
+    Credit,
+    /// Disputes of withdrawals fail with
+    /// [`TransactionError::NotDisputable`](crate::TransactionError::NotDisputable).
+    Reject,
+}
+
+/// What happens to a deposit or withdrawal reusing an existing transaction
+/// id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IdCollision {
+    /// Fail with
+    /// [`TransactionError::TransactionAlreadyExist`](crate::TransactionError::TransactionAlreadyExist).
+    #[default]
+    Reject,
+    /// Ignore the transaction without an error, which makes replaying an
+    /// input idempotent.
+    Ignore,
+}
+
+/// Which disputed transactions are evicted from the engine once their dispute
+/// is settled, to save memory on dispute heavy inputs. An evicted
+/// transaction is forgotten like one dropped by
+/// [`PaymentEngine::prune`](crate::PaymentEngine::prune): its id can be
+/// reused by a later deposit or withdrawal, and references to it are ignored
+/// like those of unknown transactions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Retention {
+    /// Keep every transaction.
+    #[default]
+    KeepAll,
+    /// Evict transactions once they are charged back, as they can never be
+    /// disputed again. They can no longer be represented either.
+    EvictChargedBack,
+    /// Evict transactions once they are resolved or charged back. A resolved
+    /// transaction could otherwise be disputed again, so this also prevents
+    /// repeated disputes of the same transaction. Charged back transactions
+    /// can no longer be represented.
+    EvictSettled,
+}
+
+impl Retention {
+    /// Whether a transaction is evicted after a `variant` settled its
+    /// dispute.
+    pub(crate) fn evicts(self, variant: &TransactionVariant) -> bool {
+        match self {
+            Retention::KeepAll => false,
+            Retention::EvictChargedBack => *variant == TransactionVariant::Chargeback,
+            Retention::EvictSettled => matches!(
+                variant,
+                TransactionVariant::Resolve | TransactionVariant::Chargeback
+            ),
+        }
+    }
+}
+
+/// How long a dispute may stay open and how it is settled after that.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisputeExpiry {
+    /// Seconds after the dispute was opened, by the clock of the engine
+    pub after: u64,
+    pub outcome: ExpiryOutcome,
+}
+
+/// How [`PaymentEngine::expire_disputes`](crate::PaymentEngine::expire_disputes)
+/// settles an expired dispute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExpiryOutcome {
+    /// Release the held funds, like a resolve.
+    #[default]
+    Resolve,
+    /// Reverse the transaction, like a chargeback, which locks the account
+    /// depending on [`LockingBehavior`].
+    Chargeback,
+}
+
+impl ExpiryOutcome {
+    pub(crate) fn variant(self) -> TransactionVariant {
+        match self {
+            ExpiryOutcome::Resolve => TransactionVariant::Resolve,
+            ExpiryOutcome::Chargeback => TransactionVariant::Chargeback,
+        }
+    }
+}
+
+/// How accounts are locked by chargebacks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LockingBehavior {
+    /// A chargeback locks the account, after which every transaction fails
+    /// with [`TransactionError::LockedAccount`](crate::TransactionError::LockedAccount).
+    #[default]
+    RejectAll,
+    /// A chargeback locks the account, after which only deposits are
+    /// accepted.
+    AllowDeposits,
+    /// Chargebacks never lock the account.
+    Never,
+}
@@ -0,0 +1,164 @@
+use std::error::Error;
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::{de, Deserialize, Deserializer};
+
+use crate::{
+    Aggregation, AlertThreshold, Amount, Compression, Filter, Format, OutputSchema,
+    ProcessingPolicy, RateSchedule, RoundingMode, TemplateColumn,
+};
+
+/// Settings of a run read from a TOML file, which the flags of the binary
+/// override.
+///
+/// The keys are the long names of the flags, and values the binary parses
+/// from text are given as strings, with amounts as strings or numbers.
+/// Unknown keys are rejected to catch typos.
+///
+/// # Examples
+///
+/// ```
+/// use randomlib::{ConfigFile, ProcessingPolicy};
+///
+/// let config: ConfigFile = r#"
+///     policy = "skip-with-log"
+///     overdraft = 100
+///     alert = ["chargebacks > 10"]
+/// "#
+/// .parse()
+/// .unwrap();
+/// assert_eq!(config.policy, Some(ProcessingPolicy::SkipWithLog));
+/// assert_eq!(config.overdraft.unwrap().to_string(), "100.0000");
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ConfigFile {
+    #[serde(deserialize_with = "parse")]
+    pub input_format: Option<Format>,
+    #[serde(deserialize_with = "parse")]
+    pub output_format: Option<Format>,
+    pub output: Option<PathBuf>,
+    #[serde(deserialize_with = "parse")]
+    pub compression: Option<Compression>,
+    pub max_rows_per_part: Option<u64>,
+    pub max_bytes_per_part: Option<u64>,
+    pub snapshot: Option<PathBuf>,
+    pub audit: Option<PathBuf>,
+    pub rejected: Option<PathBuf>,
+    pub changes: Option<PathBuf>,
+    #[serde(deserialize_with = "parse")]
+    pub filter: Option<Filter>,
+    #[serde(deserialize_with = "parse_all")]
+    pub aggregate: Vec<Aggregation>,
+    #[serde(deserialize_with = "parse_all")]
+    pub column: Vec<TemplateColumn>,
+    #[serde(deserialize_with = "parse")]
+    pub columns: Option<OutputSchema>,
+    pub unsorted: Option<bool>,
+    pub attributes: Option<PathBuf>,
+    pub metadata: Option<PathBuf>,
+    #[serde(deserialize_with = "parse_all")]
+    pub alert: Vec<AlertThreshold>,
+    pub max_resident_transactions: Option<usize>,
+    pub max_transactions_per_client: Option<u64>,
+    #[serde(deserialize_with = "parse")]
+    pub overdraft: Option<Amount>,
+    pub fee_account: Option<u16>,
+    #[serde(deserialize_with = "parse")]
+    pub rounding: Option<RoundingMode>,
+    #[serde(deserialize_with = "parse")]
+    pub interest: Option<RateSchedule>,
+    pub interest_period: Option<u64>,
+    pub progress: Option<bool>,
+    pub progress_interval: Option<u64>,
+    #[serde(deserialize_with = "parse")]
+    pub policy: Option<ProcessingPolicy>,
+    /// Only used with the `sqlite` feature
+    pub sqlite: Option<PathBuf>,
+}
+
+impl ConfigFile {
+    /// Reads the configuration file at `path`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)?;
+        contents
+            .parse()
+            .map_err(|e| format!("{}: {}", path.display(), e).into())
+    }
+}
+
+impl FromStr for ConfigFile {
+    type Err = toml::de::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        toml::from_str(s)
+    }
+}
+
+/// A value parsed with [`FromStr`], written as a string or a number.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Text {
+    String(String),
+    Integer(i64),
+    Float(f64),
+}
+
+impl Text {
+    fn parse<T, E>(self) -> Result<T, E>
+    where
+        T: FromStr,
+        T::Err: Display,
+        E: de::Error,
+    {
+        let text = match self {
+            Text::String(text) => text,
+            Text::Integer(number) => number.to_string(),
+            Text::Float(number) => number.to_string(),
+        };
+        text.parse().map_err(E::custom)
+    }
+}
+
+fn parse<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    Text::deserialize(deserializer)?.parse().map(Some)
+}
+
+fn parse_all<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    Vec::<Text>::deserialize(deserializer)?
+        .into_iter()
+        .map(Text::parse)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_invalid_settings() {
+        assert!("polcy = \"skip-invalid\"".parse::<ConfigFile>().is_err());
+        assert!("policy = \"skip\"".parse::<ConfigFile>().is_err());
+        assert!("overdraft = -1".parse::<ConfigFile>().is_err());
+        let config = "input-format = \"ndjson\"\nmax-rows-per-part = 10"
+            .parse::<ConfigFile>()
+            .unwrap();
+        assert_eq!(config.input_format, Some(Format::Ndjson));
+        assert_eq!(config.max_rows_per_part, Some(10));
+        assert_eq!(config.policy, None);
+    }
+}
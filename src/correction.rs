@@ -0,0 +1,237 @@
+use std::{error::Error, fmt::Display, io};
+
+use crate::{
+    account::Account, amount::Amount, error::TransactionError, Transaction, TransactionVariant,
+};
+
+/// A difference to a target state that cannot be corrected with transactions
+/// and needs to be handled by an operator.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ManualCorrection {
+    /// Held funds can only be released by resolving or charging back the
+    /// disputes that hold them.
+    ReleaseHeld { client: u16, amount: Amount },
+    /// A closed account cannot be reopened.
+    Reopen { client: u16 },
+    /// An account can only be closed once it has no funds.
+    CloseWithFunds { client: u16 },
+}
+
+impl Display for ManualCorrection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ManualCorrection::ReleaseHeld { client, amount } => write!(
+                f,
+                "Client `{}` holds `{}` more than the target, resolve or charge back its disputes",
+                client, amount
+            ),
+            ManualCorrection::Reopen { client } => {
+                write!(f, "Client `{}` is closed but open in the target", client)
+            }
+            ManualCorrection::CloseWithFunds { client } => {
+                write!(
+                    f,
+                    "Client `{}` is closed in the target but has funds",
+                    client
+                )
+            }
+        }
+    }
+}
+
+/// The adjustments that move a [`PaymentEngine`](crate::PaymentEngine) to a
+/// target state, see
+/// [`PaymentEngine::corrections`](crate::PaymentEngine::corrections).
+#[derive(Debug, Clone, Default)]
+pub struct Corrections {
+    /// The transactions to apply, in order.
+    pub transactions: Vec<Transaction>,
+    /// The differences that remain after the transactions.
+    pub manual: Vec<ManualCorrection>,
+}
+
+impl Corrections {
+    /// Reads target accounts as CSV in the format of
+    /// [`write_accounts`](crate::write_accounts).
+    pub fn read_target<R: io::Read>(reader: R) -> Result<Vec<Account>, Box<dyn Error>> {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(reader);
+        Ok(reader.deserialize().collect::<Result<_, _>>()?)
+    }
+
+    /// Writes the transactions as CSV in the input format, so that they can
+    /// be reviewed and then processed like any other input.
+    pub fn write<W: io::Write>(&self, writer: W) -> Result<(), Box<dyn Error>> {
+        let mut w = csv::Writer::from_writer(writer);
+        w.write_record(["type", "client", "tx", "amount"])?;
+        for tx in &self.transactions {
+            let amount = tx
+                .amount
+                .map(|amount| amount.to_string())
+                .unwrap_or_default();
+            w.write_record([
+                tx.variant.as_str(),
+                &tx.client.to_string(),
+                &tx.tx.to_string(),
+                &amount,
+            ])?;
+        }
+        w.flush()?;
+        Ok(())
+    }
+}
+
+/// Plans the transactions that move every `current` account to its `target`,
+/// with ids from `next_tx`. Only the funds without a currency are compared.
+///
+/// Per account, in this order:
+/// - a locked account that is unlocked in the target is unlocked,
+/// - missing held funds are deposited and disputed,
+/// - the available funds are corrected with a deposit or a withdrawal,
+/// - an account that is locked in the target is locked with a chargeback of
+///   an empty deposit,
+/// - an account that is closed in the target is closed.
+pub(crate) fn plan<F, G>(
+    current: F,
+    targets: &[Account],
+    mut next_tx: G,
+) -> Result<Corrections, TransactionError>
+where
+    F: Fn(u16) -> Option<Account>,
+    G: FnMut() -> Result<u32, TransactionError>,
+{
+    let mut transactions = Vec::new();
+    let mut manual = Vec::new();
+    for target in targets {
+        let client = target.client();
+        let account = current(client).unwrap_or_else(|| Account::new(client));
+        let mut push = |variant, tx, amount| {
+            transactions.push(Transaction {
+                variant,
+                client,
+                tx,
+                amount,
+                disputed: false,
+                chargeback: false,
+                destination: None,
+                currency: None,
+                to_currency: None,
+                rate: None,
+                timestamp: None,
+            })
+        };
+
+        if account.closed() {
+            if !target.closed() {
+                manual.push(ManualCorrection::Reopen { client });
+            }
+            continue;
+        }
+        let held = target.held().checked_sub(account.held())?;
+        let available = target.available().checked_sub(account.available())?;
+        let changes = !held.is_zero() || !available.is_zero();
+
+        // A locked account rejects the other corrections, so it is locked
+        // again afterwards if needed
+        let unlock = account.locked() && (!target.locked() || changes);
+        if unlock {
+            push(TransactionVariant::Unlock, next_tx()?, None);
+        }
+
+        if held > Amount::zero() {
+            let tx = next_tx()?;
+            push(TransactionVariant::Deposit, tx, Some(held));
+            push(TransactionVariant::Dispute, tx, None);
+        } else if held < Amount::zero() {
+            manual.push(ManualCorrection::ReleaseHeld {
+                client,
+                amount: -held,
+            });
+        }
+
+        if available > Amount::zero() {
+            push(TransactionVariant::Deposit, next_tx()?, Some(available));
+        } else if available < Amount::zero() {
+            push(TransactionVariant::Withdrawal, next_tx()?, Some(-available));
+        }
+
+        if target.locked() && (unlock || !account.locked()) {
+            let tx = next_tx()?;
+            push(TransactionVariant::Deposit, tx, Some(Amount::zero()));
+            push(TransactionVariant::Dispute, tx, None);
+            push(TransactionVariant::Chargeback, tx, None);
+        }
+        if target.closed() {
+            if target.total() == Amount::zero() && target.held() == Amount::zero() {
+                push(TransactionVariant::Close, next_tx()?, None);
+            } else {
+                manual.push(ManualCorrection::CloseWithFunds { client });
+            }
+        }
+    }
+    Ok(Corrections {
+        transactions,
+        manual,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PaymentEngine;
+
+    #[test]
+    fn corrections_reach_the_target() {
+        let mut engine = PaymentEngine::default();
+        let deposit = |tx, client, amount| Transaction {
+            tx,
+            amount: Some(Amount::new(amount, 0).unwrap()),
+            client,
+            disputed: false,
+            variant: TransactionVariant::Deposit,
+            chargeback: false,
+            destination: None,
+            currency: None,
+            to_currency: None,
+            rate: None,
+            timestamp: None,
+        };
+        engine.insert(deposit(1, 1, 10)).unwrap();
+        engine.insert(deposit(2, 2, 5)).unwrap();
+        engine.insert(deposit(3, 3, 5)).unwrap();
+        engine
+            .insert(Transaction {
+                amount: None,
+                variant: TransactionVariant::Dispute,
+                ..deposit(3, 3, 0)
+            })
+            .unwrap();
+
+        let target = "client,available,held,total,locked,closed
+1,7.5,2,9.5,true,false
+2,0,0,0,false,true
+3,5,0,5,false,false
+4,1,0,1,false,false";
+        let target = Corrections::read_target(target.as_bytes()).unwrap();
+        let corrections = engine.corrections(&target).unwrap();
+        assert_eq!(
+            corrections.manual,
+            vec![ManualCorrection::ReleaseHeld {
+                client: 3,
+                amount: Amount::new(5, 0).unwrap()
+            }]
+        );
+        assert_eq!(
+            corrections.transactions[0].tx,
+            crate::CounterIds::DEFAULT_START
+        );
+
+        let mut buf = Vec::new();
+        corrections.write(&mut buf).unwrap();
+        crate::process(buf.as_slice(), &mut engine).unwrap();
+        for target in target.iter().filter(|account| account.client() != 3) {
+            assert_eq!(engine.accounts().get(&target.client()), Some(target));
+        }
+    }
+}
@@ -0,0 +1,392 @@
+//! Currencies with the number of minor units they are accounted in.
+//!
+//! Codes are validated against the ISO 4217 table of active currencies, see
+//! [`Currency::custom`] for currencies that are not part of it.
+
+use std::{borrow::Cow, fmt::Display, str::FromStr};
+
+use rust_decimal::Decimal;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use thiserror::Error;
+
+use crate::Amount;
+
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum CurrencyError {
+    #[error("Unknown currency `{0}`, expected an ISO 4217 code")]
+    Unknown(String),
+    #[error("Invalid custom currency code `{0}`, expected 3 to 8 uppercase letters or digits")]
+    InvalidCode(String),
+    #[error("`{0}` is an ISO 4217 code and cannot be used for a custom currency")]
+    IsoCode(String),
+    #[error("A currency can have at most 4 minor units, `{code}` has {minor_units}")]
+    TooManyMinorUnits { code: String, minor_units: u32 },
+    #[error("`{amount}` has more decimal places than the {minor_units} of `{code}`")]
+    Precision {
+        amount: Amount,
+        code: String,
+        minor_units: u32,
+    },
+}
+
+/// A currency and the number of decimal places (minor units) amounts in it
+/// are accounted in.
+///
+/// # Examples
+///
+/// ```
+/// use randomlib::{Amount, Currency};
+///
+/// let jpy: Currency = "JPY".parse().unwrap();
+/// assert_eq!(jpy.minor_units(), 0);
+///
+/// let usd: Currency = "USD".parse().unwrap();
+/// assert_eq!(usd.format(Amount::new(15, 1).unwrap()), "1.50 USD");
+///
+/// let points = Currency::custom("POINTS", 0).unwrap();
+/// assert!(points.is_custom());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Currency {
+    code: Cow<'static, str>,
+    minor_units: u32,
+    custom: bool,
+}
+
+impl Currency {
+    /// Looks up `code` in the ISO 4217 table.
+    pub fn iso(code: &str) -> Option<Self> {
+        ISO_4217
+            .binary_search_by(|(iso, _)| (*iso).cmp(code))
+            .ok()
+            .map(|i| {
+                let (code, minor_units) = ISO_4217[i];
+                Self {
+                    code: Cow::Borrowed(code),
+                    minor_units,
+                    custom: false,
+                }
+            })
+    }
+
+    /// A currency that is not part of ISO 4217, such as loyalty points or a
+    /// token.
+    ///
+    /// `code` must be 3 to 8 uppercase ASCII letters or digits and must not be
+    /// an ISO 4217 code, so that custom currencies can never be confused with
+    /// real ones.
+    pub fn custom(code: &str, minor_units: u32) -> Result<Self, CurrencyError> {
+        let valid = (3..=8).contains(&code.len())
+            && code
+                .bytes()
+                .all(|b| b.is_ascii_uppercase() || b.is_ascii_digit());
+        if !valid {
+            return Err(CurrencyError::InvalidCode(code.to_string()));
+        }
+        if Self::iso(code).is_some() {
+            return Err(CurrencyError::IsoCode(code.to_string()));
+        }
+        // Amounts cannot be more precise than this
+        if minor_units > 4 {
+            return Err(CurrencyError::TooManyMinorUnits {
+                code: code.to_string(),
+                minor_units,
+            });
+        }
+        Ok(Self {
+            code: Cow::Owned(code.to_string()),
+            minor_units,
+            custom: true,
+        })
+    }
+
+    pub fn code(&self) -> &str {
+        &self.code
+    }
+
+    /// The number of decimal places amounts in this currency have.
+    pub fn minor_units(&self) -> u32 {
+        self.minor_units
+    }
+
+    /// Whether this currency was created with [`Currency::custom`].
+    pub fn is_custom(&self) -> bool {
+        self.custom
+    }
+
+    /// Checks that `amount` has no more decimal places than
+    /// [`Currency::minor_units`].
+    pub fn check(&self, amount: Amount) -> Result<(), CurrencyError> {
+        if Decimal::from(amount).normalize().scale() > self.minor_units {
+            return Err(CurrencyError::Precision {
+                amount,
+                code: self.code.to_string(),
+                minor_units: self.minor_units,
+            });
+        }
+        Ok(())
+    }
+
+    /// Formats `amount` with exactly [`Currency::minor_units`] decimal
+    /// places, rounding half to even if needed, followed by the code.
+    pub fn format(&self, amount: Amount) -> String {
+        let mut value = Decimal::from(amount).round_dp(self.minor_units);
+        value.rescale(self.minor_units);
+        format!("{} {}", value, self.code)
+    }
+}
+
+impl FromStr for Currency {
+    type Err = CurrencyError;
+
+    /// Parses an ISO 4217 code. Custom currencies have to be created with
+    /// [`Currency::custom`].
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::iso(s).ok_or_else(|| CurrencyError::Unknown(s.to_string()))
+    }
+}
+
+impl Display for Currency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.code)
+    }
+}
+
+impl Serialize for Currency {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.code)
+    }
+}
+
+/// Only ISO 4217 codes can be deserialized, as the minor units of custom
+/// currencies are not known.
+impl<'de> Deserialize<'de> for Currency {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let code = Cow::<str>::deserialize(deserializer)?;
+        code.parse().map_err(de::Error::custom)
+    }
+}
+
+/// Active ISO 4217 currencies with their minor units, sorted by code. Funds
+/// and precious metals without minor units are not included.
+const ISO_4217: &[(&str, u32)] = &[
+    ("AED", 2),
+    ("AFN", 2),
+    ("ALL", 2),
+    ("AMD", 2),
+    ("ANG", 2),
+    ("AOA", 2),
+    ("ARS", 2),
+    ("AUD", 2),
+    ("AWG", 2),
+    ("AZN", 2),
+    ("BAM", 2),
+    ("BBD", 2),
+    ("BDT", 2),
+    ("BGN", 2),
+    ("BHD", 3),
+    ("BIF", 0),
+    ("BMD", 2),
+    ("BND", 2),
+    ("BOB", 2),
+    ("BOV", 2),
+    ("BRL", 2),
+    ("BSD", 2),
+    ("BTN", 2),
+    ("BWP", 2),
+    ("BYN", 2),
+    ("BZD", 2),
+    ("CAD", 2),
+    ("CDF", 2),
+    ("CHE", 2),
+    ("CHF", 2),
+    ("CHW", 2),
+    ("CLF", 4),
+    ("CLP", 0),
+    ("CNY", 2),
+    ("COP", 2),
+    ("COU", 2),
+    ("CRC", 2),
+    ("CUC", 2),
+    ("CUP", 2),
+    ("CVE", 2),
+    ("CZK", 2),
+    ("DJF", 0),
+    ("DKK", 2),
+    ("DOP", 2),
+    ("DZD", 2),
+    ("EGP", 2),
+    ("ERN", 2),
+    ("ETB", 2),
+    ("EUR", 2),
+    ("FJD", 2),
+    ("FKP", 2),
+    ("GBP", 2),
+    ("GEL", 2),
+    ("GHS", 2),
+    ("GIP", 2),
+    ("GMD", 2),
+    ("GNF", 0),
+    ("GTQ", 2),
+    ("GYD", 2),
+    ("HKD", 2),
+    ("HNL", 2),
+    ("HTG", 2),
+    ("HUF", 2),
+    ("IDR", 2),
+    ("ILS", 2),
+    ("INR", 2),
+    ("IQD", 3),
+    ("IRR", 2),
+    ("ISK", 0),
+    ("JMD", 2),
+    ("JOD", 3),
+    ("JPY", 0),
+    ("KES", 2),
+    ("KGS", 2),
+    ("KHR", 2),
+    ("KMF", 0),
+    ("KPW", 2),
+    ("KRW", 0),
+    ("KWD", 3),
+    ("KYD", 2),
+    ("KZT", 2),
+    ("LAK", 2),
+    ("LBP", 2),
+    ("LKR", 2),
+    ("LRD", 2),
+    ("LSL", 2),
+    ("LYD", 3),
+    ("MAD", 2),
+    ("MDL", 2),
+    ("MGA", 2),
+    ("MKD", 2),
+    ("MMK", 2),
+    ("MNT", 2),
+    ("MOP", 2),
+    ("MRU", 2),
+    ("MUR", 2),
+    ("MVR", 2),
+    ("MWK", 2),
+    ("MXN", 2),
+    ("MXV", 2),
+    ("MYR", 2),
+    ("MZN", 2),
+    ("NAD", 2),
+    ("NGN", 2),
+    ("NIO", 2),
+    ("NOK", 2),
+    ("NPR", 2),
+    ("NZD", 2),
+    ("OMR", 3),
+    ("PAB", 2),
+    ("PEN", 2),
+    ("PGK", 2),
+    ("PHP", 2),
+    ("PKR", 2),
+    ("PLN", 2),
+    ("PYG", 0),
+    ("QAR", 2),
+    ("RON", 2),
+    ("RSD", 2),
+    ("RUB", 2),
+    ("RWF", 0),
+    ("SAR", 2),
+    ("SBD", 2),
+    ("SCR", 2),
+    ("SDG", 2),
+    ("SEK", 2),
+    ("SGD", 2),
+    ("SHP", 2),
+    ("SLE", 2),
+    ("SLL", 2),
+    ("SOS", 2),
+    ("SRD", 2),
+    ("SSP", 2),
+    ("STN", 2),
+    ("SVC", 2),
+    ("SYP", 2),
+    ("SZL", 2),
+    ("THB", 2),
+    ("TJS", 2),
+    ("TMT", 2),
+    ("TND", 3),
+    ("TOP", 2),
+    ("TRY", 2),
+    ("TTD", 2),
+    ("TWD", 2),
+    ("TZS", 2),
+    ("UAH", 2),
+    ("UGX", 0),
+    ("USD", 2),
+    ("USN", 2),
+    ("UYI", 0),
+    ("UYU", 2),
+    ("UYW", 4),
+    ("UZS", 2),
+    ("VED", 2),
+    ("VES", 2),
+    ("VND", 0),
+    ("VUV", 0),
+    ("WST", 2),
+    ("XAF", 0),
+    ("XCD", 2),
+    ("XCG", 2),
+    ("XOF", 0),
+    ("XPF", 0),
+    ("YER", 2),
+    ("ZAR", 2),
+    ("ZMW", 2),
+    ("ZWG", 2),
+    ("ZWL", 2),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn table_is_sorted() {
+        assert!(ISO_4217.windows(2).all(|w| w[0].0 < w[1].0));
+    }
+
+    #[test]
+    fn validates_codes() {
+        assert_eq!("BHD".parse::<Currency>().unwrap().minor_units(), 3);
+        assert_eq!(
+            "usd".parse::<Currency>(),
+            Err(CurrencyError::Unknown("usd".to_string()))
+        );
+        assert_eq!(
+            Currency::custom("EUR", 2),
+            Err(CurrencyError::IsoCode("EUR".to_string()))
+        );
+        assert_eq!(
+            Currency::custom("pts", 2),
+            Err(CurrencyError::InvalidCode("pts".to_string()))
+        );
+        assert!(Currency::custom("PTS", 5).is_err());
+    }
+
+    #[test]
+    fn checks_and_formats_amounts() {
+        let jpy: Currency = "JPY".parse().unwrap();
+        assert!(jpy.check(Amount::new(100, 0).unwrap()).is_ok());
+        // Trailing zeros do not count
+        assert!(jpy.check(Amount::new(1000, 1).unwrap()).is_ok());
+        assert!(jpy.check(Amount::new(1005, 1).unwrap()).is_err());
+        assert_eq!(jpy.format(Amount::new(1005, 1).unwrap()), "100 JPY");
+
+        let kwd: Currency = "KWD".parse().unwrap();
+        assert_eq!(kwd.format(Amount::new(2, 0).unwrap()), "2.000 KWD");
+    }
+
+    #[test]
+    fn serializes_as_code() {
+        let usd: Currency = "USD".parse().unwrap();
+        let json = serde_json::to_string(&usd).unwrap();
+        assert_eq!(json, "\"USD\"");
+        assert_eq!(serde_json::from_str::<Currency>(&json).unwrap(), usd);
+        assert!(serde_json::from_str::<Currency>("\"XYZ\"").is_err());
+    }
+}
@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+
+use crate::{
+    config::DailyLimit, timeline::DAY, Amount, Transaction, TransactionError, TransactionVariant,
+};
+
+/// Whether a transaction adds funds to its account or takes them out, for
+/// [`DailyLimit`]s.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Flow {
+    In,
+    Out,
+}
+
+impl Flow {
+    /// The flow of `tx` counting towards daily limits, if any.
+    pub(crate) fn of(tx: &Transaction) -> Option<(Flow, Amount)> {
+        let amount = tx.amount.filter(|_| tx.currency.is_none())?;
+        match tx.variant {
+            TransactionVariant::Deposit => Some((Flow::In, amount)),
+            TransactionVariant::Withdrawal
+            | TransactionVariant::Transfer
+            | TransactionVariant::Fee
+            | TransactionVariant::Authorize => Some((Flow::Out, amount)),
+            _ => None,
+        }
+    }
+}
+
+/// The deposits and withdrawals of every client on the day of its latest
+/// transaction.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DailyTotals {
+    /// The day, in days since the Unix epoch, with the total deposited and
+    /// withdrawn on it
+    totals: HashMap<u16, (u64, Amount, Amount)>,
+}
+
+impl DailyTotals {
+    /// Checks that `amount` flowing at `timestamp` stays within `limit`.
+    pub(crate) fn check(
+        &self,
+        client: u16,
+        timestamp: u64,
+        (flow, amount): (Flow, Amount),
+        limit: DailyLimit,
+    ) -> Result<(), TransactionError> {
+        let max = match flow {
+            Flow::In => limit.max_deposits,
+            Flow::Out => limit.max_withdrawals,
+        };
+        let max = match max {
+            Some(max) => max,
+            None => return Ok(()),
+        };
+        let total = self
+            .total(client, timestamp / DAY, flow)
+            .checked_add(amount)?;
+        if total > max {
+            return Err(TransactionError::DailyLimitExceeded {
+                client,
+                limit: max,
+                amount_attempted: amount,
+            });
+        }
+        Ok(())
+    }
+
+    /// Adds `amount` flowing at `timestamp`, starting over on a new day.
+    pub(crate) fn add(&mut self, client: u16, timestamp: u64, (flow, amount): (Flow, Amount)) {
+        let day = timestamp / DAY;
+        let entry = self
+            .totals
+            .entry(client)
+            .or_insert((day, Amount::zero(), Amount::zero()));
+        if entry.0 != day {
+            *entry = (day, Amount::zero(), Amount::zero());
+        }
+        let total = match flow {
+            Flow::In => &mut entry.1,
+            Flow::Out => &mut entry.2,
+        };
+        // The total has been checked against the limit, so it cannot overflow
+        // unless there is none
+        *total = total.checked_add(amount).unwrap_or(*total);
+    }
+
+    /// The day of the latest transaction of `client`, with the totals on it.
+    pub(crate) fn get(&self, client: u16) -> Option<(u64, Amount, Amount)> {
+        self.totals.get(&client).copied()
+    }
+
+    /// Sets the totals of `client` to what [`DailyTotals::get`] returned.
+    pub(crate) fn restore(&mut self, client: u16, totals: Option<(u64, Amount, Amount)>) {
+        match totals {
+            Some(totals) => self.totals.insert(client, totals),
+            None => self.totals.remove(&client),
+        };
+    }
+
+    fn total(&self, client: u16, day: u64, flow: Flow) -> Amount {
+        match self.totals.get(&client) {
+            Some((d, deposited, _)) if *d == day && flow == Flow::In => *deposited,
+            Some((d, _, withdrawn)) if *d == day => *withdrawn,
+            _ => Amount::zero(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{process_with_policy, PaymentEngine, ProcessingPolicy};
+
+    #[test]
+    fn limits_reset_every_day() {
+        let mut engine = PaymentEngine::builder()
+            .daily_limit(DailyLimit {
+                max_deposits: Some(Amount::new(10, 0).unwrap()),
+                max_withdrawals: Some(Amount::new(3, 0).unwrap()),
+            })
+            .client_daily_limit(2, DailyLimit::default())
+            .build();
+        let input = "type,client,tx,amount,destination,timestamp
+deposit,1,1,8.0,,100
+deposit,1,2,3.0,,200
+withdrawal,1,3,2.0,,300
+transfer,1,4,2.0,2,400
+deposit,1,5,3.0,,86400
+deposit,1,6,3.0,,
+deposit,2,7,20.0,,500
+";
+        let errors =
+            process_with_policy(input.as_bytes(), &mut engine, ProcessingPolicy::SkipWithLog)
+                .unwrap();
+        let lines = errors.iter().map(|e| e.line).collect::<Vec<_>>();
+        assert_eq!(lines, [3, 5]);
+        assert_eq!(
+            errors[0].kind.to_string(),
+            "`3.0000` exceeds the daily limit of `10.0000` of client `1`"
+        );
+        assert_eq!(
+            engine.accounts().get(&1).unwrap().total(),
+            Amount::new(12, 0).unwrap()
+        );
+    }
+}
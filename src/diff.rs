@@ -0,0 +1,41 @@
+use crate::{Account, Transaction};
+
+/// The changes from the state of one engine to another, see
+/// [`PaymentEngine::diff`](crate::PaymentEngine::diff).
+///
+/// Only accounts and transactions that exist in the other engine are
+/// considered, so accounts or transactions it does not have are not reported.
+#[derive(Debug, Clone, Default)]
+pub struct StateDiff {
+    /// Accounts that only exist in the other engine, ordered by client
+    pub added_accounts: Vec<Account>,
+    /// Accounts whose funds or state differ, ordered by client
+    pub changed_accounts: Vec<AccountChange>,
+    /// The clients of changed accounts that are locked in the other engine
+    /// but not in this one
+    pub locked: Vec<u16>,
+    /// Transactions that only exist in the other engine, ordered by id
+    pub added_transactions: Vec<Transaction>,
+}
+
+impl StateDiff {
+    /// Whether the states are the same.
+    pub fn is_empty(&self) -> bool {
+        self.added_accounts.is_empty()
+            && self.changed_accounts.is_empty()
+            && self.added_transactions.is_empty()
+    }
+}
+
+/// An account of a [`StateDiff`] before and after the change.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AccountChange {
+    pub before: Account,
+    pub after: Account,
+}
+
+impl AccountChange {
+    pub fn client(&self) -> u16 {
+        self.after.client()
+    }
+}
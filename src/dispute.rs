@@ -0,0 +1,202 @@
+//! Statistics on the outcomes of disputes.
+
+use std::collections::{BTreeMap, HashMap};
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::{timeline::Month, Amount, Transaction, TransactionError};
+
+/// A dispute settled by
+/// [`PaymentEngine::expire_disputes`](crate::PaymentEngine::expire_disputes),
+/// with the error settling it failed with, if any.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExpiredDispute {
+    pub tx: u32,
+    pub client: u16,
+    pub opened_at: u64,
+    pub error: Option<TransactionError>,
+}
+
+/// A charge or credit for the funds held by dispute `tx`, posted by
+/// [`PaymentEngine::accrue_held_funds`](crate::PaymentEngine::accrue_held_funds),
+/// with the error it was rejected with, if any.
+#[derive(Debug, Clone)]
+pub struct HeldFundsPosting {
+    pub tx: u32,
+    /// The number of periods the posting covers
+    pub periods: u64,
+    pub transaction: Transaction,
+    pub error: Option<TransactionError>,
+}
+
+/// How a dispute ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Resolved,
+    ChargedBack,
+}
+
+/// A dispute of a transaction, see [`DisputeLog`].
+#[derive(Debug, Clone)]
+struct Dispute {
+    client: u16,
+    amount: Amount,
+    opened_at: Option<u64>,
+    /// How and when the dispute ended, if it has
+    outcome: Option<(Outcome, Option<u64>)>,
+}
+
+/// Every dispute with its outcome, see
+/// [`PaymentEngine::dispute_outcomes`](crate::PaymentEngine::dispute_outcomes).
+#[derive(Debug, Default)]
+pub(crate) struct DisputeLog {
+    disputes: Vec<Dispute>,
+    /// The index of the open dispute of every transaction
+    open: HashMap<u32, usize>,
+}
+
+impl DisputeLog {
+    /// Records that `tx` of `client` over `amount` was disputed at `now`.
+    pub(crate) fn open(&mut self, tx: u32, client: u16, amount: Amount, now: Option<u64>) {
+        self.open.insert(tx, self.disputes.len());
+        self.disputes.push(Dispute {
+            client,
+            amount,
+            opened_at: now,
+            outcome: None,
+        });
+    }
+
+    /// Records that the dispute of `tx` ended at `now`, charged back or
+    /// resolved.
+    pub(crate) fn close(&mut self, tx: u32, charged_back: bool, now: Option<u64>) {
+        let dispute = match self.open.remove(&tx) {
+            Some(i) => &mut self.disputes[i],
+            None => return,
+        };
+        let outcome = if charged_back {
+            Outcome::ChargedBack
+        } else {
+            Outcome::Resolved
+        };
+        dispute.outcome = Some((outcome, now));
+    }
+
+    /// Sums up the disputes by the month they were opened in and by the
+    /// segment of their client returned by `segment`.
+    pub(crate) fn outcomes<F>(&self, segment: F) -> Vec<DisputeOutcomes>
+    where
+        F: Fn(u16) -> String,
+    {
+        let mut groups = BTreeMap::new();
+        // The total time to resolution and the number of disputes it covers
+        let mut resolution = HashMap::new();
+        for dispute in &self.disputes {
+            let period = dispute.opened_at.map(Month::of);
+            let segment = segment(dispute.client);
+            let key = (period, segment.clone());
+            let outcomes = groups
+                .entry(key.clone())
+                .or_insert_with(|| DisputeOutcomes {
+                    period,
+                    segment,
+                    ..DisputeOutcomes::default()
+                });
+            let amount = Decimal::from(dispute.amount);
+            outcomes.disputes += 1;
+            match dispute.outcome {
+                Some((Outcome::Resolved, _)) => {
+                    outcomes.resolved += 1;
+                    outcomes.resolved_amount = outcomes.resolved_amount.saturating_add(amount);
+                }
+                Some((Outcome::ChargedBack, _)) => {
+                    outcomes.charged_back += 1;
+                    outcomes.charged_back_amount =
+                        outcomes.charged_back_amount.saturating_add(amount);
+                }
+                None => {
+                    outcomes.open += 1;
+                    outcomes.open_amount = outcomes.open_amount.saturating_add(amount);
+                }
+            }
+            if let (Some(opened_at), Some((_, Some(closed_at)))) =
+                (dispute.opened_at, dispute.outcome)
+            {
+                let (total, count) = resolution.entry(key).or_insert((0u64, 0u64));
+                *total += closed_at.saturating_sub(opened_at);
+                *count += 1;
+            }
+        }
+        for (key, (total, count)) in resolution {
+            if let Some(outcomes) = groups.get_mut(&key) {
+                outcomes.average_resolution_secs = Some(total / count);
+            }
+        }
+        groups.into_values().collect()
+    }
+}
+
+/// The outcomes of the disputes opened in a period by clients of a segment,
+/// see [`PaymentEngine::dispute_outcomes`](crate::PaymentEngine::dispute_outcomes).
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct DisputeOutcomes {
+    /// The month the disputes were opened in, if they had a timestamp
+    pub period: Option<Month>,
+    /// The segment of the clients, empty for clients without one
+    pub segment: String,
+    pub disputes: u64,
+    pub resolved: u64,
+    pub charged_back: u64,
+    /// Disputes that are neither resolved nor charged back yet
+    pub open: u64,
+    pub resolved_amount: Decimal,
+    pub charged_back_amount: Decimal,
+    pub open_amount: Decimal,
+    /// The average number of seconds from a dispute to its resolve or
+    /// chargeback, over the disputes where both have a timestamp
+    pub average_resolution_secs: Option<u64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sums_outcomes_by_period_and_segment() {
+        let march = 1_709_251_200;
+        let amount = |n| Amount::new(n, 0).unwrap();
+        let mut log = DisputeLog::default();
+        log.open(1, 1, amount(5), Some(march));
+        log.open(2, 2, amount(3), Some(march + 10));
+        log.open(3, 1, amount(2), Some(march + 20));
+        log.open(4, 1, amount(7), None);
+        log.close(1, false, Some(march + 100));
+        log.close(2, true, Some(march + 310));
+        log.close(3, true, Some(march + 220));
+        // Not disputed
+        log.close(9, true, None);
+
+        let outcomes = log.outcomes(|client| {
+            if client == 2 {
+                "retail".to_string()
+            } else {
+                String::new()
+            }
+        });
+        assert_eq!(outcomes.len(), 3);
+        assert_eq!(outcomes[0].period, None);
+        assert_eq!(outcomes[0].open_amount, Decimal::from(7));
+        assert_eq!(outcomes[0].average_resolution_secs, None);
+
+        let march = &outcomes[1];
+        assert_eq!(march.period.unwrap().to_string(), "2024-03");
+        assert_eq!(march.segment, "");
+        assert_eq!((march.resolved, march.charged_back), (1, 1));
+        assert_eq!(march.resolved_amount, Decimal::from(5));
+        assert_eq!(march.charged_back_amount, Decimal::from(2));
+        assert_eq!(march.average_resolution_secs, Some(150));
+        assert_eq!(outcomes[2].segment, "retail");
+        assert_eq!(outcomes[2].average_resolution_secs, Some(300));
+    }
+}
@@ -0,0 +1,86 @@
+//! Transactions that only take effect at a later date, see
+//! [`PaymentEngine::advance_to`](crate::PaymentEngine::advance_to).
+
+use std::collections::BTreeMap;
+
+use crate::{Transaction, TransactionError};
+
+/// Transactions waiting for their effective date, in seconds since the Unix
+/// epoch. Transactions with the same date keep the order they were queued in.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct EffectiveQueue {
+    queue: BTreeMap<u64, Vec<Transaction>>,
+}
+
+impl EffectiveQueue {
+    pub(crate) fn push(&mut self, effective: u64, tx: Transaction) {
+        self.queue.entry(effective).or_default().push(tx);
+    }
+
+    /// Removes and returns the earliest transaction effective by `until`.
+    pub(crate) fn pop_due(&mut self, until: u64) -> Option<(u64, Transaction)> {
+        let mut entry = self
+            .queue
+            .first_entry()
+            .filter(|entry| *entry.key() <= until)?;
+        let effective = *entry.key();
+        let tx = entry.get_mut().remove(0);
+        if entry.get().is_empty() {
+            entry.remove();
+        }
+        Some((effective, tx))
+    }
+
+    /// The queued transactions with their effective date, earliest first.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (u64, &Transaction)> {
+        self.queue
+            .iter()
+            .flat_map(|(effective, txs)| txs.iter().map(move |tx| (*effective, tx)))
+    }
+}
+
+/// A queued transaction applied by
+/// [`PaymentEngine::advance_to`](crate::PaymentEngine::advance_to), with the
+/// error it was rejected with, if any.
+#[derive(Debug, Clone)]
+pub struct Matured {
+    /// The transaction, with its effective date as the timestamp
+    pub transaction: Transaction,
+    pub error: Option<TransactionError>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TransactionVariant;
+
+    #[test]
+    fn pops_in_effective_order() {
+        let tx = |tx| Transaction {
+            variant: TransactionVariant::Deposit,
+            client: 1,
+            tx,
+            amount: None,
+            disputed: false,
+            chargeback: false,
+            destination: None,
+            currency: None,
+            to_currency: None,
+            rate: None,
+            timestamp: None,
+        };
+        let mut queue = EffectiveQueue::default();
+        queue.push(20, tx(1));
+        queue.push(10, tx(2));
+        queue.push(20, tx(3));
+        assert_eq!(queue.iter().count(), 3);
+        let mut popped = Vec::new();
+        while let Some((effective, tx)) = queue.pop_due(20) {
+            popped.push((effective, tx.tx));
+        }
+        assert_eq!(popped, [(10, 2), (20, 1), (20, 3)]);
+        queue.push(30, tx(4));
+        assert!(queue.pop_due(29).is_none());
+        assert_eq!(queue.iter().count(), 1);
+    }
+}
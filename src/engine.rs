@@ -1,18 +1,705 @@
-use std::collections::HashMap;
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    convert::TryFrom,
+    error::Error,
+    io,
+    time::Instant,
+};
+
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::Deserialize;
+use uuid::Uuid;
 
 use crate::{
-    account::Account,
-    error::TransactionError,
+    account::{Account, Balance, ClientMetadata},
+    amount::Amount,
+    audit::{AuditRecord, AuditWriter},
+    builder::PaymentEngineBuilder,
+    changes::{AccountChanges, ChangeTracker},
+    checkpoint::Checkpoint,
+    config::{EngineConfig, IdCollision, WithdrawalDisputes},
+    correction::{self, Corrections},
+    currency::Currency,
+    daily::{DailyTotals, Flow},
+    diff::{AccountChange, StateDiff},
+    dispute::{DisputeLog, DisputeOutcomes, ExpiredDispute, HeldFundsPosting},
+    effective::{EffectiveQueue, Matured},
+    error::{BatchError, TransactionError},
+    hot::{HotAccount, HotAccountConfig, HotAccountDetector},
+    id::{CounterIds, IdGenerator},
+    interest::{self, InterestAccrual, InterestConfig},
+    journal::{Journal, JournalEntry},
+    limit::{ClientLimiter, QuarantinedClient},
+    metrics::{EngineStats, RunMetrics},
+    observer::EngineObserver,
+    prometheus::ServiceMetrics,
+    prune::PruneFilter,
+    recurring::{Occurrence, Schedule, ScheduleState},
+    rejected::RejectedRowWriter,
+    reservation::{Reservation, ReservationId},
+    run::{process, RowErrorKind},
+    simulation::SimulationResult,
+    snapshot::{Snapshot, SnapshotTransaction},
+    storage::Storage,
+    store::{AccountStore, TransactionStore},
+    sweep::{SweepConfig, SweepRecord},
+    timeline::{self, BalanceTimeline, DailyVolume, Month, MonthlyFlow},
     transaction::{Transaction, TransactionVariant},
+    validator::Validator,
+    velocity::{VelocityConfig, VelocityFlag, VelocityTracker},
 };
 
-#[derive(Debug, Default)]
-pub struct PaymentEngine {
-    transactions: HashMap<u32, Transaction>,
-    accounts: HashMap<u16, Account>,
+/// Processes [`Transaction`]s and keeps track of the resulting [`Account`]s.
+///
+/// Accounts are kept in `A` and transactions in `T`, which are [`HashMap`]s
+/// unless [`PaymentEngine::with_stores`] is used.
+#[derive(Debug)]
+pub struct PaymentEngine<A = HashMap<u16, Account>, T = HashMap<u32, Transaction>> {
+    transactions: T,
+    accounts: A,
+    hot: Option<HotAccountDetector>,
+    /// Deposits for hot clients that have been accepted but not yet applied
+    /// to the account. See [`HotAccountConfig::accumulate_deposits`].
+    pending_deposits: HashMap<u16, Amount>,
+    /// Where every change is written through to, if anywhere.
+    storage: Option<Box<dyn Storage>>,
+    metrics: RunMetrics,
+    stats: EngineStats,
+    /// The ids of the transactions of every client, oldest first, if enabled.
+    /// See [`PaymentEngine::with_history`].
+    history: Option<HashMap<u16, Vec<u32>>>,
+    observers: Vec<Box<dyn EngineObserver>>,
+    /// Rules every transaction has to pass, see
+    /// [`PaymentEngine::add_validator`].
+    validators: Vec<Box<dyn Validator>>,
+    limiter: Option<ClientLimiter>,
+    /// The recent transactions of every client, if limited. See
+    /// [`PaymentEngine::velocity_flags`].
+    velocity: Option<VelocityTracker>,
+    /// What every client deposited and withdrew today, see
+    /// [`EngineConfig::daily_limit`].
+    daily: DailyTotals,
+    interest: Option<InterestAccrual>,
+    config: EngineConfig,
+    /// Identifies the run processed by this engine, see
+    /// [`PaymentEngine::run_id`].
+    run_id: Uuid,
+    /// Custom attributes of every client, see
+    /// [`PaymentEngine::set_attribute`].
+    attributes: HashMap<u16, BTreeMap<String, String>>,
+    /// The first and the latest timestamp seen by
+    /// [`PaymentEngine::advance_time`].
+    started_at: Option<u64>,
+    now: Option<u64>,
+    /// When each client last had a transaction accepted, see
+    /// [`PaymentEngine::sweep`].
+    last_activity: HashMap<u16, u64>,
+    /// The ids of the authorizations that have been neither captured nor
+    /// voided, see [`TransactionVariant::Authorize`].
+    authorizations: HashSet<u32>,
+    /// The ids of the transactions under dispute, see
+    /// [`PaymentEngine::disputed`].
+    disputed: BTreeSet<u32>,
+    /// The disputed portion of the transactions under a partial dispute or
+    /// charged back in part. Transactions disputed in full are not in here.
+    partial_disputes: HashMap<u32, Amount>,
+    /// The ids of the transactions whose chargeback has been reversed by a
+    /// [`TransactionVariant::Representment`].
+    represented: HashSet<u32>,
+    /// When each open dispute was opened, if the engine had a clock then, see
+    /// [`PaymentEngine::expire_disputes`].
+    disputes_opened: HashMap<u32, u64>,
+    /// The periods of funds held by each open dispute that have been posted,
+    /// see [`PaymentEngine::accrue_held_funds`].
+    held_periods: HashMap<u32, u64>,
+    /// Transactions waiting for their effective date, see
+    /// [`PaymentEngine::advance_to`].
+    queued: EffectiveQueue,
+    /// Recurring transactions, see [`PaymentEngine::materialize`].
+    schedules: Vec<ScheduleState>,
+    /// Open reservations, see [`PaymentEngine::reserve`].
+    reservations: HashMap<ReservationId, Reservation>,
+    next_reservation: u64,
+    /// The balances of every client over time, if enabled. See
+    /// [`PaymentEngine::balance_at`].
+    timeline: Option<BalanceTimeline>,
+    /// The keys of the transactions applied with
+    /// [`PaymentEngine::insert_with_key`].
+    idempotency_keys: HashSet<String>,
+    /// Every dispute with its outcome, if enabled. See
+    /// [`PaymentEngine::dispute_outcomes`].
+    disputes: Option<DisputeLog>,
+    /// Generates the ids of transactions originated by the engine, see
+    /// [`PaymentEngine::next_tx_id`].
+    ids: Box<dyn IdGenerator>,
+    /// Counters of inserted transactions, if enabled. See
+    /// [`PaymentEngine::enable_service_metrics`].
+    service_metrics: Option<ServiceMetrics>,
+    /// Where the balances before and after every accepted transaction are
+    /// written to, if anywhere. See [`PaymentEngine::set_audit_writer`].
+    audit: Option<AuditWriter>,
+    /// Where rejected input rows are written to, if anywhere. See
+    /// [`PaymentEngine::set_rejected_row_writer`].
+    rejected_rows: Option<RejectedRowWriter>,
+    /// The clients whose accounts changed since the changes were last taken,
+    /// if enabled. See [`PaymentEngine::take_changes`].
+    changes: Option<ChangeTracker>,
+    /// The state changed by the most recent transactions, if enabled. See
+    /// [`PaymentEngine::rollback`].
+    journal: Option<Journal>,
+}
+
+impl Default for PaymentEngine {
+    fn default() -> Self {
+        Self::with_stores(HashMap::new(), HashMap::new())
+    }
 }
 
 impl PaymentEngine {
+    /// Returns a [`PaymentEngineBuilder`] to configure a new engine.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use randomlib::{Amount, PaymentEngine, WithdrawalDisputes};
+    ///
+    /// let engine = PaymentEngine::builder()
+    ///     .withdrawal_disputes(WithdrawalDisputes::Reject)
+    ///     .overdraft(Amount::new(100, 0).unwrap())
+    ///     .transactions_capacity(1_000_000)
+    ///     .build();
+    /// assert_eq!(engine.config().withdrawal_disputes, WithdrawalDisputes::Reject);
+    /// ```
+    pub fn builder() -> PaymentEngineBuilder {
+        PaymentEngineBuilder::default()
+    }
+
+    /// Creates a [`PaymentEngine`] that detects hot clients.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use randomlib::{HotAccountConfig, PaymentEngine};
+    ///
+    /// let engine = PaymentEngine::with_hot_accounts(HotAccountConfig::default());
+    /// assert!(engine.hot_accounts().is_empty());
+    /// ```
+    pub fn with_hot_accounts(config: HotAccountConfig) -> Self {
+        Self {
+            hot: Some(HotAccountDetector::new(config)),
+            ..Self::default()
+        }
+    }
+
+    /// Creates a [`PaymentEngine`] that keeps an index of the transactions of
+    /// every client, see [`PaymentEngine::history`].
+    pub fn with_history() -> Self {
+        Self {
+            history: Some(HashMap::new()),
+            ..Self::default()
+        }
+    }
+
+    /// Creates a [`PaymentEngine`] that starts from the state in `storage` and
+    /// writes every change through to it.
+    ///
+    /// Call [`PaymentEngine::flush`] when done to make sure all changes are
+    /// durable.
+    pub fn with_storage(mut storage: Box<dyn Storage>) -> Result<Self, TransactionError> {
+        let mut engine = Self::from_snapshot(storage.load()?);
+        engine.storage = Some(storage);
+        Ok(engine)
+    }
+
+    /// Continues a run from `checkpoint` by processing `remaining`, the part of
+    /// the input after the checkpoint, see [`PaymentEngine::checkpoint`].
+    ///
+    /// `remaining` is read like any other input, so it must start with the
+    /// header. Stops at the first row that cannot be processed, see
+    /// [`PaymentEngine::from_checkpoint`] and
+    /// [`process_with_policy`](crate::process_with_policy) for other policies.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use randomlib::{process, Checkpoint, PaymentEngine};
+    ///
+    /// let mut engine = PaymentEngine::default();
+    /// process("type,client,tx,amount\ndeposit,1,1,1.0\n".as_bytes(), &mut engine).unwrap();
+    ///
+    /// let mut stored = Vec::new();
+    /// engine.checkpoint().write(&mut stored).unwrap();
+    /// let checkpoint = Checkpoint::read(stored.as_slice()).unwrap();
+    ///
+    /// let remaining = "type,client,tx,amount\ndeposit,1,2,2.0\n";
+    /// let engine = PaymentEngine::resume(checkpoint, remaining.as_bytes()).unwrap();
+    /// assert_eq!(engine.metrics().rows, 2);
+    /// ```
+    pub fn resume<R: io::Read>(
+        checkpoint: Checkpoint,
+        remaining: R,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut engine = Self::from_checkpoint(checkpoint);
+        process(remaining, &mut engine)?;
+        Ok(engine)
+    }
+
+    /// Creates a [`PaymentEngine`] from the state stored in `checkpoint`,
+    /// keeping the run id and metrics of the interrupted run.
+    pub fn from_checkpoint(checkpoint: Checkpoint) -> Self {
+        let run_id = checkpoint.snapshot.run_id;
+        let mut engine = Self::from_snapshot(checkpoint.snapshot);
+        if let Some(run_id) = run_id {
+            engine.run_id = run_id;
+        }
+        engine.metrics = checkpoint.metrics;
+        engine.stats = checkpoint.stats;
+        engine
+    }
+
+    /// Creates a [`PaymentEngine`] from the state stored in `snapshot`.
+    pub fn from_snapshot(snapshot: Snapshot) -> Self {
+        let mut engine = Self::default();
+        for mut account in snapshot.accounts {
+            if let Some(withdrawn) = snapshot.withdrawn.get(&account.client()) {
+                account.set_withdrawn(*withdrawn);
+            }
+            account.set_frozen(snapshot.frozen.contains(&account.client()));
+            if let Some(tier) = snapshot.tiers.get(&account.client()) {
+                account.set_tier(tier.clone());
+            }
+            if let Some(metadata) = snapshot.metadata.get(&account.client()) {
+                account.set_metadata(metadata.clone());
+            }
+            AccountStore::insert(&mut engine.accounts, account);
+        }
+        for tx in snapshot.transactions {
+            if tx.disputed {
+                engine.disputed.insert(tx.tx);
+            }
+            TransactionStore::insert(&mut engine.transactions, tx.into());
+        }
+        for reservation in snapshot.reservations {
+            engine.next_reservation = engine.next_reservation.max(reservation.id.0 + 1);
+            engine.reservations.insert(reservation.id, reservation);
+        }
+        engine.pending_deposits.extend(snapshot.pending_deposits);
+        engine.idempotency_keys.extend(snapshot.idempotency_keys);
+        engine.authorizations.extend(snapshot.authorizations);
+        engine.schedules = snapshot.schedules;
+        engine.partial_disputes.extend(snapshot.partial_disputes);
+        engine.disputes_opened.extend(snapshot.disputes_opened);
+        engine.held_periods.extend(snapshot.held_periods);
+        engine.represented.extend(snapshot.represented);
+        for tx in snapshot.queued {
+            let effective = tx.timestamp.unwrap_or_default();
+            engine.queued.push(effective, tx.into());
+        }
+        engine
+    }
+}
+
+impl<A: AccountStore, T: TransactionStore> PaymentEngine<A, T> {
+    /// Creates a [`PaymentEngine`] that keeps its state in custom stores.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::collections::{BTreeMap, HashMap};
+    ///
+    /// use randomlib::{
+    ///     store::AccountStore, Account, Amount, PaymentEngine, Transaction, TransactionVariant,
+    /// };
+    ///
+    /// /// Keeps the accounts ordered by client id
+    /// #[derive(Default)]
+    /// struct OrderedAccounts(BTreeMap<u16, Account>);
+    ///
+    /// impl AccountStore for OrderedAccounts {
+    ///     fn get(&self, client: u16) -> Option<&Account> {
+    ///         self.0.get(&client)
+    ///     }
+    ///     fn get_mut(&mut self, client: u16) -> Option<&mut Account> {
+    ///         self.0.get_mut(&client)
+    ///     }
+    ///     fn get_or_create(&mut self, client: u16) -> &mut Account {
+    ///         self.0.entry(client).or_insert_with(|| Account::new(client))
+    ///     }
+    ///     fn insert(&mut self, account: Account) {
+    ///         self.0.insert(account.client(), account);
+    ///     }
+    ///     fn iter(&self) -> Box<dyn Iterator<Item = &Account> + '_> {
+    ///         Box::new(self.0.values())
+    ///     }
+    ///     fn len(&self) -> usize {
+    ///         self.0.len()
+    ///     }
+    /// }
+    ///
+    /// let mut engine = PaymentEngine::with_stores(OrderedAccounts::default(), HashMap::new());
+    /// for client in [3, 1, 2] {
+    ///     let tx = Transaction {
+    ///         tx: client.into(),
+    ///         amount: Some(Amount::new(1, 0).unwrap()),
+    ///         client,
+    ///         disputed: false,
+    ///         variant: TransactionVariant::Deposit,
+    ///         chargeback: false,
+    ///         destination: None,
+    ///         currency: None,
+    ///         to_currency: None,
+    ///         rate: None,
+    ///         timestamp: None,
+    ///     };
+    ///     assert!(engine.insert(tx).is_ok());
+    /// }
+    /// let clients = engine.accounts().iter().map(Account::client).collect::<Vec<_>>();
+    /// assert_eq!(clients, vec![1, 2, 3]);
+    /// ```
+    pub fn with_stores(accounts: A, transactions: T) -> Self {
+        Self {
+            transactions,
+            accounts,
+            hot: None,
+            pending_deposits: HashMap::new(),
+            storage: None,
+            metrics: RunMetrics::default(),
+            stats: EngineStats::default(),
+            history: None,
+            observers: Vec::new(),
+            validators: Vec::new(),
+            limiter: None,
+            velocity: None,
+            daily: DailyTotals::default(),
+            interest: None,
+            config: EngineConfig::default(),
+            run_id: Uuid::new_v4(),
+            attributes: HashMap::new(),
+            started_at: None,
+            now: None,
+            last_activity: HashMap::new(),
+            authorizations: HashSet::new(),
+            disputed: BTreeSet::new(),
+            partial_disputes: HashMap::new(),
+            represented: HashSet::new(),
+            disputes_opened: HashMap::new(),
+            held_periods: HashMap::new(),
+            queued: EffectiveQueue::default(),
+            schedules: Vec::new(),
+            reservations: HashMap::new(),
+            next_reservation: 1,
+            timeline: None,
+            idempotency_keys: HashSet::new(),
+            disputes: None,
+            ids: Box::new(CounterIds::default()),
+            service_metrics: None,
+            audit: None,
+            rejected_rows: None,
+            changes: None,
+            journal: None,
+        }
+    }
+
+    /// Registers `observer` to be notified of every change, see
+    /// [`EngineObserver`].
+    pub fn add_observer(&mut self, observer: Box<dyn EngineObserver>) {
+        self.observers.push(observer);
+    }
+
+    /// Registers `validator` to check every transaction before it is applied,
+    /// after the validators registered before it.
+    pub fn add_validator(&mut self, validator: Box<dyn Validator>) {
+        self.validators.push(validator);
+    }
+
+    /// Checks `tx` against every validator, with `account` as the account of
+    /// its client.
+    fn validate(
+        &self,
+        tx: &Transaction,
+        account: Option<&Account>,
+    ) -> Result<(), TransactionError> {
+        for validator in &self.validators {
+            validator
+                .validate(tx, account)
+                .map_err(TransactionError::Rejected)?;
+        }
+        Ok(())
+    }
+
+    /// Starts counting inserted transactions, rejections, locked accounts and
+    /// the time taken by every insert, for monitoring an engine served over
+    /// HTTP, see [`PaymentEngine::service_metrics`].
+    pub fn enable_service_metrics(&mut self) {
+        self.service_metrics
+            .get_or_insert_with(ServiceMetrics::default);
+    }
+
+    /// The metrics of inserted transactions, if enabled with
+    /// [`PaymentEngine::enable_service_metrics`].
+    pub fn service_metrics(&self) -> Option<&ServiceMetrics> {
+        self.service_metrics.as_ref()
+    }
+
+    /// Keeps what the last `capacity` applied transactions changed, so that
+    /// they can be reversed with [`PaymentEngine::rollback`].
+    pub fn set_journal(&mut self, capacity: usize) {
+        self.journal = Some(Journal::new(capacity));
+    }
+
+    /// Reverses the last `n` applied transactions, newest first, and returns
+    /// how many were reversed, which is fewer than `n` if the journal of
+    /// [`PaymentEngine::set_journal`] holds fewer.
+    ///
+    /// The balances, locks and dispute state of the transactions and accounts
+    /// are restored. Accounts created by a reversed transaction are left
+    /// empty, like those of rejected transactions, and the metrics, audit log,
+    /// observers and reports still count it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use randomlib::{process, Amount, PaymentEngine};
+    ///
+    /// let mut engine = PaymentEngine::default();
+    /// engine.set_journal(100);
+    /// let input = "type,client,tx,amount\ndeposit,1,1,2.0\ndispute,1,1,\nchargeback,1,1,";
+    /// process(input.as_bytes(), &mut engine).unwrap();
+    ///
+    /// assert_eq!(engine.rollback(1), Ok(1));
+    /// let account = engine.accounts().get(&1).unwrap();
+    /// assert!(!account.locked());
+    /// assert_eq!(account.held(), Amount::new(2, 0).unwrap());
+    /// ```
+    pub fn rollback(&mut self, n: usize) -> Result<usize, TransactionError> {
+        let mut reversed = 0;
+        while reversed < n {
+            match self.journal.as_mut().and_then(Journal::pop) {
+                Some(entry) => self.restore(entry)?,
+                None => break,
+            }
+            reversed += 1;
+        }
+        Ok(reversed)
+    }
+
+    /// The state `tx` may change, if transactions are journaled.
+    fn journal_entry(&self, tx: &Transaction) -> Option<JournalEntry> {
+        self.journal.as_ref()?;
+        let id = tx.tx;
+        let accounts = std::iter::once(tx.client)
+            .chain(self.counterparty(tx))
+            .map(|client| {
+                let account = self.accounts.get(client).cloned();
+                (client, account, self.pending_deposits.get(&client).copied())
+            })
+            .collect();
+        Some(JournalEntry {
+            client: tx.client,
+            tx: id,
+            accounts,
+            daily: self.daily.get(tx.client),
+            transaction: self.transactions.get(id).map(Cow::into_owned),
+            authorized: self.authorizations.contains(&id),
+            partial_dispute: self.partial_disputes.get(&id).copied(),
+            represented: self.represented.contains(&id),
+            dispute_opened: self.disputes_opened.get(&id).copied(),
+            held_periods: self.held_periods.get(&id).copied(),
+        })
+    }
+
+    fn journal(&mut self, entry: Option<JournalEntry>) {
+        if let (Some(journal), Some(entry)) = (&mut self.journal, entry) {
+            journal.push(entry);
+        }
+    }
+
+    /// Puts back the state of `entry`, see [`PaymentEngine::rollback`].
+    fn restore(&mut self, entry: JournalEntry) -> Result<(), TransactionError> {
+        let id = entry.tx;
+        for (client, account, pending) in entry.accounts {
+            let account = account.unwrap_or_else(|| Account::new(client));
+            if let Some(storage) = &mut self.storage {
+                storage.store_account(&account)?;
+            }
+            self.accounts.insert(account);
+            restore(&mut self.pending_deposits, client, pending);
+            if let Some(ids) = self.history.as_mut().and_then(|h| h.get_mut(&client)) {
+                if ids.last() == Some(&id) {
+                    ids.pop();
+                }
+            }
+            if let Some(changes) = &mut self.changes {
+                changes.mark(client);
+            }
+        }
+        self.daily.restore(entry.client, entry.daily);
+        self.disputed.remove(&id);
+        match entry.transaction {
+            Some(tx) => {
+                if let Some(storage) = &mut self.storage {
+                    storage.store_transaction(&tx)?;
+                }
+                if tx.disputed {
+                    self.disputed.insert(id);
+                }
+                self.transactions.insert(tx);
+            }
+            None => {
+                if self.transactions.remove(id).is_some() {
+                    if let Some(storage) = &mut self.storage {
+                        storage.remove_transaction(id)?;
+                    }
+                }
+            }
+        }
+        if entry.authorized {
+            self.authorizations.insert(id);
+        } else {
+            self.authorizations.remove(&id);
+        }
+        if entry.represented {
+            self.represented.insert(id);
+        } else {
+            self.represented.remove(&id);
+        }
+        restore(&mut self.partial_disputes, id, entry.partial_dispute);
+        restore(&mut self.disputes_opened, id, entry.dispute_opened);
+        restore(&mut self.held_periods, id, entry.held_periods);
+        Ok(())
+    }
+
+    /// Starts tracking which accounts change, see
+    /// [`PaymentEngine::take_changes`].
+    pub fn track_changes(&mut self) {
+        self.changes.get_or_insert_with(ChangeTracker::default);
+    }
+
+    /// Returns the accounts that changed since the previous call, or since
+    /// [`PaymentEngine::track_changes`] for the first one, so that a consumer
+    /// can be sent only those instead of every account. Empty unless changes
+    /// are tracked.
+    ///
+    /// An account changes when a transaction is applied to it, it is created,
+    /// or it is locked, unlocked or closed. Deposits accumulated for hot
+    /// clients change the account once they are flushed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use randomlib::{process, PaymentEngine};
+    ///
+    /// let mut engine = PaymentEngine::builder().track_changes().build();
+    /// process("type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,2,2,1.0".as_bytes(), &mut engine)
+    ///     .unwrap();
+    /// assert_eq!(engine.take_changes().accounts.len(), 2);
+    ///
+    /// process("type,client,tx,amount\ndeposit,2,3,1.0".as_bytes(), &mut engine).unwrap();
+    /// let changes = engine.take_changes();
+    /// assert_eq!(changes.sequence, 2);
+    /// assert_eq!(changes.accounts[0].client(), 2);
+    /// ```
+    pub fn take_changes(&mut self) -> AccountChanges {
+        let (sequence, clients) = match &mut self.changes {
+            Some(changes) => changes.take(),
+            None => Default::default(),
+        };
+        let accounts = clients
+            .into_iter()
+            .filter_map(|client| self.accounts.get(client).cloned())
+            .collect();
+        AccountChanges { sequence, accounts }
+    }
+
+    /// Writes the balances of every account before and after each accepted
+    /// transaction to `audit`, see [`AuditWriter`].
+    pub fn set_audit_writer(&mut self, audit: AuditWriter) {
+        self.audit = Some(audit);
+    }
+
+    /// Writes every input row that is rejected or skipped while processing
+    /// to `rejected`, see [`RejectedRowWriter`].
+    pub fn set_rejected_row_writer(&mut self, rejected: RejectedRowWriter) {
+        self.rejected_rows = Some(rejected);
+    }
+
+    /// Whether rejected rows are written anywhere.
+    pub(crate) fn writes_rejected_rows(&self) -> bool {
+        self.rejected_rows.is_some()
+    }
+
+    /// Writes `record` of the input with `headers` to the
+    /// [`RejectedRowWriter`], if any.
+    pub(crate) fn write_rejected_row(
+        &mut self,
+        headers: &csv::StringRecord,
+        record: &csv::StringRecord,
+        line: u64,
+        kind: &RowErrorKind,
+    ) -> Result<(), TransactionError> {
+        match &mut self.rejected_rows {
+            Some(rejected) => rejected.write(headers, record, line, kind),
+            None => Ok(()),
+        }
+    }
+
+    /// Starts detecting hot clients, see [`PaymentEngine::with_hot_accounts`].
+    pub(crate) fn set_hot_accounts(&mut self, config: HotAccountConfig) {
+        self.hot = Some(HotAccountDetector::new(config));
+    }
+
+    /// Replaces the configuration, see [`PaymentEngine::builder`].
+    pub(crate) fn set_config(&mut self, config: EngineConfig) {
+        self.config = config;
+    }
+
+    /// Starts keeping an index of the transactions of every client, see
+    /// [`PaymentEngine::with_history`].
+    pub(crate) fn set_history(&mut self) {
+        self.history.get_or_insert_with(HashMap::new);
+    }
+
+    /// Starts keeping a log of every dispute, see
+    /// [`PaymentEngine::dispute_outcomes`].
+    pub(crate) fn set_dispute_log(&mut self) {
+        self.disputes.get_or_insert_with(DisputeLog::default);
+    }
+
+    /// Generates the ids of transactions originated by the engine with
+    /// `ids`, see [`PaymentEngine::next_tx_id`].
+    pub(crate) fn set_id_generator(&mut self, ids: Box<dyn IdGenerator>) {
+        self.ids = ids;
+    }
+
+    /// Starts recording the balances of every client over time, see
+    /// [`PaymentEngine::balance_at`].
+    pub(crate) fn set_timeline(&mut self) {
+        self.timeline.get_or_insert_with(BalanceTimeline::default);
+    }
+
+    /// Starts accruing interest, see [`PaymentEngine::advance_time`].
+    pub(crate) fn set_interest(&mut self, config: InterestConfig) {
+        self.interest = Some(InterestAccrual::new(config));
+    }
+
+    /// Replaces the generated run id, see [`PaymentEngine::run_id`].
+    pub(crate) fn set_run_id(&mut self, run_id: Uuid) {
+        self.run_id = run_id;
+    }
+
+    /// Limits the number of transactions processed per client to `max`.
+    /// Transactions over the limit fail with
+    /// [`TransactionError::ClientLimitExceeded`] and are counted in
+    /// [`PaymentEngine::quarantined`].
+    pub(crate) fn set_client_limit(&mut self, max: u64) {
+        self.limiter = Some(ClientLimiter::new(max));
+    }
+
+    pub(crate) fn set_velocity(&mut self, config: VelocityConfig) {
+        self.velocity = Some(VelocityTracker::new(config));
+    }
+
     /// Inserts a new [`Transaction`] to the [`PaymentEngine`].
     ///
     /// Returns a [`TransactionError`] if it could not be inserted.
@@ -30,34 +717,407 @@ impl PaymentEngine {
     ///    disputed: false,
     ///    variant: TransactionVariant::Deposit,
     ///    chargeback: false,
+    ///    destination: None,
+    ///    currency: None,
+    ///    to_currency: None,
+    ///    rate: None,
+    ///    timestamp: None,
     /// };
     /// assert!(engine.insert(tx).is_ok());
     /// ```
     pub fn insert(&mut self, tx: Transaction) -> Result<(), TransactionError> {
-        let account = self
-            .accounts
-            .entry(tx.client)
-            // Or insert the Account if it does not exist already
-            .or_insert_with(|| Account::new(tx.client));
+        if self.service_metrics.is_none() {
+            return self.insert_unmetered(tx).map(|_| ());
+        }
+        let client = tx.client;
+        let variant = tx.variant.clone();
+        let was_locked = self.accounts.get(client).is_some_and(Account::locked);
+        let start = Instant::now();
+        let result = self.insert_unmetered(tx);
+        let elapsed = start.elapsed();
+        let locked = !was_locked && self.accounts.get(client).is_some_and(Account::locked);
+        if let Some(metrics) = &mut self.service_metrics {
+            metrics.record(&variant, &result, locked, elapsed);
+        }
+        result.map(|_| ())
+    }
+
+    /// Inserts `tx`, returning whether it was applied rather than ignored as
+    /// a duplicate.
+    fn insert_unmetered(&mut self, tx: Transaction) -> Result<bool, TransactionError> {
+        let client = tx.client;
+        if let Some(limiter) = &mut self.limiter {
+            if !limiter.admit(client) {
+                return Err(TransactionError::ClientLimitExceeded(client));
+            }
+        }
+        self.validate(&tx, self.accounts.get(client))?;
+        if let Some(velocity) = &mut self.velocity {
+            velocity.check(&tx, tx.timestamp.or(self.now).unwrap_or(0))?;
+        }
+        let daily = tx.timestamp.zip(Flow::of(&tx));
+        if let Some((timestamp, flow)) = daily {
+            let tier = self.accounts.get(client).and_then(Account::tier);
+            let limit = self.config.daily_limit_of(client, tier);
+            self.daily.check(client, timestamp, flow, limit)?;
+        }
+        self.apply_and_record(tx, daily)
+    }
+
+    /// Applies `tx` without the checks of [`PaymentEngine::insert`] and
+    /// records the result in the metrics, observers, audit log and storage.
+    /// `daily` is the flow `tx` adds to the daily totals, if any.
+    fn apply_and_record(
+        &mut self,
+        tx: Transaction,
+        daily: Option<(u64, (Flow, Amount))>,
+    ) -> Result<bool, TransactionError> {
+        let client = tx.client;
+        let entry = self.journal_entry(&tx);
+        let changed = match tx.variant {
+            TransactionVariant::Unlock => Some(self.unlock(client)),
+            TransactionVariant::Close => Some(self.close(client)),
+            TransactionVariant::Freeze => Some(self.freeze(client)),
+            TransactionVariant::Unfreeze => Some(self.unfreeze(client)),
+            _ => None,
+        };
+        if let Some(changed) = changed {
+            if changed.is_ok() {
+                self.journal(entry);
+            }
+            return changed.map(|_| true);
+        }
+
+        let id = tx.tx;
+        let variant = tx.variant.clone();
+        let destination = self.counterparty(&tx);
+        let amount = self.stats_amount(&tx);
+        let was_locked = self.accounts.get(client).is_some_and(Account::locked);
+        let new_accounts = std::iter::once(client)
+            .chain(destination)
+            .filter(|client| self.accounts.get(*client).is_none())
+            .collect::<Vec<_>>();
+        let before = match self.audit {
+            Some(_) => std::iter::once(client)
+                .chain(destination)
+                .map(|client| (client, self.accounts.get(client).cloned()))
+                .collect(),
+            None => Vec::new(),
+        };
+        let result = self.apply(tx);
+        // A transaction the store lost could have been misreported as missing
+        self.transactions.check()?;
+        let applied = matches!(result, Ok(true));
+        if applied {
+            self.journal(entry);
+            self.stats.record(&variant, amount);
+        }
+        if let (true, Some((timestamp, flow))) = (applied, daily) {
+            self.daily.add(client, timestamp, flow);
+        }
+        self.metrics.accounts_created += new_accounts
+            .iter()
+            .filter(|client| self.accounts.get(**client).is_some())
+            .count() as u64;
+        if !was_locked && self.accounts.get(client).is_some_and(Account::locked) {
+            self.metrics.accounts_locked += 1;
+        }
+        if let Some(changes) = &mut self.changes {
+            let changed = std::iter::once(client).chain(destination);
+            for client in changed.filter(|client| applied || new_accounts.contains(client)) {
+                changes.mark(client);
+            }
+        }
+        match &result {
+            Err(e) => tracing::info!(
+                client,
+                tx = id,
+                variant = variant.as_str(),
+                error = %e,
+                "Transaction rejected"
+            ),
+            Ok(false) => tracing::debug!(
+                client,
+                tx = id,
+                variant = variant.as_str(),
+                "Duplicate transaction ignored"
+            ),
+            Ok(true) => match variant {
+                TransactionVariant::Dispute
+                | TransactionVariant::Resolve
+                | TransactionVariant::Chargeback
+                | TransactionVariant::Representment => tracing::info!(
+                    client,
+                    tx = id,
+                    variant = variant.as_str(),
+                    locked = self.accounts.get(client).is_some_and(Account::locked),
+                    "Dispute updated"
+                ),
+                _ => tracing::trace!(
+                    client,
+                    tx = id,
+                    variant = variant.as_str(),
+                    "Transaction applied"
+                ),
+            },
+        }
+
+        if applied && !self.observers.is_empty() {
+            self.notify(&variant, client, id, was_locked);
+        }
+        if let (true, Some(now)) = (applied, self.now) {
+            self.last_activity.insert(client, now);
+        }
+        if applied {
+            self.record_balances(std::iter::once(client).chain(destination));
+            self.record_dispute(&variant, id);
+        }
+        if let (true, Some(audit)) = (applied, &mut self.audit) {
+            for (client, before) in before {
+                if let Some(after) = self.accounts.get(client) {
+                    let record = AuditRecord::new(id, variant.clone(), before.as_ref(), after);
+                    audit.write(&record)?;
+                }
+            }
+        }
+
+        // The account is created even if the transaction fails, so always store it
+        if let Some(storage) = &mut self.storage {
+            for client in std::iter::once(client).chain(destination) {
+                if let Some(account) = self.accounts.get(client) {
+                    storage.store_account(account)?;
+                }
+            }
+            if applied {
+                if let Some(tx) = self.transactions.get(id) {
+                    storage.store_transaction(&tx)?;
+                }
+            }
+        }
+        if applied && self.config.retention.evicts(&variant) {
+            self.forget(id)?;
+        }
+
+        result
+    }
+
+    /// Drops the transaction `id` and the state kept for it, see
+    /// [`PaymentEngine::prune`].
+    fn forget(&mut self, id: u32) -> Result<(), TransactionError> {
+        self.transactions.remove(id);
+        self.disputed.remove(&id);
+        self.partial_disputes.remove(&id);
+        self.represented.remove(&id);
+        self.disputes_opened.remove(&id);
+        self.held_periods.remove(&id);
+        if let Some(storage) = &mut self.storage {
+            storage.remove_transaction(id)?;
+        }
+        Ok(())
+    }
+
+    fn notify(&mut self, variant: &TransactionVariant, client: u16, id: u32, was_locked: bool) {
+        let (account, tx) = match (self.accounts.get(client), self.transactions.get(id)) {
+            (Some(account), Some(tx)) => (account, tx),
+            _ => return,
+        };
+        let accounts = &self.accounts;
+        let destination = self
+            .counterparty(&tx)
+            .and_then(|client| accounts.get(client));
+        for observer in &mut self.observers {
+            match variant {
+                TransactionVariant::Deposit => observer.on_deposit(account, &tx),
+                TransactionVariant::Withdrawal => observer.on_withdrawal(account, &tx),
+                TransactionVariant::Dispute => observer.on_dispute(account, &tx),
+                TransactionVariant::Resolve => observer.on_resolve(account, &tx),
+                TransactionVariant::Chargeback => observer.on_chargeback(account, &tx),
+                TransactionVariant::Representment => observer.on_representment(account, &tx),
+                TransactionVariant::Transfer => {
+                    if let Some(destination) = destination {
+                        observer.on_transfer(account, destination, &tx);
+                    }
+                }
+                TransactionVariant::Fee => observer.on_fee(account, destination, &tx),
+                TransactionVariant::Convert => observer.on_conversion(account, &tx),
+                // Notified by `PaymentEngine::unlock`, `PaymentEngine::close`,
+                // `PaymentEngine::freeze` and `PaymentEngine::unfreeze`
+                TransactionVariant::Unlock
+                | TransactionVariant::Close
+                | TransactionVariant::Freeze
+                | TransactionVariant::Unfreeze => (),
+                TransactionVariant::Authorize
+                | TransactionVariant::Capture
+                | TransactionVariant::Void => (),
+            }
+            if account.locked() && !was_locked {
+                observer.on_account_locked(account);
+            }
+        }
+    }
+
+    /// Records the current balances of `clients` in the timeline, if enabled.
+    fn record_balances(&mut self, clients: impl IntoIterator<Item = u16>) {
+        let (timeline, now) = match (&mut self.timeline, self.now) {
+            (Some(timeline), Some(now)) => (timeline, now),
+            _ => return,
+        };
+        for client in clients {
+            if let Some(account) = self.accounts.get(client) {
+                timeline.record(client, now, account.balance(None));
+            }
+        }
+    }
+
+    /// Records a dispute or its outcome in the dispute log, if enabled.
+    fn record_dispute(&mut self, variant: &TransactionVariant, id: u32) {
+        let (log, tx) = match (&mut self.disputes, self.transactions.get(id)) {
+            (Some(log), Some(tx)) => (log, tx),
+            _ => return,
+        };
+        let amount = self.partial_disputes.get(&id).copied().or(tx.amount);
+        match (variant, amount) {
+            // Amounts in different currencies cannot be added up
+            _ if tx.currency.is_some() => (),
+            (TransactionVariant::Dispute, Some(amount)) => {
+                log.open(id, tx.client, amount, self.now)
+            }
+            (TransactionVariant::Resolve, _) => log.close(id, false, self.now),
+            (TransactionVariant::Chargeback, _) => log.close(id, true, self.now),
+            _ => (),
+        }
+    }
+
+    /// The client credited by `tx` besides its own client, if any.
+    fn counterparty(&self, tx: &Transaction) -> Option<u16> {
+        match tx.variant {
+            TransactionVariant::Transfer => tx.destination,
+            TransactionVariant::Fee => self.config.fee_account.filter(|c| *c != tx.client),
+            _ => None,
+        }
+    }
+
+    /// Applies `tx` and returns whether it changed anything.
+    fn apply(&mut self, tx: Transaction) -> Result<bool, TransactionError> {
+        let accumulate = match &mut self.hot {
+            Some(hot) => {
+                hot.record(tx.client);
+                hot.config().accumulate_deposits
+                    && tx.variant == TransactionVariant::Deposit
+                    && tx.currency.is_none()
+                    && hot.is_hot(tx.client)
+            }
+            None => false,
+        };
+
+        // Or insert the Account if it does not exist already
+        let account = self.accounts.get_or_create(tx.client);
+
+        // Anything but an accumulated deposit needs to see the up to date balances
+        if !accumulate {
+            if let Some(pending) = self.pending_deposits.get(&tx.client).copied() {
+                account.transaction(&TransactionVariant::Deposit, pending, &self.config)?;
+                self.pending_deposits.remove(&tx.client);
+            }
+        }
 
         match tx.variant {
             TransactionVariant::Deposit | TransactionVariant::Withdrawal => {
                 // Dont allow overwriting an existing transaction
-                if self.transactions.get(&tx.tx).is_some() {
-                    return Err(TransactionError::TransactionAlreadyExist);
+                if self.transactions.contains(tx.tx) {
+                    return match self.config.id_collision {
+                        IdCollision::Reject => Err(TransactionError::TransactionAlreadyExist),
+                        IdCollision::Ignore => Ok(false),
+                    };
                 }
 
-                // SAFETY: We knnow that when `variant` is `TransactionVariant::Deposit` or
-                // `TransactionVariant::Withdrawal` that the amount is Some.
-                let amount = tx.amount.unwrap();
+                let amount = tx.amount.ok_or(TransactionError::InvalidTransaction)?;
 
-                account.transaction(&tx.variant, amount)?;
-                self.transactions.insert(tx.tx, tx);
+                if accumulate {
+                    account.check(&tx.variant, amount, &self.config)?;
+                    let pending = self
+                        .pending_deposits
+                        .entry(tx.client)
+                        .or_insert_with(Amount::zero);
+                    *pending = pending.checked_add(amount)?;
+                } else {
+                    let config = &self.config;
+                    account.in_currency(currency_code(&tx), |account| {
+                        account.transaction(&tx.variant, amount, config)
+                    })?;
+                }
+                if tx.currency.is_some() {
+                    // Amounts in different currencies cannot be added up
+                } else if tx.variant == TransactionVariant::Deposit {
+                    self.metrics.deposited =
+                        self.metrics.deposited.saturating_add(Decimal::from(amount));
+                } else {
+                    self.metrics.withdrawn =
+                        self.metrics.withdrawn.saturating_add(Decimal::from(amount));
+                }
+                if let Some(history) = &mut self.history {
+                    history.entry(tx.client).or_default().push(tx.tx);
+                }
+                self.transactions.insert(tx);
+            }
+            TransactionVariant::Transfer => {
+                let destination = tx.destination;
+                return self.transfer(tx, destination);
+            }
+            TransactionVariant::Fee => {
+                // Fees charged to the system account itself cannot be credited
+                let destination = self.config.fee_account.filter(|c| *c != tx.client);
+                return self.transfer(tx, destination);
+            }
+            TransactionVariant::Convert => return self.convert(tx),
+            TransactionVariant::Authorize => {
+                if self.transactions.contains(tx.tx) {
+                    return match self.config.id_collision {
+                        IdCollision::Reject => Err(TransactionError::TransactionAlreadyExist),
+                        IdCollision::Ignore => Ok(false),
+                    };
+                }
+                let amount = tx.amount.ok_or(TransactionError::InvalidTransaction)?;
+                let config = &self.config;
+                account.in_currency(currency_code(&tx), |account| {
+                    account.reserve(amount, config)
+                })?;
+                self.authorizations.insert(tx.tx);
+                self.transactions.insert(tx);
+            }
+            TransactionVariant::Capture | TransactionVariant::Void => {
+                let authorization = self
+                    .transactions
+                    .get(tx.tx)
+                    .filter(|authorization| {
+                        authorization.variant == TransactionVariant::Authorize
+                            && authorization.client == tx.client
+                    })
+                    .ok_or(TransactionError::TransactionNotFound)?
+                    .into_owned();
+                if !self.authorizations.contains(&tx.tx) {
+                    return Err(TransactionError::AuthorizationClosed);
+                }
+                let amount = authorization
+                    .amount
+                    .ok_or(TransactionError::InvalidTransaction)?;
+                account.in_currency(currency_code(&authorization), |account| {
+                    if tx.variant == TransactionVariant::Capture {
+                        account.commit_reservation(amount)
+                    } else {
+                        account.release_reservation(amount)
+                    }
+                })?;
+                self.authorizations.remove(&tx.tx);
+                if tx.variant == TransactionVariant::Capture && authorization.currency.is_none() {
+                    self.metrics.withdrawn =
+                        self.metrics.withdrawn.saturating_add(Decimal::from(amount));
+                }
             }
             TransactionVariant::Dispute => {
                 let tx_to_dispute = self
                     .transactions
-                    .get_mut(&tx.tx)
+                    .get_mut(tx.tx)
                     .ok_or(TransactionError::TransactionNotFound)?;
 
                 if tx_to_dispute.client != tx.client {
@@ -65,19 +1125,96 @@ impl PaymentEngine {
                 }
 
                 tx_to_dispute.can_dispute()?;
+                if matches!(
+                    tx_to_dispute.variant,
+                    TransactionVariant::Transfer
+                        | TransactionVariant::Fee
+                        | TransactionVariant::Convert
+                        | TransactionVariant::Authorize
+                ) {
+                    return Err(TransactionError::NotDisputable);
+                }
+                if tx_to_dispute.variant == TransactionVariant::Withdrawal
+                    && self.config.withdrawal_disputes == WithdrawalDisputes::Reject
+                {
+                    return Err(TransactionError::NotDisputable);
+                }
+                if self
+                    .config
+                    .tier_rules(account.tier())
+                    .is_some_and(|rules| rules.deny_disputes)
+                {
+                    return Err(TransactionError::NotDisputable);
+                }
 
-                // SAFETY: We knnow that `disputed_tx` has `variant` with value
-                // `TransactionVariant::Deposit` or `TransactionVariant::Withdrawal`.
-                // This means that `amount` is Some.
-                let disputed_amount = tx_to_dispute.amount.unwrap();
+                let amount = tx_to_dispute
+                    .amount
+                    .ok_or(TransactionError::InvalidTransaction)?;
+                let disputed_amount = tx.amount.unwrap_or(amount);
+                if disputed_amount > amount {
+                    return Err(TransactionError::DisputeExceedsAmount {
+                        disputed: disputed_amount,
+                        amount,
+                    });
+                }
 
-                account.transaction(&tx.variant, disputed_amount)?;
+                let config = &self.config;
+                account.in_currency(currency_code(tx_to_dispute), |account| {
+                    account.dispute_transaction(
+                        &tx.variant,
+                        &tx_to_dispute.variant,
+                        disputed_amount,
+                        config,
+                    )
+                })?;
                 tx_to_dispute.disputed = true;
+                self.disputed.insert(tx.tx);
+                if disputed_amount < amount {
+                    self.partial_disputes.insert(tx.tx, disputed_amount);
+                }
+                if let Some(now) = tx.timestamp.or(self.now) {
+                    self.disputes_opened.insert(tx.tx, now);
+                }
+            }
+            TransactionVariant::Unlock
+            | TransactionVariant::Close
+            | TransactionVariant::Freeze
+            | TransactionVariant::Unfreeze => {
+                unreachable!("Handled by `PaymentEngine::insert_unmetered`")
+            }
+            TransactionVariant::Representment => {
+                let charged_back = self
+                    .transactions
+                    .get(tx.tx)
+                    .filter(|charged_back| charged_back.client == tx.client)
+                    .ok_or(TransactionError::TransactionNotFound)?
+                    .into_owned();
+                if !charged_back.chargeback {
+                    return Err(TransactionError::NotChargedBack);
+                }
+                if self.represented.contains(&tx.tx) {
+                    return Err(TransactionError::AlreadyRepresented);
+                }
+
+                let amount = match self.partial_disputes.get(&tx.tx) {
+                    Some(partial) => *partial,
+                    None => charged_back
+                        .amount
+                        .ok_or(TransactionError::InvalidTransaction)?,
+                };
+                let config = &self.config;
+                account.in_currency(currency_code(&charged_back), |account| {
+                    account.represent(&charged_back.variant, amount, config)
+                })?;
+                self.represented.insert(tx.tx);
+                if self.config.unlock_on_representment {
+                    self.unlock(tx.client)?;
+                }
             }
             TransactionVariant::Resolve | TransactionVariant::Chargeback => {
                 let disputed_tx = self
                     .transactions
-                    .get_mut(&tx.tx)
+                    .get_mut(tx.tx)
                     .ok_or(TransactionError::TransactionNotFound)?;
 
                 if disputed_tx.client != tx.client {
@@ -86,331 +1223,3863 @@ impl PaymentEngine {
 
                 disputed_tx.can_resolve_or_chargeback()?;
 
-                // SAFETY: We knnow that `disputed_tx` has `variant` with value
-                // `TransactionVariant::Deposit` or `TransactionVariant::Withdrawal`.
-                // This means that `amount` is Some.
-                let disputed_amount = disputed_tx.amount.unwrap();
+                let disputed_amount = match self.partial_disputes.get(&tx.tx) {
+                    Some(partial) => *partial,
+                    None => disputed_tx
+                        .amount
+                        .ok_or(TransactionError::InvalidTransaction)?,
+                };
 
-                account.transaction(&tx.variant, disputed_amount)?;
+                let config = &self.config;
+                account.in_currency(currency_code(disputed_tx), |account| {
+                    account.dispute_transaction(
+                        &tx.variant,
+                        &disputed_tx.variant,
+                        disputed_amount,
+                        config,
+                    )
+                })?;
                 disputed_tx.disputed = false;
+                self.disputed.remove(&tx.tx);
+                // A representment reverses the portion that was charged back
+                if tx.variant == TransactionVariant::Resolve {
+                    self.partial_disputes.remove(&tx.tx);
+                }
+                self.disputes_opened.remove(&tx.tx);
+                self.held_periods.remove(&tx.tx);
 
                 // In case of chargeback we also want to mark the disputed transaction as
                 // a "chargedback" transaction
                 if tx.variant == TransactionVariant::Chargeback {
                     disputed_tx.chargeback = true;
+                    self.metrics.chargebacks += 1;
+                    if disputed_tx.currency.is_none() {
+                        self.metrics.charged_back = self
+                            .metrics
+                            .charged_back
+                            .saturating_add(Decimal::from(disputed_amount));
+                    }
                 }
             }
         }
 
-        Ok(())
+        Ok(true)
     }
 
-    pub fn accounts(&self) -> &HashMap<u16, Account> {
+    /// Debits the client of a [`TransactionVariant::Transfer`] or
+    /// [`TransactionVariant::Fee`] and credits `destination`, if any. Applied
+    /// to both accounts or neither.
+    fn transfer(
+        &mut self,
+        tx: Transaction,
+        destination: Option<u16>,
+    ) -> Result<bool, TransactionError> {
+        if self.transactions.contains(tx.tx) {
+            return match self.config.id_collision {
+                IdCollision::Reject => Err(TransactionError::TransactionAlreadyExist),
+                IdCollision::Ignore => Ok(false),
+            };
+        }
+
+        let amount = tx.amount.ok_or(TransactionError::InvalidTransaction)?;
+
+        // Check the destination before the source is changed, after which the
+        // deposit cannot fail
+        if let Some(destination) = destination {
+            let account = self.accounts.get_or_create(destination);
+            if let Some(pending) = self.pending_deposits.get(&destination).copied() {
+                account.transaction(&TransactionVariant::Deposit, pending, &self.config)?;
+                self.pending_deposits.remove(&destination);
+            }
+            account.check(&TransactionVariant::Deposit, amount, &self.config)?;
+        }
+
+        let config = &self.config;
+        let currency = currency_code(&tx);
+        self.accounts
+            .get_or_create(tx.client)
+            .in_currency(currency, |account| {
+                account.transaction(&TransactionVariant::Withdrawal, amount, config)
+            })?;
+        if let Some(destination) = destination {
+            self.accounts
+                .get_or_create(destination)
+                .in_currency(currency, |account| {
+                    account.transaction(&TransactionVariant::Deposit, amount, config)
+                })?;
+        }
+
+        if tx.currency.is_some() {
+            // Amounts in different currencies cannot be added up
+        } else if tx.variant == TransactionVariant::Fee {
+            self.metrics.fees = self.metrics.fees.saturating_add(Decimal::from(amount));
+        } else {
+            self.metrics.transferred = self
+                .metrics
+                .transferred
+                .saturating_add(Decimal::from(amount));
+        }
+        if let Some(history) = &mut self.history {
+            history.entry(tx.client).or_default().push(tx.tx);
+            if let Some(destination) = destination {
+                history.entry(destination).or_default().push(tx.tx);
+            }
+        }
+        self.transactions.insert(tx);
+        Ok(true)
+    }
+
+    /// Applies a [`TransactionVariant::Convert`] between the funds of its
+    /// client in two currencies.
+    fn convert(&mut self, tx: Transaction) -> Result<bool, TransactionError> {
+        if self.transactions.contains(tx.tx) {
+            return match self.config.id_collision {
+                IdCollision::Reject => Err(TransactionError::TransactionAlreadyExist),
+                IdCollision::Ignore => Ok(false),
+            };
+        }
+
+        let (amount, rate, to_currency) = match (tx.amount, tx.rate, &tx.to_currency) {
+            (Some(amount), Some(rate), Some(to_currency)) => (amount, rate, to_currency),
+            _ => return Err(TransactionError::InvalidTransaction),
+        };
+        let converted = Decimal::from(amount)
+            .checked_mul(rate)
+            .map(|converted| {
+                converted
+                    .round_dp_with_strategy(to_currency.minor_units(), RoundingStrategy::ToZero)
+            })
+            .and_then(|converted| Amount::try_from(converted).ok())
+            .ok_or(TransactionError::ConversionOverflow { amount, rate })?;
+
+        let config = &self.config;
+        let account = self.accounts.get_or_create(tx.client);
+        if tx.currency.is_none() {
+            if let Some(pending) = self.pending_deposits.get(&tx.client).copied() {
+                account.transaction(&TransactionVariant::Deposit, pending, config)?;
+                self.pending_deposits.remove(&tx.client);
+            }
+        }
+        account.in_currency(currency_code(&tx), |account| {
+            account.transaction(&TransactionVariant::Withdrawal, amount, config)
+        })?;
+        // Cannot fail, as the account accepted the withdrawal
+        account.in_currency(Some(to_currency.code()), |account| {
+            account.transaction(&TransactionVariant::Deposit, converted, config)
+        })?;
+
+        if let Some(history) = &mut self.history {
+            history.entry(tx.client).or_default().push(tx.tx);
+        }
+        self.transactions.insert(tx);
+        Ok(true)
+    }
+
+    /// Inserts `tx` like [`PaymentEngine::insert`] unless a transaction with
+    /// the same idempotency `key` has already been applied, in which case it
+    /// is skipped without an error and counted in [`RunMetrics::replayed`].
+    ///
+    /// The key is only recorded once `tx` has been applied, so rows that were
+    /// rejected are tried again when a file is processed a second time. Keys
+    /// are kept in snapshots and storage.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use randomlib::{Amount, PaymentEngine, Transaction, TransactionVariant};
+    ///
+    /// let mut engine = PaymentEngine::default();
+    /// for tx in [1, 2] {
+    ///     let tx = Transaction {
+    ///         tx,
+    ///         amount: Some(Amount::new(5, 0).unwrap()),
+    ///         client: 1,
+    ///         disputed: false,
+    ///         variant: TransactionVariant::Deposit,
+    ///         chargeback: false,
+    ///         destination: None,
+    ///         currency: None,
+    ///         to_currency: None,
+    ///         rate: None,
+    ///         timestamp: None,
+    ///     };
+    ///     assert!(engine.insert_with_key(tx, "partner-1").is_ok());
+    /// }
+    /// assert_eq!(engine.accounts().get(&1).unwrap().total(), Amount::new(5, 0).unwrap());
+    /// assert_eq!(engine.metrics().replayed, 1);
+    /// ```
+    pub fn insert_with_key(&mut self, tx: Transaction, key: &str) -> Result<(), TransactionError> {
+        if self.idempotency_keys.contains(key) {
+            self.metrics.replayed += 1;
+            return Ok(());
+        }
+        self.insert(tx)?;
+        if let Some(storage) = &mut self.storage {
+            storage.store_idempotency_key(key)?;
+        }
+        self.idempotency_keys.insert(key.to_string());
+        Ok(())
+    }
+
+    /// Inserts every transaction of `transactions` in order, continuing past
+    /// failures, and returns the result of each in the same order.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use randomlib::{Amount, PaymentEngine, Transaction, TransactionError, TransactionVariant};
+    ///
+    /// let mut engine = PaymentEngine::default();
+    /// let transaction = |tx, variant| Transaction {
+    ///     tx,
+    ///     amount: Some(Amount::new(5, 0).unwrap()),
+    ///     client: 1,
+    ///     disputed: false,
+    ///     variant,
+    ///     chargeback: false,
+    ///     destination: None,
+    ///     currency: None,
+    ///     to_currency: None,
+    ///     rate: None,
+    ///     timestamp: None,
+    /// };
+    /// let results = engine.insert_all(vec![
+    ///     transaction(1, TransactionVariant::Deposit),
+    ///     transaction(1, TransactionVariant::Deposit),
+    ///     transaction(2, TransactionVariant::Withdrawal),
+    /// ]);
+    /// assert_eq!(
+    ///     results,
+    ///     [Ok(()), Err(TransactionError::TransactionAlreadyExist), Ok(())]
+    /// );
+    /// ```
+    pub fn insert_all<I>(&mut self, transactions: I) -> Vec<Result<(), TransactionError>>
+    where
+        I: IntoIterator<Item = Transaction>,
+    {
+        transactions.into_iter().map(|tx| self.insert(tx)).collect()
+    }
+
+    /// Inserts every transaction of `batch` in order, or none of them, e.g. a
+    /// deposit together with the fee charged for it.
+    ///
+    /// The batch is first inserted into a scratch engine holding copies of the
+    /// accounts and transactions it touches, so a failing transaction leaves
+    /// the engine, its storage and its observers untouched. Only failures of
+    /// the storage or the audit log while applying the checked batch are not
+    /// rolled back.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use randomlib::{Amount, PaymentEngine, Transaction, TransactionVariant};
+    ///
+    /// let mut engine = PaymentEngine::builder().fee_account(0).build();
+    /// let transaction = |tx, variant, amount| Transaction {
+    ///     tx,
+    ///     amount: Some(Amount::new(amount, 0).unwrap()),
+    ///     client: 1,
+    ///     disputed: false,
+    ///     variant,
+    ///     chargeback: false,
+    ///     destination: None,
+    ///     currency: None,
+    ///     to_currency: None,
+    ///     rate: None,
+    ///     timestamp: None,
+    /// };
+    /// let batch = [
+    ///     transaction(1, TransactionVariant::Deposit, 10),
+    ///     transaction(2, TransactionVariant::Fee, 20),
+    /// ];
+    /// assert_eq!(engine.insert_batch(&batch).unwrap_err().index, 1);
+    /// assert!(engine.accounts().get(&1).is_none());
+    /// ```
+    pub fn insert_batch(&mut self, batch: &[Transaction]) -> Result<(), BatchError> {
+        self.check_batch(batch)?;
+        for (index, tx) in batch.iter().enumerate() {
+            self.insert(tx.clone())
+                .map_err(|error| BatchError { index, error })?;
+        }
+        Ok(())
+    }
+
+    /// Previews the effect of inserting every transaction of `batch` in
+    /// order, without changing the engine.
+    ///
+    /// Like [`PaymentEngine::insert_batch`], the batch is inserted into a
+    /// scratch engine holding copies of only the accounts and transactions it
+    /// touches, so the cost does not grow with the size of the engine.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use randomlib::{process, Amount, PaymentEngine, Transaction, TransactionVariant};
+    ///
+    /// let mut engine = PaymentEngine::default();
+    /// process("type,client,tx,amount\ndeposit,1,1,5.0".as_bytes(), &mut engine).unwrap();
+    /// let withdrawal = |tx, amount| Transaction {
+    ///     tx,
+    ///     amount: Some(Amount::new(amount, 0).unwrap()),
+    ///     client: 1,
+    ///     disputed: false,
+    ///     variant: TransactionVariant::Withdrawal,
+    ///     chargeback: false,
+    ///     destination: None,
+    ///     currency: None,
+    ///     to_currency: None,
+    ///     rate: None,
+    ///     timestamp: None,
+    /// };
+    /// let result = engine.simulate(&[withdrawal(2, 3), withdrawal(3, 3)]);
+    /// assert!(result.results[0].is_ok());
+    /// assert!(result.results[1].is_err());
+    /// assert_eq!(result.accounts[0].available(), Amount::new(2, 0).unwrap());
+    /// // Nothing has been withdrawn
+    /// assert_eq!(engine.accounts().get(&1).unwrap().available(), Amount::new(5, 0).unwrap());
+    /// ```
+    pub fn simulate(&self, batch: &[Transaction]) -> SimulationResult {
+        let mut scratch = self.scratch(batch);
+        let results = batch
+            .iter()
+            .map(|tx| {
+                self.validate(tx, scratch.accounts.get(&tx.client))
+                    .and_then(|_| scratch.insert_unmetered(tx.clone()))
+                    .map(|_| ())
+            })
+            .collect();
+        SimulationResult {
+            results,
+            accounts: scratch.accounts_iter(true).cloned().collect(),
+        }
+    }
+
+    /// Inserts `batch` into a scratch engine with copies of the state it
+    /// touches and returns the first error.
+    fn check_batch(&self, batch: &[Transaction]) -> Result<(), BatchError> {
+        let mut scratch = self.scratch(batch);
+        for (index, tx) in batch.iter().enumerate() {
+            self.validate(tx, scratch.accounts.get(&tx.client))
+                .and_then(|_| scratch.insert_unmetered(tx.clone()))
+                .map_err(|error| BatchError { index, error })?;
+        }
+        Ok(())
+    }
+
+    /// A scratch engine with the configuration and copies of the accounts,
+    /// transactions and dispute state `batch` touches.
+    fn scratch(&self, batch: &[Transaction]) -> PaymentEngine {
+        let mut scratch = PaymentEngine {
+            config: self.config.clone(),
+            limiter: self.limiter.clone(),
+            velocity: self.velocity.clone(),
+            daily: self.daily.clone(),
+            now: self.now,
+            ..PaymentEngine::default()
+        };
+        for tx in batch {
+            for client in std::iter::once(tx.client).chain(self.counterparty(tx)) {
+                if let Some(account) = self.accounts.get(client) {
+                    scratch.accounts.insert(client, account.clone());
+                }
+                if let Some(pending) = self.pending_deposits.get(&client) {
+                    scratch.pending_deposits.insert(client, *pending);
+                }
+            }
+            if let Some(existing) = self.transactions.get(tx.tx) {
+                TransactionStore::insert(&mut scratch.transactions, existing.into_owned());
+            }
+            if self.authorizations.contains(&tx.tx) {
+                scratch.authorizations.insert(tx.tx);
+            }
+            if self.represented.contains(&tx.tx) {
+                scratch.represented.insert(tx.tx);
+            }
+            if let Some(partial) = self.partial_disputes.get(&tx.tx) {
+                scratch.partial_disputes.insert(tx.tx, *partial);
+            }
+            if let Some(opened) = self.disputes_opened.get(&tx.tx) {
+                scratch.disputes_opened.insert(tx.tx, *opened);
+            }
+        }
+        scratch
+    }
+
+    /// Unlocks the account of `client` after it has been locked by a
+    /// chargeback, for example once an investigation has concluded.
+    ///
+    /// Also done by inserting a [`TransactionVariant::Unlock`]. Fails with
+    /// [`TransactionError::AccountNotFound`] if the client has no account.
+    pub fn unlock(&mut self, client: u16) -> Result<(), TransactionError> {
+        let account = self
+            .accounts
+            .get_mut(client)
+            .ok_or(TransactionError::AccountNotFound(client))?;
+        if !account.locked() {
+            return Ok(());
+        }
+        account.unlock();
+        for observer in &mut self.observers {
+            observer.on_account_unlocked(account);
+        }
+        if let Some(changes) = &mut self.changes {
+            changes.mark(client);
+        }
+        if let Some(storage) = &mut self.storage {
+            storage.store_account(account)?;
+        }
+        Ok(())
+    }
+
+    /// Closes the account of `client`, after which every transaction of the
+    /// client fails with [`TransactionError::ClosedAccount`].
+    ///
+    /// Also done by inserting a [`TransactionVariant::Close`]. Fails with
+    /// [`TransactionError::NonZeroBalance`] unless all funds have been
+    /// withdrawn, and with [`TransactionError::AccountNotFound`] if the client
+    /// has no account.
+    pub fn close(&mut self, client: u16) -> Result<(), TransactionError> {
+        let account = self
+            .accounts
+            .get_mut(client)
+            .ok_or(TransactionError::AccountNotFound(client))?;
+        if let Some(pending) = self.pending_deposits.get(&client).copied() {
+            account.transaction(&TransactionVariant::Deposit, pending, &self.config)?;
+            self.pending_deposits.remove(&client);
+        }
+        account.transaction(&TransactionVariant::Close, Amount::zero(), &self.config)?;
+        for observer in &mut self.observers {
+            observer.on_account_closed(account);
+        }
+        if let Some(changes) = &mut self.changes {
+            changes.mark(client);
+        }
+        if let Some(storage) = &mut self.storage {
+            storage.store_account(account)?;
+        }
+        Ok(())
+    }
+
+    /// Freezes the account of `client`, after which every transaction of the
+    /// client fails with [`TransactionError::FrozenAccount`] until it is
+    /// unfrozen. Unlike the lock of a chargeback, the account can be frozen
+    /// and unfrozen at any time, e.g. while the risk team looks into it.
+    ///
+    /// Also done by inserting a [`TransactionVariant::Freeze`]. Fails with
+    /// [`TransactionError::AccountNotFound`] if the client has no account.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use randomlib::{process, PaymentEngine};
+    ///
+    /// let mut engine = PaymentEngine::default();
+    /// let input = "type,client,tx,amount\ndeposit,1,1,5.0\nfreeze,1,0,";
+    /// process(input.as_bytes(), &mut engine).unwrap();
+    /// assert!(engine.accounts().get(&1).unwrap().frozen());
+    /// assert!(process("type,client,tx,amount\nwithdrawal,1,2,1.0".as_bytes(), &mut engine).is_err());
+    ///
+    /// engine.unfreeze(1).unwrap();
+    /// process("type,client,tx,amount\nwithdrawal,1,2,1.0".as_bytes(), &mut engine).unwrap();
+    /// ```
+    pub fn freeze(&mut self, client: u16) -> Result<(), TransactionError> {
+        self.set_frozen(client, true)
+    }
+
+    /// Lifts a [`PaymentEngine::freeze`] of the account of `client`.
+    ///
+    /// Also done by inserting a [`TransactionVariant::Unfreeze`]. Fails with
+    /// [`TransactionError::AccountNotFound`] if the client has no account.
+    pub fn unfreeze(&mut self, client: u16) -> Result<(), TransactionError> {
+        self.set_frozen(client, false)
+    }
+
+    fn set_frozen(&mut self, client: u16, frozen: bool) -> Result<(), TransactionError> {
+        let account = self
+            .accounts
+            .get_mut(client)
+            .ok_or(TransactionError::AccountNotFound(client))?;
+        if account.frozen() == frozen {
+            return Ok(());
+        }
+        // Deposits accumulated before a freeze are still accepted, while
+        // those of a frozen account wait for the next flush
+        if frozen {
+            if let Some(pending) = self.pending_deposits.get(&client).copied() {
+                account.transaction(&TransactionVariant::Deposit, pending, &self.config)?;
+                self.pending_deposits.remove(&client);
+            }
+        }
+        account.set_frozen(frozen);
+        for observer in &mut self.observers {
+            if frozen {
+                observer.on_account_frozen(account);
+            } else {
+                observer.on_account_unfrozen(account);
+            }
+        }
+        if let Some(changes) = &mut self.changes {
+            changes.mark(client);
+        }
+        if let Some(storage) = &mut self.storage {
+            storage.store_account(account)?;
+        }
+        Ok(())
+    }
+
+    /// Plans the transactions that move the accounts to the `target` state,
+    /// for example from a reconciliation, without applying them. Only the
+    /// clients in `target` are compared. The transactions get ids from
+    /// [`PaymentEngine::next_tx_id`], so they can be reviewed and then
+    /// inserted or processed as input.
+    ///
+    /// Differences that cannot be corrected with transactions, such as too
+    /// much held funds, are returned in [`Corrections::manual`]. Fails if an
+    /// accumulated deposit of a compared client is not accepted, e.g. as the
+    /// account is frozen.
+    pub fn corrections(&mut self, target: &[Account]) -> Result<Corrections, TransactionError> {
+        let mut current = HashMap::new();
+        for client in target.iter().map(Account::client) {
+            let mut account = match self.accounts.get(client) {
+                Some(account) => account.clone(),
+                None => continue,
+            };
+            if let Some(pending) = self.pending_deposits.get(&client) {
+                account.transaction(&TransactionVariant::Deposit, *pending, &self.config)?;
+            }
+            current.insert(client, account);
+        }
+        correction::plan(
+            |client| current.get(&client).cloned(),
+            target,
+            || self.next_tx_id(),
+        )
+    }
+
+    /// Returns an id for a transaction originated by the engine from its
+    /// [`IdGenerator`], skipping ids of transactions the engine already has.
+    ///
+    /// The default [`CounterIds`] starts at [`CounterIds::DEFAULT_START`],
+    /// above the ids of upstream systems. Ids are not reserved, so an input
+    /// must not use them for other transactions later. Fails with
+    /// [`TransactionError::IdsExhausted`] once the generator has no ids left.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use randomlib::{process, CounterIds, PaymentEngine};
+    ///
+    /// let mut engine = PaymentEngine::default();
+    /// process("type,client,tx,amount\ndeposit,1,4000000000,1.0".as_bytes(), &mut engine).unwrap();
+    /// assert_eq!(engine.next_tx_id(), Ok(CounterIds::DEFAULT_START + 1));
+    /// assert_eq!(engine.next_tx_id(), Ok(CounterIds::DEFAULT_START + 2));
+    /// ```
+    pub fn next_tx_id(&mut self) -> Result<u32, TransactionError> {
+        loop {
+            let id = self.ids.next_id().ok_or(TransactionError::IdsExhausted)?;
+            if self.transactions.get(id).is_none() {
+                return Ok(id);
+            }
+        }
+    }
+
+    /// Holds `amount` of the available funds of `client` for an external
+    /// system, for example while it attempts a payout, until the returned
+    /// reservation is committed with [`PaymentEngine::commit`] or released
+    /// with [`PaymentEngine::release`].
+    ///
+    /// Reserved funds are part of [`Account::held`], but unlike a dispute a
+    /// reservation does not reference a transaction. Fails if the account is
+    /// locked or closed or has insufficient available funds.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use randomlib::{Amount, PaymentEngine, Transaction, TransactionVariant};
+    ///
+    /// let mut engine = PaymentEngine::default();
+    /// let deposit = Transaction {
+    ///     tx: 1,
+    ///     amount: Some(Amount::new(10, 0).unwrap()),
+    ///     client: 1,
+    ///     disputed: false,
+    ///     variant: TransactionVariant::Deposit,
+    ///     chargeback: false,
+    ///     destination: None,
+    ///     currency: None,
+    ///     to_currency: None,
+    ///     rate: None,
+    ///     timestamp: None,
+    /// };
+    /// engine.insert(deposit).unwrap();
+    ///
+    /// let id = engine.reserve(1, Amount::new(4, 0).unwrap()).unwrap();
+    /// assert_eq!(engine.accounts().get(&1).unwrap().held(), Amount::new(4, 0).unwrap());
+    /// engine.commit(id).unwrap();
+    /// assert_eq!(engine.accounts().get(&1).unwrap().total(), Amount::new(6, 0).unwrap());
+    /// ```
+    pub fn reserve(
+        &mut self,
+        client: u16,
+        amount: Amount,
+    ) -> Result<ReservationId, TransactionError> {
+        let account = self
+            .accounts
+            .get_mut(client)
+            .ok_or(TransactionError::AccountNotFound(client))?;
+        if let Some(pending) = self.pending_deposits.get(&client).copied() {
+            account.transaction(&TransactionVariant::Deposit, pending, &self.config)?;
+            self.pending_deposits.remove(&client);
+        }
+        account.reserve(amount, &self.config)?;
+
+        let reservation = Reservation {
+            id: ReservationId(self.next_reservation),
+            client,
+            amount,
+        };
+        self.next_reservation += 1;
+        if let Some(storage) = &mut self.storage {
+            storage.store_account(account)?;
+            storage.store_reservation(&reservation)?;
+        }
+        let id = reservation.id;
+        self.reservations.insert(id, reservation);
+        self.record_balances([client]);
+        if let Some(changes) = &mut self.changes {
+            changes.mark(client);
+        }
+        Ok(id)
+    }
+
+    /// Takes the funds of reservation `id` out of the account, once the
+    /// external system succeeded. Counted as withdrawn in
+    /// [`PaymentEngine::metrics`].
+    pub fn commit(&mut self, id: ReservationId) -> Result<(), TransactionError> {
+        let amount = self.finish_reservation(id, Account::commit_reservation)?;
+        self.metrics.withdrawn = self.metrics.withdrawn.saturating_add(Decimal::from(amount));
+        Ok(())
+    }
+
+    /// Makes the funds of reservation `id` available again, for example
+    /// after the external system failed.
+    pub fn release(&mut self, id: ReservationId) -> Result<(), TransactionError> {
+        self.finish_reservation(id, Account::release_reservation)?;
+        Ok(())
+    }
+
+    /// Returns the open reservation `id`, see [`PaymentEngine::reserve`].
+    pub fn reservation(&self, id: ReservationId) -> Option<&Reservation> {
+        self.reservations.get(&id)
+    }
+
+    /// Applies `finish` to the account of reservation `id` with the reserved
+    /// amount and removes the reservation if that succeeded.
+    fn finish_reservation(
+        &mut self,
+        id: ReservationId,
+        finish: fn(&mut Account, Amount) -> Result<(), TransactionError>,
+    ) -> Result<Amount, TransactionError> {
+        let reservation = self
+            .reservations
+            .get(&id)
+            .cloned()
+            .ok_or(TransactionError::ReservationNotFound(id))?;
+        let account = self.accounts.get_or_create(reservation.client);
+        finish(account, reservation.amount)?;
+        self.reservations.remove(&id);
+        if let Some(storage) = &mut self.storage {
+            storage.store_account(account)?;
+            storage.remove_reservation(id)?;
+        }
+        self.record_balances([reservation.client]);
+        if let Some(changes) = &mut self.changes {
+            changes.mark(reservation.client);
+        }
+        Ok(reservation.amount)
+    }
+
+    /// Moves the clock of the engine to `timestamp`, in seconds since the Unix
+    /// epoch, and credits the interest of every period that ended since the
+    /// previous timestamp, see [`InterestConfig`].
+    ///
+    /// Called with the `timestamp` column of the input before each
+    /// transaction. Interest is only credited if it has been configured with
+    /// [`PaymentEngineBuilder::interest`]. Timestamps earlier than the latest
+    /// one do not move the clock back.
+    pub fn advance_time(&mut self, timestamp: u64) -> Result<(), TransactionError> {
+        self.started_at.get_or_insert(timestamp);
+        self.now = Some(self.now.map_or(timestamp, |now| now.max(timestamp)));
+
+        let periods = match &mut self.interest {
+            Some(interest) => interest.advance(timestamp),
+            None => return Ok(()),
+        };
+        if periods.is_empty() {
+            return Ok(());
+        }
+        // Interest is earned on the up to date balances
+        self.flush()?;
+
+        let clients = self
+            .accounts
+            .iter()
+            .map(Account::client)
+            .collect::<Vec<_>>();
+        for (_, rate) in periods {
+            for client in &clients {
+                let account = match self.accounts.get_mut(*client) {
+                    Some(account) => account,
+                    None => continue,
+                };
+                let amount = match interest::interest(account, rate) {
+                    Some(amount) => amount,
+                    None => continue,
+                };
+                match account.transaction(&TransactionVariant::Deposit, amount, &self.config) {
+                    // Like a balance too large to compute interest on
+                    Err(TransactionError::Overflow) => continue,
+                    result => result?,
+                }
+                self.metrics.interest = self.metrics.interest.saturating_add(Decimal::from(amount));
+                for observer in &mut self.observers {
+                    observer.on_interest(account, amount);
+                }
+                if let (Some(timeline), Some(now)) = (&mut self.timeline, self.now) {
+                    timeline.record(*client, now, account.balance(None));
+                }
+                if let Some(changes) = &mut self.changes {
+                    changes.mark(*client);
+                }
+                if let Some(storage) = &mut self.storage {
+                    storage.store_account(account)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Queues `tx` until [`PaymentEngine::advance_to`] reaches `effective`, in
+    /// seconds since the Unix epoch, or inserts it right away if the clock of
+    /// the engine already has.
+    ///
+    /// Queued transactions are only checked when they are applied.
+    pub fn insert_effective(
+        &mut self,
+        mut tx: Transaction,
+        effective: u64,
+    ) -> Result<(), TransactionError> {
+        if self.now.is_some_and(|now| now >= effective) {
+            tx.timestamp = Some(effective);
+            return self.insert(tx);
+        }
+        self.queued.push(effective, tx);
+        Ok(())
+    }
+
+    /// Applies the queued transactions effective by `date`, earliest first,
+    /// and moves the clock of the engine to `date`, see
+    /// [`PaymentEngine::insert_effective`].
+    ///
+    /// The clock is moved to the effective date of every transaction before
+    /// it is applied, so interest and limits see the date the transaction
+    /// took effect. A rejected transaction is returned with its error and
+    /// dropped. Fails on the first storage error.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use randomlib::{process, PaymentEngine};
+    ///
+    /// let mut engine = PaymentEngine::default();
+    /// let input = "type,client,tx,amount,effective\n\
+    ///              deposit,1,1,5.0,\n\
+    ///              withdrawal,1,2,2.0,1000\n";
+    /// process(input.as_bytes(), &mut engine).unwrap();
+    /// assert_eq!(engine.queued().count(), 1);
+    /// let matured = engine.advance_to(1000).unwrap();
+    /// assert!(matured[0].error.is_none());
+    /// assert_eq!(engine.accounts().get(&1).unwrap().total().to_string(), "3.0000");
+    /// ```
+    pub fn advance_to(&mut self, date: u64) -> Result<Vec<Matured>, TransactionError> {
+        let mut matured = Vec::new();
+        while let Some((effective, mut transaction)) = self.queued.pop_due(date) {
+            self.advance_time(effective)?;
+            transaction.timestamp = Some(effective);
+            let error = match self.insert(transaction.clone()) {
+                Err(e @ TransactionError::Storage(_)) => return Err(e),
+                result => result.err(),
+            };
+            matured.push(Matured { transaction, error });
+        }
+        self.advance_time(date)?;
+        Ok(matured)
+    }
+
+    /// Returns the transactions waiting for their effective date with that
+    /// date, earliest first.
+    pub fn queued(&self) -> impl Iterator<Item = (u64, &Transaction)> {
+        self.queued.iter()
+    }
+
+    /// Adds a recurring transaction, whose occurrences are applied by
+    /// [`PaymentEngine::materialize`]. Fails with
+    /// [`TransactionError::InvalidSchedule`] unless the schedule has a
+    /// positive interval and is a deposit, withdrawal or fee.
+    pub fn add_schedule(&mut self, schedule: Schedule) -> Result<(), TransactionError> {
+        if !schedule.is_valid() {
+            return Err(TransactionError::InvalidSchedule);
+        }
+        self.schedules.push(ScheduleState::new(schedule));
+        Ok(())
+    }
+
+    /// Applies every occurrence of the schedules up to and including
+    /// `until`, in seconds since the Unix epoch, that has not been applied
+    /// yet, and returns them in the order they were applied.
+    ///
+    /// Occurrences are applied by timestamp, moving the clock with
+    /// [`PaymentEngine::advance_time`], and get ids from
+    /// [`PaymentEngine::next_tx_id`]. A rejected occurrence, for example a
+    /// fee a client cannot pay, is returned with its error and not retried.
+    /// Fails on the first storage error or once no ids are left.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use randomlib::{process, PaymentEngine, Schedule};
+    ///
+    /// let mut engine = PaymentEngine::default();
+    /// process("type,client,tx,amount\ndeposit,1,1,20.0".as_bytes(), &mut engine).unwrap();
+    /// let schedules = "client,type,amount,start,interval\n1,withdrawal,9.99,0,2592000";
+    /// for schedule in Schedule::read(schedules.as_bytes()).unwrap() {
+    ///     engine.add_schedule(schedule).unwrap();
+    /// }
+    /// let occurrences = engine.materialize(2 * 2592000).unwrap();
+    /// assert_eq!(occurrences.len(), 3);
+    /// assert!(occurrences[2].error.is_some());
+    /// ```
+    pub fn materialize(&mut self, until: u64) -> Result<Vec<Occurrence>, TransactionError> {
+        let mut occurrences = Vec::new();
+        loop {
+            let due = self
+                .schedules
+                .iter()
+                .enumerate()
+                .filter_map(|(index, state)| state.due(until).map(|next| (next, index)))
+                .min();
+            let (timestamp, index) = match due {
+                Some(due) => due,
+                None => return Ok(occurrences),
+            };
+            self.advance_time(timestamp)?;
+            let schedule = &self.schedules[index].schedule;
+            let mut transaction = Transaction {
+                variant: schedule.variant.clone(),
+                client: schedule.client,
+                tx: 0,
+                amount: Some(schedule.amount),
+                disputed: false,
+                chargeback: false,
+                destination: None,
+                currency: None,
+                to_currency: None,
+                rate: None,
+                timestamp: Some(timestamp),
+            };
+            transaction.tx = self.next_tx_id()?;
+            self.schedules[index].advance();
+            let error = match self.insert(transaction.clone()) {
+                Err(e @ TransactionError::Storage(_)) => return Err(e),
+                result => result.err(),
+            };
+            occurrences.push(Occurrence {
+                schedule: index,
+                transaction,
+                error,
+            });
+        }
+    }
+
+    /// Moves the clock of the engine to `now` and settles every dispute open
+    /// for at least [`DisputeExpiry::after`](crate::DisputeExpiry::after)
+    /// seconds as configured with [`PaymentEngineBuilder::dispute_expiry`],
+    /// oldest first. Does nothing unless an expiry has been configured.
+    ///
+    /// Only disputes opened while the engine had a clock, see
+    /// [`PaymentEngine::advance_time`], can expire. Settling skips the checks
+    /// of [`PaymentEngine::insert`], such as validators and limits, but fails
+    /// like a resolve or chargeback would otherwise, e.g. for a frozen
+    /// account, in which case the dispute stays open and is returned with the
+    /// error. Fails on the first storage error.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use randomlib::{process, DisputeExpiry, ExpiryOutcome, PaymentEngine};
+    ///
+    /// let mut engine = PaymentEngine::builder()
+    ///     .dispute_expiry(DisputeExpiry {
+    ///         after: 3600,
+    ///         outcome: ExpiryOutcome::Resolve,
+    ///     })
+    ///     .build();
+    /// let input = "type,client,tx,amount,timestamp\n\
+    ///              deposit,1,1,5.0,1000\n\
+    ///              dispute,1,1,,1000\n";
+    /// process(input.as_bytes(), &mut engine).unwrap();
+    /// assert!(engine.expire_disputes(4599).unwrap().is_empty());
+    /// assert_eq!(engine.expire_disputes(4600).unwrap().len(), 1);
+    /// assert!(engine.accounts().get(&1).unwrap().held().is_zero());
+    /// ```
+    pub fn expire_disputes(&mut self, now: u64) -> Result<Vec<ExpiredDispute>, TransactionError> {
+        let expiry = match self.config.dispute_expiry {
+            Some(expiry) => expiry,
+            None => return Ok(Vec::new()),
+        };
+        self.advance_time(now)?;
+        let mut due = self
+            .disputes_opened
+            .iter()
+            .filter(|(_, opened)| now.saturating_sub(**opened) >= expiry.after)
+            .map(|(tx, opened)| (*opened, *tx))
+            .collect::<Vec<_>>();
+        due.sort_unstable();
+
+        let mut expired = Vec::with_capacity(due.len());
+        for (opened_at, id) in due {
+            let client = match self.transactions.get(id) {
+                Some(tx) => tx.client,
+                // Forgotten, e.g. pruned, so there is nothing left to settle
+                None => {
+                    self.disputes_opened.remove(&id);
+                    continue;
+                }
+            };
+            let tx = Transaction {
+                variant: expiry.outcome.variant(),
+                client,
+                tx: id,
+                amount: None,
+                disputed: false,
+                chargeback: false,
+                destination: None,
+                currency: None,
+                to_currency: None,
+                rate: None,
+                timestamp: Some(now),
+            };
+            let error = match self.apply_and_record(tx, None) {
+                Err(e @ TransactionError::Storage(_)) => return Err(e),
+                result => result.err(),
+            };
+            expired.push(ExpiredDispute {
+                tx: id,
+                client,
+                opened_at,
+                error,
+            });
+        }
+        Ok(expired)
+    }
+
+    /// Moves the clock to `now` and posts a charge or credit for the funds held
+    /// by every dispute open for longer than
+    /// [`HeldFundsConfig::after`](crate::HeldFundsConfig::after), for
+    /// the whole periods since the previous posting, see
+    /// [`PaymentEngineBuilder::held_funds`]. Does nothing if not configured.
+    ///
+    /// The postings are synthetic transactions with ids from
+    /// [`PaymentEngine::next_tx_id`], applied without the checks of
+    /// [`PaymentEngine::insert`]. Disputes opened without a clock never
+    /// accrue. A rejected posting is returned with its error and retried by
+    /// the next call. Fails on the first storage error.
+    pub fn accrue_held_funds(
+        &mut self,
+        now: u64,
+    ) -> Result<Vec<HeldFundsPosting>, TransactionError> {
+        let config = match self.config.held_funds {
+            Some(config) => config,
+            None => return Ok(Vec::new()),
+        };
+        self.advance_time(now)?;
+        let period = config.period.max(1);
+        let held_periods = &self.held_periods;
+        let mut due = self
+            .disputes_opened
+            .iter()
+            .filter_map(|(tx, opened)| {
+                let since = opened.checked_add(config.after)?;
+                let periods = now.checked_sub(since)? / period;
+                let posted = held_periods.get(tx).copied().unwrap_or(0);
+                (periods > posted).then_some((*opened, *tx, periods, periods - posted))
+            })
+            .collect::<Vec<_>>();
+        due.sort_unstable();
+
+        let mut postings = Vec::with_capacity(due.len());
+        for (_, id, periods, unposted) in due {
+            let (client, held, currency) = match self.transactions.get(id) {
+                Some(tx) => {
+                    let held = self.partial_disputes.get(&id).copied().or(tx.amount);
+                    (tx.client, held, tx.currency.clone())
+                }
+                // Forgotten, e.g. pruned, so there is nothing left to accrue on
+                None => {
+                    self.disputes_opened.remove(&id);
+                    self.held_periods.remove(&id);
+                    continue;
+                }
+            };
+            let decimals = currency.as_ref().map_or(4, Currency::minor_units);
+            let amount = match held
+                .and_then(|held| interest::held_funds(held, config.rate, unposted, decimals))
+            {
+                Some(amount) => amount,
+                None => {
+                    self.held_periods.insert(id, periods);
+                    continue;
+                }
+            };
+            let tx = Transaction {
+                variant: config.kind.variant(),
+                client,
+                tx: self.next_tx_id()?,
+                amount: Some(amount),
+                disputed: false,
+                chargeback: false,
+                destination: None,
+                currency,
+                to_currency: None,
+                rate: None,
+                timestamp: Some(now),
+            };
+            let error = match self.apply_and_record(tx.clone(), None) {
+                Err(e @ TransactionError::Storage(_)) => return Err(e),
+                result => result.err(),
+            };
+            if error.is_none() {
+                self.held_periods.insert(id, periods);
+            }
+            postings.push(HeldFundsPosting {
+                tx: id,
+                periods: unposted,
+                transaction: tx,
+                error,
+            });
+        }
+        Ok(postings)
+    }
+
+    /// Moves the available funds of every account that has been dormant for at
+    /// least [`SweepConfig::dormant_after`] to [`SweepConfig::pool`] and
+    /// returns an audit record of each move.
+    ///
+    /// Dormancy is measured by the clock of [`PaymentEngine::advance_time`],
+    /// so nothing is swept unless the input has timestamps. Clients without
+    /// an accepted transaction since the first timestamp count as last active
+    /// at that timestamp. Locked, closed and frozen accounts are not swept.
+    /// Accounts are swept in order of client id. Fails without sweeping
+    /// anything if the pool cannot receive deposits or any of the withdrawals
+    /// is refused, e.g. by a withdrawal limit.
+    pub fn sweep(&mut self, config: &SweepConfig) -> Result<Vec<SweepRecord>, TransactionError> {
+        let (started_at, now) = match (self.started_at, self.now) {
+            (Some(started_at), Some(now)) => (started_at, now),
+            _ => return Ok(Vec::new()),
+        };
+        self.flush()?;
+        self.accounts.get_or_create(config.pool).check(
+            &TransactionVariant::Deposit,
+            Amount::zero(),
+            &self.config,
+        )?;
+
+        let last_activity = &self.last_activity;
+        let mut dormant = self
+            .accounts
+            .iter()
+            .filter(|account| {
+                account.client() != config.pool
+                    && !account.locked()
+                    && !account.closed()
+                    && !account.frozen()
+                    && account.available() > Amount::zero()
+            })
+            .filter_map(|account| {
+                let active = last_activity
+                    .get(&account.client())
+                    .copied()
+                    .unwrap_or(started_at);
+                (now.saturating_sub(active) >= config.dormant_after).then_some((
+                    account.client(),
+                    account.available(),
+                    active,
+                ))
+            })
+            .collect::<Vec<_>>();
+        dormant.sort_unstable_by_key(|(client, _, _)| *client);
+
+        // Apply every move to copies first, so a refused one leaves all
+        // accounts untouched.
+        let mut pool = self.accounts.get_or_create(config.pool).clone();
+        let mut moves = Vec::with_capacity(dormant.len());
+        for (client, amount, active) in dormant {
+            let mut account = self.accounts.get_or_create(client).clone();
+            account.transaction(&TransactionVariant::Withdrawal, amount, &self.config)?;
+            pool.transaction(&TransactionVariant::Deposit, amount, &self.config)?;
+            moves.push((account, pool.clone(), amount, active));
+        }
+
+        let mut records = Vec::with_capacity(moves.len());
+        for (account, pool, amount, active) in moves {
+            let client = account.client();
+            self.accounts.insert(account);
+            self.accounts.insert(pool);
+            self.metrics.swept = self.metrics.swept.saturating_add(Decimal::from(amount));
+            let record = SweepRecord {
+                client,
+                pool: config.pool,
+                amount,
+                last_activity: active,
+                swept_at: now,
+            };
+            if let (Some(account), Some(pool)) =
+                (self.accounts.get(client), self.accounts.get(config.pool))
+            {
+                for observer in &mut self.observers {
+                    observer.on_sweep(account, pool, &record);
+                }
+                if let Some(storage) = &mut self.storage {
+                    storage.store_account(account)?;
+                    storage.store_account(pool)?;
+                }
+            }
+            self.record_balances([client, config.pool]);
+            if let Some(changes) = &mut self.changes {
+                changes.mark(client);
+                changes.mark(config.pool);
+            }
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    /// Applies all accumulated deposits to their accounts and flushes the
+    /// storage, if any. A deposit the account does not accept, e.g. as it
+    /// is frozen, is logged and kept until the next flush, see
+    /// [`PaymentEngine::pending_deposits`].
+    ///
+    /// Only needed when [`HotAccountConfig::accumulate_deposits`] is enabled or
+    /// the engine was created with [`PaymentEngine::with_storage`], otherwise
+    /// the accounts are always up to date.
+    pub fn flush(&mut self) -> Result<(), TransactionError> {
+        let mut clients = self.pending_deposits.keys().copied().collect::<Vec<_>>();
+        clients.sort_unstable();
+        for client in clients {
+            if let Some(account) = self.accounts.get_mut(client) {
+                let pending = self.pending_deposits[&client];
+                // A deposit the account no longer accepts stays pending, so
+                // that it is neither lost nor fails the deposits of others
+                if let Err(e) =
+                    account.transaction(&TransactionVariant::Deposit, pending, &self.config)
+                {
+                    tracing::warn!(client, error = %e, "Accumulated deposit not applied");
+                    continue;
+                }
+                self.pending_deposits.remove(&client);
+                if let Some(storage) = &mut self.storage {
+                    storage.store_account(account)?;
+                }
+                if let Some(changes) = &mut self.changes {
+                    changes.mark(client);
+                }
+            }
+        }
+        self.transactions.check()?;
+        if let Some(storage) = &mut self.storage {
+            storage.flush()?;
+        }
+        if let Some(audit) = &mut self.audit {
+            audit.flush()?;
+        }
+        if let Some(rejected) = &mut self.rejected_rows {
+            rejected.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Returns the accumulated deposits that have not been applied yet, by
+    /// client. After a [`PaymentEngine::flush`] these are the deposits the
+    /// accounts did not accept, which are missing from their balances.
+    pub fn pending_deposits(&self) -> BTreeMap<u16, Amount> {
+        self.pending_deposits
+            .iter()
+            .map(|(client, amount)| (*client, *amount))
+            .collect()
+    }
+
+    /// Returns the clients that received a disproportionate share of the
+    /// transactions, the hottest first.
+    ///
+    /// Always empty unless the engine was created with
+    /// [`PaymentEngine::with_hot_accounts`].
+    pub fn hot_accounts(&self) -> Vec<HotAccount> {
+        self.hot
+            .as_ref()
+            .map(HotAccountDetector::hot_accounts)
+            .unwrap_or_default()
+    }
+
+    /// The transactions that exceeded the limits of a [`VelocityConfig`]
+    /// with [`VelocityAction::Flag`](crate::VelocityAction::Flag), oldest
+    /// first. Empty unless configured with
+    /// [`PaymentEngineBuilder::velocity`](crate::PaymentEngineBuilder::velocity).
+    pub fn velocity_flags(&self) -> &[VelocityFlag] {
+        self.velocity.as_ref().map_or(&[], VelocityTracker::flags)
+    }
+
+    /// Returns the clients that exceeded the per client transaction limit,
+    /// the most quarantined first.
+    ///
+    /// Always empty unless a limit has been configured with
+    /// [`RunOptions::max_transactions_per_client`](crate::RunOptions::max_transactions_per_client).
+    pub fn quarantined(&self) -> Vec<QuarantinedClient> {
+        self.limiter
+            .as_ref()
+            .map(ClientLimiter::quarantined)
+            .unwrap_or_default()
+    }
+
+    /// Combines the accounts and transactions of `other`, for example an
+    /// engine that processed another shard of the input, into this engine.
+    ///
+    /// The balances of clients with an account in both engines are added up
+    /// in every currency, and the account stays locked or closed if it is in
+    /// either engine. The idempotency keys, open authorizations, reservations,
+    /// accumulated deposits and metrics are combined as well, while the other
+    /// state of `other`, such as its history and observers, is dropped. A
+    /// reservation of `other` whose id is taken in this engine gets a new id.
+    /// Fails without changing anything with
+    /// [`TransactionError::MergeCollision`] if both engines have a
+    /// transaction with the same id.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use randomlib::{process, PaymentEngine};
+    ///
+    /// let mut engine = PaymentEngine::default();
+    /// process("type,client,tx,amount\ndeposit,1,1,1.0".as_bytes(), &mut engine).unwrap();
+    /// let mut shard = PaymentEngine::default();
+    /// process("type,client,tx,amount\ndeposit,1,2,2.5".as_bytes(), &mut shard).unwrap();
+    ///
+    /// engine.merge(shard).unwrap();
+    /// assert_eq!(engine.accounts().get(&1).unwrap().total().to_string(), "3.5000");
+    /// ```
+    pub fn merge<A2, T2>(
+        &mut self,
+        mut other: PaymentEngine<A2, T2>,
+    ) -> Result<(), TransactionError>
+    where
+        A2: AccountStore,
+        T2: TransactionStore,
+    {
+        // Accumulated deposits are carried over rather than applied, so one
+        // the account does not accept cannot fail the merge
+        let other_pending = std::mem::take(&mut other.pending_deposits);
+        other.flush()?;
+        let collision = other
+            .transactions
+            .iter()
+            .find(|tx| self.transactions.contains(tx.tx));
+        if let Some(tx) = collision {
+            return Err(TransactionError::MergeCollision(tx.tx));
+        }
+        // Merge all accounts before changing any, so that an overflow leaves
+        // this engine unchanged
+        let mut accounts = Vec::with_capacity(other.accounts.len());
+        for account in other.accounts.iter() {
+            let merged = match self.accounts.get(account.client()) {
+                Some(existing) => {
+                    let mut merged = existing.clone();
+                    merged.merge(account)?;
+                    merged
+                }
+                None => account.clone(),
+            };
+            accounts.push(merged);
+        }
+        let mut pending_deposits = Vec::with_capacity(other_pending.len());
+        for (client, amount) in other_pending {
+            let merged = match self.pending_deposits.get(&client) {
+                Some(pending) => pending.checked_add(amount)?,
+                None => amount,
+            };
+            pending_deposits.push((client, merged));
+        }
+        let mut next_reservation = self.next_reservation.max(other.next_reservation);
+        let mut reservations = other.reservations.into_values().collect::<Vec<_>>();
+        reservations.sort_unstable_by_key(|reservation| reservation.id);
+        for reservation in &mut reservations {
+            if self.reservations.contains_key(&reservation.id) {
+                reservation.id = ReservationId(next_reservation);
+                next_reservation += 1;
+            }
+        }
+
+        for account in accounts {
+            if let Some(storage) = &mut self.storage {
+                storage.store_account(&account)?;
+            }
+            if let Some(changes) = &mut self.changes {
+                changes.mark(account.client());
+            }
+            self.accounts.insert(account);
+        }
+        for tx in other.transactions.iter() {
+            if let Some(storage) = &mut self.storage {
+                storage.store_transaction(&tx)?;
+            }
+            if tx.disputed {
+                self.disputed.insert(tx.tx);
+            }
+            self.transactions.insert(tx.into_owned());
+        }
+        for key in other.idempotency_keys {
+            if let Some(storage) = &mut self.storage {
+                storage.store_idempotency_key(&key)?;
+            }
+            self.idempotency_keys.insert(key);
+        }
+        self.pending_deposits.extend(pending_deposits);
+        for reservation in reservations {
+            if let Some(storage) = &mut self.storage {
+                storage.store_reservation(&reservation)?;
+            }
+            self.reservations.insert(reservation.id, reservation);
+        }
+        self.next_reservation = next_reservation;
+        // Authorizations are transactions, so their ids cannot collide
+        self.authorizations.extend(other.authorizations);
+        self.partial_disputes.extend(other.partial_disputes);
+        self.disputes_opened.extend(other.disputes_opened);
+        self.held_periods.extend(other.held_periods);
+        self.represented.extend(other.represented);
+        self.metrics.merge(&other.metrics);
+        self.stats.merge(&other.stats);
+        Ok(())
+    }
+
+    /// Drops the stored transactions matching `filter`, which can no longer
+    /// be disputed or are past their retention, and returns how many were
+    /// dropped. Transactions under dispute are always kept.
+    ///
+    /// A dropped transaction is forgotten: disputes referencing it are
+    /// ignored like those of unknown transactions, it is left out of
+    /// [`PaymentEngine::history`], and its id is no longer detected as a
+    /// duplicate.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use randomlib::{process, PaymentEngine, PruneFilter};
+    ///
+    /// let mut engine = PaymentEngine::default();
+    /// let input = "type,client,tx,amount\ndeposit,1,1,2.0\ndispute,1,1,\nchargeback,1,1,";
+    /// process(input.as_bytes(), &mut engine).unwrap();
+    /// let filter = PruneFilter {
+    ///     charged_back: true,
+    ///     ..PruneFilter::default()
+    /// };
+    /// assert_eq!(engine.prune(&filter), Ok(1));
+    /// ```
+    pub fn prune(&mut self, filter: &PruneFilter) -> Result<usize, TransactionError> {
+        let cutoff = filter
+            .older_than
+            .and_then(|age| Some(self.now?.saturating_sub(age)));
+        let withdrawal_disputes = self.config.withdrawal_disputes;
+        let pruned = self
+            .transactions
+            .iter()
+            // Open authorizations are needed to capture or void them
+            .filter(|tx| !self.authorizations.contains(&tx.tx))
+            .filter(|tx| filter.matches(tx, cutoff, withdrawal_disputes))
+            .map(|tx| tx.tx)
+            .collect::<Vec<_>>();
+        for id in &pruned {
+            self.forget(*id)?;
+        }
+        Ok(pruned.len())
+    }
+
+    /// Describes the changes from the state of this engine to the state of
+    /// `other`, for example the same engine after processing more
+    /// transactions, see [`StateDiff`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use randomlib::{process, PaymentEngine};
+    ///
+    /// let mut engine = PaymentEngine::default();
+    /// process("type,client,tx,amount\ndeposit,1,1,1.0".as_bytes(), &mut engine).unwrap();
+    /// let before = PaymentEngine::from_snapshot(engine.snapshot());
+    /// process("type,client,tx,amount\ndeposit,2,2,2.0".as_bytes(), &mut engine).unwrap();
+    ///
+    /// let diff = before.diff(&engine);
+    /// assert_eq!(diff.added_accounts[0].client(), 2);
+    /// assert!(diff.changed_accounts.is_empty());
+    /// assert_eq!(diff.added_transactions[0].tx, 2);
+    /// ```
+    pub fn diff<A2, T2>(&self, other: &PaymentEngine<A2, T2>) -> StateDiff
+    where
+        A2: AccountStore,
+        T2: TransactionStore,
+    {
+        let mut diff = StateDiff::default();
+        for after in other.accounts.iter() {
+            match self.accounts.get(after.client()) {
+                None => diff.added_accounts.push(after.clone()),
+                Some(before) if before != after => {
+                    if after.locked() && !before.locked() {
+                        diff.locked.push(after.client());
+                    }
+                    diff.changed_accounts.push(AccountChange {
+                        before: before.clone(),
+                        after: after.clone(),
+                    });
+                }
+                Some(_) => (),
+            }
+        }
+        diff.added_transactions = other
+            .transactions
+            .iter()
+            .filter(|tx| !self.transactions.contains(tx.tx))
+            .map(Cow::into_owned)
+            .collect();
+
+        diff.added_accounts.sort_by_key(Account::client);
+        diff.changed_accounts.sort_by_key(AccountChange::client);
+        diff.locked.sort_unstable();
+        diff.added_transactions.sort_by_key(|tx| tx.tx);
+        diff
+    }
+
+    /// Captures the state of the run so far to continue it later with
+    /// [`PaymentEngine::resume`], for example from the checkpoint store of a
+    /// job framework.
+    ///
+    /// Only call it between transactions, e.g. after
+    /// [`RunOptions::cancel`](crate::RunOptions::cancel) stopped a run.
+    /// [`Checkpoint::position`] is left empty, as only the caller knows how
+    /// far it has read the input.
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            snapshot: self.snapshot(),
+            metrics: self.metrics.clone(),
+            stats: self.stats.clone(),
+            position: None,
+        }
+    }
+
+    /// Sets the custom attribute `name` of `client`, which can be used in
+    /// output templates as `attr.<name>`, see
+    /// [`TemplateColumn`](crate::TemplateColumn). Attributes are not part of
+    /// snapshots.
+    pub fn set_attribute(&mut self, client: u16, name: &str, value: &str) {
+        self.attributes
+            .entry(client)
+            .or_default()
+            .insert(name.to_string(), value.to_string());
+    }
+
+    /// Returns the custom attributes of `client`, see
+    /// [`PaymentEngine::set_attribute`].
+    pub fn attributes(&self, client: u16) -> Option<&BTreeMap<String, String>> {
+        self.attributes.get(&client)
+    }
+
+    /// Reads custom attributes from CSV with the columns `client`, `name` and
+    /// `value`, see [`PaymentEngine::set_attribute`].
+    pub fn read_attributes<R: io::Read>(&mut self, reader: R) -> Result<(), Box<dyn Error>> {
+        #[derive(Deserialize)]
+        struct Row {
+            client: u16,
+            name: String,
+            value: String,
+        }
+
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(reader);
+        for row in reader.deserialize() {
+            let row: Row = row?;
+            self.set_attribute(row.client, &row.name, &row.value);
+        }
+        Ok(())
+    }
+
+    /// Puts the account of `client` in `tier`, creating the account if
+    /// needed so that the [`TierRules`] apply from its first transaction.
+    /// The tier is kept in snapshots.
+    pub fn set_tier(&mut self, client: u16, tier: &str) -> Result<(), TransactionError> {
+        let account = self.accounts.get_or_create(client);
+        account.set_tier(tier.to_string());
+        if let Some(changes) = &mut self.changes {
+            changes.mark(client);
+        }
+        if let Some(storage) = &mut self.storage {
+            storage.store_account(account)?;
+        }
+        Ok(())
+    }
+
+    /// Associates `metadata`, such as a name or KYC status, with the
+    /// account of `client`. It is kept in snapshots and written with the
+    /// account when [`OutputOptions::metadata`](crate::OutputOptions::metadata)
+    /// is set. Fails with [`TransactionError::AccountNotFound`] if the client
+    /// has no account.
+    pub fn set_metadata(
+        &mut self,
+        client: u16,
+        metadata: ClientMetadata,
+    ) -> Result<(), TransactionError> {
+        let account = self
+            .accounts
+            .get_mut(client)
+            .ok_or(TransactionError::AccountNotFound(client))?;
+        account.set_metadata(metadata);
+        if let Some(changes) = &mut self.changes {
+            changes.mark(client);
+        }
+        if let Some(storage) = &mut self.storage {
+            storage.store_account(account)?;
+        }
+        Ok(())
+    }
+
+    /// Reads client metadata from CSV with the columns `client`, `name`,
+    /// `tier` and `kyc_status`, any of the latter may be empty, see
+    /// [`PaymentEngine::set_metadata`] and [`PaymentEngine::set_tier`].
+    /// Clients without an account are skipped.
+    pub fn read_metadata<R: io::Read>(&mut self, reader: R) -> Result<(), Box<dyn Error>> {
+        #[derive(Deserialize)]
+        struct Row {
+            client: u16,
+            name: Option<String>,
+            tier: Option<String>,
+            kyc_status: Option<String>,
+        }
+
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(reader);
+        for row in reader.deserialize() {
+            let row: Row = row?;
+            let metadata = ClientMetadata {
+                name: row.name,
+                kyc_status: row.kyc_status,
+            };
+            match self.set_metadata(row.client, metadata) {
+                Err(TransactionError::AccountNotFound(_)) => continue,
+                result => result?,
+            }
+            if let Some(tier) = row.tier {
+                self.set_tier(row.client, &tier)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// A unique id of the run processed by this engine, randomly generated
+    /// unless set with [`PaymentEngineBuilder::run_id`] or
+    /// [`RunOptions::run_id`](crate::RunOptions::run_id). It is included in
+    /// snapshots and reports so that the artifacts of a run can be correlated.
+    pub fn run_id(&self) -> Uuid {
+        self.run_id
+    }
+
+    /// Takes a [`Snapshot`] of the current state, including any accumulated
+    /// deposits that have not been flushed yet. Those the account does not
+    /// accept are kept in [`Snapshot::pending_deposits`].
+    pub fn snapshot(&self) -> Snapshot {
+        let mut accounts = Vec::with_capacity(self.accounts.len());
+        let mut pending_deposits = BTreeMap::new();
+        for account in self.accounts.iter() {
+            let mut account = account.clone();
+            if let Some(&pending) = self.pending_deposits.get(&account.client()) {
+                if account
+                    .transaction(&TransactionVariant::Deposit, pending, &self.config)
+                    .is_err()
+                {
+                    pending_deposits.insert(account.client(), pending);
+                }
+            }
+            accounts.push(account);
+        }
+        let transactions = self
+            .transactions
+            .iter()
+            .map(|tx| tx.as_ref().into())
+            .collect();
+        let mut idempotency_keys = self.idempotency_keys.iter().cloned().collect::<Vec<_>>();
+        idempotency_keys.sort();
+        Snapshot {
+            run_id: Some(self.run_id),
+            accounts,
+            transactions,
+            reservations: self.reservations.values().cloned().collect(),
+            pending_deposits,
+            authorizations: self.authorizations.iter().copied().collect(),
+            schedules: self.schedules.clone(),
+            partial_disputes: self
+                .partial_disputes
+                .iter()
+                .map(|(tx, amount)| (*tx, *amount))
+                .collect(),
+            disputes_opened: self
+                .disputes_opened
+                .iter()
+                .map(|(tx, opened)| (*tx, *opened))
+                .collect(),
+            held_periods: self
+                .held_periods
+                .iter()
+                .map(|(tx, periods)| (*tx, *periods))
+                .collect(),
+            represented: self.represented.iter().copied().collect(),
+            queued: self
+                .queued
+                .iter()
+                .map(|(effective, tx)| SnapshotTransaction {
+                    timestamp: Some(effective),
+                    ..tx.into()
+                })
+                .collect(),
+            idempotency_keys,
+            withdrawn: self
+                .accounts
+                .iter()
+                .filter(|account| !account.withdrawn().is_zero())
+                .map(|account| (account.client(), account.withdrawn()))
+                .collect(),
+            frozen: self
+                .accounts
+                .iter()
+                .filter(|account| account.frozen())
+                .map(Account::client)
+                .collect(),
+            tiers: self
+                .accounts
+                .iter()
+                .filter_map(|account| Some((account.client(), account.tier()?.to_string())))
+                .collect(),
+            metadata: self
+                .accounts
+                .iter()
+                .filter_map(|account| Some((account.client(), account.metadata()?.clone())))
+                .collect(),
+        }
+    }
+
+    /// Returns the stored transaction with id `tx` in its current state, e.g.
+    /// to check whether it is disputed before submitting a resolve.
+    ///
+    /// Disputes, resolves and chargebacks are not stored but reflected in the
+    /// flags of the transaction they reference. Transactions that were pruned
+    /// or evicted are not found. Owned if the store keeps `tx` out of memory,
+    /// see [`TransactionStore::get`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use randomlib::{process, Amount, PaymentEngine};
+    ///
+    /// let mut engine = PaymentEngine::default();
+    /// let input = "type,client,tx,amount\ndeposit,1,1,5.0\ndispute,1,1,";
+    /// process(input.as_bytes(), &mut engine).unwrap();
+    /// let tx = engine.transaction(1).unwrap();
+    /// assert_eq!(tx.amount, Some(Amount::new(5, 0).unwrap()));
+    /// assert!(tx.disputed);
+    /// assert!(engine.transaction(2).is_none());
+    /// ```
+    pub fn transaction(&self, tx: u32) -> Option<Cow<'_, Transaction>> {
+        self.transactions.get(tx)
+    }
+
+    /// Returns the transactions under dispute, ordered by id, e.g. to show
+    /// the open disputes on a dashboard. Looked up by an index instead of
+    /// going through every transaction.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use randomlib::{process, PaymentEngine};
+    ///
+    /// let mut engine = PaymentEngine::default();
+    /// let input = "type,client,tx,amount\ndeposit,1,1,5.0\ndeposit,1,2,5.0\n\
+    ///     dispute,1,2,\ndispute,1,1,\nresolve,1,1,";
+    /// process(input.as_bytes(), &mut engine).unwrap();
+    /// let disputed = engine.disputed().map(|tx| tx.tx).collect::<Vec<_>>();
+    /// assert_eq!(disputed, vec![2]);
+    /// ```
+    pub fn disputed(&self) -> impl Iterator<Item = Cow<'_, Transaction>> + '_ {
+        self.disputed
+            .iter()
+            .filter_map(move |tx| self.transactions.get(*tx))
+    }
+
+    /// Returns the deposits and withdrawals of `client`, oldest first, in
+    /// their current state. Disputes, resolves and chargebacks are reflected
+    /// in the flags of the transaction they reference.
+    ///
+    /// Always empty unless the engine was created with
+    /// [`PaymentEngine::with_history`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use randomlib::{Amount, PaymentEngine, Transaction, TransactionVariant};
+    ///
+    /// let mut engine = PaymentEngine::with_history();
+    /// for (tx, variant) in [(1, TransactionVariant::Deposit), (2, TransactionVariant::Withdrawal)] {
+    ///     let tx = Transaction {
+    ///         tx,
+    ///         amount: Some(Amount::new(1, 0).unwrap()),
+    ///         client: 1,
+    ///         disputed: false,
+    ///         variant,
+    ///         chargeback: false,
+    ///         destination: None,
+    ///         currency: None,
+    ///         to_currency: None,
+    ///         rate: None,
+    ///         timestamp: None,
+    ///     };
+    ///     engine.insert(tx).unwrap();
+    /// }
+    /// let history = engine.history(1).map(|tx| tx.tx).collect::<Vec<_>>();
+    /// assert_eq!(history, vec![1, 2]);
+    /// ```
+    pub fn history(&self, client: u16) -> impl Iterator<Item = Cow<'_, Transaction>> + '_ {
+        self.history
+            .as_ref()
+            .and_then(|history| history.get(&client))
+            .into_iter()
+            .flatten()
+            .filter_map(move |tx| self.transactions.get(*tx))
+    }
+
+    /// Returns the balance of the funds without a currency of `client` as of
+    /// `timestamp`, in seconds since the Unix epoch, by the `timestamp`
+    /// column of the input.
+    ///
+    /// `None` unless the engine was built with
+    /// [`PaymentEngineBuilder::timeline`] and the balance of the client changed
+    /// by then. Deposits accumulated for hot clients are recorded once they
+    /// are applied to the account by a later change.
+    pub fn balance_at(&self, client: u16, timestamp: u64) -> Option<Balance> {
+        self.timeline.as_ref()?.at(client, timestamp)
+    }
+
+    /// Returns the number and sum of the deposits and withdrawals per day,
+    /// by the `timestamp` column of the input, oldest first. Transactions
+    /// without a timestamp or with a currency are not included.
+    pub fn daily_volumes(&self) -> Vec<DailyVolume> {
+        timeline::daily_volumes(self.transactions.iter())
+    }
+
+    /// Returns the inflow and outflow of every client per month from `from`
+    /// to `to`, both included, by the `timestamp` column of the input,
+    /// ordered by client and month. Months without transactions are left out.
+    ///
+    /// Amounts are summed up as they were applied by the engine. Transactions
+    /// without a timestamp or with a currency, and charged back transactions,
+    /// are not included. Always empty unless the engine was created with
+    /// [`PaymentEngine::with_history`].
+    pub fn monthly_flows(&self, from: Month, to: Month) -> Vec<MonthlyFlow> {
+        let mut clients = self
+            .history
+            .iter()
+            .flat_map(HashMap::keys)
+            .copied()
+            .collect::<Vec<_>>();
+        clients.sort_unstable();
+        clients
+            .into_iter()
+            .flat_map(|client| timeline::monthly_flows(client, self.history(client), from, to))
+            .collect()
+    }
+
+    /// Returns the number and sum of the disputes by outcome, grouped by the
+    /// month they were opened in and by the value of the `segment` attribute
+    /// of their client, see [`PaymentEngine::set_attribute`].
+    ///
+    /// Disputes are grouped by segment only if `segment` is given, and only
+    /// disputes of transactions without a currency are included. Always
+    /// empty unless the engine was built with
+    /// [`PaymentEngineBuilder::dispute_log`].
+    pub fn dispute_outcomes(&self, segment: Option<&str>) -> Vec<DisputeOutcomes> {
+        let log = match &self.disputes {
+            Some(log) => log,
+            None => return Vec::new(),
+        };
+        log.outcomes(|client| {
+            segment
+                .and_then(|name| self.attributes(client)?.get(name))
+                .cloned()
+                .unwrap_or_default()
+        })
+    }
+
+    pub fn config(&self) -> &EngineConfig {
+        &self.config
+    }
+
+    /// Returns the number and sum of the deposits, withdrawals, disputes,
+    /// resolves and chargebacks accepted so far.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use randomlib::{process, PaymentEngine};
+    ///
+    /// let mut engine = PaymentEngine::default();
+    /// let input = "type,client,tx,amount\ndeposit,1,1,5.0\ndeposit,1,2,3.0\ndispute,1,2,";
+    /// process(input.as_bytes(), &mut engine).unwrap();
+    /// let stats = engine.stats();
+    /// assert_eq!(stats.deposits.count, 2);
+    /// assert_eq!(stats.deposits.total.to_string(), "8.0");
+    /// assert_eq!(stats.disputes.total.to_string(), "3.0");
+    /// ```
+    pub fn stats(&self) -> &EngineStats {
+        &self.stats
+    }
+
+    /// The amount `tx` moves or disputes for [`EngineStats`], unless it is
+    /// in a currency.
+    fn stats_amount(&self, tx: &Transaction) -> Option<Decimal> {
+        let amount = match tx.variant {
+            TransactionVariant::Deposit | TransactionVariant::Withdrawal => {
+                tx.amount.filter(|_| tx.currency.is_none())
+            }
+            TransactionVariant::Dispute
+            | TransactionVariant::Resolve
+            | TransactionVariant::Chargeback => {
+                let disputed = self.transactions.get(tx.tx)?;
+                if disputed.currency.is_some() {
+                    return None;
+                }
+                match tx.variant {
+                    TransactionVariant::Dispute => tx.amount,
+                    _ => self.partial_disputes.get(&tx.tx).copied(),
+                }
+                .or(disputed.amount)
+            }
+            _ => None,
+        };
+        amount.map(Decimal::from)
+    }
+
+    /// Returns the metrics collected so far.
+    pub fn metrics(&self) -> &RunMetrics {
+        &self.metrics
+    }
+
+    pub(crate) fn metrics_mut(&mut self) -> &mut RunMetrics {
+        &mut self.metrics
+    }
+
+    /// Returns all accounts.
+    ///
+    /// Deposits accumulated for hot clients are not reflected until
+    /// [`PaymentEngine::flush`] is called.
+    pub fn accounts(&self) -> &A {
         &self.accounts
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::amount::Amount;
+    /// Returns the accounts locked by chargebacks, ordered by client id, e.g.
+    /// to suspend those clients downstream.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use randomlib::{process, PaymentEngine};
+    ///
+    /// let mut engine = PaymentEngine::default();
+    /// let input = "type,client,tx,amount\ndeposit,2,1,5.0\ndeposit,1,2,5.0\n\
+    ///     dispute,2,1,\nchargeback,2,1,";
+    /// process(input.as_bytes(), &mut engine).unwrap();
+    /// let locked = engine.locked_accounts().map(|account| account.client()).collect::<Vec<_>>();
+    /// assert_eq!(locked, vec![2]);
+    /// ```
+    pub fn locked_accounts(&self) -> impl Iterator<Item = &Account> + '_ {
+        let mut locked = self
+            .accounts
+            .iter()
+            .filter(|account| account.locked())
+            .collect::<Vec<_>>();
+        locked.sort_unstable_by_key(|account| account.client());
+        locked.into_iter()
+    }
+
+    /// Returns the account of `client`, if it has one.
+    ///
+    /// Like [`PaymentEngine::accounts`], deposits accumulated for hot clients
+    /// are not reflected until [`PaymentEngine::flush`] is called.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use randomlib::{process, Amount, PaymentEngine};
+    ///
+    /// let mut engine = PaymentEngine::default();
+    /// process("type,client,tx,amount\ndeposit,1,1,5.0".as_bytes(), &mut engine).unwrap();
+    /// assert_eq!(engine.account(1).unwrap().total(), Amount::new(5, 0).unwrap());
+    /// assert!(engine.account(2).is_none());
+    /// ```
+    pub fn account(&self, client: u16) -> Option<&Account> {
+        self.accounts.get(client)
+    }
+
+    /// Iterates over all accounts, ordered by client id if `sorted` and
+    /// otherwise in the order of the [`AccountStore`], which is arbitrary for
+    /// a [`HashMap`]. Accounts in an [`AccountStore::is_ordered`] store, like
+    /// a [`BTreeMap`], are never sorted again.
+    ///
+    /// Accounts are borrowed from the store one at a time. Sorting only
+    /// collects references to them first.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use randomlib::{process, Account, PaymentEngine};
+    ///
+    /// let mut engine = PaymentEngine::default();
+    /// process("type,client,tx,amount\ndeposit,2,1,1.0\ndeposit,1,2,1.0".as_bytes(), &mut engine)
+    ///     .unwrap();
+    /// let clients = engine.accounts_iter(true).map(Account::client).collect::<Vec<_>>();
+    /// assert_eq!(clients, vec![1, 2]);
+    /// ```
+    pub fn accounts_iter(&self, sorted: bool) -> Box<dyn Iterator<Item = &Account> + '_> {
+        if !sorted || self.accounts.is_ordered() {
+            return self.accounts.iter();
+        }
+        let mut accounts = self.accounts.iter().collect::<Vec<_>>();
+        accounts.sort_unstable_by_key(|account| account.client());
+        Box::new(accounts.into_iter())
+    }
+}
+
+/// The code of the currency of `tx`, if any.
+fn currency_code(tx: &Transaction) -> Option<&str> {
+    tx.currency.as_ref().map(Currency::code)
+}
+
+/// Sets or removes `key` of `map`, as it was before a transaction.
+fn restore<K: std::hash::Hash + Eq, V>(map: &mut HashMap<K, V>, key: K, value: Option<V>) {
+    match value {
+        Some(value) => map.insert(key, value),
+        None => map.remove(&key),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        amount::Amount, process_with_policy, DisputeExpiry, ExpiryOutcome, HeldFundsConfig,
+        HeldFundsKind, ProcessingPolicy, Retention, TierRules,
+    };
+
+    #[test]
+    fn simple_deposit() {
+        let mut engine = PaymentEngine::default();
+
+        let amount = Amount::new(22, 1).unwrap();
+        let client = 1;
+        let deposit = Transaction {
+            tx: 1,
+            amount: Some(amount),
+            client,
+            disputed: false,
+            variant: TransactionVariant::Deposit,
+            chargeback: false,
+            destination: None,
+            currency: None,
+            to_currency: None,
+            rate: None,
+            timestamp: None,
+        };
+        assert!(engine.insert(deposit).is_ok());
+        assert_eq!(engine.accounts.len(), 1);
+        assert_eq!(engine.transactions.len(), 1);
+        // Check account
+        let account = engine.accounts.get(&client).unwrap();
+        assert_eq!(account.available(), amount);
+        assert_eq!(account.total(), amount);
+        assert_eq!(account.held(), Amount::zero());
+        // Check transaction
+        let tx = engine.transactions.get(&1).unwrap();
+        assert_eq!(tx.amount, Some(amount));
+        assert_eq!(tx.client, client);
+    }
+
+    #[test]
+    fn simple_withdrawal() {
+        let mut engine = PaymentEngine::default();
+
+        let amount = Amount::new(22, 1).unwrap();
+        let client = 1;
+        let deposit = Transaction {
+            tx: 1,
+            amount: Some(amount),
+            client,
+            disputed: false,
+            variant: TransactionVariant::Deposit,
+            chargeback: false,
+            destination: None,
+            currency: None,
+            to_currency: None,
+            rate: None,
+            timestamp: None,
+        };
+        assert!(engine.insert(deposit).is_ok());
+
+        let withdrawal = Transaction {
+            tx: 2,
+            amount: Some(amount),
+            client,
+            disputed: false,
+            variant: TransactionVariant::Withdrawal,
+            chargeback: false,
+            destination: None,
+            currency: None,
+            to_currency: None,
+            rate: None,
+            timestamp: None,
+        };
+        assert!(engine.insert(withdrawal).is_ok());
+
+        assert_eq!(engine.accounts.len(), 1);
+        assert_eq!(engine.transactions.len(), 2);
+        // Check account
+        let account = engine.accounts.get(&client).unwrap();
+        assert_eq!(account.available(), Amount::zero());
+        assert_eq!(account.total(), Amount::zero());
+        assert_eq!(account.held(), Amount::zero());
+    }
+
+    #[test]
+    fn reject_too_large_withdrawal() {
+        let mut engine = PaymentEngine::default();
+
+        let mut amount = Amount::new(22, 1).unwrap();
+        let client = 1;
+        let deposit = Transaction {
+            tx: 1,
+            amount: Some(amount),
+            client,
+            disputed: false,
+            variant: TransactionVariant::Deposit,
+            chargeback: false,
+            destination: None,
+            currency: None,
+            to_currency: None,
+            rate: None,
+            timestamp: None,
+        };
+        assert!(engine.insert(deposit).is_ok());
+
+        amount += Amount::new(1, 1).unwrap();
+
+        let withdrawal = Transaction {
+            tx: 2,
+            // Trying to withdraw an amount larger than the amount deposited
+            amount: Some(amount),
+            client,
+            disputed: false,
+            variant: TransactionVariant::Withdrawal,
+            chargeback: false,
+            destination: None,
+            currency: None,
+            to_currency: None,
+            rate: None,
+            timestamp: None,
+        };
+        assert_eq!(
+            engine.insert(withdrawal).unwrap_err(),
+            TransactionError::InsufficientFunds {
+                client,
+                available: Amount::new(22, 1).unwrap(),
+                amount_attempted: Amount::new(23, 1).unwrap()
+            }
+        );
+    }
+
+    #[test]
+    fn reject_transaction_overwrite() {
+        let mut engine = PaymentEngine::default();
+
+        let tx = 1;
+        let client = 1;
+        let deposit = Transaction {
+            tx,
+            amount: Some(Amount::zero()),
+            client,
+            disputed: false,
+            variant: TransactionVariant::Deposit,
+            chargeback: false,
+            destination: None,
+            currency: None,
+            to_currency: None,
+            rate: None,
+            timestamp: None,
+        };
+        assert!(engine.insert(deposit).is_ok());
+        let deposit = Transaction {
+            // Trying to use the same `tx` as in the previous transaction
+            tx,
+            amount: Some(Amount::zero()),
+            client,
+            disputed: false,
+            variant: TransactionVariant::Deposit,
+            chargeback: false,
+            destination: None,
+            currency: None,
+            to_currency: None,
+            rate: None,
+            timestamp: None,
+        };
+        assert_eq!(
+            engine.insert(deposit).unwrap_err(),
+            TransactionError::TransactionAlreadyExist
+        );
+    }
+
+    #[test]
+    fn chargeback() {
+        let mut engine = PaymentEngine::default();
+
+        let client = 1;
+
+        // Deposit
+        let deposit = Transaction {
+            tx: 1,
+            amount: Some(Amount::new(10, 0).unwrap()),
+            client,
+            disputed: false,
+            variant: TransactionVariant::Deposit,
+            chargeback: false,
+            destination: None,
+            currency: None,
+            to_currency: None,
+            rate: None,
+            timestamp: None,
+        };
+        assert!(engine.insert(deposit).is_ok());
+
+        let dispute = Transaction {
+            tx: 1,
+            amount: None,
+            client,
+            disputed: false,
+            variant: TransactionVariant::Dispute,
+            chargeback: false,
+            destination: None,
+            currency: None,
+            to_currency: None,
+            rate: None,
+            timestamp: None,
+        };
+        assert!(engine.insert(dispute).is_ok());
+        let chargeback = Transaction {
+            tx: 1,
+            amount: None,
+            client,
+            disputed: false,
+            variant: TransactionVariant::Chargeback,
+            chargeback: false,
+            destination: None,
+            currency: None,
+            to_currency: None,
+            rate: None,
+            timestamp: None,
+        };
+        assert!(engine.insert(chargeback).is_ok());
+        let account_after_chargeback = engine.accounts.get(&client).unwrap();
+
+        // Check that deposit has been reversed and that everything is back to zero
+        assert_eq!(account_after_chargeback.available(), Amount::zero());
+        assert_eq!(account_after_chargeback.total(), Amount::zero());
+        assert_eq!(account_after_chargeback.held(), Amount::zero());
+        // Chargeback should lock account
+        assert!(account_after_chargeback.locked());
+    }
+
+    #[test]
+    fn resolved_dispute() {
+        let mut engine = PaymentEngine::default();
+
+        let client = 1;
+
+        // Deposit
+        let deposit = Transaction {
+            tx: 1,
+            amount: Some(Amount::new(10, 0).unwrap()),
+            client,
+            disputed: false,
+            variant: TransactionVariant::Deposit,
+            chargeback: false,
+            destination: None,
+            currency: None,
+            to_currency: None,
+            rate: None,
+            timestamp: None,
+        };
+        assert!(engine.insert(deposit).is_ok());
+
+        // Backup state of account at this point to compare after dispute is resolved
+        let accounts = engine.accounts.clone();
+        let account_before_dispute = accounts.get(&client).unwrap();
+
+        let dispute = Transaction {
+            tx: 1,
+            amount: None,
+            client,
+            disputed: false,
+            variant: TransactionVariant::Dispute,
+            chargeback: false,
+            destination: None,
+            currency: None,
+            to_currency: None,
+            rate: None,
+            timestamp: None,
+        };
+        assert!(engine.insert(dispute).is_ok());
+        let chargeback = Transaction {
+            tx: 1,
+            amount: None,
+            client,
+            disputed: false,
+            variant: TransactionVariant::Resolve,
+            chargeback: false,
+            destination: None,
+            currency: None,
+            to_currency: None,
+            rate: None,
+            timestamp: None,
+        };
+        assert!(engine.insert(chargeback).is_ok());
+        let account_after_resolve = engine.accounts.get(&client).unwrap();
+
+        // Check that deposit has been reversed and that everything is back to zero
+        assert_eq!(
+            account_after_resolve.available(),
+            account_before_dispute.available()
+        );
+        assert_eq!(
+            account_after_resolve.total(),
+            account_before_dispute.total()
+        );
+        assert_eq!(account_after_resolve.held(), account_before_dispute.held());
+        // Resolve should NOT lock account
+        assert!(!account_after_resolve.locked());
+    }
+
+    #[test]
+    fn reject_double_dispute() {
+        let mut engine = PaymentEngine::default();
+
+        let client = 1;
+
+        // Deposit
+        let deposit = Transaction {
+            tx: 1,
+            amount: Some(Amount::new(10, 0).unwrap()),
+            client,
+            disputed: false,
+            variant: TransactionVariant::Deposit,
+            chargeback: false,
+            destination: None,
+            currency: None,
+            to_currency: None,
+            rate: None,
+            timestamp: None,
+        };
+        assert!(engine.insert(deposit).is_ok());
+
+        let dispute = Transaction {
+            tx: 1,
+            amount: None,
+            client,
+            disputed: false,
+            variant: TransactionVariant::Dispute,
+            chargeback: false,
+            destination: None,
+            currency: None,
+            to_currency: None,
+            rate: None,
+            timestamp: None,
+        };
+        assert!(engine.insert(dispute).is_ok());
+
+        // Trying to dispute again which should fail
+        let dispute = Transaction {
+            tx: 1,
+            amount: None,
+            client,
+            disputed: false,
+            variant: TransactionVariant::Dispute,
+            chargeback: false,
+            destination: None,
+            currency: None,
+            to_currency: None,
+            rate: None,
+            timestamp: None,
+        };
+        assert!(engine.insert(dispute).is_err());
+    }
+
+    #[test]
+    fn reject_unauthenticated_dispute() {
+        let mut engine = PaymentEngine::default();
+
+        let client = 1;
+        let mallicous_client = 2;
+
+        // Deposit
+        let deposit = Transaction {
+            tx: 1,
+            amount: Some(Amount::new(10, 0).unwrap()),
+            client,
+            disputed: false,
+            variant: TransactionVariant::Deposit,
+            chargeback: false,
+            destination: None,
+            currency: None,
+            to_currency: None,
+            rate: None,
+            timestamp: None,
+        };
+        assert!(engine.insert(deposit).is_ok());
+
+        // mallicous_client tries to dispute transaction done by another client
+        let dispute = Transaction {
+            tx: 1,
+            amount: None,
+            client: mallicous_client,
+            disputed: false,
+            variant: TransactionVariant::Dispute,
+            chargeback: false,
+            destination: None,
+            currency: None,
+            to_currency: None,
+            rate: None,
+            timestamp: None,
+        };
+        assert!(engine.insert(dispute).is_err());
+    }
 
     #[test]
-    fn simple_deposit() {
-        let mut engine = PaymentEngine::default();
+    fn accumulated_deposits_are_applied_before_withdrawal() {
+        let mut engine = PaymentEngine::with_hot_accounts(HotAccountConfig {
+            min_share: 0.5,
+            min_transactions: 1,
+            accumulate_deposits: true,
+        });
 
-        let amount = Amount::new(22, 1).unwrap();
         let client = 1;
-        let deposit = Transaction {
-            tx: 1,
-            amount: Some(amount),
+        for tx in 1..=3 {
+            let deposit = Transaction {
+                tx,
+                amount: Some(Amount::new(10, 0).unwrap()),
+                client,
+                disputed: false,
+                variant: TransactionVariant::Deposit,
+                chargeback: false,
+                destination: None,
+                currency: None,
+                to_currency: None,
+                rate: None,
+                timestamp: None,
+            };
+            assert!(engine.insert(deposit).is_ok());
+        }
+        // The deposits are accumulated and not yet applied
+        assert_eq!(
+            engine.accounts.get(&client).unwrap().total(),
+            Amount::zero()
+        );
+        assert_eq!(engine.hot_accounts()[0].client, client);
+
+        let withdrawal = Transaction {
+            tx: 4,
+            amount: Some(Amount::new(25, 0).unwrap()),
             client,
             disputed: false,
-            variant: TransactionVariant::Deposit,
+            variant: TransactionVariant::Withdrawal,
             chargeback: false,
+            destination: None,
+            currency: None,
+            to_currency: None,
+            rate: None,
+            timestamp: None,
         };
-        assert!(engine.insert(deposit).is_ok());
-        assert_eq!(engine.accounts.len(), 1);
-        assert_eq!(engine.transactions.len(), 1);
-        // Check account
+        assert!(engine.insert(withdrawal).is_ok());
         let account = engine.accounts.get(&client).unwrap();
-        assert_eq!(account.available(), amount);
-        assert_eq!(account.total(), amount);
-        assert_eq!(account.held(), Amount::zero());
-        // Check transaction
-        let tx = engine.transactions.get(&1).unwrap();
-        assert_eq!(tx.amount, Some(amount));
-        assert_eq!(tx.client, client);
+        assert_eq!(account.available(), Amount::new(5, 0).unwrap());
+        assert_eq!(account.total(), Amount::new(5, 0).unwrap());
     }
 
     #[test]
-    fn simple_withdrawal() {
+    fn freezing_applies_accumulated_deposits() {
+        let mut engine = PaymentEngine::with_hot_accounts(HotAccountConfig {
+            min_share: 0.1,
+            min_transactions: 1,
+            accumulate_deposits: true,
+        });
+        let input = "type,client,tx,amount\n\
+            deposit,1,1,5.0\ndeposit,2,2,7.0\ndeposit,1,3,5.0\ndeposit,2,4,1.0\nfreeze,1,0,\n";
+        let errors =
+            process_with_policy(input.as_bytes(), &mut engine, ProcessingPolicy::SkipWithLog)
+                .unwrap();
+        assert!(errors.is_empty());
+        let account = engine.accounts.get(&1).unwrap();
+        assert!(account.frozen());
+        assert_eq!(account.total().to_string(), "10.0000");
+        assert_eq!(
+            engine.accounts.get(&2).unwrap().total().to_string(),
+            "8.0000"
+        );
+    }
+
+    #[test]
+    fn flush_keeps_accumulated_deposits_that_fail() {
+        let mut engine = PaymentEngine::with_hot_accounts(HotAccountConfig {
+            min_share: 0.1,
+            min_transactions: 1,
+            accumulate_deposits: true,
+        });
+        for (tx, client, amount) in [(1, 1, 5), (2, 2, 7), (3, 1, 5), (4, 2, 1)] {
+            let deposit = Transaction {
+                tx,
+                amount: Some(Amount::new(amount, 0).unwrap()),
+                client,
+                disputed: false,
+                variant: TransactionVariant::Deposit,
+                chargeback: false,
+                destination: None,
+                currency: None,
+                to_currency: None,
+                rate: None,
+                timestamp: None,
+            };
+            engine.insert(deposit).unwrap();
+        }
+        assert_eq!(engine.pending_deposits.len(), 2);
+        // The account stops accepting deposits while one is pending
+        engine.accounts.get_mut(&1).unwrap().set_frozen(true);
+
+        engine.flush().unwrap();
+        assert_eq!(
+            engine.accounts.get(&2).unwrap().total().to_string(),
+            "8.0000"
+        );
+        assert_eq!(engine.accounts.get(&1).unwrap().total(), Amount::zero());
+        assert_eq!(engine.pending_deposits[&1].to_string(), "10.0000");
+        assert_eq!(
+            engine.pending_deposits().into_iter().collect::<Vec<_>>(),
+            vec![(1, Amount::new(10, 0).unwrap())]
+        );
+
+        engine.accounts.get_mut(&1).unwrap().set_frozen(false);
+        engine.flush().unwrap();
+        assert_eq!(
+            engine.accounts.get(&1).unwrap().total().to_string(),
+            "10.0000"
+        );
+        assert!(engine.pending_deposits.is_empty());
+    }
+
+    #[test]
+    fn failed_operations_keep_accumulated_deposits() {
+        let mut engine = PaymentEngine::with_hot_accounts(HotAccountConfig {
+            min_share: 0.1,
+            min_transactions: 1,
+            accumulate_deposits: true,
+        });
+        for tx in 1..=2 {
+            let deposit = Transaction {
+                tx,
+                amount: Some(Amount::new(5, 0).unwrap()),
+                client: 1,
+                disputed: false,
+                variant: TransactionVariant::Deposit,
+                chargeback: false,
+                destination: None,
+                currency: None,
+                to_currency: None,
+                rate: None,
+                timestamp: None,
+            };
+            engine.insert(deposit).unwrap();
+        }
+        engine.accounts.get_mut(&1).unwrap().set_frozen(true);
+
+        assert_eq!(engine.close(1), Err(TransactionError::FrozenAccount));
+        assert_eq!(
+            engine.reserve(1, Amount::new(1, 0).unwrap()),
+            Err(TransactionError::FrozenAccount)
+        );
+        assert_eq!(engine.pending_deposits[&1], Amount::new(10, 0).unwrap());
+
+        engine.unfreeze(1).unwrap();
+        assert_eq!(engine.pending_deposits[&1], Amount::new(10, 0).unwrap());
+        engine.reserve(1, Amount::new(1, 0).unwrap()).unwrap();
+        assert!(engine.pending_deposits.is_empty());
+        assert_eq!(
+            engine.accounts.get(&1).unwrap().available(),
+            Amount::new(9, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn snapshots_keep_accumulated_deposits_that_fail() {
+        let mut engine = PaymentEngine::with_hot_accounts(HotAccountConfig {
+            min_share: 0.1,
+            min_transactions: 1,
+            accumulate_deposits: true,
+        });
+        for tx in 1..=2 {
+            let deposit = Transaction {
+                tx,
+                amount: Some(Amount::new(5, 0).unwrap()),
+                client: 1,
+                disputed: false,
+                variant: TransactionVariant::Deposit,
+                chargeback: false,
+                destination: None,
+                currency: None,
+                to_currency: None,
+                rate: None,
+                timestamp: None,
+            };
+            engine.insert(deposit).unwrap();
+        }
+        engine.accounts.get_mut(&1).unwrap().set_frozen(true);
+        assert!(matches!(
+            engine.corrections(&[Account::new(1)]),
+            Err(TransactionError::FrozenAccount)
+        ));
+
+        let snapshot = engine.snapshot();
+        assert_eq!(
+            snapshot.pending_deposits,
+            BTreeMap::from([(1, Amount::new(10, 0).unwrap())])
+        );
+        let mut restored = PaymentEngine::from_snapshot(snapshot);
+        restored.accounts.get_mut(&1).unwrap().set_frozen(false);
+        restored.flush().unwrap();
+        assert_eq!(
+            restored.accounts.get(&1).unwrap().total(),
+            Amount::new(10, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn history_reflects_disputes() {
+        let mut engine = PaymentEngine::with_history();
+
+        for (tx, client) in [(1, 1), (2, 2), (3, 1)] {
+            let deposit = Transaction {
+                tx,
+                amount: Some(Amount::new(10, 0).unwrap()),
+                client,
+                disputed: false,
+                variant: TransactionVariant::Deposit,
+                chargeback: false,
+                destination: None,
+                currency: None,
+                to_currency: None,
+                rate: None,
+                timestamp: None,
+            };
+            assert!(engine.insert(deposit).is_ok());
+        }
+        let dispute = Transaction {
+            tx: 3,
+            amount: None,
+            client: 1,
+            disputed: false,
+            variant: TransactionVariant::Dispute,
+            chargeback: false,
+            destination: None,
+            currency: None,
+            to_currency: None,
+            rate: None,
+            timestamp: None,
+        };
+        assert!(engine.insert(dispute).is_ok());
+
+        let history = engine.history(1).collect::<Vec<_>>();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].tx, 1);
+        assert!(!history[0].disputed);
+        assert_eq!(history[1].tx, 3);
+        assert!(history[1].disputed);
+        assert_eq!(engine.history(3).count(), 0);
+        assert_eq!(PaymentEngine::default().history(1).count(), 0);
+    }
+
+    #[derive(Debug, Default)]
+    struct Recorder(std::rc::Rc<std::cell::RefCell<Vec<String>>>);
+
+    impl EngineObserver for Recorder {
+        fn on_deposit(&mut self, _: &Account, tx: &Transaction) {
+            self.0.borrow_mut().push(format!("deposit {}", tx.tx));
+        }
+
+        fn on_chargeback(&mut self, account: &Account, tx: &Transaction) {
+            assert!(tx.chargeback);
+            assert!(account.locked());
+            self.0.borrow_mut().push(format!("chargeback {}", tx.tx));
+        }
+
+        fn on_account_locked(&mut self, account: &Account) {
+            self.0
+                .borrow_mut()
+                .push(format!("locked {}", account.client()));
+        }
+    }
+
+    #[test]
+    fn notifies_observers() {
+        let events = std::rc::Rc::default();
         let mut engine = PaymentEngine::default();
+        engine.add_observer(Box::new(Recorder(std::rc::Rc::clone(&events))));
 
-        let amount = Amount::new(22, 1).unwrap();
-        let client = 1;
+        for variant in [
+            TransactionVariant::Deposit,
+            TransactionVariant::Dispute,
+            TransactionVariant::Chargeback,
+            TransactionVariant::Deposit,
+        ] {
+            let tx = Transaction {
+                tx: 1,
+                amount: Some(Amount::new(10, 0).unwrap()),
+                client: 1,
+                disputed: false,
+                variant,
+                chargeback: false,
+                destination: None,
+                currency: None,
+                to_currency: None,
+                rate: None,
+                timestamp: None,
+            };
+            let _ = engine.insert(tx);
+        }
+
+        assert_eq!(
+            *events.borrow(),
+            vec!["deposit 1", "chargeback 1", "locked 1"]
+        );
+    }
+
+    #[test]
+    fn configured_engine() {
+        let mut engine = PaymentEngine::builder()
+            .withdrawal_disputes(WithdrawalDisputes::Reject)
+            .id_collision(IdCollision::Ignore)
+            .build();
+
+        for (tx, variant) in [
+            (1, TransactionVariant::Deposit),
+            (2, TransactionVariant::Withdrawal),
+            (1, TransactionVariant::Deposit),
+        ] {
+            let tx = Transaction {
+                tx,
+                amount: Some(Amount::new(10, 0).unwrap()),
+                client: 1,
+                disputed: false,
+                variant,
+                chargeback: false,
+                destination: None,
+                currency: None,
+                to_currency: None,
+                rate: None,
+                timestamp: None,
+            };
+            assert!(engine.insert(tx).is_ok());
+        }
+        assert_eq!(engine.accounts.get(&1).unwrap().total(), Amount::zero());
+
+        let dispute = Transaction {
+            tx: 2,
+            amount: None,
+            client: 1,
+            disputed: false,
+            variant: TransactionVariant::Dispute,
+            chargeback: false,
+            destination: None,
+            currency: None,
+            to_currency: None,
+            rate: None,
+            timestamp: None,
+        };
+        assert_eq!(engine.insert(dispute), Err(TransactionError::NotDisputable));
+    }
+
+    #[test]
+    fn unlock_after_chargeback() {
+        let mut engine = PaymentEngine::default();
+        assert_eq!(engine.unlock(1), Err(TransactionError::AccountNotFound(1)));
+
+        for (tx, variant) in [
+            (1, TransactionVariant::Deposit),
+            (1, TransactionVariant::Dispute),
+            (1, TransactionVariant::Chargeback),
+            (0, TransactionVariant::Unlock),
+            (2, TransactionVariant::Deposit),
+        ] {
+            let amount = match variant {
+                TransactionVariant::Deposit => Some(Amount::new(10, 0).unwrap()),
+                _ => None,
+            };
+            let tx = Transaction {
+                tx,
+                amount,
+                client: 1,
+                disputed: false,
+                variant,
+                chargeback: false,
+                destination: None,
+                currency: None,
+                to_currency: None,
+                rate: None,
+                timestamp: None,
+            };
+            assert!(engine.insert(tx).is_ok());
+        }
+        let account = engine.accounts.get(&1).unwrap();
+        assert!(!account.locked());
+        assert_eq!(account.total(), Amount::new(10, 0).unwrap());
+    }
+
+    #[test]
+    fn transfer_applies_to_both_accounts_or_neither() {
+        let mut engine = PaymentEngine::default();
+        let transaction = |tx, client, variant, destination| Transaction {
+            tx,
+            amount: Some(Amount::new(5, 0).unwrap()),
+            client,
+            disputed: false,
+            variant,
+            chargeback: false,
+            destination,
+            currency: None,
+            to_currency: None,
+            rate: None,
+            timestamp: None,
+        };
+        assert!(engine
+            .insert(transaction(1, 1, TransactionVariant::Deposit, None))
+            .is_ok());
+        assert!(engine
+            .insert(transaction(2, 1, TransactionVariant::Transfer, Some(2)))
+            .is_ok());
+        assert!(matches!(
+            engine.insert(transaction(3, 1, TransactionVariant::Transfer, Some(2))),
+            Err(TransactionError::InsufficientFunds { .. })
+        ));
+        assert_eq!(engine.accounts.get(&1).unwrap().total(), Amount::zero());
+        assert_eq!(
+            engine.accounts.get(&2).unwrap().total(),
+            Amount::new(5, 0).unwrap()
+        );
+
+        // A locked destination leaves the source untouched
+        engine
+            .accounts
+            .get_or_create(1)
+            .lock(crate::LockingBehavior::RejectAll);
+        assert_eq!(
+            engine.insert(transaction(4, 2, TransactionVariant::Transfer, Some(1))),
+            Err(TransactionError::LockedAccount)
+        );
+        assert_eq!(
+            engine.accounts.get(&2).unwrap().total(),
+            Amount::new(5, 0).unwrap()
+        );
+        assert_eq!(engine.metrics().transferred, Decimal::from(5));
+    }
+
+    #[test]
+    fn sweeps_dormant_accounts_into_pool() {
+        let mut engine = PaymentEngine::default();
+        let deposit = |tx, client| Transaction {
+            tx,
+            amount: Some(Amount::new(5, 0).unwrap()),
+            client,
+            disputed: false,
+            variant: TransactionVariant::Deposit,
+            chargeback: false,
+            destination: None,
+            currency: None,
+            to_currency: None,
+            rate: None,
+            timestamp: None,
+        };
+        let config = SweepConfig {
+            dormant_after: 100,
+            pool: 0,
+        };
+        // Nothing is dormant without timestamps
+        assert!(engine.insert(deposit(1, 1)).is_ok());
+        assert_eq!(engine.sweep(&config), Ok(Vec::new()));
+
+        engine.advance_time(1000).unwrap();
+        assert!(engine.insert(deposit(2, 2)).is_ok());
+        assert!(engine.insert(deposit(4, 4)).is_ok());
+        engine.freeze(4).unwrap();
+        engine.advance_time(1050).unwrap();
+        assert!(engine.insert(deposit(3, 3)).is_ok());
+        engine.advance_time(1120).unwrap();
+
+        let records = engine.sweep(&config).unwrap();
+        let swept = records.iter().map(|r| r.client).collect::<Vec<_>>();
+        assert_eq!(swept, vec![1, 2]);
+        assert!(records
+            .iter()
+            .all(|r| r.last_activity == 1000 && r.swept_at == 1120 && r.pool == 0));
+        assert_eq!(engine.accounts.get(&1).unwrap().total(), Amount::zero());
+        assert_eq!(
+            engine.accounts.get(&3).unwrap().total(),
+            Amount::new(5, 0).unwrap()
+        );
+        assert_eq!(
+            engine.accounts.get(&0).unwrap().available(),
+            Amount::new(10, 0).unwrap()
+        );
+        assert_eq!(engine.metrics().swept, Decimal::from(10));
+        assert_eq!(
+            engine.accounts.get(&4).unwrap().total(),
+            Amount::new(5, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn sweeps_nothing_when_a_withdrawal_is_refused() {
+        let limit = crate::WithdrawalLimit {
+            max_amount: Some(Amount::new(4, 0).unwrap()),
+            max_total: None,
+        };
+        let mut engine = PaymentEngine::builder()
+            .client_withdrawal_limit(2, limit)
+            .build();
+        let config = SweepConfig {
+            dormant_after: 100,
+            pool: 0,
+        };
+        engine.advance_time(1000).unwrap();
+        for client in 1..=2 {
+            let deposit = Transaction {
+                tx: u32::from(client),
+                amount: Some(Amount::new(5, 0).unwrap()),
+                client,
+                disputed: false,
+                variant: TransactionVariant::Deposit,
+                chargeback: false,
+                destination: None,
+                currency: None,
+                to_currency: None,
+                rate: None,
+                timestamp: None,
+            };
+            assert!(engine.insert(deposit).is_ok());
+        }
+        engine.advance_time(1200).unwrap();
+
+        assert!(matches!(
+            engine.sweep(&config),
+            Err(TransactionError::LimitExceeded { client: 2, .. })
+        ));
+        for client in 1..=2 {
+            assert_eq!(
+                engine.accounts.get(&client).unwrap().available(),
+                Amount::new(5, 0).unwrap()
+            );
+        }
+        assert_eq!(engine.accounts.get(&0).unwrap().total(), Amount::zero());
+        assert_eq!(engine.metrics().swept, Decimal::ZERO);
+    }
+
+    #[test]
+    fn converts_between_currencies() {
+        let mut engine = PaymentEngine::default();
+        let eur = Currency::iso("EUR");
         let deposit = Transaction {
             tx: 1,
-            amount: Some(amount),
-            client,
+            amount: Some(Amount::new(10, 0).unwrap()),
+            client: 1,
             disputed: false,
             variant: TransactionVariant::Deposit,
             chargeback: false,
+            destination: None,
+            currency: eur.clone(),
+            to_currency: None,
+            rate: None,
+            timestamp: None,
         };
         assert!(engine.insert(deposit).is_ok());
+        let convert = |tx, amount| Transaction {
+            tx,
+            amount: Some(Amount::new(amount, 0).unwrap()),
+            client: 1,
+            disputed: false,
+            variant: TransactionVariant::Convert,
+            chargeback: false,
+            destination: None,
+            currency: eur.clone(),
+            to_currency: Currency::iso("USD"),
+            rate: Some(Decimal::new(10825, 4)),
+            timestamp: None,
+        };
+        assert!(engine.insert(convert(2, 4)).is_ok());
+        assert!(matches!(
+            engine.insert(convert(3, 7)),
+            Err(TransactionError::InsufficientFunds { .. })
+        ));
 
-        let withdrawal = Transaction {
-            tx: 2,
-            amount: Some(amount),
-            client,
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(
+            account.balance(Some("EUR")).total,
+            Amount::new(6, 0).unwrap()
+        );
+        // Rounded down to cents
+        assert_eq!(
+            account.balance(Some("USD")).total,
+            Amount::new(433, 2).unwrap()
+        );
+        let dispute = Transaction {
+            amount: None,
+            currency: None,
+            to_currency: None,
+            rate: None,
+            timestamp: None,
+            variant: TransactionVariant::Dispute,
+            ..convert(2, 0)
+        };
+        assert_eq!(engine.insert(dispute), Err(TransactionError::NotDisputable));
+    }
+
+    #[test]
+    fn reports_dispute_outcomes() {
+        let mut engine = PaymentEngine::builder().dispute_log().build();
+        engine.set_attribute(2, "segment", "retail");
+        let input = "type,client,tx,amount,timestamp
+deposit,1,1,5.0,100
+deposit,2,2,3.0,100
+dispute,1,1,,200
+resolve,1,1,,260
+dispute,2,2,,300
+chargeback,2,2,,400";
+        crate::process(input.as_bytes(), &mut engine).unwrap();
+
+        let outcomes = engine.dispute_outcomes(None);
+        assert_eq!(outcomes.len(), 1);
+        assert_eq!(outcomes[0].disputes, 2);
+        assert_eq!(outcomes[0].resolved_amount, Decimal::from(5));
+        assert_eq!(outcomes[0].charged_back_amount, Decimal::from(3));
+        assert_eq!(outcomes[0].average_resolution_secs, Some(80));
+
+        let outcomes = engine.dispute_outcomes(Some("segment"));
+        let segments = outcomes
+            .iter()
+            .map(|o| o.segment.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(segments, vec!["", "retail"]);
+        assert_eq!(outcomes[1].charged_back, 1);
+    }
+
+    #[test]
+    fn records_balances_over_time() {
+        let mut engine = PaymentEngine::builder().timeline().build();
+        let input = "type,client,tx,amount,timestamp
+deposit,1,1,5.0,100
+deposit,1,2,3.0,200
+dispute,1,1,,300
+withdrawal,1,3,1.0,86500";
+        crate::process(input.as_bytes(), &mut engine).unwrap();
+
+        assert_eq!(engine.balance_at(1, 99), None);
+        let balance = engine.balance_at(1, 250).unwrap();
+        assert_eq!(balance.total, Amount::new(8, 0).unwrap());
+        assert_eq!(balance.held, Amount::zero());
+        let balance = engine.balance_at(1, 300).unwrap();
+        assert_eq!(balance.held, Amount::new(5, 0).unwrap());
+        assert_eq!(
+            engine.balance_at(1, 90000).unwrap().total,
+            Amount::new(7, 0).unwrap()
+        );
+
+        let volumes = engine.daily_volumes();
+        assert_eq!(volumes.len(), 2);
+        assert_eq!(volumes[0].deposited, Decimal::from(8));
+        assert_eq!(volumes[1].day, 86400);
+        assert_eq!(volumes[1].withdrawals, 1);
+    }
+
+    #[test]
+    fn fees_are_credited_to_fee_account() {
+        let mut engine = PaymentEngine::builder().fee_account(0).build();
+        let transaction = |tx, variant, amount| Transaction {
+            tx,
+            amount: Some(Amount::new(amount, 0).unwrap()),
+            client: 1,
             disputed: false,
-            variant: TransactionVariant::Withdrawal,
+            variant,
             chargeback: false,
+            destination: None,
+            currency: None,
+            to_currency: None,
+            rate: None,
+            timestamp: None,
         };
-        assert!(engine.insert(withdrawal).is_ok());
+        assert!(engine
+            .insert(transaction(1, TransactionVariant::Deposit, 5))
+            .is_ok());
+        assert!(engine
+            .insert(transaction(2, TransactionVariant::Fee, 2))
+            .is_ok());
+        assert!(matches!(
+            engine.insert(transaction(3, TransactionVariant::Fee, 4)),
+            Err(TransactionError::InsufficientFunds { .. })
+        ));
+        let dispute = Transaction {
+            amount: None,
+            ..transaction(2, TransactionVariant::Dispute, 0)
+        };
+        assert_eq!(engine.insert(dispute), Err(TransactionError::NotDisputable));
 
-        assert_eq!(engine.accounts.len(), 1);
-        assert_eq!(engine.transactions.len(), 2);
-        // Check account
-        let account = engine.accounts.get(&client).unwrap();
-        assert_eq!(account.available(), Amount::zero());
-        assert_eq!(account.total(), Amount::zero());
+        assert_eq!(
+            engine.accounts.get(&1).unwrap().available(),
+            Amount::new(3, 0).unwrap()
+        );
+        assert_eq!(
+            engine.accounts.get(&0).unwrap().available(),
+            Amount::new(2, 0).unwrap()
+        );
+        assert_eq!(engine.metrics().fees, Decimal::from(2));
+    }
+
+    #[test]
+    fn merges_shards() {
+        let mut engine = PaymentEngine::default();
+        let input = "type,client,tx,amount,currency\n\
+                     deposit,1,1,5.0,\n\
+                     deposit,2,2,1.0,EUR\n";
+        process(input.as_bytes(), &mut engine).unwrap();
+        let mut shard = PaymentEngine::default();
+        let input = "type,client,tx,amount,currency\n\
+                     deposit,1,3,2.0,\n\
+                     deposit,2,4,2.0,EUR\n\
+                     dispute,2,4,,\n\
+                     chargeback,2,4,,\n\
+                     deposit,3,5,1.0,\n";
+        process(input.as_bytes(), &mut shard).unwrap();
+
+        engine.merge(shard).unwrap();
+        assert_eq!(
+            engine.accounts.get(&1).unwrap().total(),
+            Amount::new(7, 0).unwrap()
+        );
+        let account = engine.accounts.get(&2).unwrap();
+        assert_eq!(
+            account.balance(Some("EUR")).total,
+            Amount::new(1, 0).unwrap()
+        );
+        assert!(account.locked());
+        assert!(engine.accounts.contains_key(&3));
+        assert_eq!(engine.transactions.len(), 5);
+        assert_eq!(engine.metrics().rows, 7);
+
+        let mut colliding = PaymentEngine::default();
+        process(
+            "type,client,tx,amount\ndeposit,4,5,1.0".as_bytes(),
+            &mut colliding,
+        )
+        .unwrap();
+        assert_eq!(
+            engine.merge(colliding),
+            Err(TransactionError::MergeCollision(5))
+        );
+        assert!(!engine.accounts.contains_key(&4));
+    }
+
+    #[test]
+    fn merges_open_authorizations() {
+        let mut engine = PaymentEngine::default();
+        process(
+            "type,client,tx,amount\ndeposit,1,1,5.0".as_bytes(),
+            &mut engine,
+        )
+        .unwrap();
+        let mut shard = PaymentEngine::default();
+        let input = "type,client,tx,amount\n\
+                     deposit,1,2,10.0\n\
+                     authorize,1,3,4.0\n\
+                     authorize,1,4,3.0\n";
+        process(input.as_bytes(), &mut shard).unwrap();
+
+        engine.merge(shard).unwrap();
+        let input = "type,client,tx,amount\ncapture,1,3,\nvoid,1,4,\n";
+        process(input.as_bytes(), &mut engine).unwrap();
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.available(), Amount::new(11, 0).unwrap());
+        assert_eq!(account.held(), Amount::zero());
+        assert_eq!(account.total(), Amount::new(11, 0).unwrap());
+    }
+
+    #[test]
+    fn merges_reservations_and_accumulated_deposits() {
+        let deposit = |tx, amount| Transaction {
+            tx,
+            amount: Some(Amount::new(amount, 0).unwrap()),
+            client: 1,
+            disputed: false,
+            variant: TransactionVariant::Deposit,
+            chargeback: false,
+            destination: None,
+            currency: None,
+            to_currency: None,
+            rate: None,
+            timestamp: None,
+        };
+        let mut engine = PaymentEngine::default();
+        engine.insert(deposit(1, 5)).unwrap();
+        let kept = engine.reserve(1, Amount::new(2, 0).unwrap()).unwrap();
+        let mut shard = PaymentEngine::with_hot_accounts(HotAccountConfig {
+            min_share: 0.1,
+            min_transactions: 1,
+            accumulate_deposits: true,
+        });
+        shard.insert(deposit(2, 10)).unwrap();
+        let renumbered = shard.reserve(1, Amount::new(3, 0).unwrap()).unwrap();
+        assert_eq!(kept, renumbered);
+        shard.insert(deposit(3, 4)).unwrap();
+        assert_eq!(shard.pending_deposits[&1], Amount::new(4, 0).unwrap());
+
+        engine.merge(shard).unwrap();
+        assert_eq!(engine.reservations.len(), 2);
+        assert_eq!(
+            engine.reservation(kept).unwrap().amount,
+            Amount::new(2, 0).unwrap()
+        );
+        let moved = engine
+            .reservations
+            .keys()
+            .copied()
+            .find(|&id| id != kept)
+            .unwrap();
+        engine.commit(moved).unwrap();
+        engine.release(kept).unwrap();
+        assert_eq!(engine.pending_deposits[&1], Amount::new(4, 0).unwrap());
+        engine.flush().unwrap();
+        let account = engine.accounts.get(&1).unwrap();
         assert_eq!(account.held(), Amount::zero());
+        assert_eq!(account.available(), Amount::new(16, 0).unwrap());
+        assert!(engine.reserve(1, Amount::new(1, 0).unwrap()).unwrap() > moved);
     }
 
     #[test]
-    fn reject_too_large_withdrawal() {
+    fn diffs_states() {
         let mut engine = PaymentEngine::default();
+        let input = "type,client,tx,amount\ndeposit,1,1,5.0\ndeposit,2,2,1.0\n";
+        process(input.as_bytes(), &mut engine).unwrap();
+        let before = PaymentEngine::from_snapshot(engine.snapshot());
+        assert!(before.diff(&engine).is_empty());
 
-        let mut amount = Amount::new(22, 1).unwrap();
-        let client = 1;
-        let deposit = Transaction {
-            tx: 1,
-            amount: Some(amount),
-            client,
-            disputed: false,
-            variant: TransactionVariant::Deposit,
-            chargeback: false,
-        };
-        assert!(engine.insert(deposit).is_ok());
+        let input = "type,client,tx,amount\ndispute,1,1,\nchargeback,1,1,\ndeposit,3,3,1.0\n";
+        process(input.as_bytes(), &mut engine).unwrap();
+        let diff = before.diff(&engine);
+        assert_eq!(diff.added_accounts.len(), 1);
+        assert_eq!(diff.changed_accounts.len(), 1);
+        let change = &diff.changed_accounts[0];
+        assert_eq!(change.client(), 1);
+        assert_eq!(change.before.total(), Amount::new(5, 0).unwrap());
+        assert_eq!(change.after.total(), Amount::zero());
+        assert_eq!(diff.locked, vec![1]);
+        let added = diff
+            .added_transactions
+            .iter()
+            .map(|tx| tx.tx)
+            .collect::<Vec<_>>();
+        assert_eq!(added, vec![3]);
+    }
 
-        amount += Amount::new(1, 1).unwrap();
+    #[test]
+    fn prunes_transactions() {
+        let mut engine = PaymentEngine::default();
+        let input = "type,client,tx,amount,destination,timestamp\n\
+                     deposit,1,1,5.0,,100\n\
+                     deposit,1,2,5.0,,100\n\
+                     dispute,1,2,,,100\n\
+                     transfer,1,3,1.0,2,200\n\
+                     deposit,1,4,1.0,,300\n";
+        process(input.as_bytes(), &mut engine).unwrap();
 
-        let withdrawal = Transaction {
-            tx: 2,
-            // Trying to withdraw an amount larger than the amount deposited
-            amount: Some(amount),
-            client,
-            disputed: false,
-            variant: TransactionVariant::Withdrawal,
-            chargeback: false,
+        let filter = PruneFilter {
+            undisputable: true,
+            ..PruneFilter::default()
         };
+        assert_eq!(engine.prune(&filter), Ok(1));
+        assert!(!engine.transactions.contains(3));
+        let filter = PruneFilter {
+            older_than: Some(150),
+            ..PruneFilter::default()
+        };
+        // The disputed deposit is kept
+        assert_eq!(engine.prune(&filter), Ok(1));
+        let mut remaining = engine.transactions.keys().copied().collect::<Vec<_>>();
+        remaining.sort_unstable();
+        assert_eq!(remaining, vec![2, 4]);
+    }
+
+    #[test]
+    fn evicts_settled_transactions() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,5.0\n\
+                     deposit,1,2,5.0\n\
+                     dispute,1,1,\n\
+                     resolve,1,1,\n\
+                     dispute,1,2,\n\
+                     chargeback,1,2,\n";
+        let mut engine = PaymentEngine::builder()
+            .retention(Retention::EvictChargedBack)
+            .build();
+        process(input.as_bytes(), &mut engine).unwrap();
+        assert!(engine.transactions.contains(1));
+        assert!(!engine.transactions.contains(2));
+
+        let mut engine = PaymentEngine::builder()
+            .retention(Retention::EvictSettled)
+            .build();
+        process(input.as_bytes(), &mut engine).unwrap();
+        assert!(engine.transactions.is_empty());
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.total(), Amount::new(5, 0).unwrap());
+        assert!(account.locked());
+    }
+
+    #[test]
+    fn evicted_transactions_are_forgotten() {
+        let mut engine = PaymentEngine::builder()
+            .retention(Retention::EvictChargedBack)
+            .build();
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,5.0\n\
+                     dispute,1,1,2.0\n\
+                     chargeback,1,1,\n";
+        process(input.as_bytes(), &mut engine).unwrap();
+        assert!(engine.partial_disputes.is_empty());
+        assert!(engine.disputed.is_empty());
+
+        // Representment needs the charged back transaction
+        let input = "type,client,tx,amount\nrepresentment,1,1,\n";
+        let errors =
+            process_with_policy(input.as_bytes(), &mut engine, ProcessingPolicy::SkipWithLog)
+                .unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(engine.metrics().rejected_for("transaction_not_found"), 1);
         assert_eq!(
-            engine.insert(withdrawal).unwrap_err(),
-            TransactionError::InsufficientFunds {
-                client,
-                available: Amount::new(22, 1).unwrap(),
-                amount_attempted: Amount::new(23, 1).unwrap()
-            }
+            engine.accounts.get(&1).unwrap().total(),
+            Amount::new(3, 0).unwrap()
+        );
+
+        // The id is free again
+        engine.unlock(1).unwrap();
+        let input = "type,client,tx,amount\ndeposit,1,1,1.0\n";
+        process(input.as_bytes(), &mut engine).unwrap();
+        assert_eq!(
+            engine.accounts.get(&1).unwrap().total(),
+            Amount::new(4, 0).unwrap()
         );
     }
 
     #[test]
-    fn reject_transaction_overwrite() {
-        let mut engine = PaymentEngine::default();
+    fn tracks_changed_accounts() {
+        let mut engine = PaymentEngine::builder().track_changes().build();
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,5.0\n\
+                     deposit,2,2,5.0\n\
+                     deposit,3,3,5.0\n";
+        process(input.as_bytes(), &mut engine).unwrap();
+        let changes = engine.take_changes();
+        assert_eq!((changes.sequence, changes.accounts.len()), (1, 3));
 
-        let tx = 1;
-        let client = 1;
-        let deposit = Transaction {
-            tx,
-            amount: Some(Amount::zero()),
-            client,
-            disputed: false,
-            variant: TransactionVariant::Deposit,
-            chargeback: false,
-        };
-        assert!(engine.insert(deposit).is_ok());
-        let deposit = Transaction {
-            // Trying to use the same `tx` as in the previous transaction
+        // Rejected transactions and unlocking an unlocked account change nothing
+        let input = "type,client,tx,amount\n\
+                     withdrawal,1,4,50.0\n\
+                     dispute,3,3,\n";
+        process_with_policy(input.as_bytes(), &mut engine, ProcessingPolicy::SkipInvalid).unwrap();
+        engine.unlock(2).unwrap();
+        let changes = engine.take_changes();
+        let clients = changes
+            .accounts
+            .iter()
+            .map(Account::client)
+            .collect::<Vec<_>>();
+        assert_eq!((changes.sequence, clients), (2, vec![3]));
+        assert!(engine.take_changes().accounts.is_empty());
+    }
+
+    #[test]
+    fn inserts_batches_atomically() {
+        let mut engine = PaymentEngine::default();
+        let transaction = |tx, client, variant, amount: Option<i64>| Transaction {
             tx,
-            amount: Some(Amount::zero()),
+            amount: amount.map(|amount| Amount::new(amount, 0).unwrap()),
             client,
             disputed: false,
-            variant: TransactionVariant::Deposit,
+            variant,
             chargeback: false,
+            destination: Some(2),
+            currency: None,
+            to_currency: None,
+            rate: None,
+            timestamp: None,
         };
+        let batch = [
+            transaction(1, 1, TransactionVariant::Deposit, Some(10)),
+            transaction(2, 1, TransactionVariant::Transfer, Some(4)),
+            transaction(1, 1, TransactionVariant::Dispute, None),
+        ];
+        assert_eq!(engine.insert_batch(&batch), Ok(()));
         assert_eq!(
-            engine.insert(deposit).unwrap_err(),
-            TransactionError::TransactionAlreadyExist
+            engine.accounts.get(&2).unwrap().total(),
+            Amount::new(4, 0).unwrap()
         );
+
+        // The withdrawal only fails after the resolve made it through
+        let batch = [
+            transaction(1, 1, TransactionVariant::Resolve, None),
+            transaction(3, 1, TransactionVariant::Withdrawal, Some(7)),
+        ];
+        let error = engine.insert_batch(&batch).unwrap_err();
+        assert_eq!(error.index, 1);
+        assert!(matches!(
+            error.error,
+            TransactionError::InsufficientFunds { .. }
+        ));
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.held(), Amount::new(10, 0).unwrap());
+        assert!(engine.transactions[&1].disputed);
+        assert!(!engine.transactions.contains(3));
     }
 
     #[test]
-    fn chargeback() {
+    fn freezing_is_independent_of_locking() {
         let mut engine = PaymentEngine::default();
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,5.0\n\
+                     deposit,1,2,5.0\n\
+                     dispute,1,1,\n\
+                     chargeback,1,1,\n\
+                     freeze,1,0,\n\
+                     unlock,1,0,\n";
+        process(input.as_bytes(), &mut engine).unwrap();
+        let account = engine.accounts.get(&1).unwrap();
+        assert!(!account.locked());
+        assert!(account.frozen());
+        assert_eq!(
+            engine.unfreeze(2),
+            Err(TransactionError::AccountNotFound(2))
+        );
 
-        let client = 1;
+        let mut restored = PaymentEngine::from_snapshot(engine.snapshot());
+        assert!(restored.accounts.get(&1).unwrap().frozen());
+        let input = "type,client,tx,amount\ndispute,1,2,\n";
+        assert!(process(input.as_bytes(), &mut restored).is_err());
+        restored.unfreeze(1).unwrap();
+        process(input.as_bytes(), &mut restored).unwrap();
+    }
 
-        // Deposit
-        let deposit = Transaction {
-            tx: 1,
-            amount: Some(Amount::new(10, 0).unwrap()),
-            client,
-            disputed: false,
-            variant: TransactionVariant::Deposit,
-            chargeback: false,
-        };
-        assert!(engine.insert(deposit).is_ok());
+    #[test]
+    fn captures_and_voids_authorizations() {
+        let mut engine = PaymentEngine::default();
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,10.0\n\
+                     authorize,1,2,4.0\n\
+                     authorize,1,3,3.0\n";
+        process(input.as_bytes(), &mut engine).unwrap();
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.available(), Amount::new(3, 0).unwrap());
+        assert_eq!(account.held(), Amount::new(7, 0).unwrap());
 
-        let dispute = Transaction {
-            tx: 1,
-            amount: None,
-            client,
-            disputed: false,
-            variant: TransactionVariant::Dispute,
-            chargeback: false,
-        };
-        assert!(engine.insert(dispute).is_ok());
-        let chargeback = Transaction {
-            tx: 1,
-            amount: None,
-            client,
-            disputed: false,
-            variant: TransactionVariant::Chargeback,
-            chargeback: false,
-        };
-        assert!(engine.insert(chargeback).is_ok());
-        let account_after_chargeback = engine.accounts.get(&client).unwrap();
+        let mut restored = PaymentEngine::from_snapshot(engine.snapshot());
+        let input = "type,client,tx,amount\n\
+                     capture,1,2,\n\
+                     void,1,3,\n";
+        process(input.as_bytes(), &mut restored).unwrap();
+        let account = restored.accounts.get(&1).unwrap();
+        assert_eq!(account.available(), Amount::new(6, 0).unwrap());
+        assert_eq!(account.held(), Amount::zero());
+        assert_eq!(account.total(), Amount::new(6, 0).unwrap());
 
-        // Check that deposit has been reversed and that everything is back to zero
-        assert_eq!(account_after_chargeback.available(), Amount::zero());
-        assert_eq!(account_after_chargeback.total(), Amount::zero());
-        assert_eq!(account_after_chargeback.held(), Amount::zero());
-        // Chargeback should lock account
-        assert!(account_after_chargeback.locked());
+        for (input, error) in [
+            ("capture,1,3,", TransactionError::AuthorizationClosed),
+            ("dispute,1,2,", TransactionError::NotDisputable),
+        ] {
+            let input = format!("type,client,tx,amount\n{}\n", input);
+            let e = process(input.as_bytes(), &mut restored).unwrap_err();
+            assert!(e.to_string().contains(&error.to_string()), "{}", e);
+        }
     }
 
     #[test]
-    fn resolved_dispute() {
+    fn materializes_schedules_once() {
         let mut engine = PaymentEngine::default();
+        let schedules = "client,type,amount,start,interval,end\n\
+                         1,deposit,10,100,50,\n\
+                         1,withdrawal,4,120,100,320\n\
+                         2,deposit,1,0,0,\n";
+        let schedules = Schedule::read(schedules.as_bytes()).unwrap();
+        engine.add_schedule(schedules[0].clone()).unwrap();
+        engine.add_schedule(schedules[1].clone()).unwrap();
+        assert_eq!(
+            engine.add_schedule(schedules[2].clone()),
+            Err(TransactionError::InvalidSchedule)
+        );
 
-        let client = 1;
+        let occurrences = engine.materialize(200).unwrap();
+        let applied = occurrences
+            .iter()
+            .map(|o| {
+                let id = o.transaction.tx - CounterIds::DEFAULT_START;
+                (o.schedule, o.transaction.timestamp, id)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            applied,
+            [
+                (0, Some(100), 0),
+                (1, Some(120), 1),
+                (0, Some(150), 2),
+                (0, Some(200), 3)
+            ]
+        );
+        assert!(engine.materialize(200).unwrap().is_empty());
 
-        // Deposit
-        let deposit = Transaction {
-            tx: 1,
-            amount: Some(Amount::new(10, 0).unwrap()),
-            client,
-            disputed: false,
-            variant: TransactionVariant::Deposit,
-            chargeback: false,
-        };
-        assert!(engine.insert(deposit).is_ok());
+        let mut restored = PaymentEngine::from_snapshot(engine.snapshot());
+        let occurrences = restored.materialize(1000).unwrap();
+        assert_eq!(occurrences.len(), 18);
+        assert!(occurrences.iter().all(|o| o.error.is_none()));
+        let account = restored.accounts.get(&1).unwrap();
+        assert_eq!(account.available(), Amount::new(178, 0).unwrap());
+    }
 
-        // Backup state of account at this point to compare after dispute is resolved
-        let accounts = engine.accounts.clone();
-        let account_before_dispute = accounts.get(&client).unwrap();
+    #[test]
+    fn applies_queued_transactions_when_effective() {
+        let mut engine = PaymentEngine::default();
+        let input = "type,client,tx,amount,timestamp,effective\n\
+                     deposit,1,1,10.0,100,\n\
+                     withdrawal,1,2,4.0,110,300\n\
+                     withdrawal,1,3,20.0,120,200\n\
+                     deposit,1,4,1.0,130,50\n";
+        process(input.as_bytes(), &mut engine).unwrap();
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.available(), Amount::new(11, 0).unwrap());
+        let queued = engine
+            .queued()
+            .map(|(date, tx)| (date, tx.tx))
+            .collect::<Vec<_>>();
+        assert_eq!(queued, [(200, 3), (300, 2)]);
 
-        let dispute = Transaction {
-            tx: 1,
-            amount: None,
-            client,
-            disputed: false,
-            variant: TransactionVariant::Dispute,
-            chargeback: false,
-        };
-        assert!(engine.insert(dispute).is_ok());
-        let chargeback = Transaction {
-            tx: 1,
-            amount: None,
-            client,
-            disputed: false,
-            variant: TransactionVariant::Resolve,
-            chargeback: false,
+        let mut restored = PaymentEngine::from_snapshot(engine.snapshot());
+        let matured = restored.advance_to(250).unwrap();
+        assert_eq!(matured.len(), 1);
+        assert_eq!(matured[0].transaction.timestamp, Some(200));
+        assert!(matches!(
+            matured[0].error,
+            Some(TransactionError::InsufficientFunds { .. })
+        ));
+        let matured = restored.advance_to(300).unwrap();
+        assert!(matured[0].error.is_none());
+        assert_eq!(restored.queued().count(), 0);
+        let account = restored.accounts.get(&1).unwrap();
+        assert_eq!(account.available(), Amount::new(7, 0).unwrap());
+    }
+
+    #[test]
+    fn disputes_a_portion_of_a_transaction() {
+        let mut engine = PaymentEngine::default();
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,50.0\n\
+                     deposit,1,2,10.0\n\
+                     dispute,1,1,20.0\n\
+                     dispute,1,2,10.0\n";
+        process(input.as_bytes(), &mut engine).unwrap();
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.held(), Amount::new(30, 0).unwrap());
+
+        let input = "type,client,tx,amount\ndispute,1,2,11.0\n";
+        let mut excessive = PaymentEngine::default();
+        process(
+            "type,client,tx,amount\ndeposit,1,2,10.0\n".as_bytes(),
+            &mut excessive,
+        )
+        .unwrap();
+        let e = process(input.as_bytes(), &mut excessive).unwrap_err();
+        assert!(e.to_string().contains("Cannot dispute `11.0000`"), "{}", e);
+
+        let mut restored = PaymentEngine::from_snapshot(engine.snapshot());
+        let input = "type,client,tx,amount\n\
+                     resolve,1,2,\n\
+                     chargeback,1,1,\n";
+        process(input.as_bytes(), &mut restored).unwrap();
+        let account = restored.accounts.get(&1).unwrap();
+        assert_eq!(account.held(), Amount::zero());
+        assert_eq!(account.available(), Amount::new(40, 0).unwrap());
+        assert!(account.locked());
+        assert_eq!(restored.metrics().charged_back, Decimal::from(20));
+    }
+
+    #[test]
+    fn expires_disputes_left_open() {
+        let config = EngineConfig {
+            dispute_expiry: Some(DisputeExpiry {
+                after: 100,
+                outcome: ExpiryOutcome::Chargeback,
+            }),
+            ..EngineConfig::default()
         };
-        assert!(engine.insert(chargeback).is_ok());
-        let account_after_resolve = engine.accounts.get(&client).unwrap();
+        let mut engine = PaymentEngine::builder().config(config.clone()).build();
+        let input = "type,client,tx,amount,timestamp\n\
+                     deposit,1,1,5.0,\n\
+                     dispute,1,1,,\n\
+                     deposit,2,2,5.0,10\n\
+                     deposit,3,3,5.0,20\n\
+                     dispute,3,3,,30\n\
+                     dispute,2,2,,40\n";
+        process(input.as_bytes(), &mut engine).unwrap();
+        engine.freeze(2).unwrap();
 
-        // Check that deposit has been reversed and that everything is back to zero
+        let mut restored = PaymentEngine::from_snapshot(engine.snapshot());
+        restored.set_config(config);
+        assert!(restored.expire_disputes(129).unwrap().is_empty());
+        let expired = restored.expire_disputes(140).unwrap();
+        let summary = expired
+            .iter()
+            .map(|e| (e.tx, e.opened_at, e.error.clone()))
+            .collect::<Vec<_>>();
         assert_eq!(
-            account_after_resolve.available(),
-            account_before_dispute.available()
+            summary,
+            [
+                (3, 30, None),
+                (2, 40, Some(TransactionError::FrozenAccount))
+            ]
         );
+        assert!(restored.accounts.get(&3).unwrap().locked());
         assert_eq!(
-            account_after_resolve.total(),
-            account_before_dispute.total()
+            restored.accounts.get(&1).unwrap().held(),
+            Amount::new(5, 0).unwrap()
         );
-        assert_eq!(account_after_resolve.held(), account_before_dispute.held());
-        // Resolve should NOT lock account
-        assert!(!account_after_resolve.locked());
+
+        restored.unfreeze(2).unwrap();
+        let expired = restored.expire_disputes(140).unwrap();
+        assert_eq!(expired.len(), 1);
+        assert!(expired[0].error.is_none());
+        assert!(restored.expire_disputes(1000).unwrap().is_empty());
     }
 
     #[test]
-    fn reject_double_dispute() {
+    fn reverses_chargebacks() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,50.0\n\
+                     deposit,1,2,10.0\n\
+                     dispute,1,1,20.0\n\
+                     chargeback,1,1,\n\
+                     representment,1,1,\n";
         let mut engine = PaymentEngine::default();
+        process(input.as_bytes(), &mut engine).unwrap();
+        let account = engine.accounts.get(&1).unwrap();
+        assert_eq!(account.available(), Amount::new(60, 0).unwrap());
+        assert_eq!(account.total(), Amount::new(60, 0).unwrap());
+        assert!(account.locked());
 
-        let client = 1;
+        let mut unlocking = PaymentEngine::builder().unlock_on_representment().build();
+        process(input.as_bytes(), &mut unlocking).unwrap();
+        assert!(!unlocking.accounts.get(&1).unwrap().locked());
 
-        // Deposit
-        let deposit = Transaction {
-            tx: 1,
-            amount: Some(Amount::new(10, 0).unwrap()),
-            client,
-            disputed: false,
-            variant: TransactionVariant::Deposit,
-            chargeback: false,
+        let mut restored = PaymentEngine::from_snapshot(unlocking.snapshot());
+        for (input, error) in [
+            ("representment,1,1,", TransactionError::AlreadyRepresented),
+            ("representment,1,2,", TransactionError::NotChargedBack),
+            ("dispute,1,1,", TransactionError::TransactionChargedback),
+        ] {
+            let input = format!("type,client,tx,amount\n{}\n", input);
+            let e = process(input.as_bytes(), &mut restored).unwrap_err();
+            assert!(e.to_string().contains(&error.to_string()), "{}", e);
+        }
+    }
+
+    #[test]
+    fn applies_tier_rules() {
+        let premium = TierRules {
+            overdraft: Some(Amount::new(10, 0).unwrap()),
+            ..TierRules::default()
         };
-        assert!(engine.insert(deposit).is_ok());
+        let basic = TierRules {
+            deny_disputes: true,
+            ..TierRules::default()
+        };
+        let mut engine = PaymentEngine::builder()
+            .tier("premium", premium)
+            .tier("basic", basic)
+            .client_overdraft(2, Amount::new(1, 0).unwrap())
+            .build();
+        for client in [1, 2] {
+            engine.set_tier(client, "premium").unwrap();
+        }
+        engine.set_tier(3, "basic").unwrap();
+        process(
+            "type,client,tx,amount\n\
+             withdrawal,1,1,8.0\n\
+             deposit,3,2,5.0\n"
+                .as_bytes(),
+            &mut engine,
+        )
+        .unwrap();
+        assert_eq!(
+            engine.accounts.get(&1).unwrap().available().to_string(),
+            "-8.0000"
+        );
 
-        let dispute = Transaction {
-            tx: 1,
-            amount: None,
-            client,
-            disputed: false,
-            variant: TransactionVariant::Dispute,
-            chargeback: false,
+        let mut restored = PaymentEngine::builder()
+            .config(engine.config().clone())
+            .build();
+        restored
+            .merge(PaymentEngine::from_snapshot(engine.snapshot()))
+            .unwrap();
+        assert_eq!(restored.accounts.get(&3).unwrap().tier(), Some("basic"));
+        for (input, error) in [
+            // The overdraft of the client takes precedence over its tier
+            (
+                "withdrawal,2,3,2.0",
+                TransactionError::InsufficientFunds {
+                    client: 2,
+                    available: Amount::zero(),
+                    amount_attempted: Amount::new(2, 0).unwrap(),
+                },
+            ),
+            ("dispute,3,2,", TransactionError::NotDisputable),
+        ] {
+            let input = format!("type,client,tx,amount\n{}\n", input);
+            let e = process(input.as_bytes(), &mut restored).unwrap_err();
+            assert!(e.to_string().contains(&error.to_string()), "{}", e);
+        }
+    }
+
+    #[test]
+    fn accrues_charges_on_held_funds() {
+        let mut config = HeldFundsConfig {
+            after: 100,
+            period: 50,
+            rate: Decimal::new(1, 2),
+            kind: HeldFundsKind::Penalty,
         };
-        assert!(engine.insert(dispute).is_ok());
+        let input = "type,client,tx,amount,timestamp\n\
+                     deposit,1,1,100.0,0\n\
+                     deposit,1,2,50.0,0\n\
+                     dispute,1,1,,10\n";
+        let mut engine = PaymentEngine::builder().held_funds(config).build();
+        process(input.as_bytes(), &mut engine).unwrap();
+        assert!(engine.accrue_held_funds(109).unwrap().is_empty());
+        let postings = engine.accrue_held_funds(160).unwrap();
+        assert_eq!(postings.len(), 1);
+        assert_eq!(postings[0].tx, 1);
+        assert_eq!(postings[0].transaction.variant, TransactionVariant::Fee);
+        assert!(postings[0].error.is_none());
+        assert!(engine.accrue_held_funds(170).unwrap().is_empty());
 
-        // Trying to dispute again which should fail
-        let dispute = Transaction {
+        let mut restored = PaymentEngine::from_snapshot(engine.snapshot());
+        restored.set_config(engine.config().clone());
+        let postings = restored.accrue_held_funds(260).unwrap();
+        assert_eq!(postings[0].periods, 2);
+        assert_eq!(
+            restored.accounts.get(&1).unwrap().available(),
+            Amount::new(47, 0).unwrap()
+        );
+        process(
+            "type,client,tx,amount\nresolve,1,1,\n".as_bytes(),
+            &mut restored,
+        )
+        .unwrap();
+        assert!(restored.accrue_held_funds(1000).unwrap().is_empty());
+
+        config.kind = HeldFundsKind::Credit;
+        let mut crediting = PaymentEngine::builder().held_funds(config).build();
+        let input = input.replace("dispute,1,1,,10", "dispute,1,1,50.0,10");
+        process(input.as_bytes(), &mut crediting).unwrap();
+        let postings = crediting.accrue_held_funds(210).unwrap();
+        assert_eq!(
+            postings[0].transaction.amount,
+            Some(Amount::new(1, 0).unwrap())
+        );
+        assert_eq!(
+            crediting.accounts.get(&1).unwrap().total(),
+            Amount::new(151, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn rolls_back_recent_transactions() {
+        let config = EngineConfig {
+            fee_account: Some(9),
+            ..EngineConfig::default()
+        };
+        let mut engine = PaymentEngine::builder().config(config).build();
+        engine.set_journal(5);
+        let before = "type,client,tx,amount,destination\n\
+            deposit,1,1,10.0,\ndeposit,2,2,5.0,\ndeposit,9,3,1.0,\n";
+        process(before.as_bytes(), &mut engine).unwrap();
+        let sorted = |mut snapshot: Snapshot| {
+            snapshot.accounts.sort_by_key(Account::client);
+            snapshot.transactions.sort_by_key(|tx| tx.tx);
+            snapshot
+        };
+        let expected = sorted(engine.snapshot());
+
+        let after = "type,client,tx,amount,destination\n\
+            transfer,1,4,3.0,2\nfee,2,5,1.0,\ndispute,1,1,4.0,\nchargeback,1,1,,\n";
+        process(after.as_bytes(), &mut engine).unwrap();
+        assert!(engine.accounts.get(&1).unwrap().locked());
+        assert_eq!(engine.rollback(4), Ok(4));
+        assert_eq!(sorted(engine.snapshot()), expected);
+
+        // Only the last five are kept
+        process(after.as_bytes(), &mut engine).unwrap();
+        assert_eq!(engine.rollback(10), Ok(5));
+        assert_eq!(engine.rollback(1), Ok(0));
+        assert_eq!(engine.accounts.get(&9).unwrap().total(), Amount::zero());
+    }
+
+    #[test]
+    fn simulates_batches_on_touched_state() {
+        let mut engine = PaymentEngine::default();
+        let input = "type,client,tx,amount\n\
+            deposit,1,1,10.0\ndispute,1,1,4.0\nchargeback,1,1,\ndeposit,2,2,3.0\ndeposit,3,3,1.0\n";
+        process(input.as_bytes(), &mut engine).unwrap();
+        let before = engine.snapshot();
+
+        let representment = Transaction {
             tx: 1,
             amount: None,
-            client,
+            client: 1,
             disputed: false,
-            variant: TransactionVariant::Dispute,
+            variant: TransactionVariant::Representment,
             chargeback: false,
+            destination: None,
+            currency: None,
+            to_currency: None,
+            rate: None,
+            timestamp: None,
         };
-        assert!(engine.insert(dispute).is_err());
+        let transfer = Transaction {
+            tx: 4,
+            amount: Some(Amount::new(2, 0).unwrap()),
+            variant: TransactionVariant::Transfer,
+            client: 2,
+            destination: Some(4),
+            ..representment.clone()
+        };
+        let overdrawn = Transaction {
+            tx: 5,
+            ..transfer.clone()
+        };
+        let result = engine.simulate(&[representment, transfer, overdrawn]);
+        assert!(!result.is_ok());
+        assert!(result.results[0].is_ok());
+        assert!(result.results[1].is_ok());
+        assert!(result.results[2]
+            .as_ref()
+            .unwrap_err()
+            .to_string()
+            .contains("Insufficient funds"));
+        let totals = result
+            .accounts
+            .iter()
+            .map(|account| (account.client(), account.total()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            totals,
+            vec![
+                (1, Amount::new(10, 0).unwrap()),
+                (2, Amount::new(1, 0).unwrap()),
+                (4, Amount::new(2, 0).unwrap()),
+            ]
+        );
+        assert_eq!(engine.snapshot(), before);
     }
 
     #[test]
-    fn reject_unauthenticated_dispute() {
+    fn indexes_disputed_transactions() {
         let mut engine = PaymentEngine::default();
+        engine.set_journal(10);
+        let input = "type,client,tx,amount\n\
+            deposit,1,1,5.0\ndeposit,1,2,5.0\ndeposit,2,3,5.0\n\
+            dispute,1,2,\ndispute,2,3,\ndispute,1,1,\nchargeback,2,3,\n";
+        process(input.as_bytes(), &mut engine).unwrap();
+        let disputed =
+            |engine: &PaymentEngine| engine.disputed().map(|tx| tx.tx).collect::<Vec<_>>();
+        assert_eq!(disputed(&engine), vec![1, 2]);
 
-        let client = 1;
-        let mallicous_client = 2;
+        let restored = PaymentEngine::from_snapshot(engine.snapshot());
+        assert_eq!(disputed(&restored), vec![1, 2]);
 
-        // Deposit
-        let deposit = Transaction {
-            tx: 1,
-            amount: Some(Amount::new(10, 0).unwrap()),
-            client,
-            disputed: false,
-            variant: TransactionVariant::Deposit,
-            chargeback: false,
-        };
-        assert!(engine.insert(deposit).is_ok());
+        engine.rollback(2).unwrap();
+        assert_eq!(disputed(&engine), vec![2, 3]);
+    }
 
-        // mallicous_client tries to dispute transaction done by another client
-        let dispute = Transaction {
-            tx: 1,
-            amount: None,
-            client: mallicous_client,
-            disputed: false,
-            variant: TransactionVariant::Dispute,
-            chargeback: false,
+    #[test]
+    fn counts_transactions_per_variant() {
+        let mut engine = PaymentEngine::default();
+        let input = "type,client,tx,amount\n\
+            deposit,1,1,5.0\ndeposit,1,2,3.0\nwithdrawal,1,3,1.5\nwithdrawal,1,4,50.0\n\
+            dispute,1,2,1.0\nresolve,1,2,\ndispute,1,1,\nchargeback,1,1,\n";
+        let errors =
+            process_with_policy(input.as_bytes(), &mut engine, ProcessingPolicy::SkipWithLog)
+                .unwrap();
+        assert_eq!(errors.len(), 1);
+        let stats = engine.stats();
+        let counts = |stats: &EngineStats| {
+            [
+                stats.deposits.count,
+                stats.withdrawals.count,
+                stats.disputes.count,
+                stats.resolves.count,
+                stats.chargebacks.count,
+            ]
         };
-        assert!(engine.insert(dispute).is_err());
+        assert_eq!(counts(stats), [2, 1, 2, 1, 1]);
+        assert_eq!(stats.deposits.total, Decimal::from(8));
+        assert_eq!(stats.withdrawals.total, Decimal::new(15, 1));
+        assert_eq!(stats.disputes.total, Decimal::from(6));
+        assert_eq!(stats.resolves.total, Decimal::from(1));
+        assert_eq!(stats.chargebacks.total, Decimal::from(5));
+
+        let mut other = PaymentEngine::default();
+        process(
+            "type,client,tx,amount\ndeposit,2,5,2.0\n".as_bytes(),
+            &mut other,
+        )
+        .unwrap();
+        engine.merge(other).unwrap();
+        assert_eq!(engine.stats().deposits.count, 3);
+        assert_eq!(engine.stats().deposits.total, Decimal::from(10));
     }
 }
@@ -1,11 +1,20 @@
+use std::{error::Error as StdError, io};
+
+use rust_decimal::Decimal;
 use thiserror::Error;
 
-use crate::Amount;
+use crate::{Amount, ReservationId, RowError};
 
-#[derive(Debug, PartialEq, Error)]
+#[derive(Debug, Clone, PartialEq, Error)]
 pub enum TransactionError {
     #[error("Account is locked")]
     LockedAccount,
+    #[error("Account is closed")]
+    ClosedAccount,
+    #[error("Account is frozen")]
+    FrozenAccount,
+    #[error("Cannot close the account of client `{client}` with a total of `{total}`")]
+    NonZeroBalance { client: u16, total: Amount },
     #[error("Cannot overwrite an existing transaction")]
     TransactionAlreadyExist,
     #[error("Insufficient funds for client `{client}` with available amount `{available}`. Attempt to withdraw `{amount_attempted}` failed.")]
@@ -14,14 +23,151 @@ pub enum TransactionError {
         available: Amount,
         amount_attempted: Amount,
     },
+    #[error(
+        "Withdrawing `{amount_attempted}` exceeds the limit of `{limit}` of client `{client}`"
+    )]
+    LimitExceeded {
+        client: u16,
+        limit: Amount,
+        amount_attempted: Amount,
+    },
+    #[error("`{amount_attempted}` exceeds the daily limit of `{limit}` of client `{client}`")]
+    DailyLimitExceeded {
+        client: u16,
+        limit: Amount,
+        amount_attempted: Amount,
+    },
     #[error("An amount used in a transaction cannot be negative")]
     NegativeAmount,
     #[error("The transaction was not found")]
     TransactionNotFound,
+    #[error("The account of client `{0}` was not found")]
+    AccountNotFound(u16),
     #[error("The transaction has been chargedback and not be updated")]
     TransactionChargedback,
+    #[error("The authorization has already been captured or voided")]
+    AuthorizationClosed,
+    #[error("Cannot dispute `{disputed}` of a transaction of `{amount}`")]
+    DisputeExceedsAmount { disputed: Amount, amount: Amount },
+    #[error("The transaction has not been charged back")]
+    NotChargedBack,
+    #[error("The chargeback has already been reversed")]
+    AlreadyRepresented,
     #[error("Cannot resolve a transaction that is not yet disputed")]
     NotDisputed,
     #[error("The transaction is already disputed")]
     AlreadyDisputed,
+    #[error("The transaction cannot be disputed")]
+    NotDisputable,
+    #[error("Client `{0}` exceeded its transaction limit, the transaction is quarantined")]
+    ClientLimitExceeded(u16),
+    #[error("Reservation `{0}` was not found")]
+    ReservationNotFound(ReservationId),
+    #[error("The transaction is missing a field its type needs, or has one it cannot have")]
+    InvalidTransaction,
+    #[error("A schedule needs a positive interval and a deposit, withdrawal or fee")]
+    InvalidSchedule,
+    #[error("No ids are left for transactions originated by the engine")]
+    IdsExhausted,
+    #[error("The amount overflows")]
+    Overflow,
+    #[error("Converting `{amount}` at a rate of `{rate}` overflows")]
+    ConversionOverflow { amount: Amount, rate: Decimal },
+    #[error("Transaction `{0}` exists in both merged engines")]
+    MergeCollision(u32),
+    #[error("Client `{0}` exceeded its transaction velocity")]
+    VelocityExceeded(u16),
+    #[error("Rejected by a validator: {0}")]
+    Rejected(String),
+    #[error("Storage error: {0}")]
+    Storage(String),
+    /// The rows rejected while processing could not be written, see
+    /// [`RejectedRowWriter`](crate::RejectedRowWriter).
+    #[error("Failed to write the rejected rows: {0}")]
+    RejectedOutput(String),
+}
+
+/// The error of [`PaymentEngine::insert_batch`](crate::PaymentEngine::insert_batch)
+/// for the first transaction of a batch that failed.
+#[derive(Debug, Clone, PartialEq, Error)]
+#[error("Transaction {index} of the batch failed: {error}")]
+pub struct BatchError {
+    /// The position of the transaction in the batch
+    pub index: usize,
+    pub error: TransactionError,
+}
+
+/// The error of [`run_bytes`](crate::run_bytes).
+#[derive(Debug, Error)]
+pub enum RunError {
+    /// A row could not be read or processed.
+    #[error(transparent)]
+    Row(RowError),
+    /// The engine failed regardless of the row, e.g. as its storage did.
+    #[error(transparent)]
+    Engine(TransactionError),
+    /// The input could not be read, e.g. as its header is not UTF-8.
+    #[error(transparent)]
+    Io(io::Error),
+}
+
+impl From<Box<dyn StdError>> for RunError {
+    fn from(e: Box<dyn StdError>) -> Self {
+        let e = match e.downcast::<RowError>() {
+            Ok(e) => return RunError::Row(*e),
+            Err(e) => e,
+        };
+        let e = match e.downcast::<TransactionError>() {
+            Ok(e) => return RunError::Engine(*e),
+            Err(e) => e,
+        };
+        let e = match e.downcast::<csv::Error>() {
+            Ok(e) => return RunError::Io((*e).into()),
+            Err(e) => e,
+        };
+        match e.downcast::<io::Error>() {
+            Ok(e) => RunError::Io(*e),
+            Err(e) => RunError::Io(io::Error::other(e.to_string())),
+        }
+    }
+}
+
+impl TransactionError {
+    /// The name of the kind of error, e.g. `insufficient_funds`, to label
+    /// metrics with.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            TransactionError::LockedAccount => "locked_account",
+            TransactionError::ClosedAccount => "closed_account",
+            TransactionError::FrozenAccount => "frozen_account",
+            TransactionError::NonZeroBalance { .. } => "non_zero_balance",
+            TransactionError::TransactionAlreadyExist => "transaction_already_exist",
+            TransactionError::InsufficientFunds { .. } => "insufficient_funds",
+            TransactionError::LimitExceeded { .. } => "limit_exceeded",
+            TransactionError::DailyLimitExceeded { .. } => "daily_limit_exceeded",
+            TransactionError::NegativeAmount => "negative_amount",
+            TransactionError::TransactionNotFound => "transaction_not_found",
+            TransactionError::AccountNotFound(_) => "account_not_found",
+            TransactionError::TransactionChargedback => "transaction_chargedback",
+            TransactionError::AuthorizationClosed => "authorization_closed",
+            TransactionError::DisputeExceedsAmount { .. } => "dispute_exceeds_amount",
+            TransactionError::NotChargedBack => "not_charged_back",
+            TransactionError::AlreadyRepresented => "already_represented",
+            TransactionError::NotDisputed => "not_disputed",
+            TransactionError::AlreadyDisputed => "already_disputed",
+            TransactionError::NotDisputable => "not_disputable",
+            TransactionError::ClientLimitExceeded(_) => "client_limit_exceeded",
+            TransactionError::ReservationNotFound(_) => "reservation_not_found",
+            TransactionError::InvalidTransaction => "invalid_transaction",
+            TransactionError::InvalidSchedule => "invalid_schedule",
+            TransactionError::IdsExhausted => "ids_exhausted",
+            TransactionError::Overflow => "overflow",
+            TransactionError::ConversionOverflow { .. } => "conversion_overflow",
+            TransactionError::MergeCollision(_) => "merge_collision",
+            TransactionError::VelocityExceeded(_) => "velocity_exceeded",
+            TransactionError::Rejected(_) => "rejected",
+            TransactionError::Storage(_) => "storage",
+            TransactionError::RejectedOutput(_) => "rejected_output",
+        }
+    }
 }
@@ -0,0 +1,237 @@
+//! A C API with the `ffi` feature, to embed the engine in C and C++
+//! programs, declared in `include/randomlib.h`.
+//!
+//! Functions return [`RANDOMLIB_OK`] or one of the other status codes. The
+//! message of a transaction rejected by the last insert can be read with
+//! [`randomlib_engine_last_error`]. Amounts are passed as strings and
+//! returned as integers in ten-thousandths, the precision of an [`Amount`].
+
+use std::{
+    ffi::{CStr, CString},
+    os::raw::{c_char, c_int},
+    ptr,
+};
+
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+
+use crate::{Amount, PaymentEngine, Transaction, TransactionVariant};
+
+pub const RANDOMLIB_OK: c_int = 0;
+/// A null engine or account, a type or amount that cannot be parsed, or a
+/// transaction without the amount it needs, or with one it cannot have.
+pub const RANDOMLIB_INVALID_ARGUMENT: c_int = -1;
+/// The engine rejected the transaction, see [`randomlib_engine_last_error`].
+pub const RANDOMLIB_REJECTED: c_int = 1;
+/// The client has no account.
+pub const RANDOMLIB_NOT_FOUND: c_int = 2;
+/// An amount does not fit into 64 bits.
+pub const RANDOMLIB_OVERFLOW: c_int = 3;
+
+/// An engine created by [`randomlib_engine_new`].
+pub struct FfiEngine {
+    engine: PaymentEngine,
+    last_error: Option<CString>,
+}
+
+/// The funds of an account, in ten-thousandths.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RandomlibAccount {
+    pub client: u16,
+    pub available: i64,
+    pub held: i64,
+    pub total: i64,
+    pub locked: bool,
+    pub closed: bool,
+}
+
+/// Creates an engine with the default configuration, to be freed with
+/// [`randomlib_engine_free`].
+#[no_mangle]
+pub extern "C" fn randomlib_engine_new() -> *mut FfiEngine {
+    Box::into_raw(Box::new(FfiEngine {
+        engine: PaymentEngine::default(),
+        last_error: None,
+    }))
+}
+
+/// Frees an engine created by [`randomlib_engine_new`]. Does nothing for
+/// null.
+///
+/// # Safety
+///
+/// `engine` must be null or returned by [`randomlib_engine_new`] and not
+/// freed before.
+#[no_mangle]
+pub unsafe extern "C" fn randomlib_engine_free(engine: *mut FfiEngine) {
+    if !engine.is_null() {
+        drop(Box::from_raw(engine));
+    }
+}
+
+/// Submits a transaction of `variant`, named like the `type` column of the
+/// input, e.g. `deposit`. `amount` may be null for transactions without one.
+///
+/// # Safety
+///
+/// `engine` must be a live engine, and `variant` and `amount`, if not null,
+/// NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn randomlib_engine_insert(
+    engine: *mut FfiEngine,
+    variant: *const c_char,
+    client: u16,
+    tx: u32,
+    amount: *const c_char,
+) -> c_int {
+    let engine = match engine.as_mut() {
+        Some(engine) => engine,
+        None => return RANDOMLIB_INVALID_ARGUMENT,
+    };
+    engine.last_error = None;
+    if variant.is_null() {
+        return RANDOMLIB_INVALID_ARGUMENT;
+    }
+    let variant = match CStr::from_ptr(variant)
+        .to_str()
+        .ok()
+        .and_then(TransactionVariant::from_name)
+    {
+        Some(variant) => variant,
+        None => return RANDOMLIB_INVALID_ARGUMENT,
+    };
+    let amount = if amount.is_null() {
+        None
+    } else {
+        match CStr::from_ptr(amount).to_str().map(str::parse::<Amount>) {
+            Ok(Ok(amount)) => Some(amount),
+            _ => return RANDOMLIB_INVALID_ARGUMENT,
+        }
+    };
+    let tx = Transaction {
+        variant,
+        client,
+        tx,
+        amount,
+        disputed: false,
+        chargeback: false,
+        destination: None,
+        currency: None,
+        to_currency: None,
+        rate: None,
+        timestamp: None,
+    };
+    if !tx.is_valid() {
+        return RANDOMLIB_INVALID_ARGUMENT;
+    }
+    match engine.engine.insert(tx) {
+        Ok(()) => RANDOMLIB_OK,
+        Err(e) => {
+            // Error messages have no NUL bytes
+            engine.last_error = CString::new(e.to_string()).ok();
+            RANDOMLIB_REJECTED
+        }
+    }
+}
+
+/// Writes the account of `client` to `account`.
+///
+/// # Safety
+///
+/// `engine` must be a live engine and `account` valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn randomlib_engine_account(
+    engine: *const FfiEngine,
+    client: u16,
+    account: *mut RandomlibAccount,
+) -> c_int {
+    let engine = match engine.as_ref() {
+        Some(engine) if !account.is_null() => engine,
+        _ => return RANDOMLIB_INVALID_ARGUMENT,
+    };
+    let found = match engine.engine.account(client) {
+        Some(found) => found,
+        None => return RANDOMLIB_NOT_FOUND,
+    };
+    let funds = [found.available(), found.held(), found.total()].map(ten_thousandths);
+    match funds {
+        [Some(available), Some(held), Some(total)] => {
+            *account = RandomlibAccount {
+                client,
+                available,
+                held,
+                total,
+                locked: found.locked(),
+                closed: found.closed(),
+            };
+            RANDOMLIB_OK
+        }
+        _ => RANDOMLIB_OVERFLOW,
+    }
+}
+
+/// The message of the transaction rejected by the last call of
+/// [`randomlib_engine_insert`], or null if that call did not return
+/// [`RANDOMLIB_REJECTED`]. Valid until the next call with `engine`.
+///
+/// # Safety
+///
+/// `engine` must be a live engine.
+#[no_mangle]
+pub unsafe extern "C" fn randomlib_engine_last_error(engine: *const FfiEngine) -> *const c_char {
+    engine
+        .as_ref()
+        .and_then(|engine| engine.last_error.as_ref())
+        .map_or(ptr::null(), |message| message.as_ptr())
+}
+
+fn ten_thousandths(amount: Amount) -> Option<i64> {
+    Decimal::from(amount)
+        .checked_mul(Decimal::from(10_000))?
+        .to_i64()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn submits_and_queries() {
+        let deposit = CString::new("deposit").unwrap();
+        let withdrawal = CString::new("withdrawal").unwrap();
+        let amount = CString::new("1.5").unwrap();
+        let too_much = CString::new("2.0").unwrap();
+        unsafe {
+            let engine = randomlib_engine_new();
+            assert_eq!(
+                randomlib_engine_insert(engine, deposit.as_ptr(), 1, 1, amount.as_ptr()),
+                RANDOMLIB_OK
+            );
+            assert!(randomlib_engine_last_error(engine).is_null());
+            assert_eq!(
+                randomlib_engine_insert(engine, withdrawal.as_ptr(), 1, 2, too_much.as_ptr()),
+                RANDOMLIB_REJECTED
+            );
+            let message = CStr::from_ptr(randomlib_engine_last_error(engine));
+            assert!(message.to_str().unwrap().starts_with("Insufficient funds"));
+            assert_eq!(
+                randomlib_engine_insert(engine, withdrawal.as_ptr(), 1, 3, ptr::null()),
+                RANDOMLIB_INVALID_ARGUMENT
+            );
+            assert!(randomlib_engine_last_error(engine).is_null());
+
+            let mut account = RandomlibAccount::default();
+            assert_eq!(
+                randomlib_engine_account(engine, 2, &mut account),
+                RANDOMLIB_NOT_FOUND
+            );
+            assert_eq!(
+                randomlib_engine_account(engine, 1, &mut account),
+                RANDOMLIB_OK
+            );
+            assert_eq!(account.available, 15_000);
+            assert_eq!(account.total, 15_000);
+            randomlib_engine_free(engine);
+        }
+    }
+}
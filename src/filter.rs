@@ -0,0 +1,455 @@
+//! A small expression language to select accounts, e.g. `held > 0 && !locked`.
+//!
+//! Expressions can reference the account fields `client`, `available`, `held`,
+//! `total`, `locked`, `closed` and `frozen`, number and boolean literals, the
+//! comparison operators `==`, `!=`, `<`, `<=`, `>` and `>=`, the logical
+//! operators `&&`, `||` and `!`, and parentheses. Expressions are type checked when parsed, so a parsed
+//! [`Filter`] can always be evaluated.
+
+use std::{fmt::Display, str::FromStr};
+
+use rust_decimal::Decimal;
+use thiserror::Error;
+
+use crate::account::Account;
+
+#[derive(Debug, PartialEq, Error)]
+pub enum FilterError {
+    #[error("Unexpected character `{0}` in filter")]
+    UnexpectedCharacter(char),
+    #[error("Unexpected end of filter")]
+    UnexpectedEnd,
+    #[error("Unexpected `{0}` in filter")]
+    UnexpectedToken(String),
+    #[error("Unknown field `{0}` in filter")]
+    UnknownField(String),
+    #[error("Invalid number `{0}` in filter")]
+    InvalidNumber(String),
+    #[error("Expected a {expected} expression in filter but found a {found} expression")]
+    TypeMismatch {
+        expected: &'static str,
+        found: &'static str,
+    },
+}
+
+/// A parsed filter expression that can be matched against [`Account`]s.
+///
+/// # Examples
+///
+/// ```
+/// use randomlib::{Account, Filter};
+///
+/// let filter: Filter = "held > 0 && !locked".parse().unwrap();
+/// assert!(!filter.matches(&Account::new(1)));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter {
+    source: String,
+    expr: Expr,
+}
+
+impl Filter {
+    pub fn matches(&self, account: &Account) -> bool {
+        matches!(self.expr.eval(account), Value::Bool(true))
+    }
+}
+
+impl FromStr for Filter {
+    type Err = FilterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(s)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.or()?;
+        if let Some(token) = parser.tokens.get(parser.pos) {
+            return Err(FilterError::UnexpectedToken(token.to_string()));
+        }
+        expect_type(&expr, Type::Bool)?;
+        Ok(Self {
+            source: s.to_string(),
+            expr,
+        })
+    }
+}
+
+impl Display for Filter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    Client,
+    Available,
+    Held,
+    Total,
+    Locked,
+    Closed,
+    Frozen,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Field(Field),
+    Number(Decimal),
+    Bool(bool),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Compare(Op, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Type {
+    Number,
+    Bool,
+}
+
+impl Type {
+    fn name(&self) -> &'static str {
+        match self {
+            Type::Number => "number",
+            Type::Bool => "boolean",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Value {
+    Number(Decimal),
+    Bool(bool),
+}
+
+impl Expr {
+    fn ty(&self) -> Type {
+        match self {
+            Expr::Field(Field::Locked | Field::Closed | Field::Frozen) | Expr::Bool(_) => {
+                Type::Bool
+            }
+            Expr::Field(_) | Expr::Number(_) => Type::Number,
+            Expr::Not(_) | Expr::And(..) | Expr::Or(..) | Expr::Compare(..) => Type::Bool,
+        }
+    }
+
+    fn eval(&self, account: &Account) -> Value {
+        match self {
+            Expr::Field(field) => match field {
+                Field::Client => Value::Number(Decimal::from(account.client())),
+                Field::Available => Value::Number(account.available().into()),
+                Field::Held => Value::Number(account.held().into()),
+                Field::Total => Value::Number(account.total().into()),
+                Field::Locked => Value::Bool(account.locked()),
+                Field::Closed => Value::Bool(account.closed()),
+                Field::Frozen => Value::Bool(account.frozen()),
+            },
+            Expr::Number(n) => Value::Number(*n),
+            Expr::Bool(b) => Value::Bool(*b),
+            Expr::Not(e) => Value::Bool(!e.eval(account).as_bool()),
+            Expr::And(l, r) => Value::Bool(l.eval(account).as_bool() && r.eval(account).as_bool()),
+            Expr::Or(l, r) => Value::Bool(l.eval(account).as_bool() || r.eval(account).as_bool()),
+            Expr::Compare(op, l, r) => {
+                let result = match (l.eval(account), r.eval(account)) {
+                    (Value::Number(l), Value::Number(r)) => compare(*op, l, r),
+                    (Value::Bool(l), Value::Bool(r)) => compare(*op, l, r),
+                    // Ruled out by the type check when parsing
+                    _ => false,
+                };
+                Value::Bool(result)
+            }
+        }
+    }
+}
+
+impl Value {
+    fn as_bool(&self) -> bool {
+        matches!(self, Value::Bool(true))
+    }
+}
+
+fn compare<T: PartialOrd>(op: Op, l: T, r: T) -> bool {
+    match op {
+        Op::Eq => l == r,
+        Op::Ne => l != r,
+        Op::Lt => l < r,
+        Op::Le => l <= r,
+        Op::Gt => l > r,
+        Op::Ge => l >= r,
+    }
+}
+
+fn expect_type(expr: &Expr, expected: Type) -> Result<(), FilterError> {
+    let found = expr.ty();
+    if found != expected {
+        return Err(FilterError::TypeMismatch {
+            expected: expected.name(),
+            found: found.name(),
+        });
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(String),
+    Op(&'static str),
+    LParen,
+    RParen,
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Ident(s) | Token::Number(s) => write!(f, "{}", s),
+            Token::Op(s) => write!(f, "{}", s),
+            Token::LParen => write!(f, "("),
+            Token::RParen => write!(f, ")"),
+        }
+    }
+}
+
+const OPERATORS: [&str; 9] = ["&&", "||", "==", "!=", "<=", ">=", "<", ">", "!"];
+
+fn tokenize(s: &str) -> Result<Vec<Token>, FilterError> {
+    let mut tokens = Vec::new();
+    let mut rest = s;
+    while let Some(c) = rest.chars().next() {
+        if c.is_whitespace() {
+            rest = &rest[c.len_utf8()..];
+        } else if c == '(' {
+            tokens.push(Token::LParen);
+            rest = &rest[1..];
+        } else if c == ')' {
+            tokens.push(Token::RParen);
+            rest = &rest[1..];
+        } else if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        } else if c.is_ascii_digit() {
+            let end = rest
+                .find(|c: char| !c.is_ascii_digit() && c != '.')
+                .unwrap_or(rest.len());
+            tokens.push(Token::Number(rest[..end].to_string()));
+            rest = &rest[end..];
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let end = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            tokens.push(Token::Ident(rest[..end].to_string()));
+            rest = &rest[end..];
+        } else {
+            return Err(FilterError::UnexpectedCharacter(c));
+        }
+    }
+    Ok(tokens)
+}
+
+/// A recursive descent parser with the usual precedence: `||` binds the
+/// loosest, then `&&`, then comparisons and finally `!`.
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<Token, FilterError> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or(FilterError::UnexpectedEnd)?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn eat(&mut self, op: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Op(o)) if *o == op) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    fn or(&mut self) -> Result<Expr, FilterError> {
+        let mut expr = self.and()?;
+        while self.eat("||") {
+            let rhs = self.and()?;
+            expect_type(&expr, Type::Bool)?;
+            expect_type(&rhs, Type::Bool)?;
+            expr = Expr::Or(Box::new(expr), Box::new(rhs));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, FilterError> {
+        let mut expr = self.comparison()?;
+        while self.eat("&&") {
+            let rhs = self.comparison()?;
+            expect_type(&expr, Type::Bool)?;
+            expect_type(&rhs, Type::Bool)?;
+            expr = Expr::And(Box::new(expr), Box::new(rhs));
+        }
+        Ok(expr)
+    }
+
+    fn comparison(&mut self) -> Result<Expr, FilterError> {
+        let lhs = self.unary()?;
+        let op = match self.peek() {
+            Some(Token::Op("==")) => Op::Eq,
+            Some(Token::Op("!=")) => Op::Ne,
+            Some(Token::Op("<")) => Op::Lt,
+            Some(Token::Op("<=")) => Op::Le,
+            Some(Token::Op(">")) => Op::Gt,
+            Some(Token::Op(">=")) => Op::Ge,
+            _ => return Ok(lhs),
+        };
+        self.pos += 1;
+        let rhs = self.unary()?;
+        expect_type(&rhs, lhs.ty())?;
+        if lhs.ty() == Type::Bool && !matches!(op, Op::Eq | Op::Ne) {
+            return Err(FilterError::TypeMismatch {
+                expected: Type::Number.name(),
+                found: Type::Bool.name(),
+            });
+        }
+        Ok(Expr::Compare(op, Box::new(lhs), Box::new(rhs)))
+    }
+
+    fn unary(&mut self) -> Result<Expr, FilterError> {
+        if self.eat("!") {
+            let expr = self.unary()?;
+            expect_type(&expr, Type::Bool)?;
+            return Ok(Expr::Not(Box::new(expr)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr, FilterError> {
+        match self.next()? {
+            Token::LParen => {
+                let expr = self.or()?;
+                match self.next()? {
+                    Token::RParen => Ok(expr),
+                    token => Err(FilterError::UnexpectedToken(token.to_string())),
+                }
+            }
+            Token::Number(n) => Decimal::from_str(&n)
+                .map(Expr::Number)
+                .map_err(|_| FilterError::InvalidNumber(n)),
+            Token::Ident(ident) => match ident.as_str() {
+                "client" => Ok(Expr::Field(Field::Client)),
+                "available" => Ok(Expr::Field(Field::Available)),
+                "held" => Ok(Expr::Field(Field::Held)),
+                "total" => Ok(Expr::Field(Field::Total)),
+                "locked" => Ok(Expr::Field(Field::Locked)),
+                "closed" => Ok(Expr::Field(Field::Closed)),
+                "frozen" => Ok(Expr::Field(Field::Frozen)),
+                "true" => Ok(Expr::Bool(true)),
+                "false" => Ok(Expr::Bool(false)),
+                _ => Err(FilterError::UnknownField(ident)),
+            },
+            token => Err(FilterError::UnexpectedToken(token.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Amount, EngineConfig, TransactionVariant};
+
+    fn account(available: i64, held: i64, locked: bool) -> Account {
+        let config = EngineConfig::default();
+        let mut account = Account::new(7);
+        account
+            .transaction(
+                &TransactionVariant::Deposit,
+                Amount::new(available + held, 0).unwrap(),
+                &config,
+            )
+            .unwrap();
+        account
+            .transaction(
+                &TransactionVariant::Dispute,
+                Amount::new(held, 0).unwrap(),
+                &config,
+            )
+            .unwrap();
+        if locked {
+            account
+                .transaction(&TransactionVariant::Chargeback, Amount::zero(), &config)
+                .unwrap();
+        }
+        account
+    }
+
+    fn matches(filter: &str, account: &Account) -> bool {
+        filter.parse::<Filter>().unwrap().matches(account)
+    }
+
+    #[test]
+    fn evaluates_expressions() {
+        let held = account(5, 3, false);
+        let locked = account(5, 3, true);
+        assert!(matches("held > 0 && !locked", &held));
+        assert!(!matches("held > 0 && !locked", &locked));
+        assert!(matches("available < 10.0", &held));
+        assert!(matches("client == 7 || locked", &held));
+        assert!(matches("!(total != 8)", &held));
+        assert!(matches("locked == true", &locked));
+    }
+
+    #[test]
+    fn rejects_invalid_expressions() {
+        let cases = [
+            ("held >", FilterError::UnexpectedEnd),
+            (
+                "balance > 0",
+                FilterError::UnknownField("balance".to_string()),
+            ),
+            ("held $ 0", FilterError::UnexpectedCharacter('$')),
+            ("(held > 0", FilterError::UnexpectedEnd),
+            ("held > 0 0", FilterError::UnexpectedToken("0".to_string())),
+            ("1.2.3 > 0", FilterError::InvalidNumber("1.2.3".to_string())),
+            (
+                "held",
+                FilterError::TypeMismatch {
+                    expected: "boolean",
+                    found: "number",
+                },
+            ),
+            (
+                "locked > 0",
+                FilterError::TypeMismatch {
+                    expected: "boolean",
+                    found: "number",
+                },
+            ),
+            (
+                "!held",
+                FilterError::TypeMismatch {
+                    expected: "boolean",
+                    found: "number",
+                },
+            ),
+        ];
+        for (filter, error) in cases {
+            assert_eq!(filter.parse::<Filter>().unwrap_err(), error, "{}", filter);
+        }
+    }
+}
@@ -0,0 +1,333 @@
+use std::error::Error;
+use std::io::{self, BufRead};
+use std::str::FromStr;
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::cancel::ResumePoint;
+
+/// The format transactions are read in and accounts are written in.
+///
+/// JSON input is an array of objects and NDJSON input one object per line,
+/// with the columns of the CSV input as keys. Values can be strings or
+/// numbers, and missing keys or `null` are like empty columns. The line of a
+/// [`RowError`](crate::RowError) is the position of the object in the array
+/// for JSON and its line for NDJSON, both starting at 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    #[default]
+    Csv,
+    /// CSV separated by tabs
+    Tsv,
+    Json,
+    /// A JSON object per line
+    Ndjson,
+}
+
+impl Format {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::Tsv => "tsv",
+            Format::Json => "json",
+            Format::Ndjson => "ndjson",
+        }
+    }
+
+    /// Whether the first row written in this format is a header.
+    pub(crate) fn has_header(self) -> bool {
+        matches!(self, Format::Csv | Format::Tsv)
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Format::Csv),
+            "tsv" => Ok(Format::Tsv),
+            "json" => Ok(Format::Json),
+            "ndjson" => Ok(Format::Ndjson),
+            _ => Err(format!(
+                "Unknown format `{}`, expected `csv`, `tsv`, `json` or `ndjson`",
+                s
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// The keys read from the objects of JSON and NDJSON input.
+const JSON_COLUMNS: [&str; 10] = [
+    "type",
+    "client",
+    "tx",
+    "amount",
+    "destination",
+    "currency",
+    "to_currency",
+    "rate",
+    "timestamp",
+    "idempotency_key",
+];
+
+/// Reads rows in a [`Format`] as CSV records, so that every format is
+/// processed the same way. The records of JSON and NDJSON have the
+/// [`JSON_COLUMNS`].
+pub(crate) enum RowReader<R: io::Read> {
+    Delimited(csv::Reader<R>),
+    Json {
+        rows: std::vec::IntoIter<Value>,
+        line: u64,
+    },
+    Ndjson {
+        reader: io::BufReader<R>,
+        line: u64,
+        byte: u64,
+    },
+}
+
+impl<R: io::Read> RowReader<R> {
+    /// Starts reading `reader`, which reads all of it for JSON.
+    pub(crate) fn new(reader: R, format: Format) -> Result<Self, Box<dyn Error>> {
+        Ok(match format {
+            Format::Csv => RowReader::Delimited(csv::Reader::from_reader(reader)),
+            Format::Tsv => RowReader::Delimited(
+                csv::ReaderBuilder::new()
+                    .delimiter(b'\t')
+                    .from_reader(reader),
+            ),
+            Format::Json => {
+                let rows: Vec<Value> = serde_json::from_reader(reader)?;
+                RowReader::Json {
+                    rows: rows.into_iter(),
+                    line: 0,
+                }
+            }
+            Format::Ndjson => RowReader::Ndjson {
+                reader: io::BufReader::new(reader),
+                line: 0,
+                byte: 0,
+            },
+        })
+    }
+
+    pub(crate) fn headers(&mut self) -> Result<csv::StringRecord, Box<dyn Error>> {
+        match self {
+            RowReader::Delimited(rdr) => Ok(rdr.headers()?.clone()),
+            _ => Ok(csv::StringRecord::from(JSON_COLUMNS.to_vec())),
+        }
+    }
+
+    /// Reads the next row into `record`, or returns `false` at the end.
+    pub(crate) fn read_record(
+        &mut self,
+        record: &mut csv::StringRecord,
+    ) -> Result<bool, Box<dyn Error>> {
+        match self {
+            RowReader::Delimited(rdr) => Ok(rdr.read_record(record)?),
+            RowReader::Json { rows, line } => {
+                let row = match rows.next() {
+                    Some(row) => row,
+                    None => return Ok(false),
+                };
+                *line += 1;
+                *record = json_record(&row, *line, 0);
+                Ok(true)
+            }
+            RowReader::Ndjson { reader, line, byte } => loop {
+                let mut buf = String::new();
+                let read = reader.read_line(&mut buf)?;
+                if read == 0 {
+                    return Ok(false);
+                }
+                *line += 1;
+                let start = *byte;
+                *byte += read as u64;
+                if buf.trim().is_empty() {
+                    continue;
+                }
+                let row = serde_json::from_str(&buf)
+                    .map_err(|e| format!("Line {}: invalid JSON: {}", line, e))?;
+                *record = json_record(&row, *line, start);
+                return Ok(true);
+            },
+        }
+    }
+
+    /// Where the next row starts. Only the line is known for JSON.
+    pub(crate) fn position(&self) -> ResumePoint {
+        match self {
+            RowReader::Delimited(rdr) => ResumePoint {
+                byte: rdr.position().byte(),
+                line: rdr.position().line(),
+            },
+            RowReader::Json { line, .. } => ResumePoint {
+                byte: 0,
+                line: line + 1,
+            },
+            RowReader::Ndjson { line, byte, .. } => ResumePoint {
+                byte: *byte,
+                line: line + 1,
+            },
+        }
+    }
+}
+
+/// The values of `row` for the [`JSON_COLUMNS`], at `line` and `byte`.
+fn json_record(row: &Value, line: u64, byte: u64) -> csv::StringRecord {
+    let mut record = JSON_COLUMNS
+        .iter()
+        .map(|column| match row.get(column) {
+            None | Some(Value::Null) => String::new(),
+            Some(Value::String(value)) => value.clone(),
+            Some(value) => value.to_string(),
+        })
+        .collect::<csv::StringRecord>();
+    let mut position = csv::Position::new();
+    position.set_line(line).set_byte(byte);
+    record.set_position(Some(position));
+    record
+}
+
+/// Writes rows in a [`Format`]. Call [`RowWriter::finish`] when done.
+pub(crate) enum RowWriter<W: io::Write> {
+    Delimited(Box<csv::Writer<W>>),
+    Json { writer: W, rows: u64 },
+    Ndjson(W),
+}
+
+impl<W: io::Write> RowWriter<W> {
+    pub(crate) fn new(writer: W, format: Format) -> Self {
+        match format {
+            Format::Csv => RowWriter::Delimited(Box::new(csv::Writer::from_writer(writer))),
+            Format::Tsv => RowWriter::Delimited(Box::new(
+                csv::WriterBuilder::new()
+                    .delimiter(b'\t')
+                    .from_writer(writer),
+            )),
+            Format::Json => RowWriter::Json { writer, rows: 0 },
+            Format::Ndjson => RowWriter::Ndjson(writer),
+        }
+    }
+
+    /// Writes `row`, with a header of its field names before the first one
+    /// for CSV and TSV.
+    pub(crate) fn serialize<S: Serialize>(&mut self, row: &S) -> Result<(), Box<dyn Error>> {
+        match self {
+            RowWriter::Delimited(w) => w.serialize(row)?,
+            RowWriter::Json { writer, rows } => {
+                writer.write_all(if *rows == 0 { b"[" } else { b"," })?;
+                serde_json::to_writer(&mut *writer, row)?;
+                *rows += 1;
+            }
+            RowWriter::Ndjson(writer) => {
+                serde_json::to_writer(&mut *writer, row)?;
+                writer.write_all(b"\n")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes a row of `values` in the columns `names`, with a header before
+    /// the `first` one for CSV and TSV.
+    pub(crate) fn write_columns(
+        &mut self,
+        names: &[&str],
+        values: Vec<String>,
+        first: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        if let RowWriter::Delimited(w) = self {
+            if first {
+                w.write_record(names)?;
+            }
+            w.write_record(&values)?;
+            return Ok(());
+        }
+        let row = names
+            .iter()
+            .map(|name| name.to_string())
+            .zip(values.into_iter().map(Value::String))
+            .collect::<Map<_, _>>();
+        self.serialize(&row)
+    }
+
+    /// Ends the output, e.g. the array of JSON, and flushes it.
+    pub(crate) fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+        match self {
+            RowWriter::Delimited(w) => w.flush()?,
+            RowWriter::Json { writer, rows } => {
+                if *rows == 0 {
+                    writer.write_all(b"[")?;
+                }
+                writer.write_all(b"]\n")?;
+                writer.flush()?;
+            }
+            RowWriter::Ndjson(writer) => writer.flush()?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_json_rows_as_records() {
+        let input = r#"[{"type": "deposit", "client": 1, "tx": 2, "amount": "1.5"},
+            {"type": "dispute", "client": 1, "tx": 2, "amount": null}]"#;
+        let mut reader = RowReader::new(input.as_bytes(), Format::Json).unwrap();
+        assert_eq!(reader.headers().unwrap().get(3), Some("amount"));
+        let mut record = csv::StringRecord::new();
+        assert!(reader.read_record(&mut record).unwrap());
+        assert_eq!(
+            record.iter().take(4).collect::<Vec<_>>(),
+            ["deposit", "1", "2", "1.5"]
+        );
+        assert!(reader.read_record(&mut record).unwrap());
+        assert_eq!(record.get(3), Some(""));
+        assert_eq!(record.position().unwrap().line(), 2);
+        assert!(!reader.read_record(&mut record).unwrap());
+    }
+
+    #[test]
+    fn reads_ndjson_lines() {
+        let input = "{\"type\": \"deposit\", \"client\": 1, \"tx\": 1, \"amount\": 2}\n\n{\"type\": \"withdrawal\"}\n";
+        let mut reader = RowReader::new(input.as_bytes(), Format::Ndjson).unwrap();
+        let mut record = csv::StringRecord::new();
+        assert!(reader.read_record(&mut record).unwrap());
+        assert_eq!(record.get(3), Some("2"));
+        assert!(reader.read_record(&mut record).unwrap());
+        assert_eq!(record.get(0), Some("withdrawal"));
+        assert_eq!(record.position().unwrap().line(), 3);
+        assert!(!reader.read_record(&mut record).unwrap());
+    }
+
+    #[test]
+    fn writes_json_arrays() {
+        let mut output = Vec::new();
+        let mut w = RowWriter::new(&mut output, Format::Json);
+        w.write_columns(&["a"], vec!["1".to_string()], true)
+            .unwrap();
+        w.write_columns(&["a"], vec!["2".to_string()], false)
+            .unwrap();
+        w.finish().unwrap();
+        drop(w);
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "[{\"a\":\"1\"},{\"a\":\"2\"}]\n"
+        );
+
+        let mut output = Vec::new();
+        RowWriter::new(&mut output, Format::Json).finish().unwrap();
+        assert_eq!(output, b"[]\n");
+    }
+}
@@ -0,0 +1,282 @@
+//! Randomized transaction streams for load tests and reproducible fixtures.
+//!
+//! The same [`GeneratorConfig`] always produces the same stream.
+
+use std::{collections::HashMap, convert::TryFrom, error::Error, io};
+
+use rust_decimal::{prelude::ToPrimitive, Decimal, RoundingStrategy};
+
+use crate::{Amount, Transaction, TransactionVariant};
+
+/// What [`Generator`] produces.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneratorConfig {
+    /// Clients are numbered from 1.
+    pub clients: u16,
+    /// The length of the stream.
+    pub transactions: usize,
+    /// The chance of a withdrawal of some of the funds of a client, instead
+    /// of a deposit.
+    pub withdrawal_rate: f64,
+    /// The chance of a dispute of an earlier deposit or withdrawal.
+    pub dispute_rate: f64,
+    /// The chance that a dispute ends in a chargeback instead of a resolve.
+    pub chargeback_rate: f64,
+    /// The largest deposit, which has two decimal places like most currencies.
+    pub max_amount: Amount,
+    pub seed: u64,
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        Self {
+            clients: 100,
+            transactions: 1000,
+            withdrawal_rate: 0.3,
+            dispute_rate: 0.01,
+            chargeback_rate: 0.2,
+            max_amount: Amount::new(1000, 0).expect("nonnegative"),
+            seed: 0,
+        }
+    }
+}
+
+/// An iterator of [`GeneratorConfig::transactions`] transactions with ids from
+/// 1 in order.
+///
+/// Disputes reference earlier transactions of the same client and are
+/// resolved or charged back a few transactions later, unless the stream ends
+/// first. Withdrawals take part of the funds the client has been given, and
+/// clients whose account is locked by a chargeback get no more transactions,
+/// so none are rejected unless all accounts are locked.
+///
+/// # Examples
+///
+/// ```
+/// use randomlib::{Generator, GeneratorConfig};
+///
+/// let config = GeneratorConfig {
+///     clients: 10,
+///     transactions: 500,
+///     seed: 7,
+///     ..GeneratorConfig::default()
+/// };
+/// let mut csv = Vec::new();
+/// Generator::new(config.clone()).write_csv(&mut csv).unwrap();
+/// let mut again = Vec::new();
+/// Generator::new(config).write_csv(&mut again).unwrap();
+/// assert_eq!(csv, again);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Generator {
+    config: GeneratorConfig,
+    rng: SplitMix64,
+    generated: usize,
+    next_id: u32,
+    /// The clients whose account is not locked
+    open: Vec<u16>,
+    /// What each client has been given and not withdrawn or disputed
+    funds: HashMap<u16, Decimal>,
+    /// The (client, tx, amount) that can be disputed
+    disputable: Vec<(u16, u32, Decimal)>,
+    /// The (due, client, tx, amount, chargeback) of open disputes
+    disputes: Vec<(usize, u16, u32, Decimal, bool)>,
+}
+
+impl Generator {
+    pub fn new(config: GeneratorConfig) -> Self {
+        Self {
+            rng: SplitMix64(config.seed),
+            open: (1..=config.clients.max(1)).collect(),
+            config,
+            generated: 0,
+            next_id: 1,
+            funds: HashMap::new(),
+            disputable: Vec::new(),
+            disputes: Vec::new(),
+        }
+    }
+
+    /// Writes the rest of the stream as CSV in the input format, with the
+    /// columns `type`, `client`, `tx` and `amount`.
+    pub fn write_csv<W: io::Write>(self, writer: W) -> Result<(), Box<dyn Error>> {
+        let mut writer = csv::Writer::from_writer(writer);
+        writer.write_record(["type", "client", "tx", "amount"])?;
+        for tx in self {
+            let amount = tx
+                .amount
+                .map(|amount| amount.to_string())
+                .unwrap_or_default();
+            writer.write_record([
+                tx.variant.as_str(),
+                &tx.client.to_string(),
+                &tx.tx.to_string(),
+                &amount,
+            ])?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    fn settle(&mut self) -> Option<Transaction> {
+        let generated = self.generated;
+        let index = self
+            .disputes
+            .iter()
+            .position(|(due, ..)| *due <= generated)?;
+        let (_, client, tx, amount, chargeback) = self.disputes.swap_remove(index);
+        let variant = if chargeback {
+            // Nothing more can happen to the account
+            self.open.retain(|open| *open != client);
+            self.disputable.retain(|(other, ..)| *other != client);
+            self.disputes.retain(|(_, other, ..)| *other != client);
+            TransactionVariant::Chargeback
+        } else {
+            *self.funds.entry(client).or_default() += amount;
+            self.disputable.push((client, tx, amount));
+            TransactionVariant::Resolve
+        };
+        Some(transaction(variant, client, tx, None))
+    }
+
+    fn dispute(&mut self) -> Option<Transaction> {
+        if self.disputable.is_empty() || !self.rng.chance(self.config.dispute_rate) {
+            return None;
+        }
+        let index = self.rng.below(self.disputable.len() as u64) as usize;
+        let (client, tx, amount) = self.disputable.swap_remove(index);
+        let due = self.generated + 1 + self.rng.below(50) as usize;
+        let chargeback = self.rng.chance(self.config.chargeback_rate);
+        *self.funds.entry(client).or_default() -= amount;
+        self.disputes.push((due, client, tx, amount, chargeback));
+        Some(transaction(TransactionVariant::Dispute, client, tx, None))
+    }
+
+    fn deposit_or_withdrawal(&mut self) -> Transaction {
+        let client = if self.open.is_empty() {
+            1 + self.rng.below(u64::from(self.config.clients.max(1))) as u16
+        } else {
+            self.open[self.rng.below(self.open.len() as u64) as usize]
+        };
+        let id = self.next_id;
+        self.next_id += 1;
+        let funds = self.funds.entry(client).or_default();
+        let (variant, amount) =
+            if *funds > Decimal::ZERO && self.rng.chance(self.config.withdrawal_rate) {
+                // Up to all of the funds, rounded down to cents
+                let share = Decimal::new(1 + self.rng.below(100) as i64, 2);
+                let amount = (*funds * share).round_dp_with_strategy(2, RoundingStrategy::ToZero);
+                (TransactionVariant::Withdrawal, amount)
+            } else {
+                let max = (Decimal::from(self.config.max_amount) * Decimal::ONE_HUNDRED)
+                    .to_u64()
+                    .unwrap_or(0);
+                let cents = 1 + self.rng.below(max.max(1));
+                (TransactionVariant::Deposit, Decimal::new(cents as i64, 2))
+            };
+        if variant == TransactionVariant::Withdrawal {
+            *funds -= amount;
+        } else {
+            *funds += amount;
+        }
+        self.disputable.push((client, id, amount));
+        let amount = Amount::try_from(amount).ok();
+        transaction(variant, client, id, amount)
+    }
+}
+
+impl Iterator for Generator {
+    type Item = Transaction;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.generated >= self.config.transactions {
+            return None;
+        }
+        let tx = match self.settle().or_else(|| self.dispute()) {
+            Some(tx) => tx,
+            None => self.deposit_or_withdrawal(),
+        };
+        self.generated += 1;
+        Some(tx)
+    }
+}
+
+fn transaction(
+    variant: TransactionVariant,
+    client: u16,
+    tx: u32,
+    amount: Option<Amount>,
+) -> Transaction {
+    Transaction {
+        variant,
+        client,
+        tx,
+        amount,
+        disputed: false,
+        chargeback: false,
+        destination: None,
+        currency: None,
+        to_currency: None,
+        rate: None,
+        timestamp: None,
+    }
+}
+
+/// A small, fast generator of pseudorandom numbers, see
+/// <https://prng.di.unimi.it/splitmix64.c>.
+#[derive(Debug, Clone)]
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number below `n`, which must not be zero.
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    fn chance(&mut self, probability: f64) -> bool {
+        ((self.next() >> 11) as f64 / (1u64 << 53) as f64) < probability
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{process_with_policy, PaymentEngine, ProcessingPolicy};
+
+    #[test]
+    fn generates_consistent_streams() {
+        let config = GeneratorConfig {
+            clients: 100,
+            transactions: 2000,
+            dispute_rate: 0.1,
+            chargeback_rate: 0.2,
+            seed: 42,
+            ..GeneratorConfig::default()
+        };
+        let txs = Generator::new(config.clone()).collect::<Vec<_>>();
+        assert_eq!(txs.len(), 2000);
+        assert!(txs.iter().all(Transaction::is_valid));
+        let count = |variant| txs.iter().filter(|tx| tx.variant == variant).count();
+        assert!(count(TransactionVariant::Dispute) > 100);
+        assert!(count(TransactionVariant::Chargeback) > 10);
+        assert!(count(TransactionVariant::Resolve) > 30);
+
+        let mut csv = Vec::new();
+        Generator::new(config).write_csv(&mut csv).unwrap();
+        let mut engine = PaymentEngine::default();
+        let errors =
+            process_with_policy(csv.as_slice(), &mut engine, ProcessingPolicy::SkipWithLog)
+                .unwrap();
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert!(engine.accounts_iter(true).any(|account| account.locked()));
+        assert!(engine.accounts_iter(true).count() <= 100);
+    }
+}
@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+
+/// Configuration for detecting clients that receive a disproportionate share
+/// of the transactions processed by a [`PaymentEngine`](crate::PaymentEngine).
+///
+/// Skewed workloads serialize on a single account, so it is useful to know
+/// which clients they are.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HotAccountConfig {
+    /// The share of all transactions (between 0 and 1) a client needs to
+    /// receive before it is considered hot.
+    pub min_share: f64,
+    /// The number of transactions that must have been processed in total
+    /// before any client can be considered hot. Prevents every client from
+    /// being flagged at the start of a run.
+    pub min_transactions: u64,
+    /// Whether deposits for hot clients should be accumulated and applied to
+    /// the account in one go instead of one by one.
+    pub accumulate_deposits: bool,
+}
+
+impl Default for HotAccountConfig {
+    fn default() -> Self {
+        Self {
+            min_share: 0.1,
+            min_transactions: 1000,
+            accumulate_deposits: false,
+        }
+    }
+}
+
+/// A client that has been detected as hot.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HotAccount {
+    pub client: u16,
+    /// The number of transactions that referenced the client
+    pub transactions: u64,
+    /// The share of all transactions that referenced the client
+    pub share: f64,
+}
+
+/// Counts transactions per client to find hot clients.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct HotAccountDetector {
+    config: HotAccountConfig,
+    counts: HashMap<u16, u64>,
+    total: u64,
+}
+
+impl HotAccountDetector {
+    pub(crate) fn new(config: HotAccountConfig) -> Self {
+        Self {
+            config,
+            counts: HashMap::new(),
+            total: 0,
+        }
+    }
+
+    pub(crate) fn config(&self) -> &HotAccountConfig {
+        &self.config
+    }
+
+    /// Records a transaction for `client`.
+    pub(crate) fn record(&mut self, client: u16) {
+        *self.counts.entry(client).or_insert(0) += 1;
+        self.total += 1;
+    }
+
+    pub(crate) fn is_hot(&self, client: u16) -> bool {
+        if self.total < self.config.min_transactions {
+            return false;
+        }
+        let count = self.counts.get(&client).copied().unwrap_or(0);
+        count as f64 / self.total as f64 >= self.config.min_share
+    }
+
+    /// Returns all hot clients, the hottest first.
+    pub(crate) fn hot_accounts(&self) -> Vec<HotAccount> {
+        let mut hot = self
+            .counts
+            .iter()
+            .filter(|(client, _)| self.is_hot(**client))
+            .map(|(client, count)| HotAccount {
+                client: *client,
+                transactions: *count,
+                share: *count as f64 / self.total as f64,
+            })
+            .collect::<Vec<_>>();
+        hot.sort_by(|a, b| {
+            b.transactions
+                .cmp(&a.transactions)
+                .then(a.client.cmp(&b.client))
+        });
+        hot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_hot_accounts_before_min_transactions() {
+        let mut detector = HotAccountDetector::new(HotAccountConfig {
+            min_share: 0.5,
+            min_transactions: 10,
+            accumulate_deposits: false,
+        });
+        for _ in 0..9 {
+            detector.record(1);
+        }
+        assert!(!detector.is_hot(1));
+        assert!(detector.hot_accounts().is_empty());
+    }
+
+    #[test]
+    fn detects_skewed_client() {
+        let mut detector = HotAccountDetector::new(HotAccountConfig {
+            min_share: 0.5,
+            min_transactions: 10,
+            accumulate_deposits: false,
+        });
+        for _ in 0..8 {
+            detector.record(1);
+        }
+        detector.record(2);
+        detector.record(3);
+        assert!(detector.is_hot(1));
+        assert!(!detector.is_hot(2));
+        let hot = detector.hot_accounts();
+        assert_eq!(hot.len(), 1);
+        assert_eq!(hot[0].client, 1);
+        assert_eq!(hot[0].transactions, 8);
+    }
+}
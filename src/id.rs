@@ -0,0 +1,128 @@
+//! Ids for transactions originated by the engine, such as corrections.
+
+use std::{collections::VecDeque, fmt::Debug, iter::FromIterator};
+
+/// Generates candidate ids for transactions originated by a
+/// [`PaymentEngine`](crate::PaymentEngine), see
+/// [`PaymentEngine::next_tx_id`](crate::PaymentEngine::next_tx_id).
+///
+/// Candidates that collide with the id of a transaction the engine already
+/// has are skipped by the engine, so generators do not need to know about
+/// ingested transactions.
+pub trait IdGenerator: Debug {
+    /// Returns the next candidate, or `None` once no ids are left.
+    fn next_id(&mut self) -> Option<u32>;
+}
+
+/// Counts up from a starting id. The default starts at
+/// [`CounterIds::DEFAULT_START`].
+///
+/// Upstream ids usually count up from 1, so starting high keeps the ids apart
+/// from those of later inputs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CounterIds {
+    next: Option<u32>,
+}
+
+impl CounterIds {
+    /// The first id of the default counter, leaving the ids below it to
+    /// upstream systems.
+    pub const DEFAULT_START: u32 = 4_000_000_000;
+
+    pub fn new(start: u32) -> Self {
+        Self { next: Some(start) }
+    }
+}
+
+impl Default for CounterIds {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_START)
+    }
+}
+
+impl IdGenerator for CounterIds {
+    fn next_id(&mut self) -> Option<u32> {
+        let id = self.next?;
+        self.next = id.checked_add(1);
+        Some(id)
+    }
+}
+
+/// Prefixes a sequence with the id of a node in the highest
+/// [`SnowflakeIds::NODE_BITS`] bits, so that engines running on different
+/// nodes never generate the same id. Every node has 2^24 ids.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnowflakeIds {
+    node: u8,
+    sequence: u32,
+}
+
+impl SnowflakeIds {
+    /// The number of bits of an id that identify the node.
+    pub const NODE_BITS: u32 = 8;
+
+    pub fn new(node: u8) -> Self {
+        Self { node, sequence: 0 }
+    }
+}
+
+impl IdGenerator for SnowflakeIds {
+    fn next_id(&mut self) -> Option<u32> {
+        let sequence_bits = u32::BITS - Self::NODE_BITS;
+        if self.sequence >> sequence_bits != 0 {
+            return None;
+        }
+        let id = u32::from(self.node) << sequence_bits | self.sequence;
+        self.sequence += 1;
+        Some(id)
+    }
+}
+
+/// Hands out ids supplied by the caller, for example reserved in an upstream
+/// system, in order.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SuppliedIds(VecDeque<u32>);
+
+impl FromIterator<u32> for SuppliedIds {
+    fn from_iter<I: IntoIterator<Item = u32>>(iter: I) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+impl IdGenerator for SuppliedIds {
+    fn next_id(&mut self) -> Option<u32> {
+        self.0.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_from_a_reserved_range_by_default() {
+        let mut counter = CounterIds::default();
+        assert_eq!(counter.next_id(), Some(CounterIds::DEFAULT_START));
+        assert_eq!(counter.next_id(), Some(CounterIds::DEFAULT_START + 1));
+    }
+
+    #[test]
+    fn generators_run_out() {
+        let mut counter = CounterIds::new(u32::MAX - 1);
+        assert_eq!(counter.next_id(), Some(u32::MAX - 1));
+        assert_eq!(counter.next_id(), Some(u32::MAX));
+        assert_eq!(counter.next_id(), None);
+
+        let mut snowflake = SnowflakeIds::new(3);
+        assert_eq!(snowflake.next_id(), Some(3 << 24));
+        assert_eq!(snowflake.next_id(), Some((3 << 24) + 1));
+        snowflake.sequence = (1 << 24) - 1;
+        assert_eq!(snowflake.next_id(), Some((4 << 24) - 1));
+        assert_eq!(snowflake.next_id(), None);
+
+        let mut supplied = SuppliedIds::from_iter(vec![9, 7]);
+        assert_eq!(supplied.next_id(), Some(9));
+        assert_eq!(supplied.next_id(), Some(7));
+        assert_eq!(supplied.next_id(), None);
+    }
+}
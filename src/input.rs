@@ -0,0 +1,110 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Expands the input `paths` to the files to process, in order.
+///
+/// A path whose file name contains `*` (any characters) or `?` (one
+/// character) is replaced by the files in its directory matching it, ordered
+/// by name, so that e.g. `transactions/2024-03-01T*.csv` processes hourly
+/// files in chronological order. Other paths are kept as they are. Fails if
+/// a pattern matches no file.
+pub fn expand_inputs<P: AsRef<Path>>(paths: &[P]) -> io::Result<Vec<PathBuf>> {
+    let mut inputs = Vec::new();
+    for path in paths {
+        let path = path.as_ref();
+        let pattern = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) if name.contains(['*', '?']) => name,
+            _ => {
+                inputs.push(path.to_path_buf());
+                continue;
+            }
+        };
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let mut matches = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let is_match = name
+                .to_str()
+                .is_some_and(|name| matches_pattern(pattern, name));
+            if is_match && entry.file_type()?.is_file() {
+                matches.push(dir.join(name));
+            }
+        }
+        if matches.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No files match `{}`", path.display()),
+            ));
+        }
+        matches.sort();
+        inputs.extend(matches);
+    }
+    Ok(inputs)
+}
+
+/// Whether `name` matches `pattern` with `*` and `?` wildcards.
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let name = name.chars().collect::<Vec<_>>();
+    // The positions after the last `*` in both, to backtrack to when the
+    // rest does not match
+    let (mut p, mut n) = (0, 0);
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, n));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    p = star_p;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_wildcards() {
+        assert!(matches_pattern("*.csv", "a.csv"));
+        assert!(matches_pattern("2024-03-01T??.csv", "2024-03-01T09.csv"));
+        assert!(matches_pattern("a*b*c", "aXbYbZc"));
+        assert!(matches_pattern("*", ""));
+        assert!(!matches_pattern("*.csv", "a.csv.gz"));
+        assert!(!matches_pattern("?.csv", "ab.csv"));
+    }
+
+    #[test]
+    fn expands_patterns_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["02.csv", "01.csv", "notes.txt"] {
+            fs::write(dir.path().join(name), "").unwrap();
+        }
+        let other = dir.path().join("other.csv");
+        let inputs = expand_inputs(&[other.clone(), dir.path().join("*.csv")]).unwrap();
+        assert_eq!(
+            inputs,
+            vec![other, dir.path().join("01.csv"), dir.path().join("02.csv"),]
+        );
+        assert!(expand_inputs(&[dir.path().join("*.json")]).is_err());
+    }
+}
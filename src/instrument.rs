@@ -0,0 +1,66 @@
+//! Spans and timings of the stages of processing an input, which compile to
+//! nothing without the `instrumentation` feature.
+
+#[cfg(feature = "instrumentation")]
+use std::time::{Duration, Instant};
+
+/// The time spent in each stage of processing the rows of an input.
+#[derive(Debug, Default)]
+pub(crate) struct StageTimings {
+    #[cfg(feature = "instrumentation")]
+    deserialize: Duration,
+    #[cfg(feature = "instrumentation")]
+    insert: Duration,
+}
+
+impl StageTimings {
+    /// Deserializes a row with `f` in a `deserialize` span.
+    pub(crate) fn deserialize<R>(&mut self, f: impl FnOnce() -> R) -> R {
+        #[cfg(feature = "instrumentation")]
+        {
+            let start = Instant::now();
+            let result = tracing::trace_span!("deserialize").in_scope(f);
+            self.deserialize += start.elapsed();
+            result
+        }
+        #[cfg(not(feature = "instrumentation"))]
+        f()
+    }
+
+    /// Inserts a transaction into the engine with `f` in an `insert` span.
+    pub(crate) fn insert<R>(&mut self, f: impl FnOnce() -> R) -> R {
+        #[cfg(feature = "instrumentation")]
+        {
+            let start = Instant::now();
+            let result = tracing::trace_span!("insert").in_scope(f);
+            self.insert += start.elapsed();
+            result
+        }
+        #[cfg(not(feature = "instrumentation"))]
+        f()
+    }
+
+    /// Logs the time spent per stage for `rows`.
+    pub(crate) fn report(&self, rows: u64) {
+        #[cfg(feature = "instrumentation")]
+        tracing::info!(
+            rows,
+            deserialize = ?self.deserialize,
+            insert = ?self.insert,
+            "Processed rows"
+        );
+        #[cfg(not(feature = "instrumentation"))]
+        let _ = rows;
+    }
+}
+
+/// Enters an `info` span named `$name` with the given fields until the end of
+/// the enclosing block.
+macro_rules! stage_span {
+    ($name:literal $(, $($fields:tt)*)?) => {
+        #[cfg(feature = "instrumentation")]
+        let _span = tracing::info_span!($name $(, $($fields)*)?).entered();
+    };
+}
+
+pub(crate) use stage_span;
@@ -0,0 +1,296 @@
+use std::{convert::TryFrom, fmt::Display, str::FromStr};
+
+use rust_decimal::{Decimal, RoundingStrategy};
+use thiserror::Error;
+
+use crate::{account::Account, Amount, TransactionVariant};
+
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum RateScheduleError {
+    #[error("Invalid rate `{0}`, expected `<from timestamp>:<rate>`")]
+    InvalidRate(String),
+    #[error("Interest rates cannot be negative, found `{0}`")]
+    NegativeRate(Decimal),
+    #[error("Rates must be sorted by the timestamp they start at")]
+    Unsorted,
+}
+
+/// The interest rates per period over time.
+///
+/// Written as comma separated `<from timestamp>:<rate>` pairs sorted by
+/// timestamp, e.g. `0:0.0001,1700000000:0.0002`. Each rate applies from its
+/// timestamp until the next one, and no interest accrues before the first.
+///
+/// # Examples
+///
+/// ```
+/// use randomlib::RateSchedule;
+///
+/// let schedule: RateSchedule = "0:0.01,100:0.02".parse().unwrap();
+/// assert_eq!(schedule.rate_at(99).unwrap().to_string(), "0.01");
+/// assert_eq!(schedule.rate_at(100).unwrap().to_string(), "0.02");
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateSchedule {
+    rates: Vec<(u64, Decimal)>,
+}
+
+impl RateSchedule {
+    /// The rate in effect at `timestamp`, if any.
+    pub fn rate_at(&self, timestamp: u64) -> Option<Decimal> {
+        self.rates
+            .iter()
+            .take_while(|(from, _)| *from <= timestamp)
+            .last()
+            .map(|(_, rate)| *rate)
+    }
+}
+
+impl FromStr for RateSchedule {
+    type Err = RateScheduleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut rates = Vec::new();
+        for rate in s.split(',') {
+            let (from, value) = rate
+                .split_once(':')
+                .and_then(|(from, value)| {
+                    Some((
+                        from.trim().parse::<u64>().ok()?,
+                        value.trim().parse::<Decimal>().ok()?,
+                    ))
+                })
+                .ok_or_else(|| RateScheduleError::InvalidRate(rate.to_string()))?;
+            if value.is_sign_negative() {
+                return Err(RateScheduleError::NegativeRate(value));
+            }
+            rates.push((from, value));
+        }
+        if rates.windows(2).any(|w| w[0].0 >= w[1].0) {
+            return Err(RateScheduleError::Unsorted);
+        }
+        Ok(Self { rates })
+    }
+}
+
+impl Display for RateSchedule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let rates = self
+            .rates
+            .iter()
+            .map(|(from, rate)| format!("{}:{}", from, rate))
+            .collect::<Vec<_>>();
+        write!(f, "{}", rates.join(","))
+    }
+}
+
+/// Configures interest on the available funds of accounts, credited at the
+/// end of every period, see
+/// [`PaymentEngineBuilder::interest`](crate::PaymentEngineBuilder::interest).
+///
+/// Time only advances through the timestamps of the processed transactions,
+/// see [`PaymentEngine::advance_time`](crate::PaymentEngine::advance_time).
+/// Periods are aligned to multiples of [`InterestConfig::period`] seconds
+/// since the Unix epoch, so a daily period ends at midnight UTC.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InterestConfig {
+    /// The length of a period in seconds.
+    pub period: u64,
+    pub schedule: RateSchedule,
+}
+
+/// Configures a charge or credit for the funds a dispute holds for longer
+/// than [`HeldFundsConfig::after`] seconds, posted for every whole
+/// [`HeldFundsConfig::period`] after that, see
+/// [`PaymentEngine::accrue_held_funds`](crate::PaymentEngine::accrue_held_funds).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HeldFundsConfig {
+    /// Seconds after the dispute was opened, by the clock of the engine
+    pub after: u64,
+    /// The length of a period in seconds
+    pub period: u64,
+    /// The fraction of the held amount posted per period
+    pub rate: Decimal,
+    pub kind: HeldFundsKind,
+}
+
+/// Whether held funds cost the client or earn it something.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HeldFundsKind {
+    /// Posted as a [`TransactionVariant::Fee`], which is credited to the
+    /// fee account if there is one.
+    #[default]
+    Penalty,
+    /// Posted as a [`TransactionVariant::Deposit`].
+    Credit,
+}
+
+impl HeldFundsKind {
+    pub(crate) fn variant(self) -> TransactionVariant {
+        match self {
+            HeldFundsKind::Penalty => TransactionVariant::Fee,
+            HeldFundsKind::Credit => TransactionVariant::Deposit,
+        }
+    }
+}
+
+/// The amount posted for `held` funds over `periods` at `rate`, rounded down
+/// to `decimals` places, at most four, if there is any.
+pub(crate) fn held_funds(
+    held: Amount,
+    rate: Decimal,
+    periods: u64,
+    decimals: u32,
+) -> Option<Amount> {
+    let amount = Decimal::from(held)
+        .checked_mul(rate)?
+        .checked_mul(Decimal::from(periods))?
+        .round_dp_with_strategy(decimals.min(4), RoundingStrategy::ToZero);
+    if amount <= Decimal::ZERO {
+        return None;
+    }
+    Amount::try_from(amount).ok()
+}
+
+/// Keeps track of the period boundaries that have been passed.
+#[derive(Debug)]
+pub(crate) struct InterestAccrual {
+    config: InterestConfig,
+    /// The latest timestamp seen
+    now: Option<u64>,
+}
+
+impl InterestAccrual {
+    pub(crate) fn new(config: InterestConfig) -> Self {
+        Self { config, now: None }
+    }
+
+    /// Moves the clock to `timestamp` and returns the ends of the periods that
+    /// were passed, oldest first, with the rate of the period that ended.
+    /// Timestamps earlier than the latest one are ignored.
+    pub(crate) fn advance(&mut self, timestamp: u64) -> Vec<(u64, Decimal)> {
+        let period = self.config.period.max(1);
+        let now = match self.now {
+            Some(now) if timestamp <= now => return Vec::new(),
+            Some(now) => now,
+            // Nothing has been held for a full period before the first timestamp
+            None => {
+                self.now = Some(timestamp);
+                return Vec::new();
+            }
+        };
+        self.now = Some(timestamp);
+
+        let mut boundaries = Vec::new();
+        let mut end = (now / period + 1) * period;
+        while end <= timestamp {
+            if let Some(rate) = self.config.schedule.rate_at(end - period) {
+                boundaries.push((end, rate));
+            }
+            end += period;
+        }
+        boundaries
+    }
+}
+
+/// The interest `account` earns over a period at `rate`, if any.
+///
+/// Only the available funds earn interest, so held funds do not, and neither
+/// do locked, closed or frozen accounts. Rounded down to the precision of an
+/// [`Amount`]. Balances too large to earn interest without overflowing earn
+/// nothing.
+pub(crate) fn interest(account: &Account, rate: Decimal) -> Option<Amount> {
+    if account.locked() || account.closed() || account.frozen() {
+        return None;
+    }
+    let available = Decimal::from(account.available());
+    if available <= Decimal::ZERO {
+        return None;
+    }
+    let interest = available
+        .checked_mul(rate)?
+        .round_dp_with_strategy(4, RoundingStrategy::ToZero);
+    if interest.is_zero() {
+        return None;
+    }
+    Amount::try_from(interest).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_schedules() {
+        assert!("0:0.01,10:0.02".parse::<RateSchedule>().is_ok());
+        assert_eq!(
+            "10:0.01,0:0.02".parse::<RateSchedule>(),
+            Err(RateScheduleError::Unsorted)
+        );
+        assert_eq!(
+            "0:-0.01".parse::<RateSchedule>(),
+            Err(RateScheduleError::NegativeRate(Decimal::new(-1, 2)))
+        );
+        assert!("0.01".parse::<RateSchedule>().is_err());
+    }
+
+    #[test]
+    fn passes_period_boundaries() {
+        let mut accrual = InterestAccrual::new(InterestConfig {
+            period: 10,
+            schedule: "0:0.01,20:0.02".parse().unwrap(),
+        });
+        assert!(accrual.advance(5).is_empty());
+        assert!(accrual.advance(9).is_empty());
+        let rates = accrual.advance(31);
+        assert_eq!(
+            rates,
+            vec![
+                (10, Decimal::new(1, 2)),
+                (20, Decimal::new(1, 2)),
+                (30, Decimal::new(2, 2))
+            ]
+        );
+        assert!(accrual.advance(25).is_empty());
+    }
+
+    #[test]
+    fn rounds_held_funds_down() {
+        let held = Amount::new(1005, 1).unwrap();
+        let rate = Decimal::new(1, 3);
+        assert_eq!(held_funds(held, rate, 2, 4).unwrap().to_string(), "0.2010");
+        assert_eq!(held_funds(held, rate, 2, 1).unwrap().to_string(), "0.2000");
+        assert_eq!(held_funds(held, rate, 2, 0), None);
+        assert_eq!(held_funds(held, Decimal::ZERO, 2, 4), None);
+    }
+
+    #[test]
+    fn frozen_accounts_earn_nothing() {
+        let mut engine = crate::PaymentEngine::builder()
+            .interest(InterestConfig {
+                period: 10,
+                schedule: "0:0.01".parse().unwrap(),
+            })
+            .build();
+        let input = "type,client,tx,amount,timestamp\n\
+                     deposit,1,1,100.0,5\n\
+                     deposit,2,2,100.0,6\n\
+                     freeze,1,0,,7\n\
+                     deposit,2,3,1.0,15\n";
+        crate::process(input.as_bytes(), &mut engine).unwrap();
+        let total = |client| engine.accounts().get(&client).unwrap().total().to_string();
+        assert_eq!(total(1), "100.0000");
+        assert_eq!(total(2), "102.0000");
+    }
+
+    #[test]
+    fn huge_balances_earn_nothing() {
+        let mut account = Account::new(1);
+        let config = crate::EngineConfig::default();
+        let max = Amount::try_from(Decimal::MAX).unwrap();
+        account
+            .transaction(&crate::TransactionVariant::Deposit, max, &config)
+            .unwrap();
+        assert_eq!(interest(&account, Decimal::TWO), None);
+    }
+}
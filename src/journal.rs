@@ -0,0 +1,56 @@
+//! The undo journal of [`PaymentEngine::rollback`](crate::PaymentEngine::rollback).
+
+use std::collections::VecDeque;
+
+use crate::{Account, Amount, Transaction};
+
+/// The state an applied transaction changed, as it was before.
+#[derive(Debug, Clone)]
+pub(crate) struct JournalEntry {
+    pub(crate) client: u16,
+    pub(crate) tx: u32,
+    /// The accounts of the client and the counterparty, if any, with their
+    /// deposits held back for hot clients
+    pub(crate) accounts: Vec<(u16, Option<Account>, Option<Amount>)>,
+    /// The deposits and withdrawals of the client on its latest day
+    pub(crate) daily: Option<(u64, Amount, Amount)>,
+    /// The stored transaction with the id of the transaction and the state
+    /// kept for it
+    pub(crate) transaction: Option<Transaction>,
+    pub(crate) authorized: bool,
+    pub(crate) partial_dispute: Option<Amount>,
+    pub(crate) represented: bool,
+    pub(crate) dispute_opened: Option<u64>,
+    pub(crate) held_periods: Option<u64>,
+}
+
+/// The most recent [`JournalEntry`]s, up to a capacity.
+#[derive(Debug, Clone)]
+pub(crate) struct Journal {
+    entries: VecDeque<JournalEntry>,
+    capacity: usize,
+}
+
+impl Journal {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    /// Adds `entry`, forgetting the oldest one when full.
+    pub(crate) fn push(&mut self, entry: JournalEntry) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<JournalEntry> {
+        self.entries.pop_back()
+    }
+}
@@ -1,41 +1,220 @@
 mod account;
+mod aggregate;
 mod amount;
+mod atomic;
+mod audit;
+mod builder;
+mod cancel;
+mod changes;
+mod checkpoint;
+mod compression;
+mod config;
+mod config_file;
+mod correction;
+mod currency;
+mod daily;
+mod diff;
+mod dispute;
+mod effective;
 mod engine;
 mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod filter;
+mod format;
+mod generator;
+mod hot;
+mod id;
+mod input;
+mod instrument;
+mod interest;
+mod journal;
+mod limit;
+mod metrics;
+mod observer;
+mod output;
+mod policy;
+mod progress;
+mod prometheus;
+mod prune;
+mod query;
+mod reconcile;
+mod recurring;
+mod rejected;
+mod reservation;
+mod run;
+pub mod server;
+mod simulation;
+mod snapshot;
+pub mod storage;
+pub mod store;
+mod sweep;
+mod template;
+#[cfg(feature = "testing")]
+pub mod testing;
+mod timeline;
 mod transaction;
-
-use error::TransactionError;
+mod validation;
+mod validator;
+mod velocity;
+#[cfg(feature = "wasm")]
+mod wasm;
 
 use std::error::Error;
 use std::io;
 
-pub use amount::Amount;
+pub use account::{Account, Balance, ClientMetadata};
+pub use aggregate::{
+    AggregateField, AggregateFunction, AggregateValue, Aggregation, AggregationError,
+};
+pub use amount::{Amount, RoundingMode};
+pub use atomic::write_atomically;
+pub use audit::{AuditRecord, AuditWriter};
+pub use builder::PaymentEngineBuilder;
+pub use cancel::{CancellationToken, ResumePoint};
+pub use changes::AccountChanges;
+pub use checkpoint::{Checkpoint, CheckpointOptions};
+pub use compression::Compression;
+pub use config::{
+    DailyLimit, DisputeExpiry, EngineConfig, ExpiryOutcome, IdCollision, LockingBehavior,
+    Retention, TierRules, WithdrawalDisputes, WithdrawalLimit,
+};
+pub use config_file::ConfigFile;
+pub use correction::{Corrections, ManualCorrection};
+pub use currency::{Currency, CurrencyError};
+pub use diff::{AccountChange, StateDiff};
+pub use dispute::{DisputeOutcomes, ExpiredDispute, HeldFundsPosting};
+pub use effective::Matured;
 pub use engine::PaymentEngine;
+pub use error::{BatchError, RunError, TransactionError};
+pub use filter::{Filter, FilterError};
+pub use format::Format;
+pub use generator::{Generator, GeneratorConfig};
+pub use hot::{HotAccount, HotAccountConfig};
+pub use id::{CounterIds, IdGenerator, SnowflakeIds, SuppliedIds};
+pub use input::expand_inputs;
+pub use interest::{
+    HeldFundsConfig, HeldFundsKind, InterestConfig, RateSchedule, RateScheduleError,
+};
+pub use limit::QuarantinedClient;
+pub use metrics::{
+    Alert, AlertThreshold, AlertThresholdError, EngineStats, Metric, RunMetrics, VariantStats,
+    ALERT_EXIT_CODE,
+};
+pub use observer::EngineObserver;
+pub use output::{write_accounts, write_accounts_to_path, write_changes, OutputOptions};
+pub use policy::ProcessingPolicy;
+pub use progress::{Progress, ProgressReporter};
+pub use prometheus::{Histogram, ServiceMetrics};
+pub use prune::PruneFilter;
+pub use query::QueryEngine;
+pub use reconcile::{reconcile, reconcile_accounts, Difference};
+pub use recurring::{Occurrence, Schedule, ScheduleState};
+pub use rejected::RejectedRowWriter;
+pub use reservation::{Reservation, ReservationId};
+pub use run::{
+    process, process_with_format, process_with_policy, process_with_progress, run_with_options,
+    RowError, RowErrorKind, RunOptions, RunReport, TimedOut,
+};
+pub use simulation::SimulationResult;
+pub use snapshot::{Snapshot, SnapshotTransaction};
+pub use sweep::{SweepConfig, SweepRecord};
+pub use template::{OutputSchema, OutputTemplate, TemplateColumn, TemplateError};
+pub use timeline::{DailyVolume, Month, MonthError, MonthlyFlow};
 pub use transaction::{Transaction, TransactionVariant};
+pub use validation::{dry_run, validate, DryRunReport, RowOutcome, ValidationReport};
+pub use validator::{AllowedClients, MaxAmount, Validator, WellFormed};
+pub use velocity::{VelocityAction, VelocityConfig, VelocityFlag};
+#[cfg(feature = "wasm")]
+pub use wasm::WasmEngine;
 
+/// Reads transactions as CSV from `reader`, processes them and writes the
+/// resulting accounts as CSV to `writer`, with the default [`RunOptions`].
+///
+/// Stops at the first row that cannot be processed, see
+/// [`ProcessingPolicy::StopOnFirstError`]. This includes rows missing an
+/// amount or having one they should not have and disputes, resolves and
+/// chargebacks of unknown transactions, which were skipped silently before
+/// version 0.2, see the changelog; use [`run_with_options`] with
+/// [`ProcessingPolicy::SkipInvalid`] to skip them.
 pub fn run<R: io::Read, W: io::Write>(reader: R, writer: W) -> Result<(), Box<dyn Error>> {
+    run_with_options(reader, writer, &RunOptions::default())?;
+    Ok(())
+}
+
+/// Processes `input` as CSV like [`run`] and returns the accounts ordered by
+/// client id instead of writing them.
+///
+/// Never panics, whatever the bytes, so it can be used as a fuzz target and
+/// with untrusted input.
+///
+/// # Examples
+///
+/// ```
+/// let accounts = randomlib::run_bytes(b"type,client,tx,amount\ndeposit,1,1,2.5").unwrap();
+/// assert_eq!(accounts[0].total().to_string(), "2.5000");
+/// assert!(randomlib::run_bytes(b"type,client,tx,amount\nwithdrawal,1,1,").is_err());
+/// ```
+pub fn run_bytes(input: &[u8]) -> Result<Vec<Account>, RunError> {
     let mut engine = PaymentEngine::default();
+    process(input, &mut engine)?;
+    Ok(engine.accounts_iter(true).cloned().collect())
+}
 
-    let mut rdr = csv::Reader::from_reader(reader);
-    for result in rdr.deserialize() {
-        let tx: Transaction = result?;
-        if !tx.is_valid() {
-            // TODO: maybe stop processing?
-            continue;
+/// Like [`run`], but skips rows that cannot be processed instead of stopping
+/// and returns them with their line numbers, see
+/// [`ProcessingPolicy::SkipWithLog`].
+pub fn run_lenient<R: io::Read, W: io::Write>(
+    reader: R,
+    writer: W,
+) -> Result<Vec<RowError>, Box<dyn Error>> {
+    let options = RunOptions {
+        policy: ProcessingPolicy::SkipWithLog,
+        ..RunOptions::default()
+    };
+    Ok(run_with_options(reader, writer, &options)?.errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run_bytes_rejects_malformed_input() {
+        let header = "type,client,tx,amount,destination,currency,to_currency,rate\n";
+        for row in [
+            "withdrawal,1,1,,,,,",
+            "deposit,1,1,-1,,,,",
+            "deposit,1,1,1.00001,,,,",
+            "deposit,70000,1,1,,,,",
+            "transfer,1,1,1,1,,,",
+            "convert,1,1,1,,EUR,,",
+            "convert,1,1,1,,EUR,USD,0",
+            "chargeback,1,1,5,,,,",
+            "nonsense,1,1,1,,,,",
+            "deposit,1,1,79228162514264337593543950335,,,,",
+        ] {
+            let input = format!("{}deposit,1,2,1,,,,\n{}\n", header, row);
+            assert!(run_bytes(input.as_bytes()).is_err(), "{}", row);
         }
-        match engine.insert(tx) {
-            // It is ok to ignore disputes that references a transaction that does not exist
-            Err(TransactionError::TransactionNotFound) => (),
-            // All other errors should stop the program
-            Err(e) => return Err(Box::new(e)),
-            _ => (),
+        for input in [&b""[..], b"\xff\xfe", b"type\n\n", b"\"", header.as_bytes()] {
+            let _ = run_bytes(input);
         }
     }
 
-    let mut w = csv::Writer::from_writer(writer);
-    for client in engine.accounts().values() {
-        w.serialize(client)?;
+    #[test]
+    fn run_bytes_keeps_the_row_error() {
+        let input = "type,client,tx,amount\ndeposit,1,1,1.0\nwithdrawal,1,2,5.0\n";
+        match run_bytes(input.as_bytes()) {
+            Err(RunError::Row(RowError {
+                line: 3,
+                kind: RowErrorKind::Transaction(TransactionError::InsufficientFunds { .. }),
+            })) => (),
+            result => panic!("{:?}", result),
+        }
+        assert!(matches!(
+            run_bytes(b"\xff,client\n1,2\n"),
+            Err(RunError::Io(_))
+        ));
     }
-
-    Ok(())
 }
@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+
+/// A client whose transactions exceeded the per client limit, see
+/// [`RunOptions::max_transactions_per_client`](crate::RunOptions::max_transactions_per_client).
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuarantinedClient {
+    pub client: u16,
+    /// The number of transactions that were not processed
+    pub transactions: u64,
+}
+
+/// Caps the number of transactions processed per client.
+#[derive(Debug, Clone)]
+pub(crate) struct ClientLimiter {
+    max: u64,
+    counts: HashMap<u16, u64>,
+    quarantined: HashMap<u16, u64>,
+}
+
+impl ClientLimiter {
+    pub(crate) fn new(max: u64) -> Self {
+        Self {
+            max,
+            counts: HashMap::new(),
+            quarantined: HashMap::new(),
+        }
+    }
+
+    /// Counts a transaction for `client` and returns whether it is within the
+    /// limit. Transactions over the limit are quarantined instead.
+    pub(crate) fn admit(&mut self, client: u16) -> bool {
+        let count = self.counts.entry(client).or_insert(0);
+        if *count < self.max {
+            *count += 1;
+            true
+        } else {
+            *self.quarantined.entry(client).or_insert(0) += 1;
+            false
+        }
+    }
+
+    /// Returns all clients with quarantined transactions, the most quarantined
+    /// first.
+    pub(crate) fn quarantined(&self) -> Vec<QuarantinedClient> {
+        let mut quarantined = self
+            .quarantined
+            .iter()
+            .map(|(client, transactions)| QuarantinedClient {
+                client: *client,
+                transactions: *transactions,
+            })
+            .collect::<Vec<_>>();
+        quarantined.sort_by(|a, b| {
+            b.transactions
+                .cmp(&a.transactions)
+                .then(a.client.cmp(&b.client))
+        });
+        quarantined
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quarantines_transactions_over_limit() {
+        let mut limiter = ClientLimiter::new(2);
+        assert!(limiter.admit(1));
+        assert!(limiter.admit(1));
+        assert!(!limiter.admit(1));
+        assert!(!limiter.admit(1));
+        assert!(limiter.admit(2));
+        assert_eq!(
+            limiter.quarantined(),
+            vec![QuarantinedClient {
+                client: 1,
+                transactions: 2
+            }]
+        );
+    }
+}
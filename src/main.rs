@@ -1,14 +1,1008 @@
-use std::fs::File;
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    fs::File,
+    io::{self, IsTerminal},
+    net::TcpListener,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
-use randomlib::run;
+use clap::{Parser, Subcommand};
+#[cfg(feature = "sqlite")]
+use randomlib::storage::SqliteStorage;
+use randomlib::{
+    dry_run, expand_inputs, process_with_format, process_with_progress, reconcile, server,
+    store::{AccountStore, SpillingTransactionStore, TransactionStore},
+    write_accounts, write_accounts_to_path, write_atomically, write_changes, Aggregation,
+    AlertThreshold, Amount, AuditWriter, Compression, ConfigFile, Corrections, EngineConfig,
+    Filter, Format, InterestConfig, Month, OutputOptions, OutputSchema, PaymentEngine,
+    ProcessingPolicy, ProgressReporter, QueryEngine, RateSchedule, RejectedRowWriter, RoundingMode,
+    RunOptions, Schedule, Snapshot, SweepConfig, TemplateColumn, ALERT_EXIT_CODE,
+};
+use tracing_subscriber::{fmt::format::FmtSpan, EnvFilter};
+use uuid::Uuid;
+
+/// The exit code when the run fails, e.g. as an input cannot be read or a
+/// row is rejected under the `stop-on-first-error` policy. Alerts exit with
+/// [`ALERT_EXIT_CODE`] instead.
+const ERROR_EXIT_CODE: i32 = 1;
+
+/// Writes a message to stderr, prefixed with the run id.
+macro_rules! log {
+    ($run_id:expr, $($arg:tt)*) => {
+        eprintln!("[{}] {}", $run_id, format_args!($($arg)*))
+    };
+}
+
+#[derive(Parser)]
+#[command(args_conflicts_with_subcommands = true)]
+struct Cli {
+    /// Paths to the input files with transactions, processed in order into
+    /// one set of accounts. `*` and `?` in file names match the files in
+    /// their directory, in order of their names. `-` or no path reads from
+    /// stdin
+    inputs: Vec<PathBuf>,
+    /// Read settings from a TOML file whose keys are the long names of these
+    /// flags. Flags override it
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// The format of the input: `csv` (default), `tsv`, `json` or `ndjson`
+    #[arg(long)]
+    input_format: Option<Format>,
+    /// The format of the accounts: `csv` (default), `tsv`, `json` or `ndjson`
+    #[arg(long)]
+    output_format: Option<Format>,
+    /// Identifies this run in logs and snapshots. Randomly generated if not
+    /// set.
+    #[arg(long)]
+    run_id: Option<Uuid>,
+    /// Write the accounts to this path instead of stdout. The file is only
+    /// replaced once it has been written completely. Compressed if the path
+    /// ends with `.gz` or `.zst`
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+    /// The compression of `--output`: `none`, `gzip` or `zstd`. Overrides the
+    /// extension
+    #[arg(long)]
+    compression: Option<Compression>,
+    /// Split `--output` into numbered parts of at most this many accounts,
+    /// e.g. `accounts.part-00001.csv`.
+    #[arg(long)]
+    max_rows_per_part: Option<u64>,
+    /// Split `--output` into numbered parts of at most this many bytes
+    /// before compression.
+    #[arg(long)]
+    max_bytes_per_part: Option<u64>,
+    /// Write a snapshot of the final state to this path, compressed if it ends
+    /// with `.gz` or `.zst`
+    #[arg(long)]
+    snapshot: Option<PathBuf>,
+    /// Start from a snapshot written with `--snapshot` and process only the
+    /// new inputs on top of it. Deposits and withdrawals reusing the id of a
+    /// transaction in the snapshot are rejected
+    #[arg(long, conflicts_with = "max_resident_transactions")]
+    resume: Option<PathBuf>,
+    /// Write the balances of every account before and after each accepted
+    /// transaction as CSV to this path
+    #[arg(long)]
+    audit: Option<PathBuf>,
+    /// Write every rejected or skipped row as CSV to this path, with its
+    /// line, error code and error message appended
+    #[arg(long)]
+    rejected: Option<PathBuf>,
+    /// After each input, write the accounts changed by it to
+    /// `changes-<sequence>.<format>` in this directory
+    #[arg(long)]
+    changes: Option<PathBuf>,
+    /// Only output accounts matching this expression, e.g. `held > 0 && !locked`
+    #[arg(long)]
+    filter: Option<Filter>,
+    /// Compute an aggregation over the accounts and print it to stderr, e.g.
+    /// `low=sum(available) where client < 2000`. Can be repeated
+    #[arg(long = "aggregate")]
+    aggregations: Vec<Aggregation>,
+    /// Write this column instead of the account fields, e.g.
+    /// `utilization={{ held / total }}`. Can be repeated
+    #[arg(long = "column")]
+    columns: Vec<TemplateColumn>,
+    /// Write the accounts in the order they are stored in instead of ordered
+    /// by client id, which saves sorting them
+    #[arg(long)]
+    unsorted: bool,
+    /// Write only these columns in this order, each optionally renamed, e.g.
+    /// `id=client,available,total.EUR`
+    #[arg(long = "columns", conflicts_with = "columns")]
+    schema: Option<OutputSchema>,
+    /// Read custom attributes for `--column` from a CSV file with the columns
+    /// `client`, `name` and `value`
+    #[arg(long)]
+    attributes: Option<PathBuf>,
+    /// Read client metadata from a CSV file with the columns `client`,
+    /// `name`, `tier` and `kyc_status` and add it to the output
+    #[arg(long)]
+    metadata: Option<PathBuf>,
+    /// Exit with code 3 when a metric exceeds a threshold, e.g.
+    /// `chargebacks > 10`. Metrics are `rejected`, `chargebacks` and
+    /// `net_outflow`. Can be repeated
+    #[arg(long = "alert")]
+    alerts: Vec<AlertThreshold>,
+    /// Keep at most this many transactions in memory and spill the rest to disk
+    #[arg(long)]
+    max_resident_transactions: Option<usize>,
+    /// Process at most this many transactions per client and quarantine the
+    /// rest, which are handled by the policy and written to `--rejected`
+    #[arg(long)]
+    max_transactions_per_client: Option<u64>,
+    /// Allow withdrawals to take the available funds of every account below
+    /// zero by at most this amount
+    #[arg(long)]
+    overdraft: Option<Amount>,
+    /// Credit `fee` transactions to the account of this client
+    #[arg(long)]
+    fee_account: Option<u16>,
+    /// Round amounts with more than four decimal places instead of rejecting
+    /// them: `bankers`, `half-up` or `truncate`
+    #[arg(long)]
+    rounding: Option<RoundingMode>,
+    /// Accrue interest on available funds by the `timestamp` column, with
+    /// rates per period given as `<from timestamp>:<rate>,...`
+    #[arg(long)]
+    interest: Option<RateSchedule>,
+    /// The length of an interest period in seconds [default: 86400]
+    #[arg(long)]
+    interest_period: Option<u64>,
+    /// After processing, apply the rows whose `effective` column is at most
+    /// this timestamp, which are otherwise left out
+    #[arg(long)]
+    advance_to: Option<u64>,
+    /// After processing, apply the recurring transactions read from a CSV
+    /// file with the columns `client`, `type`, `amount`, `start`, `interval`
+    /// and `end`, up to `--materialize-until`
+    #[arg(long, requires = "materialize_until")]
+    schedules: Option<PathBuf>,
+    /// The last timestamp of the occurrences of `--schedules`
+    #[arg(long, requires = "schedules")]
+    materialize_until: Option<u64>,
+    /// After processing, move the available funds of accounts without
+    /// transactions for this many seconds, by the `timestamp` column, to
+    /// `--sweep-pool`
+    #[arg(long, requires = "sweep_pool")]
+    sweep_dormant_after: Option<u64>,
+    /// The pool account dormant balances are moved to
+    #[arg(long, requires = "sweep_dormant_after")]
+    sweep_pool: Option<u16>,
+    /// Write an audit record of every swept balance as CSV to this path
+    #[arg(long, requires = "sweep_pool")]
+    sweep_audit: Option<PathBuf>,
+    /// Write the inflow, outflow and net flow of every client per month, by
+    /// the `timestamp` column, as CSV to this path
+    #[arg(long)]
+    monthly_flows: Option<PathBuf>,
+    /// The first month of `--monthly-flows`, e.g. `2024-01`
+    #[arg(long, requires = "monthly_flows")]
+    flows_from: Option<Month>,
+    /// The last month of `--monthly-flows`, e.g. `2024-12`
+    #[arg(long, requires = "monthly_flows")]
+    flows_to: Option<Month>,
+    /// Write the outcomes of disputes per month as CSV to this path
+    #[arg(long)]
+    dispute_report: Option<PathBuf>,
+    /// Group `--dispute-report` by this attribute of the clients, read with
+    /// `--attributes`
+    #[arg(long, requires = "dispute_report")]
+    segment_attribute: Option<String>,
+    /// Report the rows processed, the rate and the time left to stderr
+    #[arg(long)]
+    progress: bool,
+    /// Seconds between progress reports [default: 5]
+    #[arg(long)]
+    progress_interval: Option<u64>,
+    /// How to handle rows that cannot be processed: `stop-on-first-error`
+    /// (default), `skip-invalid` or `skip-with-log`
+    #[arg(long)]
+    policy: Option<ProcessingPolicy>,
+    /// Persist the state to a SQLite database, continuing from its contents
+    #[cfg(feature = "sqlite")]
+    #[arg(long, conflicts_with_all = ["max_resident_transactions", "resume"])]
+    sqlite: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Serve read-only queries against a snapshot over HTTP
+    Query {
+        /// Path to a snapshot written with `--snapshot`
+        snapshot: PathBuf,
+        /// The address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        listen: String,
+    },
+    /// Write the transactions that move the state of a snapshot to a target
+    /// state for review, and report differences that need manual corrections
+    Corrections {
+        /// Path to a snapshot written with `--snapshot`
+        snapshot: PathBuf,
+        /// The target accounts, in the output format
+        #[arg(long)]
+        target: PathBuf,
+        /// Write the transactions to this path instead of stdout
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Compare two account CSVs, e.g. the output of a legacy system, and
+    /// write the differing fields of every client as CSV. Exits with code 1
+    /// if there are any
+    Reconcile {
+        left: PathBuf,
+        right: PathBuf,
+        /// Write the differences to this path instead of stdout
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Report whether every transaction of a file would be accepted and the
+    /// resulting accounts as JSON, without changing any state. Exits with
+    /// code 1 if any would be rejected
+    DryRun {
+        /// Path to the input file with transactions as CSV
+        input: PathBuf,
+        /// Start from a snapshot written with `--snapshot`
+        #[arg(long)]
+        snapshot: Option<PathBuf>,
+        /// Start from the contents of a SQLite database, which is not changed
+        #[cfg(feature = "sqlite")]
+        #[arg(long, conflicts_with = "snapshot")]
+        sqlite: Option<PathBuf>,
+        /// Write the report to this path instead of stdout
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// Serve reservations of funds for external payout systems and
+    /// transactions over HTTP, with metrics for Prometheus at `/metrics`
+    Serve {
+        /// Start from a snapshot written with `--snapshot`
+        #[arg(long)]
+        snapshot: Option<PathBuf>,
+        /// Persist the state to a SQLite database, continuing from its contents
+        #[cfg(feature = "sqlite")]
+        #[arg(long, conflicts_with = "snapshot")]
+        sqlite: Option<PathBuf>,
+        /// The address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        listen: String,
+        /// Keep this many of the latest transactions to reverse with
+        /// `POST /rollback`
+        #[arg(long)]
+        journal: Option<usize>,
+    },
+}
+
+impl Cli {
+    /// Fills in the settings of `file` that were not given as flags.
+    fn merge(&mut self, file: ConfigFile) {
+        fn or<T>(flag: &mut Option<T>, setting: Option<T>) {
+            if flag.is_none() {
+                *flag = setting;
+            }
+        }
+        fn or_all<T>(flags: &mut Vec<T>, settings: Vec<T>) {
+            if flags.is_empty() {
+                *flags = settings;
+            }
+        }
+        or(&mut self.input_format, file.input_format);
+        or(&mut self.output_format, file.output_format);
+        or(&mut self.output, file.output);
+        or(&mut self.compression, file.compression);
+        or(&mut self.max_rows_per_part, file.max_rows_per_part);
+        or(&mut self.max_bytes_per_part, file.max_bytes_per_part);
+        or(&mut self.snapshot, file.snapshot);
+        or(&mut self.audit, file.audit);
+        or(&mut self.rejected, file.rejected);
+        or(&mut self.changes, file.changes);
+        or(&mut self.filter, file.filter);
+        or_all(&mut self.aggregations, file.aggregate);
+        or_all(&mut self.alerts, file.alert);
+        // `--column` and `--columns` conflict, so either replaces both
+        if self.columns.is_empty() && self.schema.is_none() {
+            self.columns = file.column;
+            self.schema = file.columns;
+        }
+        self.unsorted |= file.unsorted.unwrap_or(false);
+        or(&mut self.attributes, file.attributes);
+        or(&mut self.metadata, file.metadata);
+        or(
+            &mut self.max_resident_transactions,
+            file.max_resident_transactions,
+        );
+        or(
+            &mut self.max_transactions_per_client,
+            file.max_transactions_per_client,
+        );
+        or(&mut self.overdraft, file.overdraft);
+        or(&mut self.fee_account, file.fee_account);
+        or(&mut self.rounding, file.rounding);
+        or(&mut self.interest, file.interest);
+        or(&mut self.interest_period, file.interest_period);
+        self.progress |= file.progress.unwrap_or(false);
+        or(&mut self.progress_interval, file.progress_interval);
+        or(&mut self.policy, file.policy);
+        #[cfg(feature = "sqlite")]
+        or(&mut self.sqlite, file.sqlite);
+    }
+
+    /// Checks the constraints between settings that can also come from the
+    /// configuration file, which clap only sees on the command line.
+    fn check(&self) -> Result<(), String> {
+        let requires = [
+            (
+                "compression",
+                self.compression.is_some(),
+                "output",
+                self.output.is_some(),
+            ),
+            (
+                "max-rows-per-part",
+                self.max_rows_per_part.is_some(),
+                "output",
+                self.output.is_some(),
+            ),
+            (
+                "max-bytes-per-part",
+                self.max_bytes_per_part.is_some(),
+                "output",
+                self.output.is_some(),
+            ),
+            (
+                "interest-period",
+                self.interest_period.is_some(),
+                "interest",
+                self.interest.is_some(),
+            ),
+            (
+                "progress-interval",
+                self.progress_interval.is_some(),
+                "progress",
+                self.progress,
+            ),
+        ];
+        for (setting, given, required, present) in requires {
+            if given && !present {
+                return Err(format!("`{}` requires `{}`", setting, required));
+            }
+        }
+        let conflicts = [
+            (
+                "resume",
+                self.resume.is_some(),
+                "max-resident-transactions",
+                self.max_resident_transactions.is_some(),
+            ),
+            (
+                "column",
+                !self.columns.is_empty(),
+                "columns",
+                self.schema.is_some(),
+            ),
+            #[cfg(feature = "sqlite")]
+            (
+                "sqlite",
+                self.sqlite.is_some(),
+                "max-resident-transactions",
+                self.max_resident_transactions.is_some(),
+            ),
+            #[cfg(feature = "sqlite")]
+            (
+                "sqlite",
+                self.sqlite.is_some(),
+                "resume",
+                self.resume.is_some(),
+            ),
+        ];
+        for (setting, given, other, present) in conflicts {
+            if given && present {
+                return Err(format!("`{}` cannot be used with `{}`", setting, other));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Logs how long every stage took when its span closes.
+#[cfg(feature = "instrumentation")]
+fn span_events() -> FmtSpan {
+    FmtSpan::CLOSE
+}
+
+#[cfg(not(feature = "instrumentation"))]
+fn span_events() -> FmtSpan {
+    FmtSpan::NONE
+}
 
 fn main() {
-    let args = std::env::args().collect::<Vec<_>>();
-    let input_file = args.get(1).expect("Path to input file to be provided");
+    // Structured logs are off unless enabled with `RUST_LOG`
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .with_writer(io::stderr)
+        .with_ansi(io::stderr().is_terminal())
+        .with_span_events(span_events())
+        .init();
+    let mut cli = Cli::parse();
+    if let Some(path) = &cli.config {
+        match ConfigFile::load(path) {
+            Ok(file) => cli.merge(file),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(ERROR_EXIT_CODE);
+            }
+        }
+    }
+    if let Err(e) = cli.check() {
+        eprintln!("{}", e);
+        std::process::exit(ERROR_EXIT_CODE);
+    }
+
+    match cli.command {
+        Some(Command::Query { snapshot, listen }) => {
+            let snapshot = or_exit(Snapshot::load(&snapshot), snapshot.display());
+            let mut query = QueryEngine::from_snapshot(snapshot);
+            let listener = or_exit(TcpListener::bind(&listen), &listen);
+            if let Err(e) = server::serve(listener, &mut query) {
+                eprintln!("{}", e);
+                std::process::exit(ERROR_EXIT_CODE);
+            }
+        }
+        Some(Command::Reconcile {
+            left,
+            right,
+            output,
+        }) => {
+            let left = or_exit(File::open(&left), left.display());
+            let right = or_exit(File::open(&right), right.display());
+            let differences = match reconcile(left, right) {
+                Ok(differences) => differences,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(ERROR_EXIT_CODE);
+                }
+            };
+            let write = |file: &mut dyn io::Write| -> Result<(), Box<dyn std::error::Error>> {
+                let mut w = csv::Writer::from_writer(file);
+                for difference in &differences {
+                    w.serialize(difference)?;
+                }
+                w.flush()?;
+                Ok(())
+            };
+            let written = match output {
+                Some(path) => write_atomically(path, |file| write(file)),
+                None => write(&mut io::stdout()),
+            };
+            if let Err(e) = written {
+                eprintln!("{}", e);
+                std::process::exit(ERROR_EXIT_CODE);
+            }
+            eprintln!("{} differences", differences.len());
+            if !differences.is_empty() {
+                std::process::exit(1);
+            }
+        }
+        Some(Command::DryRun {
+            input,
+            snapshot,
+            #[cfg(feature = "sqlite")]
+            sqlite,
+            output,
+        }) => {
+            let engine = match snapshot {
+                Some(path) => {
+                    PaymentEngine::from_snapshot(or_exit(Snapshot::load(&path), path.display()))
+                }
+                None => PaymentEngine::default(),
+            };
+            #[cfg(feature = "sqlite")]
+            let engine = match sqlite {
+                Some(path) => open_sqlite(&path),
+                None => engine,
+            };
+            let f = match File::open(&input) {
+                Ok(f) => f,
+                Err(e) => {
+                    eprintln!("{}: {}", input.display(), e);
+                    std::process::exit(ERROR_EXIT_CODE);
+                }
+            };
+            let report = match dry_run(f, &engine) {
+                Ok(report) => report,
+                Err(e) => {
+                    eprintln!("{}: {}", input.display(), e);
+                    std::process::exit(ERROR_EXIT_CODE);
+                }
+            };
+            let write = |file: &mut dyn io::Write| -> Result<(), Box<dyn std::error::Error>> {
+                serde_json::to_writer_pretty(&mut *file, &report)?;
+                writeln!(file)?;
+                Ok(())
+            };
+            let written = match output {
+                Some(path) => write_atomically(path, |file| write(file)),
+                None => write(&mut io::stdout()),
+            };
+            if let Err(e) = written {
+                eprintln!("{}", e);
+                std::process::exit(ERROR_EXIT_CODE);
+            }
+            let rejected = report.rows.iter().filter(|row| !row.is_accepted()).count();
+            eprintln!(
+                "{} of {} rows would be rejected",
+                rejected,
+                report.rows.len()
+            );
+            if rejected > 0 {
+                std::process::exit(1);
+            }
+        }
+        Some(Command::Corrections {
+            snapshot,
+            target,
+            output,
+        }) => {
+            let snapshot = or_exit(Snapshot::load(&snapshot), snapshot.display());
+            let mut engine = PaymentEngine::from_snapshot(snapshot);
+            let target = or_exit(File::open(&target), target.display());
+            let target = match Corrections::read_target(target) {
+                Ok(target) => target,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(ERROR_EXIT_CODE);
+                }
+            };
+            let corrections = match engine.corrections(&target) {
+                Ok(corrections) => corrections,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(ERROR_EXIT_CODE);
+                }
+            };
+            for manual in &corrections.manual {
+                eprintln!("{}", manual);
+            }
+            let written = match output {
+                Some(path) => write_atomically(path, |file| corrections.write(file)),
+                None => corrections.write(std::io::stdout()),
+            };
+            if let Err(e) = written {
+                eprintln!("{}", e);
+                std::process::exit(ERROR_EXIT_CODE);
+            }
+        }
+        Some(Command::Serve {
+            snapshot,
+            #[cfg(feature = "sqlite")]
+            sqlite,
+            listen,
+            journal,
+        }) => {
+            let mut engine = match snapshot {
+                Some(path) => {
+                    PaymentEngine::from_snapshot(or_exit(Snapshot::load(&path), path.display()))
+                }
+                None => PaymentEngine::default(),
+            };
+            #[cfg(feature = "sqlite")]
+            if let Some(path) = sqlite {
+                engine = open_sqlite(&path);
+            }
+            engine.enable_service_metrics();
+            if let Some(capacity) = journal {
+                engine.set_journal(capacity);
+            }
+            let listener = or_exit(TcpListener::bind(&listen), &listen);
+            if let Err(e) = server::serve(listener, &mut engine) {
+                eprintln!("{}", e);
+                std::process::exit(ERROR_EXIT_CODE);
+            }
+        }
+        None => match cli.max_resident_transactions {
+            Some(max_resident) => {
+                let transactions =
+                    or_exit(SpillingTransactionStore::new(max_resident), "Spill file");
+                std::process::exit(run(
+                    PaymentEngine::with_stores(HashMap::new(), transactions),
+                    cli,
+                ));
+            }
+            None => {
+                let engine = match &cli.resume {
+                    Some(path) => match Snapshot::load(path) {
+                        Ok(snapshot) => PaymentEngine::from_snapshot(snapshot),
+                        Err(e) => {
+                            eprintln!("{}: {}", path.display(), e);
+                            std::process::exit(ERROR_EXIT_CODE);
+                        }
+                    },
+                    None => PaymentEngine::default(),
+                };
+                #[cfg(feature = "sqlite")]
+                let engine = match &cli.sqlite {
+                    Some(path) => open_sqlite(path),
+                    None => engine,
+                };
+                std::process::exit(run(engine, cli));
+            }
+        },
+    }
+}
+
+/// Returns the value of `result`, or prints its error after `context`, e.g.
+/// the path it is about, and exits with [`ERROR_EXIT_CODE`].
+fn or_exit<T, E: Display>(result: Result<T, E>, context: impl Display) -> T {
+    match result {
+        Ok(value) => value,
+        Err(e) => {
+            eprintln!("{}: {}", context, e);
+            std::process::exit(ERROR_EXIT_CODE);
+        }
+    }
+}
+
+/// Creates an engine persisting its state to the SQLite database at `path`,
+/// or exits with [`ERROR_EXIT_CODE`] if it cannot be opened.
+#[cfg(feature = "sqlite")]
+fn open_sqlite(path: &Path) -> PaymentEngine {
+    let storage = or_exit(SqliteStorage::open(path), path.display());
+    or_exit(
+        PaymentEngine::with_storage(Box::new(storage)),
+        path.display(),
+    )
+}
+
+/// Processes the inputs of `cli` and returns the exit code, after the engine
+/// has been dropped so that its writers are flushed.
+fn run<A: AccountStore, T: TransactionStore>(engine: PaymentEngine<A, T>, cli: Cli) -> i32 {
+    let stdin = Path::new("-");
+    let inputs = match cli.inputs.as_slice() {
+        [] => Ok(vec![stdin.to_path_buf()]),
+        paths => expand_inputs(paths),
+    };
+    let inputs = match inputs {
+        Ok(inputs) => inputs,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ERROR_EXIT_CODE;
+        }
+    };
 
-    let f = File::open(input_file).expect("Input file to exist");
+    let interest_period = cli.interest_period.unwrap_or(86400);
+    let policy = cli.policy.unwrap_or_default();
+    let input_format = cli.input_format.unwrap_or_default();
+    let options = RunOptions {
+        max_transactions_per_client: cli.max_transactions_per_client,
+        run_id: cli.run_id,
+        interest: cli.interest.map(|schedule| InterestConfig {
+            period: interest_period,
+            schedule,
+        }),
+        config: EngineConfig {
+            fee_account: cli.fee_account,
+            overdraft: cli.overdraft.unwrap_or_else(Amount::zero),
+            rounding: cli.rounding,
+            ..EngineConfig::default()
+        },
+        history: cli.monthly_flows.is_some(),
+        dispute_log: cli.dispute_report.is_some(),
+        ..RunOptions::default()
+    };
+    let mut engine = options.configure(engine);
+    let run_id = engine.run_id();
+    if let Some(path) = &cli.audit {
+        match File::create(path) {
+            Ok(file) => engine.set_audit_writer(AuditWriter::new(io::BufWriter::new(file))),
+            Err(e) => {
+                eprintln!("{}: {}", path.display(), e);
+                return ERROR_EXIT_CODE;
+            }
+        }
+    }
+    if let Some(path) = &cli.rejected {
+        match File::create(path) {
+            Ok(file) => {
+                engine.set_rejected_row_writer(RejectedRowWriter::new(io::BufWriter::new(file)))
+            }
+            Err(e) => {
+                eprintln!("{}: {}", path.display(), e);
+                return ERROR_EXIT_CODE;
+            }
+        }
+    }
+    let output = OutputOptions {
+        filter: cli.filter,
+        aggregations: cli.aggregations,
+        compression: cli.compression,
+        max_rows_per_part: cli.max_rows_per_part,
+        max_bytes_per_part: cli.max_bytes_per_part,
+        template: match cli.schema {
+            Some(schema) => schema.columns,
+            None => cli.columns,
+        },
+        metadata: cli.metadata.is_some(),
+        sorted: !cli.unsorted,
+        format: cli.output_format.unwrap_or_default(),
+    };
+    if let Some(dir) = &cli.changes {
+        if let Err(e) = std::fs::create_dir_all(dir) {
+            eprintln!("{}: {}", dir.display(), e);
+            return ERROR_EXIT_CODE;
+        }
+        engine.track_changes();
+    }
+    if let Some(path) = &cli.attributes {
+        let f = match File::open(path) {
+            Ok(f) => f,
+            Err(e) => {
+                eprintln!("{}: {}", path.display(), e);
+                return ERROR_EXIT_CODE;
+            }
+        };
+        if let Err(e) = engine.read_attributes(f) {
+            eprintln!("{}", e);
+            return ERROR_EXIT_CODE;
+        }
+    }
+    for input in &inputs {
+        let f: Box<dyn io::Read> = if input == stdin {
+            log!(run_id, "Processing stdin");
+            Box::new(io::stdin().lock())
+        } else {
+            log!(run_id, "Processing {}", input.display());
+            match File::open(input) {
+                Ok(f) => Box::new(f),
+                Err(e) => {
+                    eprintln!("{}: {}", input.display(), e);
+                    return ERROR_EXIT_CODE;
+                }
+            }
+        };
+        let processed = if cli.progress {
+            let total_bytes = std::fs::metadata(input).ok().map(|metadata| metadata.len());
+            let interval = Duration::from_secs(cli.progress_interval.unwrap_or(5));
+            let mut progress = ProgressReporter::new(interval, total_bytes, |progress| {
+                log!(run_id, "{}", progress)
+            });
+            process_with_progress(f, &mut engine, policy, input_format, &mut progress)
+        } else {
+            process_with_format(f, &mut engine, policy, input_format)
+        };
+        match processed {
+            Ok(errors) => {
+                for e in errors {
+                    log!(run_id, "{}: {}", input.display(), e);
+                }
+            }
+            Err(e) => {
+                eprintln!("{}: {}", input.display(), e);
+                return ERROR_EXIT_CODE;
+            }
+        }
+        if let Some(dir) = &cli.changes {
+            let changes = engine.take_changes();
+            let path = dir.join(format!(
+                "changes-{:05}.{}",
+                changes.sequence,
+                output.format.as_str()
+            ));
+            let written = write_atomically(&path, |file| {
+                let mut w = io::BufWriter::new(file);
+                write_changes(&engine, &changes, &mut w, &output)?;
+                io::Write::flush(&mut w)?;
+                Ok(())
+            });
+            if let Err(e) = written {
+                eprintln!("{}: {}", path.display(), e);
+                return ERROR_EXIT_CODE;
+            }
+        }
+    }
+    for quarantined in engine.quarantined() {
+        log!(
+            run_id,
+            "Quarantined {} transactions of client {}",
+            quarantined.transactions,
+            quarantined.client
+        );
+    }
+    for (client, amount) in engine.pending_deposits() {
+        log!(
+            run_id,
+            "Accumulated deposit of {} to client {} not applied",
+            amount,
+            client
+        );
+    }
+    if let Some(date) = cli.advance_to {
+        let matured = match engine.advance_to(date) {
+            Ok(matured) => matured,
+            Err(e) => {
+                eprintln!("{}", e);
+                return ERROR_EXIT_CODE;
+            }
+        };
+        for matured in &matured {
+            if let Some(e) = &matured.error {
+                log!(run_id, "Transaction {}: {}", matured.transaction.tx, e);
+            }
+        }
+        log!(
+            run_id,
+            "Applied {} transactions effective by {}",
+            matured.len(),
+            date
+        );
+    }
+    if let (Some(path), Some(until)) = (&cli.schedules, cli.materialize_until) {
+        let f = match File::open(path) {
+            Ok(f) => f,
+            Err(e) => {
+                eprintln!("{}: {}", path.display(), e);
+                return ERROR_EXIT_CODE;
+            }
+        };
+        let schedules = match Schedule::read(f) {
+            Ok(schedules) => schedules,
+            Err(e) => {
+                eprintln!("{}: {}", path.display(), e);
+                return ERROR_EXIT_CODE;
+            }
+        };
+        for schedule in schedules {
+            if let Err(e) = engine.add_schedule(schedule) {
+                eprintln!("{}: {}", path.display(), e);
+                return ERROR_EXIT_CODE;
+            }
+        }
+        let occurrences = match engine.materialize(until) {
+            Ok(occurrences) => occurrences,
+            Err(e) => {
+                eprintln!("{}", e);
+                return ERROR_EXIT_CODE;
+            }
+        };
+        for occurrence in &occurrences {
+            if let Some(e) = &occurrence.error {
+                log!(
+                    run_id,
+                    "Schedule {} at {}: {}",
+                    occurrence.schedule + 1,
+                    occurrence.transaction.timestamp.unwrap_or_default(),
+                    e
+                );
+            }
+        }
+        log!(run_id, "Materialized {} occurrences", occurrences.len());
+    }
+    if let Some(path) = &cli.metadata {
+        let f = match File::open(path) {
+            Ok(f) => f,
+            Err(e) => {
+                eprintln!("{}: {}", path.display(), e);
+                return ERROR_EXIT_CODE;
+            }
+        };
+        if let Err(e) = engine.read_metadata(f) {
+            eprintln!("{}: {}", path.display(), e);
+            return ERROR_EXIT_CODE;
+        }
+    }
+    if let (Some(dormant_after), Some(pool)) = (cli.sweep_dormant_after, cli.sweep_pool) {
+        let config = SweepConfig {
+            dormant_after,
+            pool,
+        };
+        let records = match engine.sweep(&config) {
+            Ok(records) => records,
+            Err(e) => {
+                eprintln!("{}", e);
+                return ERROR_EXIT_CODE;
+            }
+        };
+        log!(
+            run_id,
+            "Swept {} dormant accounts into {}",
+            records.len(),
+            pool
+        );
+        if let Some(path) = &cli.sweep_audit {
+            let written = write_atomically(path, |file| {
+                let mut w = csv::Writer::from_writer(file);
+                for record in &records {
+                    w.serialize(record)?;
+                }
+                w.flush()?;
+                Ok(())
+            });
+            if let Err(e) = written {
+                eprintln!("{}", e);
+                return ERROR_EXIT_CODE;
+            }
+        }
+    }
+    if let Some(path) = &cli.monthly_flows {
+        let from = cli.flows_from.unwrap_or(Month { year: 0, month: 1 });
+        let to = cli.flows_to.unwrap_or(Month {
+            year: u32::MAX,
+            month: 12,
+        });
+        let written = write_atomically(path, |file| {
+            let mut w = csv::Writer::from_writer(file);
+            for flow in engine.monthly_flows(from, to) {
+                w.serialize(flow)?;
+            }
+            w.flush()?;
+            Ok(())
+        });
+        if let Err(e) = written {
+            eprintln!("{}", e);
+            return ERROR_EXIT_CODE;
+        }
+    }
+    if let Some(path) = &cli.dispute_report {
+        let outcomes = engine.dispute_outcomes(cli.segment_attribute.as_deref());
+        let written = write_atomically(path, |file| {
+            let mut w = csv::Writer::from_writer(file);
+            for outcome in outcomes {
+                w.serialize(outcome)?;
+            }
+            w.flush()?;
+            Ok(())
+        });
+        if let Err(e) = written {
+            eprintln!("{}", e);
+            return ERROR_EXIT_CODE;
+        }
+    }
+    let written = match &cli.output {
+        Some(path) => write_accounts_to_path(&engine, path, &output),
+        None => write_accounts(&engine, std::io::stdout(), &output),
+    };
+    match written {
+        Ok(aggregations) => {
+            for aggregation in aggregations {
+                match aggregation.value {
+                    Some(value) => log!(run_id, "{}: {}", aggregation.name, value),
+                    None => log!(run_id, "{}: -", aggregation.name),
+                }
+            }
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            return ERROR_EXIT_CODE;
+        }
+    }
+    if let Some(path) = cli.snapshot {
+        if let Err(e) = engine.snapshot().save(path) {
+            eprintln!("{}", e);
+            return ERROR_EXIT_CODE;
+        }
+    }
+    for line in engine.metrics().to_string().lines() {
+        log!(run_id, "{}", line);
+    }
+    for line in engine.stats().to_string().lines() {
+        log!(run_id, "{}", line);
+    }
 
-    if let Err(e) = run(f, std::io::stdout()) {
-        println!("{}", e);
+    let alerts = cli
+        .alerts
+        .iter()
+        .filter_map(|threshold| threshold.check(engine.metrics()))
+        .collect::<Vec<_>>();
+    if !alerts.is_empty() {
+        for alert in alerts {
+            log!(run_id, "{}", alert);
+        }
+        return ALERT_EXIT_CODE;
     }
+    0
 }
@@ -0,0 +1,438 @@
+//! Metrics collected during a run and alerting thresholds on them.
+
+use std::{collections::BTreeMap, fmt::Display, str::FromStr};
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::TransactionVariant;
+
+/// The exit code used by the binary when an [`AlertThreshold`] is exceeded.
+pub const ALERT_EXIT_CODE: i32 = 3;
+
+/// Counters collected while processing transactions.
+///
+/// The sums only include transactions without a currency, as amounts in
+/// different currencies cannot be added up. They saturate instead of
+/// overflowing.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RunMetrics {
+    /// The number of rows read
+    pub rows: u64,
+    /// The number of rows that could not be processed, including those
+    /// referencing an unknown transaction
+    pub rejected: u64,
+    /// The number of rejected rows by reason, see
+    /// [`RowErrorKind::reason`](crate::RowErrorKind::reason)
+    #[serde(default)]
+    pub rejected_by_reason: BTreeMap<String, u64>,
+    /// The number of rows that were skipped because their client exceeded
+    /// the per client transaction limit
+    pub quarantined: u64,
+    /// The sum of all accepted deposits
+    pub deposited: Decimal,
+    /// The sum of all accepted withdrawals
+    pub withdrawn: Decimal,
+    /// The number of chargebacks
+    pub chargebacks: u64,
+    /// The sum of all charged back transactions
+    pub charged_back: Decimal,
+    /// The sum of all accepted transfers between accounts, which do not
+    /// change [`RunMetrics::net_outflow`]
+    pub transferred: Decimal,
+    /// The sum of all accepted fees
+    pub fees: Decimal,
+    /// The sum of all interest credited, see
+    /// [`PaymentEngine::advance_time`](crate::PaymentEngine::advance_time)
+    pub interest: Decimal,
+    /// The sum of all balances moved from dormant accounts, see
+    /// [`PaymentEngine::sweep`](crate::PaymentEngine::sweep)
+    pub swept: Decimal,
+    /// The number of rows that were skipped because a transaction with the
+    /// same idempotency key had already been applied, see
+    /// [`PaymentEngine::insert_with_key`](crate::PaymentEngine::insert_with_key)
+    pub replayed: u64,
+    /// The number of accounts created by transactions
+    #[serde(default)]
+    pub accounts_created: u64,
+    /// The number of accounts locked by chargebacks
+    #[serde(default)]
+    pub accounts_locked: u64,
+}
+
+impl RunMetrics {
+    /// The money that left the system: withdrawals and chargebacks minus
+    /// deposits. Negative when more was deposited than withdrawn.
+    pub fn net_outflow(&self) -> Decimal {
+        self.withdrawn
+            .saturating_add(self.charged_back)
+            .saturating_sub(self.deposited)
+    }
+
+    /// The number of rows that were applied, or ignored as duplicates.
+    pub fn accepted(&self) -> u64 {
+        self.rows
+            .saturating_sub(self.rejected)
+            .saturating_sub(self.quarantined)
+            .saturating_sub(self.replayed)
+    }
+
+    /// Adds the counters and sums of `other`, see
+    /// [`PaymentEngine::merge`](crate::PaymentEngine::merge).
+    pub(crate) fn merge(&mut self, other: &RunMetrics) {
+        self.rows += other.rows;
+        self.rejected += other.rejected;
+        for (reason, count) in &other.rejected_by_reason {
+            *self.rejected_by_reason.entry(reason.clone()).or_default() += count;
+        }
+        self.quarantined += other.quarantined;
+        self.deposited = self.deposited.saturating_add(other.deposited);
+        self.withdrawn = self.withdrawn.saturating_add(other.withdrawn);
+        self.chargebacks += other.chargebacks;
+        self.charged_back = self.charged_back.saturating_add(other.charged_back);
+        self.transferred = self.transferred.saturating_add(other.transferred);
+        self.fees = self.fees.saturating_add(other.fees);
+        self.interest = self.interest.saturating_add(other.interest);
+        self.swept = self.swept.saturating_add(other.swept);
+        self.replayed += other.replayed;
+        self.accounts_created += other.accounts_created;
+        self.accounts_locked += other.accounts_locked;
+    }
+
+    /// Counts a row rejected for `reason`.
+    pub(crate) fn reject(&mut self, reason: &str) {
+        self.rejected += 1;
+        *self
+            .rejected_by_reason
+            .entry(reason.to_string())
+            .or_default() += 1;
+    }
+
+    /// The number of rows rejected for `reason`, e.g. `insufficient_funds`,
+    /// see [`RunMetrics::rejected_by_reason`].
+    pub fn rejected_for(&self, reason: &str) -> u64 {
+        self.rejected_by_reason.get(reason).copied().unwrap_or(0)
+    }
+
+    /// Returns the value of `metric`.
+    pub fn get(&self, metric: &Metric) -> Decimal {
+        match metric {
+            Metric::Rejected => self.rejected.into(),
+            Metric::RejectedFor(reason) => self.rejected_for(reason).into(),
+            Metric::Chargebacks => self.chargebacks.into(),
+            Metric::NetOutflow => self.net_outflow(),
+        }
+    }
+}
+
+/// The number and sum of the accepted transactions of a variant, see
+/// [`EngineStats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct VariantStats {
+    pub count: u64,
+    /// The sum of the amounts without a currency
+    pub total: Decimal,
+}
+
+impl VariantStats {
+    fn record(&mut self, amount: Option<Decimal>) {
+        self.count += 1;
+        if let Some(amount) = amount {
+            self.total = self.total.saturating_add(amount);
+        }
+    }
+
+    fn merge(&mut self, other: &VariantStats) {
+        self.count += other.count;
+        self.total = self.total.saturating_add(other.total);
+    }
+}
+
+/// The accepted deposits, withdrawals, disputes, resolves and chargebacks of
+/// an engine, see [`PaymentEngine::stats`](crate::PaymentEngine::stats).
+///
+/// The totals of disputes, resolves and chargebacks are the disputed
+/// amounts. Like [`RunMetrics`], the totals only include transactions without
+/// a currency and saturate instead of overflowing.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct EngineStats {
+    pub deposits: VariantStats,
+    pub withdrawals: VariantStats,
+    pub disputes: VariantStats,
+    pub resolves: VariantStats,
+    pub chargebacks: VariantStats,
+}
+
+impl EngineStats {
+    /// Counts an accepted transaction of `variant` over `amount`, if it is
+    /// one of the counted variants.
+    pub(crate) fn record(&mut self, variant: &TransactionVariant, amount: Option<Decimal>) {
+        let stats = match variant {
+            TransactionVariant::Deposit => &mut self.deposits,
+            TransactionVariant::Withdrawal => &mut self.withdrawals,
+            TransactionVariant::Dispute => &mut self.disputes,
+            TransactionVariant::Resolve => &mut self.resolves,
+            TransactionVariant::Chargeback => &mut self.chargebacks,
+            _ => return,
+        };
+        stats.record(amount);
+    }
+
+    /// Adds the counts and totals of `other`, see
+    /// [`PaymentEngine::merge`](crate::PaymentEngine::merge).
+    pub(crate) fn merge(&mut self, other: &EngineStats) {
+        self.deposits.merge(&other.deposits);
+        self.withdrawals.merge(&other.withdrawals);
+        self.disputes.merge(&other.disputes);
+        self.resolves.merge(&other.resolves);
+        self.chargebacks.merge(&other.chargebacks);
+    }
+}
+
+/// Writes a line per variant with its count and total.
+impl Display for EngineStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let variants = [
+            ("Deposits", &self.deposits),
+            ("Withdrawals", &self.withdrawals),
+            ("Disputes", &self.disputes),
+            ("Resolves", &self.resolves),
+            ("Chargebacks", &self.chargebacks),
+        ];
+        for (i, (name, stats)) in variants.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}: {} totalling {}", name, stats.count, stats.total)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes a summary of the run for operators, a line per metric.
+impl Display for RunMetrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Rows read: {}", self.rows)?;
+        writeln!(f, "Accepted: {}", self.accepted())?;
+        writeln!(f, "Rejected: {}", self.rejected)?;
+        for (reason, count) in &self.rejected_by_reason {
+            writeln!(f, "  {}: {}", reason, count)?;
+        }
+        if self.quarantined > 0 {
+            writeln!(f, "Quarantined: {}", self.quarantined)?;
+        }
+        if self.replayed > 0 {
+            writeln!(f, "Replayed: {}", self.replayed)?;
+        }
+        writeln!(f, "Accounts created: {}", self.accounts_created)?;
+        writeln!(f, "Accounts locked: {}", self.accounts_locked)?;
+        writeln!(f, "Deposited: {}", self.deposited)?;
+        write!(f, "Withdrawn: {}", self.withdrawn)
+    }
+}
+
+/// A metric of [`RunMetrics`] that an [`AlertThreshold`] can be declared on.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Metric {
+    Rejected,
+    /// The rows rejected for a reason, written as `rejected.<reason>`, see
+    /// [`RunMetrics::rejected_for`]
+    RejectedFor(String),
+    Chargebacks,
+    NetOutflow,
+}
+
+impl Display for Metric {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Metric::Rejected => write!(f, "rejected"),
+            Metric::RejectedFor(reason) => write!(f, "rejected.{}", reason),
+            Metric::Chargebacks => write!(f, "chargebacks"),
+            Metric::NetOutflow => write!(f, "net_outflow"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Error)]
+pub enum AlertThresholdError {
+    #[error("Threshold `{0}` is not of the form `<metric> > <value>`")]
+    InvalidThreshold(String),
+    #[error(
+        "Unknown metric `{0}`, expected `rejected`, `rejected.<reason>`, `chargebacks` or \
+         `net_outflow`"
+    )]
+    UnknownMetric(String),
+    #[error("Invalid threshold value `{0}`")]
+    InvalidValue(String),
+}
+
+/// A limit on a [`Metric`], written as `<metric> > <value>`.
+///
+/// # Examples
+///
+/// ```
+/// use randomlib::{AlertThreshold, RunMetrics};
+///
+/// let threshold: AlertThreshold = "chargebacks > 1".parse().unwrap();
+/// let metrics = RunMetrics {
+///     chargebacks: 2,
+///     ..RunMetrics::default()
+/// };
+/// assert!(threshold.check(&metrics).is_some());
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlertThreshold {
+    pub metric: Metric,
+    /// The alert fires when the metric is strictly greater than this
+    pub max: Decimal,
+}
+
+impl AlertThreshold {
+    /// Returns an [`Alert`] if `metrics` exceed this threshold.
+    pub fn check(&self, metrics: &RunMetrics) -> Option<Alert> {
+        let value = metrics.get(&self.metric);
+        if value > self.max {
+            Some(Alert {
+                threshold: self.clone(),
+                value,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+impl FromStr for AlertThreshold {
+    type Err = AlertThresholdError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (metric, max) = s
+            .split_once('>')
+            .ok_or_else(|| AlertThresholdError::InvalidThreshold(s.to_string()))?;
+        let metric = match metric.trim() {
+            "rejected" => Metric::Rejected,
+            "chargebacks" => Metric::Chargebacks,
+            "net_outflow" => Metric::NetOutflow,
+            metric => match metric.strip_prefix("rejected.") {
+                Some(reason) if !reason.is_empty() => Metric::RejectedFor(reason.to_string()),
+                _ => return Err(AlertThresholdError::UnknownMetric(metric.to_string())),
+            },
+        };
+        let max = max
+            .trim()
+            .parse()
+            .map_err(|_| AlertThresholdError::InvalidValue(max.trim().to_string()))?;
+        Ok(Self { metric, max })
+    }
+}
+
+impl Display for AlertThreshold {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} > {}", self.metric, self.max)
+    }
+}
+
+/// An [`AlertThreshold`] that was exceeded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alert {
+    pub threshold: AlertThreshold,
+    pub value: Decimal,
+}
+
+impl Display for Alert {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Alert: {} is {}, which exceeds {}",
+            self.threshold.metric, self.value, self.threshold.max
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_thresholds() {
+        let metrics = RunMetrics {
+            rejected: 3,
+            deposited: Decimal::from(10),
+            withdrawn: Decimal::from(8),
+            charged_back: Decimal::from(5),
+            ..RunMetrics::default()
+        };
+        let threshold: AlertThreshold = "net_outflow > 2.5".parse().unwrap();
+        let alert = threshold.check(&metrics).unwrap();
+        assert_eq!(alert.value, Decimal::from(3));
+        assert_eq!(
+            alert.to_string(),
+            "Alert: net_outflow is 3, which exceeds 2.5"
+        );
+
+        let threshold: AlertThreshold = "rejected > 3".parse().unwrap();
+        assert!(threshold.check(&metrics).is_none());
+    }
+
+    #[test]
+    fn alerts_on_rejection_reasons() {
+        let mut metrics = RunMetrics::default();
+        for _ in 0..3 {
+            metrics.reject("insufficient_funds");
+        }
+        metrics.reject("parse");
+        assert_eq!(metrics.rejected_for("insufficient_funds"), 3);
+        assert_eq!(metrics.rejected_for("locked_account"), 0);
+
+        let threshold: AlertThreshold = "rejected.insufficient_funds > 2".parse().unwrap();
+        assert_eq!(
+            threshold.metric,
+            Metric::RejectedFor("insufficient_funds".to_string())
+        );
+        assert_eq!(
+            threshold.check(&metrics).unwrap().to_string(),
+            "Alert: rejected.insufficient_funds is 3, which exceeds 2"
+        );
+        let threshold: AlertThreshold = "rejected.parse > 2".parse().unwrap();
+        assert!(threshold.check(&metrics).is_none());
+        assert_eq!(
+            "rejected. > 2".parse::<AlertThreshold>().unwrap_err(),
+            AlertThresholdError::UnknownMetric("rejected.".to_string())
+        );
+    }
+
+    #[test]
+    fn summarizes_runs() {
+        let mut metrics = RunMetrics {
+            rows: 5,
+            accounts_created: 2,
+            deposited: Decimal::from(10),
+            ..RunMetrics::default()
+        };
+        metrics.reject("insufficient_funds");
+        metrics.reject("parse");
+        metrics.reject("insufficient_funds");
+        assert_eq!(
+            metrics.to_string(),
+            "Rows read: 5\nAccepted: 2\nRejected: 3\n  insufficient_funds: 2\n  parse: 1\n\
+             Accounts created: 2\nAccounts locked: 0\nDeposited: 10\nWithdrawn: 0"
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_thresholds() {
+        assert_eq!(
+            "rejected".parse::<AlertThreshold>().unwrap_err(),
+            AlertThresholdError::InvalidThreshold("rejected".to_string())
+        );
+        assert_eq!(
+            "disputes > 1".parse::<AlertThreshold>().unwrap_err(),
+            AlertThresholdError::UnknownMetric("disputes".to_string())
+        );
+        assert_eq!(
+            "rejected > many".parse::<AlertThreshold>().unwrap_err(),
+            AlertThresholdError::InvalidValue("many".to_string())
+        );
+    }
+}
@@ -0,0 +1,87 @@
+//! Hooks for reacting to state changes of a [`PaymentEngine`](crate::PaymentEngine).
+
+use std::fmt::Debug;
+
+use crate::{Account, Amount, SweepRecord, Transaction};
+
+/// Callbacks invoked by a [`PaymentEngine`](crate::PaymentEngine) after a
+/// transaction has been applied successfully, registered with
+/// [`PaymentEngine::add_observer`](crate::PaymentEngine::add_observer).
+///
+/// All callbacks receive the account after the change. Deposits accumulated
+/// for hot clients are only reflected in the account once they are applied,
+/// see [`HotAccountConfig`](crate::HotAccountConfig). Callbacks for disputes,
+/// resolves and chargebacks receive the transaction they reference, which
+/// holds the amount. Every callback does nothing by default.
+///
+/// # Examples
+///
+/// ```
+/// use randomlib::{Account, EngineObserver, PaymentEngine, Transaction};
+///
+/// #[derive(Debug)]
+/// struct Notifier;
+///
+/// impl EngineObserver for Notifier {
+///     fn on_account_locked(&mut self, account: &Account) {
+///         println!("Client {} has been locked", account.client());
+///     }
+/// }
+///
+/// let mut engine = PaymentEngine::default();
+/// engine.add_observer(Box::new(Notifier));
+/// ```
+#[allow(unused_variables)]
+pub trait EngineObserver: Debug {
+    fn on_deposit(&mut self, account: &Account, tx: &Transaction) {}
+
+    fn on_withdrawal(&mut self, account: &Account, tx: &Transaction) {}
+
+    fn on_dispute(&mut self, account: &Account, disputed: &Transaction) {}
+
+    fn on_resolve(&mut self, account: &Account, resolved: &Transaction) {}
+
+    fn on_chargeback(&mut self, account: &Account, charged_back: &Transaction) {}
+
+    fn on_representment(&mut self, account: &Account, represented: &Transaction) {}
+
+    /// Called after `transfer` moved funds from `source` to `destination`.
+    fn on_transfer(&mut self, source: &Account, destination: &Account, transfer: &Transaction) {}
+
+    /// Called after a fee was charged to `account`, with the
+    /// [`EngineConfig::fee_account`](crate::EngineConfig::fee_account) it was
+    /// credited to, if any.
+    fn on_fee(&mut self, account: &Account, fee_account: Option<&Account>, fee: &Transaction) {}
+
+    /// Called after a [`TransactionVariant::Convert`](crate::TransactionVariant::Convert)
+    /// moved funds of `account` between currencies.
+    fn on_conversion(&mut self, account: &Account, tx: &Transaction) {}
+
+    /// Called after `amount` of interest was credited to `account`, see
+    /// [`PaymentEngine::advance_time`](crate::PaymentEngine::advance_time).
+    fn on_interest(&mut self, account: &Account, amount: Amount) {}
+
+    /// Called after the balance of the dormant `account` was moved to `pool`,
+    /// see [`PaymentEngine::sweep`](crate::PaymentEngine::sweep).
+    fn on_sweep(&mut self, account: &Account, pool: &Account, record: &SweepRecord) {}
+
+    /// Called after [`EngineObserver::on_chargeback`] when it locked the
+    /// account.
+    fn on_account_locked(&mut self, account: &Account) {}
+
+    /// Called when a locked account is unlocked, see
+    /// [`PaymentEngine::unlock`](crate::PaymentEngine::unlock).
+    fn on_account_unlocked(&mut self, account: &Account) {}
+
+    /// Called when an account is closed, see
+    /// [`PaymentEngine::close`](crate::PaymentEngine::close).
+    fn on_account_closed(&mut self, account: &Account) {}
+
+    /// Called when an account is frozen, see
+    /// [`PaymentEngine::freeze`](crate::PaymentEngine::freeze).
+    fn on_account_frozen(&mut self, account: &Account) {}
+
+    /// Called when a frozen account is unfrozen, see
+    /// [`PaymentEngine::unfreeze`](crate::PaymentEngine::unfreeze).
+    fn on_account_unfrozen(&mut self, account: &Account) {}
+}
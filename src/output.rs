@@ -0,0 +1,502 @@
+use std::cell::RefCell;
+use std::error::Error;
+use std::ffi::OsString;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::OnceLock;
+
+use serde::Serialize;
+
+use crate::{
+    aggregate::{AggregateValue, Aggregation, Aggregator},
+    atomic::{write_atomically, AtomicFile},
+    compression::Compression,
+    filter::Filter,
+    format::{Format, RowWriter},
+    instrument::stage_span,
+    store::{AccountStore, TransactionStore},
+    template::{OutputSchema, OutputTemplate},
+    Account, AccountChanges, Amount, Balance, PaymentEngine,
+};
+
+/// Options controlling how accounts are written by [`write_accounts`].
+#[derive(Debug, Clone, Default)]
+pub struct OutputOptions {
+    /// Only write the accounts matching this filter.
+    pub filter: Option<Filter>,
+    /// Aggregations computed over all accounts while they are written,
+    /// regardless of [`OutputOptions::filter`].
+    pub aggregations: Vec<Aggregation>,
+    /// The compression of files written by [`write_accounts_to_path`]. Selected
+    /// by the extension of the path if not set, see
+    /// [`Compression::from_path`].
+    pub compression: Option<Compression>,
+    /// Split files written by [`write_accounts_to_path`] into numbered parts
+    /// of at most this many accounts each, see [`part_path`].
+    pub max_rows_per_part: Option<u64>,
+    /// Split files written by [`write_accounts_to_path`] into numbered parts
+    /// of at most this many bytes each before compression, see [`part_path`].
+    /// A part holds at least one account even if that exceeds the limit.
+    pub max_bytes_per_part: Option<u64>,
+    /// Write these columns instead of the fields of the accounts, see
+    /// [`TemplateColumn`](crate::TemplateColumn) and
+    /// [`OutputSchema`](crate::OutputSchema). Ignored if empty.
+    pub template: OutputTemplate,
+    /// Add the `name`, `tier` and `kyc_status` of the client to the account
+    /// fields, see [`PaymentEngine::set_metadata`]. Like with
+    /// [`OutputOptions::template`], only the funds without a currency are
+    /// written. Ignored if a template is set, which can include them.
+    pub metadata: bool,
+    /// Write the accounts ordered by client id instead of in the order of the
+    /// [`AccountStore`], which is arbitrary for a [`HashMap`](std::collections::HashMap).
+    pub sorted: bool,
+    /// The format the accounts are written in. JSON cannot be split into
+    /// parts, as every part would need to be a whole array.
+    pub format: Format,
+}
+
+impl OutputOptions {
+    fn split(&self) -> bool {
+        self.max_rows_per_part.is_some() || self.max_bytes_per_part.is_some()
+    }
+
+    /// The columns to write, if not the fields of the accounts.
+    fn template(&self) -> &OutputTemplate {
+        static METADATA: OnceLock<OutputTemplate> = OnceLock::new();
+        if self.template.is_empty() && self.metadata {
+            METADATA.get_or_init(|| {
+                "client,available,held,total,locked,closed,name,tier,kyc_status"
+                    .parse::<OutputSchema>()
+                    .expect("valid schema")
+                    .columns
+            })
+        } else {
+            &self.template
+        }
+    }
+}
+
+/// Writes the accounts of `engine` in [`OutputOptions::format`] to `writer`
+/// and returns the values of [`OutputOptions::aggregations`].
+pub fn write_accounts<W, A, T>(
+    engine: &PaymentEngine<A, T>,
+    writer: W,
+    options: &OutputOptions,
+) -> Result<Vec<AggregateValue>, Box<dyn Error>>
+where
+    W: io::Write,
+    A: AccountStore,
+    T: TransactionStore,
+{
+    stage_span!("serialize", format = options.format.as_str());
+    let mut w = RowWriter::new(writer, options.format);
+    let mut aggregator = Aggregator::new(&options.aggregations);
+    let currencies = has_currencies(engine);
+    let mut first = true;
+    for client in engine.accounts_iter(options.sorted) {
+        aggregator.add(client);
+        if let Some(filter) = &options.filter {
+            if !filter.matches(client) {
+                continue;
+            }
+        }
+        write_row(&mut w, engine, client, options, first, currencies)?;
+        first = false;
+    }
+    w.finish()?;
+    Ok(aggregator.finish())
+}
+
+/// Writes the accounts of `changes` taken from `engine` like
+/// [`write_accounts`], without aggregations.
+///
+/// # Examples
+///
+/// ```
+/// use randomlib::{process, write_changes, OutputOptions, PaymentEngine};
+///
+/// let mut engine = PaymentEngine::builder().track_changes().build();
+/// process("type,client,tx,amount\ndeposit,2,1,1.0".as_bytes(), &mut engine).unwrap();
+/// let changes = engine.take_changes();
+/// let mut out = Vec::new();
+/// write_changes(&engine, &changes, &mut out, &OutputOptions::default()).unwrap();
+/// assert_eq!(
+///     String::from_utf8(out).unwrap(),
+///     "client,available,held,total,locked,closed\n2,1.0,0,1.0,false,false\n"
+/// );
+/// ```
+pub fn write_changes<W, A, T>(
+    engine: &PaymentEngine<A, T>,
+    changes: &AccountChanges,
+    writer: W,
+    options: &OutputOptions,
+) -> Result<(), Box<dyn Error>>
+where
+    W: io::Write,
+    A: AccountStore,
+    T: TransactionStore,
+{
+    stage_span!("serialize", format = options.format.as_str());
+    let mut w = RowWriter::new(writer, options.format);
+    let currencies = has_currencies(engine);
+    let mut first = true;
+    for account in &changes.accounts {
+        if let Some(filter) = &options.filter {
+            if !filter.matches(account) {
+                continue;
+            }
+        }
+        write_row(&mut w, engine, account, options, first, currencies)?;
+        first = false;
+    }
+    w.finish()?;
+    Ok(())
+}
+
+/// Writes the accounts of `engine` to the file at `path` atomically,
+/// see [`write_atomically`] and [`write_accounts`], compressed according to
+/// [`OutputOptions::compression`].
+///
+/// When [`OutputOptions::max_rows_per_part`] or
+/// [`OutputOptions::max_bytes_per_part`] is set, the accounts are written to
+/// numbered parts next to `path` instead, each with its own header. Every part
+/// is written atomically. Fails for [`Format::Json`], which cannot be split.
+pub fn write_accounts_to_path<P, A, T>(
+    engine: &PaymentEngine<A, T>,
+    path: P,
+    options: &OutputOptions,
+) -> Result<Vec<AggregateValue>, Box<dyn Error>>
+where
+    P: AsRef<Path>,
+    A: AccountStore,
+    T: TransactionStore,
+{
+    let compression = options
+        .compression
+        .unwrap_or_else(|| Compression::from_path(path.as_ref()));
+    if options.split() {
+        return write_parts(engine, path.as_ref(), compression, options);
+    }
+    let mut aggregations = Vec::new();
+    write_atomically(path, |file| {
+        let mut encoder = compression.encoder(io::BufWriter::new(file))?;
+        aggregations = write_accounts(engine, &mut encoder, options)?;
+        io::Write::flush(&mut encoder.finish()?)?;
+        Ok(())
+    })?;
+    Ok(aggregations)
+}
+
+/// An account in one currency, written instead of the account when any
+/// account has funds in a currency, see [`Account::balances`].
+#[derive(Serialize)]
+struct CurrencyRow<'a> {
+    client: u16,
+    currency: Option<&'a str>,
+    available: Amount,
+    held: Amount,
+    total: Amount,
+    locked: bool,
+    closed: bool,
+}
+
+/// Whether any account of `engine` has funds in a currency.
+fn has_currencies<A, T>(engine: &PaymentEngine<A, T>) -> bool
+where
+    A: AccountStore,
+    T: TransactionStore,
+{
+    engine
+        .accounts_iter(false)
+        .any(|account| !account.balances().is_empty())
+}
+
+/// Writes `account` as a row, or the columns of [`OutputOptions::template`] if
+/// set. The header is written before the `first` row.
+///
+/// With `currencies`, the account is written as a row per currency with a
+/// `currency` column, which is empty for the funds without a currency. That
+/// row is left out if it is empty and the account has funds in a currency.
+fn write_row<W, A, T>(
+    w: &mut RowWriter<W>,
+    engine: &PaymentEngine<A, T>,
+    account: &Account,
+    options: &OutputOptions,
+    first: bool,
+    currencies: bool,
+) -> Result<(), Box<dyn Error>>
+where
+    W: io::Write,
+    A: AccountStore,
+    T: TransactionStore,
+{
+    let template = options.template();
+    if template.is_empty() && !currencies {
+        w.serialize(account)?;
+        return Ok(());
+    }
+    if template.is_empty() {
+        let balances = account.balances();
+        let base = account.balance(None);
+        let base = (balances.is_empty() || base != Balance::default()).then_some((None, base));
+        let balances = balances
+            .iter()
+            .map(|(currency, balance)| (Some(currency.as_str()), *balance));
+        for (currency, balance) in base.into_iter().chain(balances) {
+            w.serialize(&CurrencyRow {
+                client: account.client(),
+                currency,
+                available: balance.available,
+                held: balance.held,
+                total: balance.total,
+                locked: account.locked(),
+                closed: account.closed(),
+            })?;
+        }
+        return Ok(());
+    }
+    let names = template
+        .iter()
+        .map(|column| column.name.as_str())
+        .collect::<Vec<_>>();
+    let attributes = engine.attributes(account.client());
+    let values = template
+        .iter()
+        .map(|column| column.render(account, attributes))
+        .collect();
+    w.write_columns(&names, values, first)
+}
+
+/// The path of part `index` (starting at 1) of the output at `path`, which
+/// inserts the part number before the extensions of the file name, e.g.
+/// `accounts.part-00001.csv.gz` for `accounts.csv.gz`.
+pub fn part_path<P: AsRef<Path>>(path: P, index: usize) -> PathBuf {
+    let path = path.as_ref();
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let (stem, extensions) = match name.find('.') {
+        Some(dot) => name.split_at(dot),
+        None => (name.as_str(), ""),
+    };
+    let part = OsString::from(format!("{}.part-{:05}{}", stem, index, extensions));
+    path.with_file_name(part)
+}
+
+/// A [`Write`] into a buffer that can be taken out while the writer is still
+/// in use.
+#[derive(Debug, Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl SharedBuffer {
+    fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut self.0.borrow_mut())
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A part being written by [`write_parts`].
+struct Part {
+    file: AtomicFile,
+    rows: u64,
+    bytes: u64,
+}
+
+impl Part {
+    fn commit(self, compression: Compression, contents: Vec<u8>) -> Result<(), Box<dyn Error>> {
+        let mut file = self.file;
+        let mut encoder = compression.encoder(io::BufWriter::new(file.as_file_mut()))?;
+        encoder.write_all(&contents)?;
+        encoder.finish()?.flush()?;
+        file.commit()
+    }
+}
+
+/// Writes the accounts into parts, see [`write_accounts_to_path`].
+fn write_parts<A, T>(
+    engine: &PaymentEngine<A, T>,
+    path: &Path,
+    compression: Compression,
+    options: &OutputOptions,
+) -> Result<Vec<AggregateValue>, Box<dyn Error>>
+where
+    A: AccountStore,
+    T: TransactionStore,
+{
+    if options.format == Format::Json {
+        return Err("JSON output cannot be split into parts, use NDJSON instead".into());
+    }
+    let max_rows = options.max_rows_per_part.unwrap_or(u64::MAX);
+    let max_bytes = options.max_bytes_per_part.unwrap_or(u64::MAX);
+
+    // Every row is serialized on its own to know its size before it is
+    // written. The first one includes the header, if any.
+    let buffer = SharedBuffer::default();
+    let mut w = RowWriter::new(buffer.clone(), options.format);
+    let mut header = Vec::new();
+    let mut first = true;
+
+    let mut aggregator = Aggregator::new(&options.aggregations);
+    let currencies = has_currencies(engine);
+    let mut index = 0;
+    let mut part: Option<(Part, Vec<u8>)> = None;
+    for client in engine.accounts_iter(options.sorted) {
+        aggregator.add(client);
+        if let Some(filter) = &options.filter {
+            if !filter.matches(client) {
+                continue;
+            }
+        }
+        write_row(&mut w, engine, client, options, first, currencies)?;
+        w.finish()?;
+        let mut row = buffer.take();
+        if first && options.format.has_header() {
+            let end = row.iter().position(|b| *b == b'\n').map_or(0, |i| i + 1);
+            header = row.drain(..end).collect();
+        }
+        first = false;
+
+        if let Some((current, _)) = &part {
+            if current.rows >= max_rows || current.bytes + row.len() as u64 > max_bytes {
+                let (current, contents) = part.take().unwrap();
+                current.commit(compression, contents)?;
+            }
+        }
+        let (current, contents) = match &mut part {
+            Some(part) => part,
+            None => {
+                index += 1;
+                let current = Part {
+                    file: AtomicFile::create(part_path(path, index))?,
+                    rows: 0,
+                    bytes: header.len() as u64,
+                };
+                part.insert((current, header.clone()))
+            }
+        };
+        current.rows += 1;
+        current.bytes += row.len() as u64;
+        contents.extend_from_slice(&row);
+    }
+
+    match part {
+        Some((current, contents)) => current.commit(compression, contents)?,
+        // Always write at least one part, so that an empty output can be
+        // told apart from a failed run
+        None => {
+            let current = Part {
+                file: AtomicFile::create(part_path(path, 1))?,
+                rows: 0,
+                bytes: 0,
+            };
+            current.commit(compression, Vec::new())?;
+        }
+    }
+    Ok(aggregator.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::{Amount, Transaction, TransactionVariant};
+
+    #[test]
+    fn names_parts() {
+        assert_eq!(
+            part_path("out/accounts.csv.gz", 2),
+            PathBuf::from("out/accounts.part-00002.csv.gz")
+        );
+        assert_eq!(
+            part_path("accounts", 1),
+            PathBuf::from("accounts.part-00001")
+        );
+    }
+
+    #[test]
+    fn splits_output_into_parts() {
+        let mut engine = PaymentEngine::default();
+        for client in 1..=5 {
+            let tx = Transaction {
+                tx: client.into(),
+                amount: Some(Amount::new(1, 0).unwrap()),
+                client,
+                disputed: false,
+                variant: TransactionVariant::Deposit,
+                chargeback: false,
+                destination: None,
+                currency: None,
+                to_currency: None,
+                rate: None,
+                timestamp: None,
+            };
+            engine.insert(tx).unwrap();
+        }
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("accounts.csv");
+        let options = OutputOptions {
+            max_rows_per_part: Some(2),
+            ..OutputOptions::default()
+        };
+        write_accounts_to_path(&engine, &path, &options).unwrap();
+
+        let mut rows = 0;
+        for index in 1..=3 {
+            let part = fs::read_to_string(part_path(&path, index)).unwrap();
+            let mut lines = part.lines();
+            assert_eq!(
+                lines.next(),
+                Some("client,available,held,total,locked,closed")
+            );
+            rows += lines.count();
+        }
+        assert_eq!(rows, 5);
+        assert!(!part_path(&path, 4).exists());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn writes_client_metadata() {
+        let mut engine = PaymentEngine::default();
+        crate::process(
+            "type,client,tx,amount\ndeposit,1,1,2.0\ndeposit,2,2,3.0".as_bytes(),
+            &mut engine,
+        )
+        .unwrap();
+        engine
+            .read_metadata(
+                "client,name,tier,kyc_status\n1,Ada,gold,verified\n2,,,pending\n3,Bob,,\n"
+                    .as_bytes(),
+            )
+            .unwrap();
+        assert_eq!(engine.accounts().get(&1).unwrap().tier(), Some("gold"));
+        assert!(engine.accounts().get(&3).is_none());
+
+        let options = OutputOptions {
+            metadata: true,
+            sorted: true,
+            ..OutputOptions::default()
+        };
+        let mut out = Vec::new();
+        write_accounts(&engine, &mut out, &options).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "client,available,held,total,locked,closed,name,tier,kyc_status\n\
+             1,2.0,0,2.0,false,false,Ada,gold,verified\n\
+             2,3.0,0,3.0,false,false,,,pending\n"
+        );
+    }
+}
@@ -0,0 +1,59 @@
+use std::{fmt::Display, str::FromStr};
+
+/// How rows that cannot be processed are handled, see
+/// [`RunOptions::policy`](crate::RunOptions::policy).
+///
+/// A row cannot be processed when it cannot be parsed, is invalid (for example
+/// a deposit without an amount) or is rejected by the engine (for example a
+/// withdrawal exceeding the available funds), including disputes, resolves
+/// and chargebacks referencing a transaction that does not exist and
+/// transactions quarantined by
+/// [`RunOptions::max_transactions_per_client`](crate::RunOptions::max_transactions_per_client).
+/// I/O and storage errors always stop processing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ProcessingPolicy {
+    /// Stop at the first row that cannot be processed and return its error.
+    ///
+    /// The default, also for invalid rows such as a deposit without an
+    /// amount, which were skipped silently by earlier versions.
+    #[default]
+    StopOnFirstError,
+    /// Skip rows that cannot be processed. They are only counted in
+    /// [`RunMetrics::rejected`](crate::RunMetrics::rejected).
+    SkipInvalid,
+    /// Skip rows that cannot be processed and report them with their line
+    /// number in [`RunReport::errors`](crate::RunReport::errors).
+    SkipWithLog,
+}
+
+impl ProcessingPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProcessingPolicy::StopOnFirstError => "stop-on-first-error",
+            ProcessingPolicy::SkipInvalid => "skip-invalid",
+            ProcessingPolicy::SkipWithLog => "skip-with-log",
+        }
+    }
+}
+
+impl FromStr for ProcessingPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stop-on-first-error" => Ok(ProcessingPolicy::StopOnFirstError),
+            "skip-invalid" => Ok(ProcessingPolicy::SkipInvalid),
+            "skip-with-log" => Ok(ProcessingPolicy::SkipWithLog),
+            _ => Err(format!(
+                "Unknown policy `{}`, expected `stop-on-first-error`, `skip-invalid` or `skip-with-log`",
+                s
+            )),
+        }
+    }
+}
+
+impl Display for ProcessingPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
@@ -0,0 +1,144 @@
+use std::fmt::{self, Display};
+use std::time::{Duration, Instant};
+
+/// How far processing an input has come, see [`ProgressReporter`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Progress {
+    /// The rows processed so far
+    pub rows: u64,
+    /// The bytes of the input read so far. Unknown for JSON, which is read
+    /// as a whole up front.
+    pub bytes: u64,
+    /// The size of the input, if known
+    pub total_bytes: Option<u64>,
+    pub elapsed: Duration,
+}
+
+impl Progress {
+    pub fn rows_per_sec(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 {
+            self.rows as f64 / secs
+        } else {
+            0.0
+        }
+    }
+
+    /// The share of the input read so far, from 0 to 1.
+    pub fn fraction(&self) -> Option<f64> {
+        match self.total_bytes {
+            Some(total) if total > 0 && self.bytes > 0 => {
+                Some((self.bytes as f64 / total as f64).min(1.0))
+            }
+            _ => None,
+        }
+    }
+
+    /// The time left at the rate the input has been read so far.
+    pub fn eta(&self) -> Option<Duration> {
+        let fraction = self.fraction()?;
+        let total = self.elapsed.as_secs_f64() / fraction;
+        Some(Duration::from_secs_f64(total - self.elapsed.as_secs_f64()))
+    }
+}
+
+/// Writes e.g. `120000 rows, 40000 rows/s, 25%, ETA 9s`.
+impl Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} rows, {:.0} rows/s", self.rows, self.rows_per_sec())?;
+        if let (Some(fraction), Some(eta)) = (self.fraction(), self.eta()) {
+            write!(f, ", {:.0}%, ETA {}s", fraction * 100.0, eta.as_secs())?;
+        }
+        Ok(())
+    }
+}
+
+/// Calls a function with the [`Progress`] of processing an input at most
+/// once per interval, and once more at the end, see
+/// [`process_with_progress`](crate::process_with_progress).
+pub struct ProgressReporter<'a> {
+    interval: Duration,
+    total_bytes: Option<u64>,
+    report: Box<dyn FnMut(&Progress) + 'a>,
+    start: Instant,
+    last: Instant,
+}
+
+impl<'a> ProgressReporter<'a> {
+    /// Reports to `report` every `interval`, with an ETA if the size of the
+    /// input, `total_bytes`, is known.
+    pub fn new<F>(interval: Duration, total_bytes: Option<u64>, report: F) -> Self
+    where
+        F: FnMut(&Progress) + 'a,
+    {
+        let now = Instant::now();
+        Self {
+            interval,
+            total_bytes,
+            report: Box::new(report),
+            start: now,
+            last: now,
+        }
+    }
+
+    /// Reports the progress if the interval has passed since the last report.
+    pub(crate) fn update(&mut self, rows: u64, bytes: u64) {
+        // Checking the time for every row would slow down processing
+        if !rows.is_multiple_of(1024) {
+            return;
+        }
+        let now = Instant::now();
+        if now.duration_since(self.last) >= self.interval {
+            self.last = now;
+            self.report(rows, bytes);
+        }
+    }
+
+    pub(crate) fn finish(&mut self, rows: u64, bytes: u64) {
+        self.report(rows, bytes);
+    }
+
+    fn report(&mut self, rows: u64, bytes: u64) {
+        let progress = Progress {
+            rows,
+            bytes,
+            total_bytes: self.total_bytes,
+            elapsed: self.start.elapsed(),
+        };
+        (self.report)(&progress);
+    }
+}
+
+impl fmt::Debug for ProgressReporter<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProgressReporter")
+            .field("interval", &self.interval)
+            .field("total_bytes", &self.total_bytes)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimates_time_left() {
+        let progress = Progress {
+            rows: 1000,
+            bytes: 250,
+            total_bytes: Some(1000),
+            elapsed: Duration::from_secs(10),
+        };
+        assert_eq!(progress.rows_per_sec(), 100.0);
+        assert_eq!(progress.eta(), Some(Duration::from_secs(30)));
+        assert_eq!(progress.to_string(), "1000 rows, 100 rows/s, 25%, ETA 30s");
+
+        let unknown = Progress {
+            total_bytes: None,
+            ..progress
+        };
+        assert_eq!(unknown.eta(), None);
+        assert_eq!(unknown.to_string(), "1000 rows, 100 rows/s");
+    }
+}
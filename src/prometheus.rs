@@ -0,0 +1,165 @@
+//! Metrics of a long running engine in the Prometheus text format, see
+//! [`PaymentEngine::enable_service_metrics`](crate::PaymentEngine::enable_service_metrics).
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::Duration;
+
+use crate::{TransactionError, TransactionVariant};
+
+/// The upper bounds of the buckets of the insert latency histogram, in
+/// seconds.
+const LATENCY_BUCKETS: [f64; 10] = [
+    0.000_001, 0.000_005, 0.000_01, 0.000_05, 0.000_1, 0.000_5, 0.001, 0.005, 0.01, 0.05,
+];
+
+/// Counters and histograms of the transactions inserted into an engine, for
+/// monitoring an engine served over HTTP.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ServiceMetrics {
+    /// The number of applied transactions by [`TransactionVariant::as_str`](crate::TransactionVariant::as_str)
+    pub transactions: BTreeMap<&'static str, u64>,
+    /// The number of rejected transactions by [`TransactionError::kind`](crate::TransactionError::kind)
+    pub rejects: BTreeMap<&'static str, u64>,
+    /// The number of accounts locked by chargebacks
+    pub accounts_locked: u64,
+    pub insert_latency: Histogram,
+}
+
+impl ServiceMetrics {
+    /// Counts the `result` of inserting a transaction of `variant`, which
+    /// `locked` its account, and took `elapsed`.
+    pub(crate) fn record(
+        &mut self,
+        variant: &TransactionVariant,
+        result: &Result<bool, TransactionError>,
+        locked: bool,
+        elapsed: Duration,
+    ) {
+        match result {
+            Ok(true) => *self.transactions.entry(variant.as_str()).or_default() += 1,
+            Ok(false) => (),
+            Err(e) => *self.rejects.entry(e.kind()).or_default() += 1,
+        }
+        if locked {
+            self.accounts_locked += 1;
+        }
+        self.insert_latency.observe(elapsed);
+    }
+
+    /// Writes the metrics in the Prometheus text exposition format.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use randomlib::ServiceMetrics;
+    ///
+    /// let mut metrics = ServiceMetrics::default();
+    /// metrics.transactions.insert("deposit", 2);
+    /// assert!(metrics
+    ///     .render()
+    ///     .contains("payments_transactions_total{variant=\"deposit\"} 2\n"));
+    /// ```
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        out.push_str("# HELP payments_transactions_total Transactions applied by variant.\n");
+        out.push_str("# TYPE payments_transactions_total counter\n");
+        for (variant, count) in &self.transactions {
+            let _ = writeln!(
+                out,
+                "payments_transactions_total{{variant=\"{}\"}} {}",
+                variant, count
+            );
+        }
+        out.push_str("# HELP payments_rejects_total Transactions rejected by error kind.\n");
+        out.push_str("# TYPE payments_rejects_total counter\n");
+        for (kind, count) in &self.rejects {
+            let _ = writeln!(out, "payments_rejects_total{{kind=\"{}\"}} {}", kind, count);
+        }
+        out.push_str("# HELP payments_accounts_locked_total Accounts locked by chargebacks.\n");
+        out.push_str("# TYPE payments_accounts_locked_total counter\n");
+        let _ = writeln!(
+            out,
+            "payments_accounts_locked_total {}",
+            self.accounts_locked
+        );
+        out.push_str(
+            "# HELP payments_insert_latency_seconds Time taken to insert a transaction.\n",
+        );
+        out.push_str("# TYPE payments_insert_latency_seconds histogram\n");
+        self.insert_latency
+            .render("payments_insert_latency_seconds", &mut out);
+        out
+    }
+}
+
+/// A histogram of durations with fixed buckets.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    /// The number of observations in each bucket, not cumulative
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    count: u64,
+    sum: Duration,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            buckets: [0; LATENCY_BUCKETS.len()],
+            count: 0,
+            sum: Duration::ZERO,
+        }
+    }
+}
+
+impl Histogram {
+    pub fn observe(&mut self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|le| secs <= *le) {
+            self.buckets[bucket] += 1;
+        }
+        self.count += 1;
+        self.sum += duration;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn sum(&self) -> Duration {
+        self.sum
+    }
+
+    fn render(&self, name: &str, out: &mut String) {
+        let mut cumulative = 0;
+        for (le, count) in LATENCY_BUCKETS.iter().zip(&self.buckets) {
+            cumulative += count;
+            let _ = writeln!(out, "{}_bucket{{le=\"{}\"}} {}", name, le, cumulative);
+        }
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, self.count);
+        let _ = writeln!(out, "{}_sum {}", name, self.sum.as_secs_f64());
+        let _ = writeln!(out, "{}_count {}", name, self.count);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_cumulative_buckets() {
+        let mut metrics = ServiceMetrics::default();
+        metrics.rejects.insert("insufficient_funds", 1);
+        metrics.insert_latency.observe(Duration::from_micros(3));
+        metrics.insert_latency.observe(Duration::from_micros(30));
+        metrics.insert_latency.observe(Duration::from_secs(1));
+        let text = metrics.render();
+        assert!(text.contains("payments_rejects_total{kind=\"insufficient_funds\"} 1\n"));
+        assert!(text.contains("payments_accounts_locked_total 0\n"));
+        assert!(text.contains("payments_insert_latency_seconds_bucket{le=\"0.000005\"} 1\n"));
+        assert!(text.contains("payments_insert_latency_seconds_bucket{le=\"0.00005\"} 2\n"));
+        assert!(text.contains("payments_insert_latency_seconds_bucket{le=\"0.05\"} 2\n"));
+        assert!(text.contains("payments_insert_latency_seconds_bucket{le=\"+Inf\"} 3\n"));
+        assert!(text.contains("payments_insert_latency_seconds_count 3\n"));
+    }
+}
@@ -0,0 +1,58 @@
+use crate::{Transaction, TransactionVariant, WithdrawalDisputes};
+
+/// Which transactions [`PaymentEngine::prune`](crate::PaymentEngine::prune)
+/// drops. Transactions under dispute are always kept, and a transaction is
+/// dropped if it matches any of the enabled conditions.
+///
+/// # Examples
+///
+/// ```
+/// use randomlib::PruneFilter;
+///
+/// // Keep a year of transactions that can still be disputed
+/// let filter = PruneFilter {
+///     charged_back: true,
+///     undisputable: true,
+///     older_than: Some(365 * 86400),
+/// };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PruneFilter {
+    /// Drop transactions that have been charged back, which cannot be
+    /// disputed again
+    pub charged_back: bool,
+    /// Drop transactions that can never be disputed: transfers, fees,
+    /// conversions, and withdrawals with [`WithdrawalDisputes::Reject`]
+    pub undisputable: bool,
+    /// Drop transactions with a timestamp more than this many seconds before
+    /// the latest timestamp seen by the engine, see
+    /// [`PaymentEngine::advance_time`](crate::PaymentEngine::advance_time).
+    /// Transactions without a timestamp are kept.
+    pub older_than: Option<u64>,
+}
+
+impl PruneFilter {
+    /// Whether `tx` is dropped, given the timestamp before which transactions
+    /// are too old, if any.
+    pub(crate) fn matches(
+        &self,
+        tx: &Transaction,
+        cutoff: Option<u64>,
+        withdrawal_disputes: WithdrawalDisputes,
+    ) -> bool {
+        if tx.disputed {
+            return false;
+        }
+        let undisputable = match tx.variant {
+            TransactionVariant::Transfer
+            | TransactionVariant::Fee
+            | TransactionVariant::Convert
+            | TransactionVariant::Authorize => true,
+            TransactionVariant::Withdrawal => withdrawal_disputes == WithdrawalDisputes::Reject,
+            _ => false,
+        };
+        let old =
+            matches!((tx.timestamp, cutoff), (Some(timestamp), Some(cutoff)) if timestamp < cutoff);
+        (self.charged_back && tx.chargeback) || (self.undisputable && undisputable) || old
+    }
+}
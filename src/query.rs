@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::io;
+
+use crate::{
+    account::Account,
+    server::{Handler, Request, Response},
+    snapshot::Snapshot,
+    timeline::{self, DailyVolume},
+    Transaction,
+};
+
+/// A read-only view of a [`Snapshot`].
+///
+/// Unlike [`PaymentEngine`](crate::PaymentEngine) it has no write path, so it
+/// can be used to inspect an older state without any risk of changing it.
+#[derive(Debug, Default)]
+pub struct QueryEngine {
+    accounts: HashMap<u16, Account>,
+    transactions: HashMap<u32, Transaction>,
+}
+
+impl QueryEngine {
+    pub fn from_snapshot(snapshot: Snapshot) -> Self {
+        Self {
+            accounts: snapshot
+                .accounts
+                .into_iter()
+                .map(|account| (account.client(), account))
+                .collect(),
+            transactions: snapshot
+                .transactions
+                .into_iter()
+                .map(|tx| (tx.tx, tx.into()))
+                .collect(),
+        }
+    }
+
+    /// Loads a [`QueryEngine`] from a snapshot written by [`Snapshot::write`].
+    pub fn read<R: io::Read>(reader: R) -> Result<Self, Box<dyn Error>> {
+        Ok(Self::from_snapshot(Snapshot::read(reader)?))
+    }
+
+    pub fn account(&self, client: u16) -> Option<&Account> {
+        self.accounts.get(&client)
+    }
+
+    pub fn accounts(&self) -> impl Iterator<Item = &Account> {
+        self.accounts.values()
+    }
+
+    pub fn transaction(&self, tx: u32) -> Option<&Transaction> {
+        self.transactions.get(&tx)
+    }
+
+    /// Returns the deposits and withdrawals per day, see
+    /// [`PaymentEngine::daily_volumes`](crate::PaymentEngine::daily_volumes).
+    pub fn daily_volumes(&self) -> Vec<DailyVolume> {
+        timeline::daily_volumes(self.transactions.values())
+    }
+}
+
+/// Serves the following routes:
+///
+/// - `GET /accounts`
+/// - `GET /accounts/{client}`
+/// - `GET /transactions/{tx}`
+/// - `GET /volumes/daily`
+impl Handler for QueryEngine {
+    fn handle(&mut self, request: &Request) -> Response {
+        if request.method != "GET" {
+            return Response::error(405, "Only GET is supported");
+        }
+        match request.segments().as_slice() {
+            ["accounts"] => {
+                let mut accounts = self.accounts().collect::<Vec<_>>();
+                accounts.sort_by_key(|account| account.client());
+                Response::json(&accounts)
+            }
+            ["accounts", client] => match client.parse() {
+                Ok(client) => self
+                    .account(client)
+                    .map(Response::json)
+                    .unwrap_or_else(Response::not_found),
+                Err(_) => Response::error(400, "Invalid client id"),
+            },
+            ["transactions", tx] => match tx.parse() {
+                Ok(tx) => self
+                    .transaction(tx)
+                    .map(Response::json)
+                    .unwrap_or_else(Response::not_found),
+                Err(_) => Response::error(400, "Invalid transaction id"),
+            },
+            ["volumes", "daily"] => Response::json(&self.daily_volumes()),
+            _ => Response::not_found(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{snapshot::SnapshotTransaction, Amount, TransactionVariant};
+
+    fn get(path: &str) -> Request {
+        Request {
+            method: "GET".to_string(),
+            path: path.to_string(),
+            body: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn serves_accounts_and_transactions() {
+        let snapshot = Snapshot {
+            run_id: None,
+            accounts: vec![Account::new(1)],
+            transactions: vec![SnapshotTransaction {
+                variant: TransactionVariant::Deposit,
+                client: 1,
+                tx: 7,
+                amount: Some(Amount::zero()),
+                disputed: false,
+                chargeback: false,
+                destination: None,
+                currency: None,
+                to_currency: None,
+                rate: None,
+                timestamp: None,
+            }],
+            reservations: Vec::new(),
+            pending_deposits: Default::default(),
+            idempotency_keys: Vec::new(),
+            withdrawn: Default::default(),
+            frozen: Default::default(),
+            authorizations: Default::default(),
+            schedules: Default::default(),
+            queued: Default::default(),
+            partial_disputes: Default::default(),
+            disputes_opened: Default::default(),
+            held_periods: Default::default(),
+            represented: Default::default(),
+            tiers: Default::default(),
+            metadata: Default::default(),
+        };
+        let mut query = QueryEngine::from_snapshot(snapshot);
+
+        assert_eq!(query.handle(&get("/accounts/1")).status, 200);
+        assert_eq!(query.handle(&get("/accounts/2")).status, 404);
+        assert_eq!(query.handle(&get("/accounts/abc")).status, 400);
+        assert_eq!(query.handle(&get("/transactions/7")).status, 200);
+        let mut post = get("/accounts");
+        post.method = "POST".to_string();
+        assert_eq!(query.handle(&post).status, 405);
+    }
+}
@@ -0,0 +1,138 @@
+use std::{collections::BTreeMap, error::Error, io};
+
+use serde::Serialize;
+
+use crate::{Account, Corrections};
+
+/// A field of the account of a client that differs between two sets of
+/// accounts, see [`reconcile`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Difference {
+    pub client: u16,
+    /// `available`, `held`, `total` or `locked`, or `account` when only one
+    /// side has an account for the client
+    pub field: &'static str,
+    /// The value on the left, or `missing`
+    pub left: String,
+    /// The value on the right, or `missing`
+    pub right: String,
+}
+
+/// Compares two sets of accounts in the format of
+/// [`write_accounts`](crate::write_accounts), e.g. the output of this engine
+/// and of a legacy system, and returns the differences ordered by client.
+///
+/// Only the funds without a currency are compared, and the `closed` column
+/// may be missing.
+///
+/// # Examples
+///
+/// ```
+/// use randomlib::reconcile;
+///
+/// let left = "client,available,held,total,locked\n1,1.5,0,1.5,false\n2,1,0,1,false\n";
+/// let right = "client,available,held,total,locked\n1,1.5000,0,1.5,false\n";
+/// let differences = reconcile(left.as_bytes(), right.as_bytes()).unwrap();
+/// assert_eq!(differences.len(), 1);
+/// assert_eq!(differences[0].client, 2);
+/// assert_eq!(differences[0].right, "missing");
+/// ```
+pub fn reconcile<L, R>(left: L, right: R) -> Result<Vec<Difference>, Box<dyn Error>>
+where
+    L: io::Read,
+    R: io::Read,
+{
+    let left = Corrections::read_target(left)?;
+    let right = Corrections::read_target(right)?;
+    Ok(reconcile_accounts(&left, &right))
+}
+
+/// The differences between the `left` and `right` accounts.
+pub fn reconcile_accounts(left: &[Account], right: &[Account]) -> Vec<Difference> {
+    let mut clients = BTreeMap::<u16, (Option<&Account>, Option<&Account>)>::new();
+    for account in left {
+        clients.entry(account.client()).or_default().0 = Some(account);
+    }
+    for account in right {
+        clients.entry(account.client()).or_default().1 = Some(account);
+    }
+
+    let mut differences = Vec::new();
+    for (client, accounts) in clients {
+        let (left, right) = match accounts {
+            (Some(left), Some(right)) => (left, right),
+            (left, _) => {
+                let (left, right) = match left {
+                    Some(_) => ("present", "missing"),
+                    None => ("missing", "present"),
+                };
+                differences.push(Difference {
+                    client,
+                    field: "account",
+                    left: left.to_string(),
+                    right: right.to_string(),
+                });
+                continue;
+            }
+        };
+        let fields = [
+            ("available", left.available() != right.available()),
+            ("held", left.held() != right.held()),
+            ("total", left.total() != right.total()),
+            ("locked", left.locked() != right.locked()),
+        ];
+        for (field, differs) in fields {
+            if differs {
+                differences.push(Difference {
+                    client,
+                    field,
+                    left: value(left, field),
+                    right: value(right, field),
+                });
+            }
+        }
+    }
+    differences
+}
+
+/// The value of the compared `field` of `account`.
+fn value(account: &Account, field: &str) -> String {
+    match field {
+        "available" => account.available().to_string(),
+        "held" => account.held().to_string(),
+        "total" => account.total().to_string(),
+        _ => account.locked().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_differing_fields() {
+        let left = "client,available,held,total,locked,closed
+1,1,2,3,false,false
+2,0,0,0,false,false
+3,5,0,5,true,false";
+        let right = "client,available,held,total,locked
+1,1.0,1,2,false
+3,5,0,5,false
+4,0,0,0,false";
+        let differences = reconcile(left.as_bytes(), right.as_bytes()).unwrap();
+        let summary = differences
+            .iter()
+            .map(|d| format!("{} {} {} {}", d.client, d.field, d.left, d.right))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            [
+                "1 held 2.0000 1.0000",
+                "1 total 3.0000 2.0000",
+                "2 account present missing",
+                "3 locked true false",
+                "4 account missing present",
+            ]
+        );
+    }
+}
@@ -0,0 +1,111 @@
+//! Transactions that repeat on a fixed interval, such as subscription fees,
+//! see [`PaymentEngine::materialize`](crate::PaymentEngine::materialize).
+
+use std::{error::Error, io};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Amount, Transaction, TransactionError, TransactionVariant};
+
+/// A deposit, withdrawal or fee of `amount` for `client` every `interval`
+/// seconds from `start`, in seconds since the Unix epoch, until `end` if
+/// given.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Schedule {
+    pub client: u16,
+    #[serde(rename = "type")]
+    pub variant: TransactionVariant,
+    pub amount: Amount,
+    pub start: u64,
+    pub interval: u64,
+    /// The last timestamp an occurrence may have
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub end: Option<u64>,
+}
+
+impl Schedule {
+    /// Reads schedules as CSV with the columns `client`, `type`, `amount`,
+    /// `start`, `interval` and an optional `end`.
+    pub fn read<R: io::Read>(reader: R) -> Result<Vec<Schedule>, Box<dyn Error>> {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(reader);
+        Ok(reader.deserialize().collect::<Result<_, _>>()?)
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.interval > 0
+            && matches!(
+                self.variant,
+                TransactionVariant::Deposit
+                    | TransactionVariant::Withdrawal
+                    | TransactionVariant::Fee
+            )
+    }
+}
+
+/// A schedule and the timestamp of its next occurrence, or `None` once it
+/// has ended.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScheduleState {
+    pub schedule: Schedule,
+    pub next: Option<u64>,
+}
+
+impl ScheduleState {
+    pub(crate) fn new(schedule: Schedule) -> Self {
+        let next =
+            Some(schedule.start).filter(|start| schedule.end.is_none_or(|end| *start <= end));
+        Self { schedule, next }
+    }
+
+    /// The timestamp of the next occurrence if it is due by `until`.
+    pub(crate) fn due(&self, until: u64) -> Option<u64> {
+        self.next.filter(|next| *next <= until)
+    }
+
+    /// Moves to the occurrence after the current one.
+    pub(crate) fn advance(&mut self) {
+        let end = self.schedule.end.unwrap_or(u64::MAX);
+        self.next = self
+            .next
+            .and_then(|next| next.checked_add(self.schedule.interval))
+            .filter(|next| *next <= end);
+    }
+}
+
+/// A transaction generated from a schedule by
+/// [`PaymentEngine::materialize`](crate::PaymentEngine::materialize), with
+/// the error it was rejected with, if any.
+#[derive(Debug, Clone)]
+pub struct Occurrence {
+    /// The position of the schedule in the order it was added
+    pub schedule: usize,
+    pub transaction: Transaction,
+    pub error: Option<TransactionError>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stops_at_the_end() {
+        let schedules = Schedule::read(
+            "client,type,amount,start,interval,end\n\
+             1,withdrawal,9.99,100,30,160\n\
+             2,deposit,5,100,30,\n"
+                .as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(schedules[1].end, None);
+        let mut state = ScheduleState::new(schedules[0].clone());
+        let mut occurrences = Vec::new();
+        while let Some(next) = state.due(1000) {
+            occurrences.push(next);
+            state.advance();
+        }
+        assert_eq!(occurrences, [100, 130, 160]);
+        assert_eq!(state.next, None);
+    }
+}
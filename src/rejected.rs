@@ -0,0 +1,142 @@
+use std::{fmt, io};
+
+use crate::{RowErrorKind, TransactionError};
+
+/// Writes every input row that was rejected or skipped as CSV, with the
+/// columns of the input followed by `line`, `error_code` and `error_message`,
+/// registered with
+/// [`PaymentEngine::set_rejected_row_writer`](crate::PaymentEngine::set_rejected_row_writer).
+///
+/// The header is that of the first input with a rejected row, so inputs
+/// written to the same writer should have the same columns. The error code is
+/// the [`RowErrorKind::reason`].
+pub struct RejectedRowWriter {
+    writer: csv::Writer<Box<dyn io::Write>>,
+    header_written: bool,
+}
+
+impl RejectedRowWriter {
+    pub fn new<W: io::Write + 'static>(writer: W) -> Self {
+        Self {
+            writer: csv::WriterBuilder::new()
+                .flexible(true)
+                .from_writer(Box::new(writer)),
+            header_written: false,
+        }
+    }
+
+    pub(crate) fn write(
+        &mut self,
+        headers: &csv::StringRecord,
+        record: &csv::StringRecord,
+        line: u64,
+        kind: &RowErrorKind,
+    ) -> Result<(), TransactionError> {
+        if !self.header_written {
+            let header = headers
+                .iter()
+                .chain(["line", "error_code", "error_message"]);
+            self.writer.write_record(header).map_err(rejected_error)?;
+            self.header_written = true;
+        }
+        let line = line.to_string();
+        let message = kind.to_string();
+        let row = record
+            .iter()
+            .chain([line.as_str(), kind.reason(), message.as_str()]);
+        self.writer.write_record(row).map_err(rejected_error)
+    }
+
+    pub(crate) fn flush(&mut self) -> Result<(), TransactionError> {
+        self.writer.flush().map_err(rejected_error)
+    }
+}
+
+impl fmt::Debug for RejectedRowWriter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RejectedRowWriter").finish_non_exhaustive()
+    }
+}
+
+fn rejected_error(e: impl fmt::Display) -> TransactionError {
+    TransactionError::RejectedOutput(e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+
+    use super::*;
+    use crate::{process_with_policy, PaymentEngine, ProcessingPolicy, RoundingMode};
+
+    #[test]
+    fn writes_rejected_rows() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rejected.csv");
+        let mut engine = PaymentEngine::builder()
+            .rounding(RoundingMode::HalfUp)
+            .build();
+        engine.set_rejected_row_writer(RejectedRowWriter::new(File::create(&path).unwrap()));
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,5.0\n\
+                     withdrawal,1,2,9.00001\n\
+                     deposit,1,x,1.0\n\
+                     dispute,1,7,\n\
+                     deposit,1,1,\"1,0\"\n";
+        let errors =
+            process_with_policy(input.as_bytes(), &mut engine, ProcessingPolicy::SkipWithLog)
+                .unwrap();
+        assert_eq!(errors.len(), 4);
+        engine.flush().unwrap();
+        let written = fs::read_to_string(&path).unwrap();
+        let mut lines = written.lines();
+        assert_eq!(
+            lines.next(),
+            Some("type,client,tx,amount,line,error_code,error_message")
+        );
+        assert!(lines
+            .next()
+            .unwrap()
+            .starts_with("withdrawal,1,2,9.00001,3,insufficient_funds,Insufficient funds"));
+        assert!(lines
+            .next()
+            .unwrap()
+            .starts_with("deposit,1,x,1.0,4,parse,"));
+        assert_eq!(
+            lines.next(),
+            Some("dispute,1,7,,5,transaction_not_found,The transaction was not found")
+        );
+        assert!(lines
+            .next()
+            .unwrap()
+            .starts_with("deposit,1,1,\"1,0\",6,parse,"));
+        assert_eq!(lines.next(), None);
+    }
+
+    struct Failing;
+
+    impl io::Write for Failing {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::other("disk full"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn fails_with_its_own_error() {
+        let mut engine = PaymentEngine::default();
+        engine.set_rejected_row_writer(RejectedRowWriter::new(Failing));
+        let input = "type,client,tx,amount\nwithdrawal,1,1,1.0\n";
+        let e = process_with_policy(input.as_bytes(), &mut engine, ProcessingPolicy::SkipInvalid)
+            .unwrap_err();
+        match e.downcast_ref::<TransactionError>() {
+            Some(TransactionError::RejectedOutput(message)) => {
+                assert!(message.contains("disk full"), "{}", message)
+            }
+            _ => panic!("{}", e),
+        }
+    }
+}
@@ -0,0 +1,286 @@
+use std::{fmt::Display, num::ParseIntError, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    server::{Handler, Request, Response},
+    store::{AccountStore, TransactionStore},
+    validation::validate,
+    Amount, PaymentEngine, Transaction, TransactionError,
+};
+
+/// Identifies a [`Reservation`], see
+/// [`PaymentEngine::reserve`](crate::PaymentEngine::reserve).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ReservationId(pub u64);
+
+impl Display for ReservationId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for ReservationId {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(Self)
+    }
+}
+
+/// Funds of a client held for an external system, for example while it
+/// attempts a payout, until they are committed or released.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Reservation {
+    pub id: ReservationId,
+    pub client: u16,
+    pub amount: Amount,
+}
+
+/// The body of `POST /reservations`.
+#[derive(Deserialize)]
+struct ReserveRequest {
+    client: u16,
+    amount: Amount,
+}
+
+/// The body of `POST /rollback`.
+#[derive(Deserialize)]
+struct RollbackRequest {
+    count: usize,
+}
+
+/// Serves the following routes for external systems coordinating payouts:
+///
+/// - `GET /accounts/{client}`
+/// - `POST /reservations` with `{"client": 1, "amount": "4.0"}`, which
+///   responds with the reservation, see [`PaymentEngine::reserve`]
+/// - `GET /reservations/{id}`
+/// - `POST /reservations/{id}/commit`, see [`PaymentEngine::commit`]
+/// - `POST /reservations/{id}/release`, see [`PaymentEngine::release`]
+/// - `POST /validate` with transactions as CSV, which responds with a
+///   [`ValidationReport`](crate::ValidationReport) without applying them, see [`validate`]
+/// - `POST /transactions` with a transaction as JSON, e.g.
+///   `{"type": "deposit", "client": 1, "tx": 1, "amount": "4.0"}`, which
+///   responds with the account, see [`PaymentEngine::insert`]
+/// - `POST /rollback` with the number of transactions to reverse, e.g.
+///   `{"count": 1}`, which responds with how many were, see
+///   [`PaymentEngine::rollback`]
+/// - `GET /metrics` with the [`ServiceMetrics`](crate::ServiceMetrics) in
+///   the Prometheus text format, if enabled with
+///   [`PaymentEngine::enable_service_metrics`]
+impl<A: AccountStore, T: TransactionStore> Handler for PaymentEngine<A, T> {
+    fn handle(&mut self, request: &Request) -> Response {
+        let segments = request.segments();
+        let id = match segments.as_slice() {
+            ["reservations", id, ..] => match id.parse::<ReservationId>() {
+                Ok(id) => Some(id),
+                Err(_) => return Response::error(400, "Invalid reservation id"),
+            },
+            _ => None,
+        };
+        match (request.method.as_str(), segments.as_slice(), id) {
+            ("GET", ["accounts", client], _) => match client.parse() {
+                Ok(client) => self
+                    .account(client)
+                    .map(Response::json)
+                    .unwrap_or_else(Response::not_found),
+                Err(_) => Response::error(400, "Invalid client id"),
+            },
+            ("POST", ["reservations"], _) => {
+                let body: ReserveRequest = match serde_json::from_slice(&request.body) {
+                    Ok(body) => body,
+                    Err(e) => return Response::error(400, &e.to_string()),
+                };
+                match self.reserve(body.client, body.amount) {
+                    Ok(id) => Response::json(&self.reservation(id)),
+                    Err(e) => error_response(&e),
+                }
+            }
+            ("GET", ["reservations", _], Some(id)) => self
+                .reservation(id)
+                .map(Response::json)
+                .unwrap_or_else(Response::not_found),
+            ("POST", ["reservations", _, action], Some(id)) => {
+                let result = match *action {
+                    "commit" => self.commit(id),
+                    "release" => self.release(id),
+                    _ => return Response::not_found(),
+                };
+                match result.and_then(|_| self.flush()) {
+                    Ok(()) => Response::json(&serde_json::json!({ "id": id })),
+                    Err(e) => error_response(&e),
+                }
+            }
+            ("POST", ["transactions"], _) => {
+                let tx: Transaction = match serde_json::from_slice(&request.body) {
+                    Ok(tx) => tx,
+                    Err(e) => return Response::error(400, &e.to_string()),
+                };
+                let client = tx.client;
+                match self.insert(tx).and_then(|_| self.flush()) {
+                    Ok(()) => self
+                        .account(client)
+                        .map(Response::json)
+                        .unwrap_or_else(Response::not_found),
+                    Err(e) => error_response(&e),
+                }
+            }
+            ("POST", ["rollback"], _) => {
+                let body: RollbackRequest = match serde_json::from_slice(&request.body) {
+                    Ok(body) => body,
+                    Err(e) => return Response::error(400, &e.to_string()),
+                };
+                let reversed = self.rollback(body.count);
+                match reversed.and_then(|reversed| self.flush().map(|_| reversed)) {
+                    Ok(reversed) => Response::json(&serde_json::json!({ "reversed": reversed })),
+                    Err(e) => error_response(&e),
+                }
+            }
+            ("GET", ["metrics"], _) => self
+                .service_metrics()
+                .map(|metrics| Response::text(metrics.render()))
+                .unwrap_or_else(Response::not_found),
+            ("POST", ["validate"], _) => match validate(request.body.as_slice(), self) {
+                Ok(report) => Response::json(&report),
+                Err(e) => Response::error(400, &e.to_string()),
+            },
+            (_, ["accounts", _], _)
+            | (_, ["reservations", ..], _)
+            | (_, ["validate"], _)
+            | (_, ["transactions"], _)
+            | (_, ["rollback"], _)
+            | (_, ["metrics"], _) => Response::error(405, "Method not allowed"),
+            _ => Response::not_found(),
+        }
+    }
+}
+
+fn error_response(e: &TransactionError) -> Response {
+    let status = match e {
+        TransactionError::AccountNotFound(_) | TransactionError::ReservationNotFound(_) => 404,
+        TransactionError::NegativeAmount | TransactionError::Rejected(_) => 422,
+        TransactionError::Storage(_) => 500,
+        _ => 409,
+    };
+    Response::error(status, &e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Transaction, TransactionVariant};
+
+    fn request(method: &str, path: &str, body: &str) -> Request {
+        Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn serves_reservations() {
+        let mut engine = PaymentEngine::default();
+        let deposit = Transaction {
+            tx: 1,
+            amount: Some(Amount::new(10, 0).unwrap()),
+            client: 1,
+            disputed: false,
+            variant: TransactionVariant::Deposit,
+            chargeback: false,
+            destination: None,
+            currency: None,
+            to_currency: None,
+            rate: None,
+            timestamp: None,
+        };
+        engine.insert(deposit).unwrap();
+
+        let response = engine.handle(&request(
+            "POST",
+            "/reservations",
+            r#"{"client": 1, "amount": "4.0"}"#,
+        ));
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, r#"{"id":1,"client":1,"amount":"4.0"}"#);
+        let response = engine.handle(&request(
+            "POST",
+            "/reservations",
+            r#"{"client": 1, "amount": "7.0"}"#,
+        ));
+        assert_eq!(response.status, 409);
+
+        let response = engine.handle(&request("POST", "/reservations/1/release", ""));
+        assert_eq!(response.status, 200);
+        let account = engine.accounts().get(&1).unwrap();
+        assert_eq!(account.available(), Amount::new(10, 0).unwrap());
+        assert_eq!(account.held(), Amount::zero());
+
+        let response = engine.handle(&request("POST", "/reservations/1/commit", ""));
+        assert_eq!(response.status, 404);
+        let response = engine.handle(&request("GET", "/reservations/x", ""));
+        assert_eq!(response.status, 400);
+
+        let response = engine.handle(&request(
+            "POST",
+            "/validate",
+            "type,client,tx,amount\nwithdrawal,1,2,11.0\n",
+        ));
+        assert_eq!(response.status, 200);
+        assert!(response
+            .body
+            .starts_with(r#"{"rows":1,"errors":[{"line":2,"#));
+        let account = engine.accounts().get(&1).unwrap();
+        assert_eq!(account.available(), Amount::new(10, 0).unwrap());
+    }
+
+    #[test]
+    fn serves_transactions_and_metrics() {
+        let mut engine = PaymentEngine::default();
+        let response = engine.handle(&request("GET", "/metrics", ""));
+        assert_eq!(response.status, 404);
+        engine.enable_service_metrics();
+
+        let deposit = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "5.0"}"#;
+        let response = engine.handle(&request("POST", "/transactions", deposit));
+        assert_eq!(response.status, 200);
+        let withdrawal = r#"{"type": "withdrawal", "client": 1, "tx": 2, "amount": "6.0"}"#;
+        let response = engine.handle(&request("POST", "/transactions", withdrawal));
+        assert_eq!(response.status, 409);
+        for (tx, variant) in [(1, "dispute"), (1, "chargeback")] {
+            let body = format!(r#"{{"type": "{}", "client": 1, "tx": {}}}"#, variant, tx);
+            let response = engine.handle(&request("POST", "/transactions", &body));
+            assert_eq!(response.status, 200);
+        }
+
+        let response = engine.handle(&request("GET", "/metrics", ""));
+        assert_eq!(response.status, 200);
+        assert!(response.content_type.starts_with("text/plain"));
+        for line in [
+            "payments_transactions_total{variant=\"deposit\"} 1\n",
+            "payments_transactions_total{variant=\"chargeback\"} 1\n",
+            "payments_rejects_total{kind=\"insufficient_funds\"} 1\n",
+            "payments_accounts_locked_total 1\n",
+            "payments_insert_latency_seconds_count 4\n",
+        ] {
+            assert!(response.body.contains(line), "{}", line);
+        }
+    }
+
+    #[test]
+    fn serves_rollbacks() {
+        let mut engine = PaymentEngine::default();
+        engine.set_journal(10);
+        let deposit = r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "5.0"}"#;
+        engine.handle(&request("POST", "/transactions", deposit));
+
+        let response = engine.handle(&request("POST", "/rollback", r#"{"count": 2}"#));
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, r#"{"reversed":1}"#);
+        assert_eq!(engine.accounts().get(&1).unwrap().total(), Amount::zero());
+        let response = engine.handle(&request("GET", "/rollback", ""));
+        assert_eq!(response.status, 405);
+    }
+}
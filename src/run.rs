@@ -0,0 +1,734 @@
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::io;
+use std::time::{Duration, Instant};
+
+use rust_decimal::Decimal;
+use serde::{ser::SerializeStruct, Serialize, Serializer};
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::{
+    aggregate::AggregateValue,
+    amount::{Amount, RoundingMode},
+    cancel::{CancellationToken, ResumePoint},
+    checkpoint::CheckpointOptions,
+    config::EngineConfig,
+    error::TransactionError,
+    format::{Format, RowReader},
+    hot::{HotAccount, HotAccountConfig},
+    instrument::{stage_span, StageTimings},
+    interest::InterestConfig,
+    limit::QuarantinedClient,
+    metrics::{Alert, AlertThreshold, EngineStats, RunMetrics},
+    output::{write_accounts, OutputOptions},
+    policy::ProcessingPolicy,
+    progress::ProgressReporter,
+    store::{AccountStore, SpillingTransactionStore, TransactionStore},
+    PaymentEngine, Transaction,
+};
+
+/// Options for [`run_with_options`].
+#[derive(Debug, Clone, Default)]
+pub struct RunOptions {
+    /// The configuration of the engine, see [`PaymentEngine::builder`].
+    pub config: EngineConfig,
+    /// Detect hot clients and report them in [`RunReport::hot_accounts`].
+    pub hot_accounts: Option<HotAccountConfig>,
+    /// Keep at most this many transactions in memory and spill the rest to a
+    /// temporary file, see [`SpillingTransactionStore`].
+    pub max_resident_transactions: Option<usize>,
+    /// Process at most this many transactions per client. Any further
+    /// transactions of the client are quarantined: they are counted in
+    /// [`RunReport::quarantined`] and handled by [`RunOptions::policy`] like
+    /// rows that cannot be processed, so they are listed in
+    /// [`RunReport::errors`] under [`ProcessingPolicy::SkipWithLog`].
+    pub max_transactions_per_client: Option<u64>,
+    pub output: OutputOptions,
+    /// Thresholds that are checked against [`RunReport::metrics`] at the end
+    /// of the run. Exceeded ones are reported in [`RunReport::alerts`].
+    pub alerts: Vec<AlertThreshold>,
+    /// How rows that cannot be processed are handled.
+    pub policy: ProcessingPolicy,
+    /// Stop processing once this is cancelled. The accounts processed so far
+    /// are still written and [`RunReport::resume`] says where to continue.
+    pub cancel: Option<CancellationToken>,
+    /// Stop processing once the run has taken this long. The accounts
+    /// processed so far are still written, after which [`run_with_options`]
+    /// fails with [`TimedOut`].
+    pub timeout: Option<Duration>,
+    /// Write a checkpoint every so many accepted transactions.
+    pub checkpoint: Option<CheckpointOptions>,
+    /// Identifies the run in [`RunReport::run_id`] and snapshots. Randomly
+    /// generated if not set, see [`PaymentEngine::run_id`].
+    pub run_id: Option<Uuid>,
+    /// Accrue interest by the `timestamp` column of the input, see
+    /// [`PaymentEngine::advance_time`].
+    pub interest: Option<InterestConfig>,
+    /// Keep an index of the transactions of every client, see
+    /// [`PaymentEngine::history`].
+    pub history: bool,
+    /// Keep a log of every dispute, see [`PaymentEngine::dispute_outcomes`].
+    pub dispute_log: bool,
+    /// The format of the transactions. The format of the accounts is
+    /// [`OutputOptions::format`].
+    pub input_format: Format,
+}
+
+impl RunOptions {
+    /// Creates a [`PaymentEngine`] configured according to these options.
+    ///
+    /// Ignores [`RunOptions::max_resident_transactions`], as that requires a
+    /// different store.
+    pub fn engine(&self) -> PaymentEngine {
+        self.configure(PaymentEngine::default())
+    }
+
+    /// Applies these options to `engine`.
+    pub fn configure<A, T>(&self, mut engine: PaymentEngine<A, T>) -> PaymentEngine<A, T>
+    where
+        A: AccountStore,
+        T: TransactionStore,
+    {
+        engine.set_config(self.config.clone());
+        if let Some(config) = &self.hot_accounts {
+            engine.set_hot_accounts(config.clone());
+        }
+        if let Some(max) = self.max_transactions_per_client {
+            engine.set_client_limit(max);
+        }
+        if let Some(run_id) = self.run_id {
+            engine.set_run_id(run_id);
+        }
+        if let Some(config) = &self.interest {
+            engine.set_interest(config.clone());
+        }
+        if self.history {
+            engine.set_history();
+        }
+        if self.dispute_log {
+            engine.set_dispute_log();
+        }
+        engine
+    }
+}
+
+/// The error returned by [`run_with_options`] when
+/// [`RunOptions::timeout`] was exceeded.
+#[derive(Debug, Clone, Error)]
+#[error("Processing timed out after {elapsed:?} and {} rows", report.metrics.rows)]
+pub struct TimedOut {
+    pub elapsed: Duration,
+    /// The report of the partial run, with [`RunReport::resume`] set to where
+    /// processing stopped
+    pub report: RunReport,
+}
+
+/// A report of a finished run.
+#[derive(Debug, Clone, Default)]
+pub struct RunReport {
+    /// The id of the run, see [`PaymentEngine::run_id`].
+    pub run_id: Uuid,
+    /// Clients that received a disproportionate share of the transactions.
+    pub hot_accounts: Vec<HotAccount>,
+    /// Clients whose transactions exceeded
+    /// [`RunOptions::max_transactions_per_client`].
+    pub quarantined: Vec<QuarantinedClient>,
+    /// Accumulated deposits the accounts did not accept, e.g. as they are
+    /// frozen, by client. They are missing from the written balances.
+    pub pending_deposits: BTreeMap<u16, Amount>,
+    /// The values of [`OutputOptions::aggregations`], in the same order.
+    pub aggregations: Vec<AggregateValue>,
+    pub metrics: RunMetrics,
+    /// The accepted transactions by variant, see [`PaymentEngine::stats`].
+    pub stats: EngineStats,
+    /// The thresholds of [`RunOptions::alerts`] that were exceeded.
+    pub alerts: Vec<Alert>,
+    /// The rows that were skipped, when the policy is
+    /// [`ProcessingPolicy::SkipWithLog`].
+    pub errors: Vec<RowError>,
+    /// Where in the input processing stopped, if it was cancelled with
+    /// [`RunOptions::cancel`] or timed out.
+    pub resume: Option<ResumePoint>,
+}
+
+/// Reads transactions as CSV from `reader`, processes them and writes the
+/// resulting accounts as CSV to `writer`.
+pub fn run_with_options<R: io::Read, W: io::Write>(
+    reader: R,
+    writer: W,
+    options: &RunOptions,
+) -> Result<RunReport, Box<dyn Error>> {
+    match options.max_resident_transactions {
+        Some(max_resident) => {
+            let transactions = SpillingTransactionStore::new(max_resident)?;
+            let engine = PaymentEngine::with_stores(HashMap::new(), transactions);
+            run_engine(options.configure(engine), reader, writer, options)
+        }
+        None => run_engine(options.engine(), reader, writer, options),
+    }
+}
+
+fn run_engine<R, W, A, T>(
+    mut engine: PaymentEngine<A, T>,
+    reader: R,
+    writer: W,
+    options: &RunOptions,
+) -> Result<RunReport, Box<dyn Error>>
+where
+    R: io::Read,
+    W: io::Write,
+    A: AccountStore,
+    T: TransactionStore,
+{
+    let start = Instant::now();
+    let processed = process_rows(
+        reader,
+        &mut engine,
+        options.policy,
+        options.input_format,
+        None,
+        options.cancel.as_ref(),
+        options.timeout.map(|timeout| start + timeout),
+        options.checkpoint.as_ref(),
+    )?;
+    let aggregations = write_accounts(&engine, writer, &options.output)?;
+
+    let metrics = engine.metrics().clone();
+    let report = RunReport {
+        run_id: engine.run_id(),
+        hot_accounts: engine.hot_accounts(),
+        quarantined: engine.quarantined(),
+        pending_deposits: engine.pending_deposits(),
+        aggregations,
+        alerts: options
+            .alerts
+            .iter()
+            .filter_map(|threshold| threshold.check(&metrics))
+            .collect(),
+        metrics,
+        stats: engine.stats().clone(),
+        errors: processed.errors,
+        resume: processed.resume,
+    };
+    if processed.timed_out {
+        return Err(Box::new(TimedOut {
+            elapsed: start.elapsed(),
+            report,
+        }));
+    }
+    Ok(report)
+}
+
+/// An error in a single row that could not be processed.
+#[derive(Debug, Clone, PartialEq, Error)]
+#[error("Line {line}: {kind}")]
+pub struct RowError {
+    /// The line of the row in the input, starting at 1 for the header
+    pub line: u64,
+    pub kind: RowErrorKind,
+}
+
+impl Serialize for RowError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("RowError", 2)?;
+        state.serialize_field("line", &self.line)?;
+        state.serialize_field("error", &self.kind.to_string())?;
+        state.end()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum RowErrorKind {
+    /// The row could not be parsed as a [`Transaction`]
+    #[error("{0}")]
+    Parse(String),
+    /// The row is missing an amount or has one it should not have
+    #[error("Invalid transaction")]
+    Invalid,
+    #[error(transparent)]
+    Transaction(#[from] TransactionError),
+}
+
+impl RowErrorKind {
+    /// The reason of the error, `parse`, `invalid` or the
+    /// [`TransactionError::kind`], to count rejected rows by.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            RowErrorKind::Parse(_) => "parse",
+            RowErrorKind::Invalid => "invalid",
+            RowErrorKind::Transaction(e) => e.kind(),
+        }
+    }
+}
+
+/// Reads transactions as CSV from `reader` and inserts them into `engine`.
+///
+/// Stops at the first row that cannot be processed, see
+/// [`process_with_policy`] for skipping them instead.
+pub fn process<R, A, T>(reader: R, engine: &mut PaymentEngine<A, T>) -> Result<(), Box<dyn Error>>
+where
+    R: io::Read,
+    A: AccountStore,
+    T: TransactionStore,
+{
+    process_with_policy(reader, engine, ProcessingPolicy::StopOnFirstError)?;
+    Ok(())
+}
+
+/// Reads transactions as CSV from `reader` and inserts them into `engine`,
+/// handling rows that cannot be processed according to `policy`.
+///
+/// Returns the skipped rows with their errors when the policy is
+/// [`ProcessingPolicy::SkipWithLog`].
+pub fn process_with_policy<R, A, T>(
+    reader: R,
+    engine: &mut PaymentEngine<A, T>,
+    policy: ProcessingPolicy,
+) -> Result<Vec<RowError>, Box<dyn Error>>
+where
+    R: io::Read,
+    A: AccountStore,
+    T: TransactionStore,
+{
+    process_with_format(reader, engine, policy, Format::Csv)
+}
+
+/// Like [`process_with_policy`], but reads the transactions in `format`.
+pub fn process_with_format<R, A, T>(
+    reader: R,
+    engine: &mut PaymentEngine<A, T>,
+    policy: ProcessingPolicy,
+    format: Format,
+) -> Result<Vec<RowError>, Box<dyn Error>>
+where
+    R: io::Read,
+    A: AccountStore,
+    T: TransactionStore,
+{
+    Ok(process_rows(reader, engine, policy, format, None, None, None, None)?.errors)
+}
+
+/// Like [`process_with_format`], but reports how far processing has come to
+/// `progress`.
+pub fn process_with_progress<R, A, T>(
+    reader: R,
+    engine: &mut PaymentEngine<A, T>,
+    policy: ProcessingPolicy,
+    format: Format,
+    progress: &mut ProgressReporter<'_>,
+) -> Result<Vec<RowError>, Box<dyn Error>>
+where
+    R: io::Read,
+    A: AccountStore,
+    T: TransactionStore,
+{
+    Ok(process_rows(
+        reader,
+        engine,
+        policy,
+        format,
+        Some(progress),
+        None,
+        None,
+        None,
+    )?
+    .errors)
+}
+
+/// The outcome of [`process_rows`].
+struct Processed {
+    errors: Vec<RowError>,
+    /// Where processing stopped if it was cancelled or timed out
+    resume: Option<ResumePoint>,
+    timed_out: bool,
+}
+
+/// Processes all rows according to `policy`. Stops before the next row once
+/// `cancel` is cancelled or `deadline` has passed.
+#[allow(clippy::too_many_arguments)]
+fn process_rows<R, A, T>(
+    reader: R,
+    engine: &mut PaymentEngine<A, T>,
+    policy: ProcessingPolicy,
+    format: Format,
+    mut progress: Option<&mut ProgressReporter<'_>>,
+    cancel: Option<&CancellationToken>,
+    deadline: Option<Instant>,
+    checkpoint: Option<&CheckpointOptions>,
+) -> Result<Processed, Box<dyn Error>>
+where
+    R: io::Read,
+    A: AccountStore,
+    T: TransactionStore,
+{
+    let mut errors = Vec::new();
+    stage_span!("process", format = format.as_str());
+    let mut timings = StageTimings::default();
+    let mut rdr = RowReader::new(reader, format)?;
+    let headers = rdr.headers()?;
+    // The optional column advancing the clock of the engine
+    let timestamp_column = headers.iter().position(|header| header == "timestamp");
+    // The optional column queueing rows until the engine advances to it
+    let effective_column = headers.iter().position(|header| header == "effective");
+    // The optional column identifying rows that must be applied only once
+    let key_column = headers
+        .iter()
+        .position(|header| header == "idempotency_key");
+    let amount_column = headers.iter().position(|header| header == "amount");
+    let mut record = csv::StringRecord::new();
+    let mut resume = None;
+    let mut timed_out = false;
+    let mut rows = 0;
+    let mut accepted = 0;
+    loop {
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            timed_out = true;
+        }
+        if timed_out || cancel.is_some_and(CancellationToken::is_cancelled) {
+            resume = Some(rdr.position());
+            break;
+        }
+        if !rdr.read_record(&mut record)? {
+            break;
+        }
+        let line = record.position().map_or(0, csv::Position::line);
+        engine.metrics_mut().rows += 1;
+        rows += 1;
+        if let Some(progress) = progress.as_deref_mut() {
+            progress.update(rows, rdr.position().byte);
+        }
+        // Rejected rows are written as they were read
+        let mut unrounded = None;
+        if let (Some(column), Some(mode)) = (amount_column, engine.config().rounding) {
+            if engine.writes_rejected_rows() {
+                unrounded = Some(record.clone());
+            }
+            round_amount(&mut record, column, mode);
+        }
+
+        let timestamp = timestamp_column
+            .and_then(|i| record.get(i))
+            .filter(|timestamp| !timestamp.is_empty())
+            .map(|timestamp| timestamp.trim().parse::<u64>());
+        let effective = effective_column
+            .and_then(|i| record.get(i))
+            .filter(|effective| !effective.is_empty())
+            .map(|effective| effective.trim().parse::<u64>());
+        let parsed = timings.deserialize(|| record.deserialize::<Transaction>(Some(&headers)));
+        let kind = match (parsed, timestamp, effective) {
+            (_, Some(Err(e)), _) => RowErrorKind::Parse(format!("Invalid timestamp: {}", e)),
+            (_, _, Some(Err(e))) => RowErrorKind::Parse(format!("Invalid effective date: {}", e)),
+            (Ok(mut tx), timestamp, effective) => {
+                if let Some(Ok(timestamp)) = timestamp {
+                    engine.advance_time(timestamp)?;
+                    tx.timestamp = Some(timestamp);
+                }
+                if !tx.is_valid() {
+                    RowErrorKind::Invalid
+                } else {
+                    let key = key_column
+                        .and_then(|i| record.get(i))
+                        .map(str::trim)
+                        .filter(|key| !key.is_empty());
+                    let result = timings.insert(|| match (effective, key) {
+                        (Some(Ok(effective)), _) => engine.insert_effective(tx, effective),
+                        (_, Some(key)) => engine.insert_with_key(tx, key),
+                        _ => engine.insert(tx),
+                    });
+                    match result {
+                        // The storage is unusable, so there is no point in continuing
+                        Err(e @ TransactionError::Storage(_)) => return Err(Box::new(e)),
+                        Err(e) => RowErrorKind::Transaction(e),
+                        Ok(()) => {
+                            accepted += 1;
+                            if let Some(options) = checkpoint {
+                                if accepted % options.every.max(1) == 0 {
+                                    engine.flush()?;
+                                    let mut checkpoint = engine.checkpoint();
+                                    checkpoint.position = Some(rdr.position());
+                                    checkpoint.save(&options.path)?;
+                                    tracing::debug!(line, "Checkpoint written");
+                                }
+                            }
+                            continue;
+                        }
+                    }
+                }
+            }
+            (Err(e), _, _) => RowErrorKind::Parse(e.to_string()),
+        };
+
+        match &kind {
+            // Quarantined rows are counted apart from the rejected ones
+            RowErrorKind::Transaction(TransactionError::ClientLimitExceeded(client)) => {
+                tracing::debug!(line, client, "Quarantined a transaction");
+                engine.metrics_mut().quarantined += 1;
+            }
+            _ => {
+                engine.metrics_mut().reject(kind.reason());
+                tracing::warn!(line, error = %kind, "Row rejected");
+            }
+        }
+        engine.write_rejected_row(&headers, unrounded.as_ref().unwrap_or(&record), line, &kind)?;
+        let error = RowError { line, kind };
+        match policy {
+            ProcessingPolicy::StopOnFirstError => return Err(Box::new(error)),
+            ProcessingPolicy::SkipInvalid => (),
+            ProcessingPolicy::SkipWithLog => errors.push(error),
+        }
+    }
+    // Partial results of a cancelled run are flushed as well
+    engine.flush()?;
+    timings.report(rows);
+    if let Some(progress) = progress {
+        progress.finish(rows, rdr.position().byte);
+    }
+    Ok(Processed {
+        errors,
+        resume,
+        timed_out,
+    })
+}
+
+/// Rounds the amount in `column` of `record` if it has more than four decimal
+/// places. Anything else, including negative amounts that would round to
+/// zero, is left to the deserialization to reject.
+fn round_amount(record: &mut csv::StringRecord, column: usize, mode: RoundingMode) {
+    let rounded = match record
+        .get(column)
+        .map(|amount| amount.trim().parse::<Decimal>())
+    {
+        Some(Ok(amount)) if amount.scale() > 4 && !amount.is_sign_negative() => {
+            mode.round(amount).to_string()
+        }
+        _ => return,
+    };
+    let mut fields = record
+        .iter()
+        .enumerate()
+        .map(|(i, field)| if i == column { rounded.as_str() } else { field })
+        .collect::<csv::StringRecord>();
+    fields.set_position(record.position().cloned());
+    *record = fields;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INPUT: &str = "type,client,tx,amount
+deposit,1,1,5.0
+withdrawal,1,2,10.0
+deposit,1,three,1.0
+deposit,1,1,1.0
+deposit,1,5,
+withdrawal,1,4,1.0
+";
+
+    #[test]
+    fn strict_processing_stops_at_first_error() {
+        let mut engine = PaymentEngine::default();
+        let e = process(INPUT.as_bytes(), &mut engine).unwrap_err();
+        assert!(e.to_string().starts_with("Line 3: Insufficient funds"));
+        assert_eq!(engine.metrics().rows, 2);
+    }
+
+    #[test]
+    fn runs_stop_at_invalid_rows_by_default() {
+        assert_eq!(
+            ProcessingPolicy::default(),
+            ProcessingPolicy::StopOnFirstError
+        );
+        let input = "type,client,tx,amount\ndeposit,1,1,\ndeposit,1,2,1.0\n";
+        let e = crate::run(input.as_bytes(), io::sink()).unwrap_err();
+        assert_eq!(e.to_string(), "Line 2: Invalid transaction");
+    }
+
+    #[test]
+    fn skip_with_log_collects_errors() {
+        let mut engine = PaymentEngine::default();
+        let errors =
+            process_with_policy(INPUT.as_bytes(), &mut engine, ProcessingPolicy::SkipWithLog)
+                .unwrap();
+        let lines = errors.iter().map(|e| e.line).collect::<Vec<_>>();
+        assert_eq!(lines, vec![3, 4, 5, 6]);
+        assert!(matches!(errors[1].kind, RowErrorKind::Parse(_)));
+        assert_eq!(
+            errors[2].kind,
+            RowErrorKind::Transaction(TransactionError::TransactionAlreadyExist)
+        );
+        assert_eq!(errors[3].kind, RowErrorKind::Invalid);
+        assert_eq!(engine.metrics().rejected, 4);
+        assert_eq!(engine.metrics().rejected_by_reason["parse"], 1);
+        assert_eq!(engine.metrics().rejected_by_reason["invalid"], 1);
+        assert_eq!(engine.metrics().accounts_created, 1);
+
+        let account = engine.accounts().get(&1).unwrap();
+        assert_eq!(account.available(), crate::Amount::new(4, 0).unwrap());
+    }
+
+    #[test]
+    fn skip_invalid_skips_silently() {
+        let mut engine = PaymentEngine::default();
+        let errors =
+            process_with_policy(INPUT.as_bytes(), &mut engine, ProcessingPolicy::SkipInvalid)
+                .unwrap();
+        assert!(errors.is_empty());
+        assert_eq!(engine.metrics().rejected, 4);
+    }
+
+    #[test]
+    fn unknown_and_quarantined_rows_follow_the_policy() {
+        let input = "type,client,tx,amount
+deposit,1,1,5.0
+dispute,1,9,
+deposit,1,2,1.0
+deposit,1,3,1.0
+";
+        let mut options = RunOptions {
+            max_transactions_per_client: Some(3),
+            ..RunOptions::default()
+        };
+        let e = run_with_options(input.as_bytes(), io::sink(), &options).unwrap_err();
+        assert_eq!(e.to_string(), "Line 3: The transaction was not found");
+
+        options.policy = ProcessingPolicy::SkipWithLog;
+        let report = run_with_options(input.as_bytes(), io::sink(), &options).unwrap();
+        let kinds = report
+            .errors
+            .iter()
+            .map(|e| (e.line, e.kind.reason()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![(3, "transaction_not_found"), (5, "client_limit_exceeded")]
+        );
+        assert_eq!(report.metrics.rejected, 1);
+        assert_eq!(report.metrics.quarantined, 1);
+    }
+
+    #[test]
+    fn cancelled_run_reports_resume_point() {
+        let token = CancellationToken::new();
+        token.cancel();
+        let options = RunOptions {
+            cancel: Some(token),
+            ..RunOptions::default()
+        };
+        let report = run_with_options(INPUT.as_bytes(), io::sink(), &options).unwrap();
+        let resume = report.resume.unwrap();
+        assert_eq!(resume.line, 2);
+        assert_eq!(resume.byte, "type,client,tx,amount\n".len() as u64);
+        assert_eq!(report.metrics.rows, 0);
+    }
+
+    #[test]
+    fn timed_out_run_reports_progress() {
+        let options = RunOptions {
+            timeout: Some(Duration::ZERO),
+            ..RunOptions::default()
+        };
+        let e = run_with_options(INPUT.as_bytes(), io::sink(), &options).unwrap_err();
+        let timed_out = e.downcast::<TimedOut>().unwrap();
+        assert_eq!(timed_out.report.resume.unwrap().line, 2);
+        assert_eq!(timed_out.report.metrics.rows, 0);
+    }
+
+    #[test]
+    fn overflowing_amounts_are_rejected() {
+        let input = "type,client,tx,amount
+deposit,1,1,79228162514264337593543950335
+deposit,1,2,1.0
+withdrawal,1,3,1.0
+";
+        let mut engine = PaymentEngine::default();
+        let errors =
+            process_with_policy(input.as_bytes(), &mut engine, ProcessingPolicy::SkipWithLog)
+                .unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0],
+            RowError {
+                line: 3,
+                kind: RowErrorKind::Transaction(TransactionError::Overflow),
+            }
+        );
+        let account = engine.accounts().get(&1).unwrap();
+        assert_eq!(
+            account.total().to_string(),
+            "79228162514264337593543950334.0000"
+        );
+    }
+
+    #[test]
+    fn rounds_precise_amounts_when_configured() {
+        let input = "type,client,tx,amount
+deposit,1,1,1.000050
+withdrawal,1,2,0.000149
+deposit,1,3,-0.000001
+";
+        let mut engine = PaymentEngine::builder()
+            .rounding(RoundingMode::HalfUp)
+            .build();
+        let errors =
+            process_with_policy(input.as_bytes(), &mut engine, ProcessingPolicy::SkipWithLog)
+                .unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 4);
+        let account = engine.accounts().get(&1).unwrap();
+        assert_eq!(account.total().to_string(), "1.0000");
+
+        let mut engine = PaymentEngine::default();
+        assert!(process(input.as_bytes(), &mut engine).is_err());
+    }
+
+    #[test]
+    fn rerun_skips_rows_with_applied_keys() {
+        let input = "type,client,tx,amount,idempotency_key
+deposit,1,1,5.0,a
+withdrawal,1,2,8.0,b
+";
+        let mut engine = PaymentEngine::default();
+        process_with_policy(input.as_bytes(), &mut engine, ProcessingPolicy::SkipInvalid).unwrap();
+
+        // The rejected withdrawal is tried again once there are enough funds
+        let input = "type,client,tx,amount,idempotency_key
+deposit,1,1,5.0,a
+deposit,1,3,5.0,c
+withdrawal,1,2,8.0,b
+";
+        let mut engine = PaymentEngine::from_snapshot(engine.snapshot());
+        process(input.as_bytes(), &mut engine).unwrap();
+        let account = engine.accounts().get(&1).unwrap();
+        assert_eq!(account.total().to_string(), "2.0000");
+        assert_eq!(engine.metrics().replayed, 1);
+    }
+
+    #[test]
+    fn accrues_interest_by_timestamp() {
+        let input = "type,client,tx,amount,timestamp
+deposit,1,1,100.0,5
+deposit,2,2,100.0,6
+dispute,2,2,,7
+deposit,1,3,1.0,25
+";
+        let options = RunOptions {
+            interest: Some(InterestConfig {
+                period: 10,
+                schedule: "0:0.01".parse().unwrap(),
+            }),
+            ..RunOptions::default()
+        };
+        let mut engine = options.engine();
+        process(input.as_bytes(), &mut engine).unwrap();
+
+        // Two periods ended before the last deposit, compounding on client 1
+        // while the held funds of client 2 earn nothing
+        let account = engine.accounts().get(&1).unwrap();
+        assert_eq!(account.total().to_string(), "103.0100");
+        let account = engine.accounts().get(&2).unwrap();
+        assert_eq!(account.total().to_string(), "100.0000");
+        assert_eq!(engine.metrics().interest.to_string(), "2.0100");
+    }
+}
@@ -0,0 +1,172 @@
+//! A minimal HTTP/1.1 server used to expose engines over the network.
+//!
+//! Requests are handled one at a time and every connection is closed after
+//! its response, which keeps the implementation small and is plenty for
+//! support tooling.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use serde::Serialize;
+
+const JSON: &str = "application/json";
+
+/// An HTTP request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    pub method: String,
+    /// The path of the request, without the query string
+    pub path: String,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// Returns the non-empty segments of the path.
+    pub fn segments(&self) -> Vec<&str> {
+        self.path.split('/').filter(|s| !s.is_empty()).collect()
+    }
+}
+
+/// An HTTP response, with a JSON body unless created with
+/// [`Response::text`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Response {
+    /// A `200 OK` response with `value` serialized as JSON.
+    pub fn json<T: Serialize + ?Sized>(value: &T) -> Self {
+        match serde_json::to_string(value) {
+            Ok(body) => Self {
+                status: 200,
+                content_type: JSON,
+                body,
+            },
+            Err(e) => Self::error(500, &e.to_string()),
+        }
+    }
+
+    /// A response with `status` and an error message.
+    pub fn error(status: u16, message: &str) -> Self {
+        Self {
+            status,
+            content_type: JSON,
+            body: serde_json::json!({ "error": message }).to_string(),
+        }
+    }
+
+    /// A `200 OK` response with a plain text `body`, in the version of the
+    /// format Prometheus scrapes.
+    pub fn text(body: String) -> Self {
+        Self {
+            status: 200,
+            content_type: "text/plain; version=0.0.4",
+            body,
+        }
+    }
+
+    pub fn not_found() -> Self {
+        Self::error(404, "Not found")
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            409 => "Conflict",
+            422 => "Unprocessable Entity",
+            _ => "Internal Server Error",
+        }
+    }
+}
+
+/// Something that can answer [`Request`]s.
+pub trait Handler {
+    fn handle(&mut self, request: &Request) -> Response;
+}
+
+/// Accepts connections on `listener` and answers them with `handler` until
+/// the listener fails.
+pub fn serve<H: Handler>(listener: TcpListener, handler: &mut H) -> io::Result<()> {
+    for stream in listener.incoming() {
+        let mut stream = stream?;
+        let response = match read_request(&mut stream) {
+            Ok(request) => handler.handle(&request),
+            Err(e) => Response::error(400, &e.to_string()),
+        };
+        // A client that hangs up early should not stop the server
+        let _ = write_response(&mut stream, &response);
+    }
+    Ok(())
+}
+
+fn read_request(stream: &mut TcpStream) -> io::Result<Request> {
+    let mut reader = BufReader::new(stream);
+
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => (method.to_string(), target),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Malformed request line",
+            ))
+        }
+    };
+    let path = target.split('?').next().unwrap_or_default().to_string();
+
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().map_err(|_| {
+                    io::Error::new(io::ErrorKind::InvalidData, "Invalid Content-Length")
+                })?;
+            }
+        }
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+
+    Ok(Request { method, path, body })
+}
+
+fn write_response(stream: &mut TcpStream, response: &Response) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        response.reason(),
+        response.content_type,
+        response.body.len(),
+        response.body
+    )?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_segments_skip_empty_parts() {
+        let request = Request {
+            method: "GET".to_string(),
+            path: "/accounts//1/".to_string(),
+            body: Vec::new(),
+        };
+        assert_eq!(request.segments(), vec!["accounts", "1"]);
+    }
+}
@@ -0,0 +1,19 @@
+use crate::{Account, TransactionError};
+
+/// The effect a batch of transactions would have, see
+/// [`PaymentEngine::simulate`](crate::PaymentEngine::simulate).
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulationResult {
+    /// The result of every transaction of the batch, in order
+    pub results: Vec<Result<(), TransactionError>>,
+    /// The accounts touched by the batch as they would be afterwards,
+    /// ordered by client
+    pub accounts: Vec<Account>,
+}
+
+impl SimulationResult {
+    /// Whether every transaction would be applied.
+    pub fn is_ok(&self) -> bool {
+        self.results.iter().all(Result::is_ok)
+    }
+}
@@ -0,0 +1,283 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    account::{Account, ClientMetadata},
+    amount::Amount,
+    atomic::write_atomically,
+    compression::Compression,
+    currency::Currency,
+    recurring::ScheduleState,
+    reservation::Reservation,
+    Transaction, TransactionVariant,
+};
+
+/// A point in time copy of the state of a [`PaymentEngine`](crate::PaymentEngine).
+///
+/// Snapshots are stored as JSON and can be loaded into a
+/// [`PaymentEngine`](crate::PaymentEngine) or a [`QueryEngine`](crate::QueryEngine).
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// The run that took the snapshot, see
+    /// [`PaymentEngine::run_id`](crate::PaymentEngine::run_id).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run_id: Option<Uuid>,
+    pub accounts: Vec<Account>,
+    pub transactions: Vec<SnapshotTransaction>,
+    /// Reservations that have been neither committed nor released.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reservations: Vec<Reservation>,
+    /// Accumulated deposits the accounts did not accept yet, e.g. as they are
+    /// frozen, by client. All others are included in the balances.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub pending_deposits: BTreeMap<u16, Amount>,
+    /// The keys of the transactions applied with
+    /// [`PaymentEngine::insert_with_key`](crate::PaymentEngine::insert_with_key).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub idempotency_keys: Vec<String>,
+    /// The total withdrawn from every account that had withdrawals, see
+    /// [`Account::withdrawn`].
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub withdrawn: BTreeMap<u16, Amount>,
+    /// The clients whose accounts are frozen, see [`Account::frozen`].
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub frozen: BTreeSet<u16>,
+    /// The authorizations that have been neither captured nor voided, by
+    /// transaction id.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub authorizations: BTreeSet<u32>,
+    /// The recurring transactions and their next occurrences.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub schedules: Vec<ScheduleState>,
+    /// The transactions waiting for their effective date, which is their
+    /// timestamp.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub queued: Vec<SnapshotTransaction>,
+    /// The disputed portion of the transactions under a partial dispute, by
+    /// transaction id.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub partial_disputes: BTreeMap<u32, Amount>,
+    /// When the open disputes were opened, by transaction id, for
+    /// [`PaymentEngine::expire_disputes`](crate::PaymentEngine::expire_disputes).
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub disputes_opened: BTreeMap<u32, u64>,
+    /// The periods of funds held by each open dispute that have been posted,
+    /// see
+    /// [`PaymentEngine::accrue_held_funds`](crate::PaymentEngine::accrue_held_funds).
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub held_periods: BTreeMap<u32, u64>,
+    /// The transactions whose chargeback has been reversed.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub represented: BTreeSet<u32>,
+    /// The tiers of the clients that have one.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tiers: BTreeMap<u16, String>,
+    /// The metadata of the clients that have it.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub metadata: BTreeMap<u16, ClientMetadata>,
+}
+
+/// A [`Transaction`] as it is stored in a [`Snapshot`].
+///
+/// Unlike [`Transaction`] this includes the dispute state, which must never be
+/// read from an input file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotTransaction {
+    #[serde(rename = "type")]
+    pub variant: TransactionVariant,
+    pub client: u16,
+    pub tx: u32,
+    pub amount: Option<Amount>,
+    pub disputed: bool,
+    pub chargeback: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub destination: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<Currency>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to_currency: Option<Currency>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate: Option<Decimal>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+}
+
+impl From<&Transaction> for SnapshotTransaction {
+    fn from(tx: &Transaction) -> Self {
+        Self {
+            variant: tx.variant.clone(),
+            client: tx.client,
+            tx: tx.tx,
+            amount: tx.amount,
+            disputed: tx.disputed,
+            chargeback: tx.chargeback,
+            destination: tx.destination,
+            currency: tx.currency.clone(),
+            to_currency: tx.to_currency.clone(),
+            rate: tx.rate,
+            timestamp: tx.timestamp,
+        }
+    }
+}
+
+impl From<SnapshotTransaction> for Transaction {
+    fn from(tx: SnapshotTransaction) -> Self {
+        Self {
+            variant: tx.variant,
+            client: tx.client,
+            tx: tx.tx,
+            amount: tx.amount,
+            disputed: tx.disputed,
+            chargeback: tx.chargeback,
+            destination: tx.destination,
+            currency: tx.currency,
+            to_currency: tx.to_currency,
+            rate: tx.rate,
+            timestamp: tx.timestamp,
+        }
+    }
+}
+
+impl Snapshot {
+    pub fn read<R: io::Read>(reader: R) -> Result<Self, Box<dyn Error>> {
+        Ok(serde_json::from_reader(io::BufReader::new(reader))?)
+    }
+
+    pub fn write<W: io::Write>(&self, writer: W) -> Result<(), Box<dyn Error>> {
+        let mut writer = io::BufWriter::new(writer);
+        serde_json::to_writer(&mut writer, self)?;
+        io::Write::flush(&mut writer)?;
+        Ok(())
+    }
+
+    /// Reads a snapshot from the file at `path`, decompressing it according
+    /// to its extension, see [`Compression::from_path`].
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let file = File::open(path)?;
+        Self::read(Compression::from_path(path).decoder(file)?)
+    }
+
+    /// Writes the snapshot to `path` atomically, see [`write_atomically`],
+    /// compressing it according to its extension, see
+    /// [`Compression::from_path`].
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        let compression = Compression::from_path(path.as_ref());
+        write_atomically(path, |file| {
+            let mut encoder = compression.encoder(file)?;
+            self.write(&mut encoder)?;
+            encoder.finish()?;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PaymentEngine, TransactionError};
+
+    #[test]
+    fn engine_round_trips_through_snapshot() {
+        let mut engine = PaymentEngine::default();
+        let deposit = Transaction {
+            tx: 1,
+            amount: Some(Amount::new(15, 1).unwrap()),
+            client: 1,
+            disputed: false,
+            variant: TransactionVariant::Deposit,
+            chargeback: false,
+            destination: None,
+            currency: None,
+            to_currency: None,
+            rate: None,
+            timestamp: None,
+        };
+        assert!(engine.insert(deposit).is_ok());
+        let dispute = Transaction {
+            tx: 1,
+            amount: None,
+            client: 1,
+            disputed: false,
+            variant: TransactionVariant::Dispute,
+            chargeback: false,
+            destination: None,
+            currency: None,
+            to_currency: None,
+            rate: None,
+            timestamp: None,
+        };
+        assert!(engine.insert(dispute).is_ok());
+
+        let mut buf = Vec::new();
+        engine.snapshot().write(&mut buf).unwrap();
+        let snapshot = Snapshot::read(buf.as_slice()).unwrap();
+        assert_eq!(snapshot, engine.snapshot());
+
+        let restored = PaymentEngine::from_snapshot(snapshot);
+        let account = restored.accounts().get(&1).unwrap();
+        assert_eq!(account.held(), Amount::new(15, 1).unwrap());
+        assert_eq!(account.available(), Amount::zero());
+    }
+
+    #[test]
+    fn snapshot_keeps_withdrawn_totals() {
+        let mut engine = PaymentEngine::default();
+        crate::process(
+            "type,client,tx,amount\ndeposit,1,1,5.0\nwithdrawal,1,2,2.0".as_bytes(),
+            &mut engine,
+        )
+        .unwrap();
+        let restored = PaymentEngine::from_snapshot(engine.snapshot());
+        let account = restored.accounts().get(&1).unwrap();
+        assert_eq!(account.withdrawn(), Amount::new(2, 0).unwrap());
+    }
+
+    #[test]
+    fn resumed_engine_rejects_ids_in_snapshot() {
+        let mut engine = PaymentEngine::default();
+        crate::process(
+            "type,client,tx,amount\ndeposit,1,1,5.0\ndeposit,2,2,1.0".as_bytes(),
+            &mut engine,
+        )
+        .unwrap();
+        let mut resumed = PaymentEngine::from_snapshot(engine.snapshot());
+        crate::process(
+            "type,client,tx,amount\ndeposit,1,3,2.0\ndispute,2,2,".as_bytes(),
+            &mut resumed,
+        )
+        .unwrap();
+        let replayed = crate::process(
+            "type,client,tx,amount\nwithdrawal,1,1,1.0".as_bytes(),
+            &mut resumed,
+        );
+        assert!(replayed
+            .unwrap_err()
+            .to_string()
+            .contains(&TransactionError::TransactionAlreadyExist.to_string()));
+        let accounts = resumed.accounts();
+        assert_eq!(
+            accounts.get(&1).unwrap().total(),
+            Amount::new(7, 0).unwrap()
+        );
+        assert_eq!(accounts.get(&2).unwrap().held(), Amount::new(1, 0).unwrap());
+    }
+
+    #[test]
+    fn snapshot_records_run_id() {
+        let run_id = Uuid::new_v4();
+        let engine = PaymentEngine::builder().run_id(run_id).build();
+        assert_eq!(engine.snapshot().run_id, Some(run_id));
+
+        // Snapshots taken before run ids were recorded
+        let snapshot = Snapshot::read(r#"{"accounts":[],"transactions":[]}"#.as_bytes()).unwrap();
+        assert_eq!(snapshot.run_id, None);
+    }
+}
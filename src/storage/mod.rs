@@ -0,0 +1,55 @@
+//! Durable storage for the state of a [`PaymentEngine`](crate::PaymentEngine).
+//!
+//! The engine keeps its working state in memory and writes every account and
+//! transaction it touches through to a [`Storage`]. When an engine is created
+//! with [`PaymentEngine::with_storage`](crate::PaymentEngine::with_storage) the
+//! previously stored state is loaded first, so processing can continue across
+//! process restarts.
+
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+use std::fmt::Debug;
+
+use crate::{
+    account::Account,
+    error::TransactionError,
+    reservation::{Reservation, ReservationId},
+    snapshot::Snapshot,
+    Transaction,
+};
+
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStorage;
+
+pub trait Storage: Debug {
+    /// Loads everything that has been stored so far.
+    fn load(&mut self) -> Result<Snapshot, TransactionError>;
+
+    /// Inserts or replaces `account`.
+    fn store_account(&mut self, account: &Account) -> Result<(), TransactionError>;
+
+    /// Inserts or replaces `tx`.
+    fn store_transaction(&mut self, tx: &Transaction) -> Result<(), TransactionError>;
+
+    /// Removes the transaction `tx`, see
+    /// [`PaymentEngine::prune`](crate::PaymentEngine::prune).
+    fn remove_transaction(&mut self, tx: u32) -> Result<(), TransactionError>;
+
+    /// Inserts `reservation`, see
+    /// [`PaymentEngine::reserve`](crate::PaymentEngine::reserve).
+    fn store_reservation(&mut self, reservation: &Reservation) -> Result<(), TransactionError>;
+
+    /// Removes the reservation `id` once it has been committed or released.
+    fn remove_reservation(&mut self, id: ReservationId) -> Result<(), TransactionError>;
+
+    /// Records that the transaction with the idempotency `key` has been
+    /// applied, see
+    /// [`PaymentEngine::insert_with_key`](crate::PaymentEngine::insert_with_key).
+    fn store_idempotency_key(&mut self, key: &str) -> Result<(), TransactionError>;
+
+    /// Makes sure everything stored so far is durable.
+    fn flush(&mut self) -> Result<(), TransactionError> {
+        Ok(())
+    }
+}
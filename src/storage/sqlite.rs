@@ -0,0 +1,516 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+    str::FromStr,
+};
+
+use rusqlite::{params, Connection};
+use rust_decimal::Decimal;
+
+use super::Storage;
+use crate::{
+    account::{Account, Balance},
+    amount::Amount,
+    currency::Currency,
+    error::TransactionError,
+    reservation::{Reservation, ReservationId},
+    snapshot::Snapshot,
+    Transaction, TransactionVariant,
+};
+
+/// The number of writes that are grouped into a single SQL transaction.
+const BATCH_SIZE: usize = 1000;
+
+/// A [`Storage`] backed by a SQLite database.
+///
+/// Writes are batched in SQL transactions of [`BATCH_SIZE`] writes, so
+/// [`Storage::flush`] must be called for the last writes to be durable.
+///
+/// Amounts are stored as text to keep their exact decimal representation.
+#[derive(Debug)]
+pub struct SqliteStorage {
+    conn: Connection,
+    pending: usize,
+}
+
+impl SqliteStorage {
+    /// Opens or creates the database at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, TransactionError> {
+        Self::from_connection(Connection::open(path).map_err(storage_error)?)
+    }
+
+    /// Creates a database that only lives in memory.
+    pub fn open_in_memory() -> Result<Self, TransactionError> {
+        Self::from_connection(Connection::open_in_memory().map_err(storage_error)?)
+    }
+
+    fn from_connection(conn: Connection) -> Result<Self, TransactionError> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS accounts (
+                client INTEGER PRIMARY KEY,
+                available TEXT NOT NULL,
+                held TEXT NOT NULL,
+                total TEXT NOT NULL,
+                locked INTEGER NOT NULL,
+                closed INTEGER NOT NULL DEFAULT 0
+            );
+            CREATE TABLE IF NOT EXISTS transactions (
+                tx INTEGER PRIMARY KEY,
+                type TEXT NOT NULL,
+                client INTEGER NOT NULL,
+                amount TEXT,
+                disputed INTEGER NOT NULL,
+                chargeback INTEGER NOT NULL,
+                destination INTEGER,
+                currency TEXT,
+                currency_minor_units INTEGER,
+                to_currency TEXT,
+                to_currency_minor_units INTEGER,
+                rate TEXT,
+                timestamp INTEGER
+            );
+            CREATE TABLE IF NOT EXISTS balances (
+                client INTEGER NOT NULL,
+                currency TEXT NOT NULL,
+                available TEXT NOT NULL,
+                held TEXT NOT NULL,
+                total TEXT NOT NULL,
+                PRIMARY KEY (client, currency)
+            );
+            CREATE TABLE IF NOT EXISTS reservations (
+                id INTEGER PRIMARY KEY,
+                client INTEGER NOT NULL,
+                amount TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS idempotency_keys (
+                key TEXT PRIMARY KEY
+            );",
+        )
+        .map_err(storage_error)?;
+        // Databases created by earlier versions lack these columns
+        add_column(&conn, "accounts", "closed", "INTEGER NOT NULL DEFAULT 0")?;
+        add_column(&conn, "transactions", "destination", "INTEGER")?;
+        add_column(&conn, "transactions", "currency", "TEXT")?;
+        add_column(&conn, "transactions", "currency_minor_units", "INTEGER")?;
+        add_column(&conn, "transactions", "to_currency", "TEXT")?;
+        add_column(&conn, "transactions", "to_currency_minor_units", "INTEGER")?;
+        add_column(&conn, "transactions", "rate", "TEXT")?;
+        add_column(&conn, "transactions", "timestamp", "INTEGER")?;
+        Ok(Self { conn, pending: 0 })
+    }
+
+    fn write(&mut self) -> Result<(), TransactionError> {
+        if self.pending == 0 {
+            self.conn.execute_batch("BEGIN").map_err(storage_error)?;
+        }
+        self.pending += 1;
+        if self.pending >= BATCH_SIZE {
+            self.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn load(&mut self) -> Result<Snapshot, TransactionError> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT client, available, held, total, locked, closed FROM accounts")
+            .map_err(storage_error)?;
+        let mut accounts = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, u16>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, bool>(4)?,
+                    row.get::<_, bool>(5)?,
+                ))
+            })
+            .map_err(storage_error)?
+            .map(|row| {
+                let (client, available, held, total, locked, closed) =
+                    row.map_err(storage_error)?;
+                Ok(Account::restore(
+                    client,
+                    parse_amount(&available)?,
+                    parse_amount(&held)?,
+                    parse_amount(&total)?,
+                    locked,
+                    closed,
+                ))
+            })
+            .collect::<Result<Vec<_>, TransactionError>>()?;
+
+        let mut stmt = self
+            .conn
+            .prepare("SELECT client, currency, available, held, total FROM balances")
+            .map_err(storage_error)?;
+        let mut rows = stmt.query([]).map_err(storage_error)?;
+        while let Some(row) = rows.next().map_err(storage_error)? {
+            let client = row.get::<_, u16>(0).map_err(storage_error)?;
+            let currency = row.get::<_, String>(1).map_err(storage_error)?;
+            let balance = Balance {
+                available: parse_amount(&row.get::<_, String>(2).map_err(storage_error)?)?,
+                held: parse_amount(&row.get::<_, String>(3).map_err(storage_error)?)?,
+                total: parse_amount(&row.get::<_, String>(4).map_err(storage_error)?)?,
+            };
+            if let Some(account) = accounts.iter_mut().find(|a| a.client() == client) {
+                account.restore_balance(currency, balance);
+            }
+        }
+        drop(rows);
+
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT tx, type, client, amount, disputed, chargeback, destination, currency,
+                currency_minor_units, to_currency, to_currency_minor_units, rate, timestamp
+                FROM transactions",
+            )
+            .map_err(storage_error)?;
+        let transactions = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, u32>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, u16>(2)?,
+                    row.get::<_, Option<String>>(3)?,
+                    row.get::<_, bool>(4)?,
+                    row.get::<_, bool>(5)?,
+                    row.get::<_, Option<u16>>(6)?,
+                    row.get::<_, Option<String>>(7)?,
+                    row.get::<_, Option<u32>>(8)?,
+                    row.get::<_, Option<String>>(9)?,
+                    row.get::<_, Option<u32>>(10)?,
+                    row.get::<_, Option<String>>(11)?,
+                    row.get::<_, Option<i64>>(12)?,
+                ))
+            })
+            .map_err(storage_error)?
+            .map(|row| {
+                let (
+                    tx,
+                    variant,
+                    client,
+                    amount,
+                    disputed,
+                    chargeback,
+                    destination,
+                    currency,
+                    minor_units,
+                    to_currency,
+                    to_minor_units,
+                    rate,
+                    timestamp,
+                ) = row.map_err(storage_error)?;
+                let tx = Transaction {
+                    variant: parse_variant(&variant)?,
+                    client,
+                    tx,
+                    amount: amount.as_deref().map(parse_amount).transpose()?,
+                    disputed,
+                    chargeback,
+                    destination,
+                    currency: currency
+                        .map(|code| parse_currency(&code, minor_units.unwrap_or(0)))
+                        .transpose()?,
+                    to_currency: to_currency
+                        .map(|code| parse_currency(&code, to_minor_units.unwrap_or(0)))
+                        .transpose()?,
+                    rate: rate.as_deref().map(parse_rate).transpose()?,
+                    timestamp: timestamp.map(|timestamp| timestamp as u64),
+                };
+                Ok((&tx).into())
+            })
+            .collect::<Result<Vec<_>, TransactionError>>()?;
+
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id, client, amount FROM reservations")
+            .map_err(storage_error)?;
+        let reservations = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, u16>(1)?,
+                    row.get::<_, String>(2)?,
+                ))
+            })
+            .map_err(storage_error)?
+            .map(|row| {
+                let (id, client, amount) = row.map_err(storage_error)?;
+                Ok(Reservation {
+                    id: ReservationId(id as u64),
+                    client,
+                    amount: parse_amount(&amount)?,
+                })
+            })
+            .collect::<Result<Vec<_>, TransactionError>>()?;
+
+        let mut stmt = self
+            .conn
+            .prepare("SELECT key FROM idempotency_keys")
+            .map_err(storage_error)?;
+        let idempotency_keys = stmt
+            .query_map([], |row| row.get::<_, String>(0))
+            .map_err(storage_error)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(storage_error)?;
+
+        Ok(Snapshot {
+            run_id: None,
+            accounts,
+            transactions,
+            reservations,
+            pending_deposits: BTreeMap::new(),
+            idempotency_keys,
+            withdrawn: BTreeMap::new(),
+            frozen: BTreeSet::new(),
+            authorizations: BTreeSet::new(),
+            schedules: Vec::new(),
+            queued: Vec::new(),
+            partial_disputes: BTreeMap::new(),
+            disputes_opened: BTreeMap::new(),
+            held_periods: BTreeMap::new(),
+            represented: BTreeSet::new(),
+            tiers: BTreeMap::new(),
+            metadata: BTreeMap::new(),
+        })
+    }
+
+    fn store_account(&mut self, account: &Account) -> Result<(), TransactionError> {
+        self.write()?;
+        self.conn
+            .prepare_cached(
+                "INSERT OR REPLACE INTO accounts (client, available, held, total, locked, closed)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            )
+            .and_then(|mut stmt| {
+                stmt.execute(params![
+                    account.client(),
+                    account.available().to_string(),
+                    account.held().to_string(),
+                    account.total().to_string(),
+                    account.locked(),
+                    account.closed(),
+                ])
+            })
+            .map_err(storage_error)?;
+        self.conn
+            .prepare_cached("DELETE FROM balances WHERE client = ?1")
+            .and_then(|mut stmt| stmt.execute(params![account.client()]))
+            .map_err(storage_error)?;
+        for (currency, balance) in account.balances() {
+            self.conn
+                .prepare_cached(
+                    "INSERT INTO balances (client, currency, available, held, total)
+                    VALUES (?1, ?2, ?3, ?4, ?5)",
+                )
+                .and_then(|mut stmt| {
+                    stmt.execute(params![
+                        account.client(),
+                        currency,
+                        balance.available.to_string(),
+                        balance.held.to_string(),
+                        balance.total.to_string(),
+                    ])
+                })
+                .map_err(storage_error)?;
+        }
+        Ok(())
+    }
+
+    fn store_transaction(&mut self, tx: &Transaction) -> Result<(), TransactionError> {
+        self.write()?;
+        self.conn
+            .prepare_cached(
+                "INSERT OR REPLACE INTO transactions
+                (tx, type, client, amount, disputed, chargeback, destination, currency,
+                currency_minor_units, to_currency, to_currency_minor_units, rate, timestamp)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+            )
+            .and_then(|mut stmt| {
+                stmt.execute(params![
+                    tx.tx,
+                    tx.variant.as_str(),
+                    tx.client,
+                    tx.amount.map(|amount| amount.to_string()),
+                    tx.disputed,
+                    tx.chargeback,
+                    tx.destination,
+                    tx.currency.as_ref().map(Currency::code),
+                    tx.currency.as_ref().map(Currency::minor_units),
+                    tx.to_currency.as_ref().map(Currency::code),
+                    tx.to_currency.as_ref().map(Currency::minor_units),
+                    tx.rate.map(|rate| rate.to_string()),
+                    tx.timestamp.map(|timestamp| timestamp as i64),
+                ])
+            })
+            .map_err(storage_error)?;
+        Ok(())
+    }
+
+    fn store_reservation(&mut self, reservation: &Reservation) -> Result<(), TransactionError> {
+        self.write()?;
+        self.conn
+            .prepare_cached(
+                "INSERT OR REPLACE INTO reservations (id, client, amount) VALUES (?1, ?2, ?3)",
+            )
+            .and_then(|mut stmt| {
+                stmt.execute(params![
+                    reservation.id.0 as i64,
+                    reservation.client,
+                    reservation.amount.to_string(),
+                ])
+            })
+            .map_err(storage_error)?;
+        Ok(())
+    }
+
+    fn remove_transaction(&mut self, tx: u32) -> Result<(), TransactionError> {
+        self.write()?;
+        self.conn
+            .prepare_cached("DELETE FROM transactions WHERE tx = ?1")
+            .and_then(|mut stmt| stmt.execute(params![tx]))
+            .map_err(storage_error)?;
+        Ok(())
+    }
+
+    fn remove_reservation(&mut self, id: ReservationId) -> Result<(), TransactionError> {
+        self.write()?;
+        self.conn
+            .prepare_cached("DELETE FROM reservations WHERE id = ?1")
+            .and_then(|mut stmt| stmt.execute(params![id.0 as i64]))
+            .map_err(storage_error)?;
+        Ok(())
+    }
+
+    fn store_idempotency_key(&mut self, key: &str) -> Result<(), TransactionError> {
+        self.write()?;
+        self.conn
+            .prepare_cached("INSERT OR IGNORE INTO idempotency_keys (key) VALUES (?1)")
+            .and_then(|mut stmt| stmt.execute(params![key]))
+            .map_err(storage_error)?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), TransactionError> {
+        if self.pending > 0 {
+            self.conn.execute_batch("COMMIT").map_err(storage_error)?;
+            self.pending = 0;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for SqliteStorage {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+/// Adds `column` to `table` unless it exists already.
+fn add_column(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> Result<(), TransactionError> {
+    let exists: bool = conn
+        .query_row(
+            "SELECT COUNT(*) FROM pragma_table_info(?1) WHERE name = ?2",
+            params![table, column],
+            |row| row.get(0),
+        )
+        .map_err(storage_error)?;
+    if !exists {
+        conn.execute_batch(&format!(
+            "ALTER TABLE {} ADD COLUMN {} {}",
+            table, column, definition
+        ))
+        .map_err(storage_error)?;
+    }
+    Ok(())
+}
+
+fn storage_error(e: rusqlite::Error) -> TransactionError {
+    TransactionError::Storage(e.to_string())
+}
+
+fn parse_amount(value: &str) -> Result<Amount, TransactionError> {
+    Amount::parse_unchecked(value).map_err(TransactionError::Storage)
+}
+
+fn parse_currency(code: &str, minor_units: u32) -> Result<Currency, TransactionError> {
+    Currency::iso(code)
+        .map_or_else(|| Currency::custom(code, minor_units), Ok)
+        .map_err(|e| TransactionError::Storage(e.to_string()))
+}
+
+fn parse_rate(value: &str) -> Result<Decimal, TransactionError> {
+    Decimal::from_str(value)
+        .map_err(|e| TransactionError::Storage(format!("`{}` is not a valid rate: {}", value, e)))
+}
+
+fn parse_variant(value: &str) -> Result<TransactionVariant, TransactionError> {
+    TransactionVariant::from_name(value)
+        .ok_or_else(|| TransactionError::Storage(format!("Unknown transaction type `{}`", value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PaymentEngine;
+
+    #[test]
+    fn state_survives_reopening() {
+        let dir = std::env::temp_dir().join(format!("randomlib-sqlite-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("state.db");
+        let _ = std::fs::remove_file(&path);
+
+        {
+            let storage = SqliteStorage::open(&path).unwrap();
+            let mut engine = PaymentEngine::with_storage(Box::new(storage)).unwrap();
+            let deposit = Transaction {
+                tx: 1,
+                amount: Some(Amount::new(15, 1).unwrap()),
+                client: 1,
+                disputed: false,
+                variant: TransactionVariant::Deposit,
+                chargeback: false,
+                destination: None,
+                currency: None,
+                to_currency: None,
+                rate: None,
+                timestamp: None,
+            };
+            assert!(engine.insert(deposit).is_ok());
+            let dispute = Transaction {
+                tx: 1,
+                amount: None,
+                client: 1,
+                disputed: false,
+                variant: TransactionVariant::Dispute,
+                chargeback: false,
+                destination: None,
+                currency: None,
+                to_currency: None,
+                rate: None,
+                timestamp: None,
+            };
+            assert!(engine.insert(dispute).is_ok());
+            engine.flush().unwrap();
+        }
+
+        let storage = SqliteStorage::open(&path).unwrap();
+        let engine = PaymentEngine::with_storage(Box::new(storage)).unwrap();
+        let account = engine.accounts().get(&1).unwrap();
+        assert_eq!(account.held(), Amount::new(15, 1).unwrap());
+        assert_eq!(account.available(), Amount::zero());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,169 @@
+//! The containers a [`PaymentEngine`](crate::PaymentEngine) keeps its working
+//! state in.
+//!
+//! By default accounts and transactions are kept in a [`HashMap`], but any type
+//! implementing [`AccountStore`] and [`TransactionStore`] can be used instead,
+//! see [`PaymentEngine::with_stores`](crate::PaymentEngine::with_stores).
+//! Accounts kept in a [`BTreeMap`] are iterated in order of client id, see
+//! [`PaymentEngineBuilder::build_ordered`](crate::PaymentEngineBuilder::build_ordered).
+//! [`SpillingTransactionStore`] bounds the memory used for transactions by
+//! moving them to disk.
+
+mod spill;
+
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+};
+
+use crate::{account::Account, Transaction, TransactionError};
+
+pub use spill::SpillingTransactionStore;
+
+/// A container of [`Account`]s keyed by client id.
+pub trait AccountStore {
+    fn get(&self, client: u16) -> Option<&Account>;
+
+    fn get_mut(&mut self, client: u16) -> Option<&mut Account>;
+
+    /// Returns the account of `client`, creating an empty one if it does not
+    /// exist yet.
+    fn get_or_create(&mut self, client: u16) -> &mut Account;
+
+    /// Inserts or replaces `account`.
+    fn insert(&mut self, account: Account);
+
+    fn iter(&self) -> Box<dyn Iterator<Item = &Account> + '_>;
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether [`AccountStore::iter`] returns the accounts ordered by client
+    /// id, so they do not need to be sorted for output.
+    fn is_ordered(&self) -> bool {
+        false
+    }
+}
+
+/// A container of [`Transaction`]s keyed by transaction id.
+///
+/// Lookups return a [`Cow`] so stores that do not keep every transaction in
+/// memory, like [`SpillingTransactionStore`], can hand out owned copies.
+pub trait TransactionStore {
+    fn get(&self, tx: u32) -> Option<Cow<'_, Transaction>>;
+
+    fn get_mut(&mut self, tx: u32) -> Option<&mut Transaction>;
+
+    fn contains(&self, tx: u32) -> bool {
+        self.get(tx).is_some()
+    }
+
+    /// Inserts or replaces `tx`.
+    fn insert(&mut self, tx: Transaction);
+
+    fn remove(&mut self, tx: u32) -> Option<Transaction>;
+
+    fn iter(&self) -> Box<dyn Iterator<Item = Cow<'_, Transaction>> + '_>;
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Fails with [`TransactionError::Storage`] once the store could not keep
+    /// or read back a transaction, e.g. as its disk failed, after which the
+    /// engine stops. Stores kept in memory never fail.
+    fn check(&self) -> Result<(), TransactionError> {
+        Ok(())
+    }
+}
+
+impl AccountStore for HashMap<u16, Account> {
+    fn get(&self, client: u16) -> Option<&Account> {
+        HashMap::get(self, &client)
+    }
+
+    fn get_mut(&mut self, client: u16) -> Option<&mut Account> {
+        HashMap::get_mut(self, &client)
+    }
+
+    fn get_or_create(&mut self, client: u16) -> &mut Account {
+        self.entry(client).or_insert_with(|| Account::new(client))
+    }
+
+    fn insert(&mut self, account: Account) {
+        HashMap::insert(self, account.client(), account);
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = &Account> + '_> {
+        Box::new(self.values())
+    }
+
+    fn len(&self) -> usize {
+        HashMap::len(self)
+    }
+}
+
+impl AccountStore for BTreeMap<u16, Account> {
+    fn get(&self, client: u16) -> Option<&Account> {
+        BTreeMap::get(self, &client)
+    }
+
+    fn get_mut(&mut self, client: u16) -> Option<&mut Account> {
+        BTreeMap::get_mut(self, &client)
+    }
+
+    fn get_or_create(&mut self, client: u16) -> &mut Account {
+        self.entry(client).or_insert_with(|| Account::new(client))
+    }
+
+    fn insert(&mut self, account: Account) {
+        BTreeMap::insert(self, account.client(), account);
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = &Account> + '_> {
+        Box::new(self.values())
+    }
+
+    fn len(&self) -> usize {
+        BTreeMap::len(self)
+    }
+
+    fn is_ordered(&self) -> bool {
+        true
+    }
+}
+
+impl TransactionStore for HashMap<u32, Transaction> {
+    fn get(&self, tx: u32) -> Option<Cow<'_, Transaction>> {
+        HashMap::get(self, &tx).map(Cow::Borrowed)
+    }
+
+    fn get_mut(&mut self, tx: u32) -> Option<&mut Transaction> {
+        HashMap::get_mut(self, &tx)
+    }
+
+    fn contains(&self, tx: u32) -> bool {
+        self.contains_key(&tx)
+    }
+
+    fn insert(&mut self, tx: Transaction) {
+        HashMap::insert(self, tx.tx, tx);
+    }
+
+    fn remove(&mut self, tx: u32) -> Option<Transaction> {
+        HashMap::remove(self, &tx)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = Cow<'_, Transaction>> + '_> {
+        Box::new(self.values().map(Cow::Borrowed))
+    }
+
+    fn len(&self) -> usize {
+        HashMap::len(self)
+    }
+}
@@ -0,0 +1,476 @@
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+    fs::File,
+    io::{self, Read, Seek, SeekFrom, Write},
+};
+
+use rust_decimal::Decimal;
+
+use super::TransactionStore;
+use crate::{amount::Amount, Currency, Transaction, TransactionError, TransactionVariant};
+
+/// The size of a spilled transaction on disk, see [`encode`].
+const RECORD_SIZE: u64 = 68;
+
+/// A [`TransactionStore`] that keeps at most a fixed number of transactions in
+/// memory and moves the oldest ones to a temporary file.
+///
+/// Disputed transactions are never spilled, as they will be needed again by
+/// the resolve or chargeback. Spilled transactions are loaded back into memory
+/// when they are looked up mutably, which is what happens when they are
+/// disputed.
+///
+/// Only a small index entry is kept in memory for each spilled transaction,
+/// which is needed to find it again and to detect duplicate transaction ids.
+///
+/// # Errors
+///
+/// The first I/O error on the temporary file, for example because the disk is
+/// full, or the first record that cannot be decoded is returned by
+/// [`TransactionStore::check`], which stops the engine with
+/// [`TransactionError::Storage`]. A transaction that cannot be written stays in
+/// memory, while one that cannot be read back is treated as missing until
+/// then.
+#[derive(Debug)]
+pub struct SpillingTransactionStore {
+    max_resident: usize,
+    resident: HashMap<u32, Transaction>,
+    /// Ids of resident transactions, oldest first. Can contain ids that are no
+    /// longer resident, which are skipped.
+    age: VecDeque<u32>,
+    /// The record slot in `file` of every spilled transaction
+    spilled: HashMap<u32, u64>,
+    /// Slots of transactions that have been loaded back or removed
+    free: Vec<u64>,
+    slots: u64,
+    file: File,
+    /// The first error on `file`, see [`TransactionStore::check`]
+    error: RefCell<Option<String>>,
+}
+
+impl SpillingTransactionStore {
+    /// Creates a store that keeps at most `max_resident` transactions in
+    /// memory, backed by a new temporary file that is deleted on drop.
+    pub fn new(max_resident: usize) -> io::Result<Self> {
+        Ok(Self {
+            max_resident,
+            resident: HashMap::new(),
+            age: VecDeque::new(),
+            spilled: HashMap::new(),
+            free: Vec::new(),
+            slots: 0,
+            file: tempfile::tempfile()?,
+            error: RefCell::new(None),
+        })
+    }
+
+    /// The number of transactions currently on disk.
+    pub fn spilled(&self) -> usize {
+        self.spilled.len()
+    }
+
+    fn spill_excess(&mut self) {
+        // Every disputed transaction is requeued, so stop once all of them
+        // have been seen
+        let mut requeued = 0;
+        while self.resident.len() > self.max_resident && requeued <= self.age.len() {
+            let id = match self.age.pop_front() {
+                Some(id) => id,
+                None => break,
+            };
+            if let Some(tx) = self.resident.remove(&id) {
+                if tx.disputed {
+                    self.resident.insert(id, tx);
+                    self.age.push_back(id);
+                    requeued += 1;
+                } else if let Err(e) = self.write(&tx) {
+                    // Rather keep it in memory than lose it
+                    self.fail(e);
+                    self.resident.insert(id, tx);
+                    self.age.push_front(id);
+                    break;
+                }
+            }
+        }
+    }
+
+    fn write(&mut self, tx: &Transaction) -> io::Result<()> {
+        let slot = self.free.pop().unwrap_or_else(|| {
+            self.slots += 1;
+            self.slots - 1
+        });
+        let written = self
+            .file
+            .seek(SeekFrom::Start(slot * RECORD_SIZE))
+            .and_then(|_| self.file.write_all(&encode(tx)));
+        if let Err(e) = written {
+            self.free.push(slot);
+            return Err(e);
+        }
+        self.spilled.insert(tx.tx, slot);
+        Ok(())
+    }
+
+    fn read(&self, slot: u64) -> io::Result<Transaction> {
+        let mut file = &self.file;
+        let mut record = [0; RECORD_SIZE as usize];
+        file.seek(SeekFrom::Start(slot * RECORD_SIZE))?;
+        file.read_exact(&mut record)?;
+        decode(&record)
+    }
+
+    /// Reads the transaction in `slot`, keeping the error if that fails.
+    fn load(&self, slot: u64) -> Option<Transaction> {
+        self.read(slot).map_err(|e| self.fail(e)).ok()
+    }
+
+    fn fail(&self, e: io::Error) {
+        self.error
+            .borrow_mut()
+            .get_or_insert_with(|| format!("Spill file: {}", e));
+    }
+}
+
+impl TransactionStore for SpillingTransactionStore {
+    fn get(&self, tx: u32) -> Option<Cow<'_, Transaction>> {
+        if let Some(resident) = self.resident.get(&tx) {
+            return Some(Cow::Borrowed(resident));
+        }
+        self.spilled
+            .get(&tx)
+            .and_then(|slot| self.load(*slot))
+            .map(Cow::Owned)
+    }
+
+    fn get_mut(&mut self, tx: u32) -> Option<&mut Transaction> {
+        if let Some(&slot) = self.spilled.get(&tx) {
+            let loaded = self.load(slot)?;
+            self.spilled.remove(&tx);
+            self.free.push(slot);
+            // The store is allowed to grow past `max_resident` until the next
+            // insert, so the transaction is not spilled again right away
+            self.resident.insert(tx, loaded);
+            self.age.push_back(tx);
+        }
+        self.resident.get_mut(&tx)
+    }
+
+    fn contains(&self, tx: u32) -> bool {
+        self.resident.contains_key(&tx) || self.spilled.contains_key(&tx)
+    }
+
+    fn insert(&mut self, tx: Transaction) {
+        if let Some(slot) = self.spilled.remove(&tx.tx) {
+            self.free.push(slot);
+        }
+        self.age.push_back(tx.tx);
+        self.resident.insert(tx.tx, tx);
+        self.spill_excess();
+    }
+
+    fn remove(&mut self, tx: u32) -> Option<Transaction> {
+        if let Some(resident) = self.resident.remove(&tx) {
+            return Some(resident);
+        }
+        let slot = *self.spilled.get(&tx)?;
+        let removed = self.load(slot)?;
+        self.spilled.remove(&tx);
+        self.free.push(slot);
+        Some(removed)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = Cow<'_, Transaction>> + '_> {
+        let resident = self.resident.values().map(Cow::Borrowed);
+        let spilled = self
+            .spilled
+            .values()
+            .filter_map(move |slot| self.load(*slot).map(Cow::Owned));
+        Box::new(resident.chain(spilled))
+    }
+
+    fn len(&self) -> usize {
+        self.resident.len() + self.spilled.len()
+    }
+
+    fn check(&self) -> Result<(), TransactionError> {
+        match &*self.error.borrow() {
+            Some(e) => Err(TransactionError::Storage(e.clone())),
+            None => Ok(()),
+        }
+    }
+}
+
+const HAS_AMOUNT: u8 = 1;
+const DISPUTED: u8 = 1 << 1;
+const CHARGEBACK: u8 = 1 << 2;
+const HAS_DESTINATION: u8 = 1 << 3;
+const HAS_CURRENCY: u8 = 1 << 4;
+const HAS_TO_CURRENCY: u8 = 1 << 5;
+const HAS_RATE: u8 = 1 << 6;
+const HAS_TIMESTAMP: u8 = 1 << 7;
+
+/// Encodes `tx` as
+/// `tx (4) | client (2) | variant (1) | flags (1) | amount (16) | destination (2)
+/// | currency (9) | to currency (9) | rate (16) | timestamp (8)`, see
+/// [`encode_currency`].
+fn encode(tx: &Transaction) -> [u8; RECORD_SIZE as usize] {
+    let mut record = [0; RECORD_SIZE as usize];
+    record[0..4].copy_from_slice(&tx.tx.to_le_bytes());
+    record[4..6].copy_from_slice(&tx.client.to_le_bytes());
+    record[6] = match tx.variant {
+        TransactionVariant::Deposit => 0,
+        TransactionVariant::Withdrawal => 1,
+        TransactionVariant::Dispute => 2,
+        TransactionVariant::Resolve => 3,
+        TransactionVariant::Chargeback => 4,
+        TransactionVariant::Unlock => 5,
+        TransactionVariant::Close => 6,
+        TransactionVariant::Transfer => 7,
+        TransactionVariant::Fee => 8,
+        TransactionVariant::Convert => 9,
+        TransactionVariant::Freeze => 10,
+        TransactionVariant::Unfreeze => 11,
+        TransactionVariant::Authorize => 12,
+        TransactionVariant::Capture => 13,
+        TransactionVariant::Void => 14,
+        TransactionVariant::Representment => 15,
+    };
+    let mut flags = 0;
+    if let Some(amount) = tx.amount {
+        flags |= HAS_AMOUNT;
+        record[8..24].copy_from_slice(&amount.to_bytes());
+    }
+    if let Some(destination) = tx.destination {
+        flags |= HAS_DESTINATION;
+        record[24..26].copy_from_slice(&destination.to_le_bytes());
+    }
+    if let Some(currency) = &tx.currency {
+        flags |= HAS_CURRENCY;
+        encode_currency(currency, &mut record[26..35]);
+    }
+    if let Some(currency) = &tx.to_currency {
+        flags |= HAS_TO_CURRENCY;
+        encode_currency(currency, &mut record[35..44]);
+    }
+    if let Some(rate) = tx.rate {
+        flags |= HAS_RATE;
+        record[44..60].copy_from_slice(&rate.serialize());
+    }
+    if let Some(timestamp) = tx.timestamp {
+        flags |= HAS_TIMESTAMP;
+        record[60..68].copy_from_slice(&timestamp.to_le_bytes());
+    }
+    if tx.disputed {
+        flags |= DISPUTED;
+    }
+    if tx.chargeback {
+        flags |= CHARGEBACK;
+    }
+    record[7] = flags;
+    record
+}
+
+fn decode(record: &[u8; RECORD_SIZE as usize]) -> io::Result<Transaction> {
+    let mut tx = [0; 4];
+    tx.copy_from_slice(&record[0..4]);
+    let mut client = [0; 2];
+    client.copy_from_slice(&record[4..6]);
+    let mut amount = [0; 16];
+    amount.copy_from_slice(&record[8..24]);
+    let mut destination = [0; 2];
+    destination.copy_from_slice(&record[24..26]);
+    let flags = record[7];
+    let mut rate = [0; 16];
+    rate.copy_from_slice(&record[44..60]);
+    let mut timestamp = [0; 8];
+    timestamp.copy_from_slice(&record[60..68]);
+
+    Ok(Transaction {
+        variant: match record[6] {
+            0 => TransactionVariant::Deposit,
+            1 => TransactionVariant::Withdrawal,
+            2 => TransactionVariant::Dispute,
+            3 => TransactionVariant::Resolve,
+            4 => TransactionVariant::Chargeback,
+            5 => TransactionVariant::Unlock,
+            6 => TransactionVariant::Close,
+            7 => TransactionVariant::Transfer,
+            8 => TransactionVariant::Fee,
+            9 => TransactionVariant::Convert,
+            10 => TransactionVariant::Freeze,
+            11 => TransactionVariant::Unfreeze,
+            12 => TransactionVariant::Authorize,
+            13 => TransactionVariant::Capture,
+            14 => TransactionVariant::Void,
+            15 => TransactionVariant::Representment,
+            variant => {
+                return Err(invalid_data(format!(
+                    "Unknown transaction type `{}`",
+                    variant
+                )))
+            }
+        },
+        client: u16::from_le_bytes(client),
+        tx: u32::from_le_bytes(tx),
+        amount: if flags & HAS_AMOUNT != 0 {
+            Some(Amount::from_bytes(amount))
+        } else {
+            None
+        },
+        disputed: flags & DISPUTED != 0,
+        chargeback: flags & CHARGEBACK != 0,
+        destination: if flags & HAS_DESTINATION != 0 {
+            Some(u16::from_le_bytes(destination))
+        } else {
+            None
+        },
+        currency: (flags & HAS_CURRENCY != 0)
+            .then(|| decode_currency(&record[26..35]))
+            .transpose()?,
+        to_currency: (flags & HAS_TO_CURRENCY != 0)
+            .then(|| decode_currency(&record[35..44]))
+            .transpose()?,
+        rate: (flags & HAS_RATE != 0).then(|| Decimal::deserialize(rate)),
+        timestamp: (flags & HAS_TIMESTAMP != 0).then(|| u64::from_le_bytes(timestamp)),
+    })
+}
+
+/// Encodes `currency` into 9 bytes as `code (8) | minor units (1)`, where the
+/// code is padded with zeros.
+fn encode_currency(currency: &Currency, record: &mut [u8]) {
+    let code = currency.code().as_bytes();
+    record[..code.len()].copy_from_slice(code);
+    record[8] = currency.minor_units() as u8;
+}
+
+fn decode_currency(record: &[u8]) -> io::Result<Currency> {
+    let code = &record[..8];
+    let len = code.iter().position(|b| *b == 0).unwrap_or(code.len());
+    let code = std::str::from_utf8(&code[..len]).map_err(invalid_data)?;
+    Currency::iso(code)
+        .or_else(|| Currency::custom(code, record[8].into()).ok())
+        .ok_or_else(|| invalid_data(format!("Invalid currency `{}`", code)))
+}
+
+fn invalid_data(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deposit(tx: u32) -> Transaction {
+        Transaction {
+            tx,
+            amount: Some(Amount::new(tx as i64, 2).unwrap()),
+            client: 1,
+            disputed: false,
+            variant: TransactionVariant::Deposit,
+            chargeback: false,
+            destination: None,
+            currency: None,
+            to_currency: None,
+            rate: None,
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn spills_oldest_transactions_and_loads_them_back() {
+        let mut store = SpillingTransactionStore::new(2).unwrap();
+        for tx in 1..=5 {
+            store.insert(deposit(tx));
+        }
+        assert_eq!(store.len(), 5);
+        assert_eq!(store.spilled(), 3);
+        assert!(store.contains(1));
+
+        let spilled = store.get(1).unwrap();
+        assert!(matches!(spilled, Cow::Owned(_)));
+        assert_eq!(spilled.amount, Some(Amount::new(1, 2).unwrap()));
+
+        store.get_mut(1).unwrap().disputed = true;
+        assert_eq!(store.spilled(), 2);
+        assert!(matches!(store.get(1), Some(Cow::Borrowed(_))));
+        assert_eq!(store.iter().count(), 5);
+    }
+
+    #[test]
+    fn never_spills_disputed_transactions() {
+        let mut store = SpillingTransactionStore::new(1).unwrap();
+        store.insert(deposit(1));
+        store.get_mut(1).unwrap().disputed = true;
+        store.insert(deposit(2));
+        store.insert(deposit(3));
+        assert!(matches!(store.get(1), Some(Cow::Borrowed(_))));
+        assert!(store.get(1).unwrap().disputed);
+    }
+
+    #[test]
+    fn removes_spilled_transactions() {
+        let mut store = SpillingTransactionStore::new(1).unwrap();
+        store.insert(deposit(1));
+        store.insert(deposit(2));
+        assert_eq!(store.remove(1).unwrap().tx, 1);
+        assert!(!store.contains(1));
+        assert_eq!(store.len(), 1);
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let mut tx = deposit(42);
+        tx.disputed = true;
+        let decoded = decode(&encode(&tx)).unwrap();
+        assert_eq!(decoded.tx, 42);
+        assert_eq!(decoded.amount, tx.amount);
+        assert!(decoded.disputed);
+        assert!(!decoded.chargeback);
+        assert_eq!(decoded.currency, None);
+
+        for currency in [Currency::iso("EUR"), Currency::custom("POINTS", 2).ok()] {
+            tx.currency = currency;
+            assert_eq!(decode(&encode(&tx)).unwrap().currency, tx.currency);
+        }
+
+        tx.variant = TransactionVariant::Convert;
+        tx.to_currency = Currency::iso("USD");
+        tx.rate = Some(Decimal::new(10825, 4));
+        let decoded = decode(&encode(&tx)).unwrap();
+        assert_eq!(decoded.variant, TransactionVariant::Convert);
+        assert_eq!(decoded.to_currency, tx.to_currency);
+        assert_eq!(decoded.rate, tx.rate);
+        assert_eq!(decoded.timestamp, None);
+
+        tx.timestamp = Some(1_700_000_000);
+        assert_eq!(decode(&encode(&tx)).unwrap().timestamp, tx.timestamp);
+
+        let mut record = encode(&tx);
+        record[6] = 16;
+        assert_eq!(
+            decode(&record).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn keeps_transactions_it_cannot_spill() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("spill");
+        File::create(&path).unwrap();
+        let mut store = SpillingTransactionStore::new(1).unwrap();
+        assert_eq!(store.check(), Ok(()));
+        // Writes to a file opened read-only fail
+        store.file = File::open(&path).unwrap();
+
+        for tx in 1..=3 {
+            store.insert(deposit(tx));
+        }
+        assert_eq!(store.spilled(), 0);
+        assert_eq!(store.len(), 3);
+        assert!(store.get(1).is_some());
+        assert!(matches!(store.check(), Err(TransactionError::Storage(_))));
+    }
+}
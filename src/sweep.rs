@@ -0,0 +1,28 @@
+use serde::Serialize;
+
+use crate::Amount;
+
+/// When and where [`PaymentEngine::sweep`](crate::PaymentEngine::sweep) moves
+/// the balances of dormant accounts.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SweepConfig {
+    /// An account is dormant when none of its own transactions was accepted
+    /// for this many seconds, measured by the `timestamp` column of the input.
+    pub dormant_after: u64,
+    /// The pool or suspense account the balances are moved to.
+    pub pool: u16,
+}
+
+/// The audit record of a balance moved by
+/// [`PaymentEngine::sweep`](crate::PaymentEngine::sweep).
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SweepRecord {
+    pub client: u16,
+    pub pool: u16,
+    pub amount: Amount,
+    /// When the client was last active, or the first timestamp of the input
+    /// if it never was since.
+    pub last_activity: u64,
+    /// When the balance was moved.
+    pub swept_at: u64,
+}
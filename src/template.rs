@@ -0,0 +1,545 @@
+//! Derived output columns, e.g. `utilization={{ held / total }}`.
+//!
+//! A column is written as `<name>=<template>`, where the template is text with
+//! `{{ <expression> }}` placeholders. Expressions can use the account fields
+//! `client`, `available`, `held`, `total`, `locked`, `closed` and `frozen`,
+//! `tier`, the client metadata `name` and `kyc_status`, see
+//! [`PaymentEngine::set_metadata`](crate::PaymentEngine::set_metadata), the
+//! funds in a currency as `available.<code>`, `held.<code>` and
+//! `total.<code>`, custom attributes as `attr.<name>`, see
+//! [`PaymentEngine::set_attribute`](crate::PaymentEngine::set_attribute),
+//! number literals, `+`, `-`, `*`, `/` and parentheses. Numbers are rounded
+//! to four decimal places. An expression that cannot be evaluated, such as a
+//! division by zero or arithmetic on a missing attribute, renders as an empty
+//! value.
+
+use std::{collections::BTreeMap, fmt::Display, str::FromStr};
+
+use rust_decimal::Decimal;
+use thiserror::Error;
+
+use crate::account::Account;
+
+#[derive(Debug, PartialEq, Error)]
+pub enum TemplateError {
+    #[error("Column `{0}` is missing a `<name>=`")]
+    MissingName(String),
+    #[error("Unclosed `{{{{` in template `{0}`")]
+    Unclosed(String),
+    #[error("Unexpected character `{0}` in template expression")]
+    UnexpectedCharacter(char),
+    #[error("Unexpected end of template expression")]
+    UnexpectedEnd,
+    #[error("Unexpected `{0}` in template expression")]
+    UnexpectedToken(String),
+    #[error("Unknown field `{0}` in template expression")]
+    UnknownField(String),
+    #[error("Invalid number `{0}` in template expression")]
+    InvalidNumber(String),
+}
+
+/// The columns written instead of the account fields, see
+/// [`OutputOptions::template`](crate::OutputOptions::template).
+pub type OutputTemplate = Vec<TemplateColumn>;
+
+/// A named output column rendered from a template.
+///
+/// # Examples
+///
+/// ```
+/// use randomlib::TemplateColumn;
+///
+/// let column: TemplateColumn = "utilization={{ held / total }}".parse().unwrap();
+/// assert_eq!(column.name, "utilization");
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct TemplateColumn {
+    pub name: String,
+    segments: Vec<Segment>,
+    source: String,
+}
+
+impl TemplateColumn {
+    /// Renders the column for `account` with its custom `attributes`.
+    pub fn render(
+        &self,
+        account: &Account,
+        attributes: Option<&BTreeMap<String, String>>,
+    ) -> String {
+        let mut rendered = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Text(text) => rendered.push_str(text),
+                Segment::Expr(expr) => {
+                    if let Some(value) = expr.eval(account, attributes) {
+                        rendered.push_str(&value.to_string());
+                    }
+                }
+            }
+        }
+        rendered
+    }
+}
+
+impl FromStr for TemplateColumn {
+    type Err = TemplateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, template) = s
+            .split_once('=')
+            .filter(|(name, _)| !name.trim().is_empty())
+            .ok_or_else(|| TemplateError::MissingName(s.to_string()))?;
+
+        let mut segments = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find("{{") {
+            if start > 0 {
+                segments.push(Segment::Text(rest[..start].to_string()));
+            }
+            let end = rest[start..]
+                .find("}}")
+                .ok_or_else(|| TemplateError::Unclosed(template.to_string()))?;
+            let tokens = tokenize(&rest[start + 2..start + end])?;
+            let mut parser = Parser { tokens, pos: 0 };
+            let expr = parser.sum()?;
+            if let Some(token) = parser.tokens.get(parser.pos) {
+                return Err(TemplateError::UnexpectedToken(token.to_string()));
+            }
+            segments.push(Segment::Expr(expr));
+            rest = &rest[start + end + 2..];
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Text(rest.to_string()));
+        }
+
+        Ok(Self {
+            name: name.trim().to_string(),
+            segments,
+            source: s.to_string(),
+        })
+    }
+}
+
+impl Display for TemplateColumn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+/// A fixed set of output columns, written as a comma separated list of
+/// expressions that can each be renamed with `<name>=`, e.g.
+/// `id=client,available,total.EUR`. A column without a name is named after
+/// its expression.
+///
+/// # Examples
+///
+/// ```
+/// use randomlib::OutputSchema;
+///
+/// let schema: OutputSchema = "id=client,available,total.EUR".parse().unwrap();
+/// let names = schema.columns.iter().map(|c| c.name.as_str()).collect::<Vec<_>>();
+/// assert_eq!(names, vec!["id", "available", "total.EUR"]);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputSchema {
+    pub columns: OutputTemplate,
+    source: String,
+}
+
+impl FromStr for OutputSchema {
+    type Err = TemplateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let columns = s
+            .split(',')
+            .map(|column| {
+                let (name, expr) = match column.split_once('=') {
+                    Some((name, expr)) => (name.trim(), expr.trim()),
+                    None => (column.trim(), column.trim()),
+                };
+                format!("{}={{{{ {} }}}}", name, expr).parse()
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            columns,
+            source: s.to_string(),
+        })
+    }
+}
+
+impl Display for OutputSchema {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Text(String),
+    Expr(Expr),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    Client,
+    Available,
+    Held,
+    Total,
+    Locked,
+    Closed,
+    Frozen,
+    Name,
+    Tier,
+    KycStatus,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Field(Field),
+    /// A field of the funds in a currency
+    Currency(Field, String),
+    Attribute(String),
+    Number(Decimal),
+    Neg(Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, PartialEq)]
+enum Value {
+    Number(Decimal),
+    Text(String),
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            // Amounts keep their scale, like in the regular output
+            Value::Number(n) if n.scale() <= 4 => write!(f, "{}", n),
+            Value::Number(n) => write!(f, "{}", n.round_dp(4)),
+            Value::Text(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+impl Value {
+    fn number(&self) -> Option<Decimal> {
+        match self {
+            Value::Number(n) => Some(*n),
+            Value::Text(s) => Decimal::from_str(s).ok(),
+        }
+    }
+}
+
+impl Expr {
+    fn eval(
+        &self,
+        account: &Account,
+        attributes: Option<&BTreeMap<String, String>>,
+    ) -> Option<Value> {
+        let value = match self {
+            Expr::Field(field) => match field {
+                Field::Client => Value::Number(Decimal::from(account.client())),
+                Field::Available => Value::Number(account.available().into()),
+                Field::Held => Value::Number(account.held().into()),
+                Field::Total => Value::Number(account.total().into()),
+                Field::Locked => Value::Text(account.locked().to_string()),
+                Field::Closed => Value::Text(account.closed().to_string()),
+                Field::Frozen => Value::Text(account.frozen().to_string()),
+                Field::Name => Value::Text(account.metadata()?.name.clone()?),
+                Field::Tier => Value::Text(account.tier()?.to_string()),
+                Field::KycStatus => Value::Text(account.metadata()?.kyc_status.clone()?),
+            },
+            Expr::Currency(field, code) => {
+                let balance = account.balance(Some(code));
+                Value::Number(
+                    match field {
+                        Field::Available => balance.available,
+                        Field::Held => balance.held,
+                        _ => balance.total,
+                    }
+                    .into(),
+                )
+            }
+            Expr::Attribute(name) => Value::Text(attributes?.get(name)?.clone()),
+            Expr::Number(n) => Value::Number(*n),
+            Expr::Neg(e) => Value::Number(-e.eval(account, attributes)?.number()?),
+            Expr::Binary(op, l, r) => {
+                let l = l.eval(account, attributes)?.number()?;
+                let r = r.eval(account, attributes)?.number()?;
+                Value::Number(match op {
+                    Op::Add => l.checked_add(r)?,
+                    Op::Sub => l.checked_sub(r)?,
+                    Op::Mul => l.checked_mul(r)?,
+                    Op::Div => l.checked_div(r)?,
+                })
+            }
+        };
+        Some(value)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(String),
+    Op(char),
+    LParen,
+    RParen,
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Ident(s) | Token::Number(s) => write!(f, "{}", s),
+            Token::Op(c) => write!(f, "{}", c),
+            Token::LParen => write!(f, "("),
+            Token::RParen => write!(f, ")"),
+        }
+    }
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, TemplateError> {
+    let mut tokens = Vec::new();
+    let mut rest = s;
+    while let Some(c) = rest.chars().next() {
+        if c.is_whitespace() {
+            rest = &rest[c.len_utf8()..];
+        } else if c == '(' {
+            tokens.push(Token::LParen);
+            rest = &rest[1..];
+        } else if c == ')' {
+            tokens.push(Token::RParen);
+            rest = &rest[1..];
+        } else if "+-*/".contains(c) {
+            tokens.push(Token::Op(c));
+            rest = &rest[1..];
+        } else if c.is_ascii_digit() {
+            let end = rest
+                .find(|c: char| !c.is_ascii_digit() && c != '.')
+                .unwrap_or(rest.len());
+            tokens.push(Token::Number(rest[..end].to_string()));
+            rest = &rest[end..];
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            // Attributes are written as `attr.<name>`
+            let end = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_' && c != '.')
+                .unwrap_or(rest.len());
+            tokens.push(Token::Ident(rest[..end].to_string()));
+            rest = &rest[end..];
+        } else {
+            return Err(TemplateError::UnexpectedCharacter(c));
+        }
+    }
+    Ok(tokens)
+}
+
+/// A recursive descent parser where `*` and `/` bind tighter than `+` and
+/// `-`.
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Result<Token, TemplateError> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or(TemplateError::UnexpectedEnd)?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn eat(&mut self, ops: &[char]) -> Option<char> {
+        match self.tokens.get(self.pos) {
+            Some(Token::Op(op)) if ops.contains(op) => {
+                self.pos += 1;
+                Some(*op)
+            }
+            _ => None,
+        }
+    }
+
+    fn sum(&mut self) -> Result<Expr, TemplateError> {
+        let mut expr = self.product()?;
+        while let Some(op) = self.eat(&['+', '-']) {
+            let op = if op == '+' { Op::Add } else { Op::Sub };
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.product()?));
+        }
+        Ok(expr)
+    }
+
+    fn product(&mut self) -> Result<Expr, TemplateError> {
+        let mut expr = self.unary()?;
+        while let Some(op) = self.eat(&['*', '/']) {
+            let op = if op == '*' { Op::Mul } else { Op::Div };
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, TemplateError> {
+        if self.eat(&['-']).is_some() {
+            return Ok(Expr::Neg(Box::new(self.unary()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr, TemplateError> {
+        match self.next()? {
+            Token::LParen => {
+                let expr = self.sum()?;
+                match self.next()? {
+                    Token::RParen => Ok(expr),
+                    token => Err(TemplateError::UnexpectedToken(token.to_string())),
+                }
+            }
+            Token::Number(n) => Decimal::from_str(&n)
+                .map(Expr::Number)
+                .map_err(|_| TemplateError::InvalidNumber(n)),
+            Token::Ident(ident) => match ident.as_str() {
+                "client" => Ok(Expr::Field(Field::Client)),
+                "available" => Ok(Expr::Field(Field::Available)),
+                "held" => Ok(Expr::Field(Field::Held)),
+                "total" => Ok(Expr::Field(Field::Total)),
+                "locked" => Ok(Expr::Field(Field::Locked)),
+                "closed" => Ok(Expr::Field(Field::Closed)),
+                "frozen" => Ok(Expr::Field(Field::Frozen)),
+                "name" => Ok(Expr::Field(Field::Name)),
+                "tier" => Ok(Expr::Field(Field::Tier)),
+                "kyc_status" => Ok(Expr::Field(Field::KycStatus)),
+                _ => match ident.split_once('.') {
+                    Some(("attr", name)) if !name.is_empty() => {
+                        Ok(Expr::Attribute(name.to_string()))
+                    }
+                    Some((field, code)) if !code.is_empty() => {
+                        let field = match field {
+                            "available" => Field::Available,
+                            "held" => Field::Held,
+                            "total" => Field::Total,
+                            _ => return Err(TemplateError::UnknownField(ident)),
+                        };
+                        Ok(Expr::Currency(field, code.to_string()))
+                    }
+                    _ => Err(TemplateError::UnknownField(ident)),
+                },
+            },
+            token => Err(TemplateError::UnexpectedToken(token.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Amount, EngineConfig, TransactionVariant};
+
+    fn render(column: &str, account: &Account, attributes: &BTreeMap<String, String>) -> String {
+        column
+            .parse::<TemplateColumn>()
+            .unwrap()
+            .render(account, Some(attributes))
+    }
+
+    #[test]
+    fn renders_columns() {
+        let mut account = Account::new(7);
+        let config = EngineConfig::default();
+        let amount = Amount::new(4, 0).unwrap();
+        account
+            .transaction(&TransactionVariant::Deposit, amount, &config)
+            .unwrap();
+        account
+            .transaction(
+                &TransactionVariant::Dispute,
+                Amount::new(1, 0).unwrap(),
+                &config,
+            )
+            .unwrap();
+        let mut attributes = BTreeMap::new();
+        attributes.insert("segment".to_string(), "retail".to_string());
+        attributes.insert("limit".to_string(), "8".to_string());
+
+        assert_eq!(
+            render("u={{ held / total }}", &account, &attributes),
+            "0.25"
+        );
+        assert_eq!(render("u={{ held / 3 }}", &account, &attributes), "0.3333");
+        assert_eq!(
+            render("id=c{{client}}-{{attr.segment}}", &account, &attributes),
+            "c7-retail"
+        );
+        assert_eq!(
+            render(
+                "h={{ attr.limit - (total + 1) * 2 }}",
+                &account,
+                &attributes
+            ),
+            "-2"
+        );
+        assert_eq!(
+            render("x={{ held / (total - 4) }}", &account, &attributes),
+            ""
+        );
+        assert_eq!(
+            render("x={{ attr.missing * 2 }}", &account, &attributes),
+            ""
+        );
+    }
+
+    #[test]
+    fn renders_schema_columns() {
+        let mut account = Account::new(7);
+        let config = EngineConfig::default();
+        account
+            .in_currency(Some("EUR"), |account| {
+                account.transaction(
+                    &TransactionVariant::Deposit,
+                    Amount::new(25, 1).unwrap(),
+                    &config,
+                )
+            })
+            .unwrap();
+        let schema: OutputSchema = "id=client, total.EUR ,total.USD,locked".parse().unwrap();
+        let row = schema
+            .columns
+            .iter()
+            .map(|column| column.render(&account, None))
+            .collect::<Vec<_>>();
+        assert_eq!(row, vec!["7", "2.5", "0", "false"]);
+        assert_eq!(schema.columns[1].name, "total.EUR");
+        assert_eq!(
+            "client,locked.EUR".parse::<OutputSchema>().unwrap_err(),
+            TemplateError::UnknownField("locked.EUR".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_columns() {
+        assert_eq!(
+            "{{ held }}".parse::<TemplateColumn>().unwrap_err(),
+            TemplateError::MissingName("{{ held }}".to_string())
+        );
+        assert_eq!(
+            "x={{ held".parse::<TemplateColumn>().unwrap_err(),
+            TemplateError::Unclosed("{{ held".to_string())
+        );
+        assert_eq!(
+            "x={{ owner }}".parse::<TemplateColumn>().unwrap_err(),
+            TemplateError::UnknownField("owner".to_string())
+        );
+        assert_eq!(
+            "x={{ held + }}".parse::<TemplateColumn>().unwrap_err(),
+            TemplateError::UnexpectedEnd
+        );
+    }
+}
@@ -0,0 +1,211 @@
+//! [`proptest`] support with the `testing` feature, to property test
+//! integrations against the engine.
+//!
+//! [`Amount`], [`TransactionVariant`] and [`Transaction`] implement
+//! [`Arbitrary`], and [`transactions`] produces sequences that reference each
+//! other like real inputs do.
+
+use std::convert::TryFrom;
+
+use proptest::{
+    arbitrary::{any, Arbitrary},
+    collection,
+    sample::{self, Index},
+    strategy::{BoxedStrategy, Strategy},
+};
+use rust_decimal::{Decimal, RoundingStrategy};
+
+use crate::{Amount, Currency, Transaction, TransactionVariant};
+
+/// The largest amount generated, in ten-thousandths.
+const MAX_AMOUNT: i64 = 10_000_000_000;
+
+const VARIANTS: [TransactionVariant; 16] = [
+    TransactionVariant::Deposit,
+    TransactionVariant::Withdrawal,
+    TransactionVariant::Dispute,
+    TransactionVariant::Resolve,
+    TransactionVariant::Chargeback,
+    TransactionVariant::Representment,
+    TransactionVariant::Unlock,
+    TransactionVariant::Close,
+    TransactionVariant::Freeze,
+    TransactionVariant::Unfreeze,
+    TransactionVariant::Transfer,
+    TransactionVariant::Fee,
+    TransactionVariant::Authorize,
+    TransactionVariant::Capture,
+    TransactionVariant::Void,
+    TransactionVariant::Convert,
+];
+
+/// Amounts from zero to a million, with up to four decimal places.
+impl Arbitrary for Amount {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        (0..=MAX_AMOUNT)
+            .prop_map(|n| Amount::new(n, 4).expect("nonnegative"))
+            .boxed()
+    }
+}
+
+impl Arbitrary for TransactionVariant {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        sample::select(&VARIANTS[..]).boxed()
+    }
+}
+
+/// Well formed transactions, see [`Transaction::is_valid`], that do not
+/// necessarily reference existing ones.
+impl Arbitrary for Transaction {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<Self>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        (
+            any::<TransactionVariant>(),
+            any::<u16>(),
+            any::<u32>(),
+            any::<Amount>(),
+            any::<u16>(),
+        )
+            .prop_map(|(variant, client, tx, amount, destination)| {
+                let mut tx = transaction(variant, client, tx, None);
+                match tx.variant {
+                    TransactionVariant::Deposit
+                    | TransactionVariant::Withdrawal
+                    | TransactionVariant::Fee
+                    | TransactionVariant::Authorize => tx.amount = Some(amount),
+                    TransactionVariant::Transfer => {
+                        tx.amount = Some(amount);
+                        tx.destination = Some(if destination == client {
+                            client.wrapping_add(1)
+                        } else {
+                            destination
+                        });
+                    }
+                    TransactionVariant::Convert => {
+                        // Rounded down to the minor units of the currency
+                        let cents = Decimal::from(amount)
+                            .round_dp_with_strategy(2, RoundingStrategy::ToZero);
+                        tx.amount = Some(Amount::try_from(cents).expect("nonnegative"));
+                        tx.currency = Currency::iso("EUR");
+                        tx.to_currency = Currency::iso("USD");
+                        tx.rate = Some(Decimal::new(11, 1));
+                    }
+                    _ => {}
+                }
+                tx
+            })
+            .boxed()
+    }
+}
+
+/// Sequences of up to `len` deposits, withdrawals, transfers, fees, disputes,
+/// resolves and chargebacks of clients `1..=clients` with unique ids from 1.
+///
+/// Disputes only reference earlier deposits and withdrawals of the same
+/// client that are not disputed or charged back, and resolves and
+/// chargebacks only open disputes. Transactions may still be rejected, e.g.
+/// for insufficient funds.
+pub fn transactions(clients: u16, len: usize) -> impl Strategy<Value = Vec<Transaction>> {
+    let clients = clients.max(1);
+    let op = (0u8..8, 1..=clients, any::<Amount>(), any::<Index>());
+    collection::vec(op, 0..=len).prop_map(move |ops| {
+        let mut txs = Vec::with_capacity(ops.len());
+        // The (client, tx) that can be disputed, and that are disputed
+        let mut disputable = Vec::new();
+        let mut disputed = Vec::new();
+        for (id, (kind, client, amount, index)) in (1..).zip(ops) {
+            let tx = match kind {
+                2 if !disputable.is_empty() => {
+                    let (client, tx) = disputable.swap_remove(index.index(disputable.len()));
+                    disputed.push((client, tx));
+                    transaction(TransactionVariant::Dispute, client, tx, None)
+                }
+                3 | 4 if !disputed.is_empty() => {
+                    let (client, tx) = disputed.swap_remove(index.index(disputed.len()));
+                    if kind == 3 {
+                        disputable.push((client, tx));
+                        transaction(TransactionVariant::Resolve, client, tx, None)
+                    } else {
+                        transaction(TransactionVariant::Chargeback, client, tx, None)
+                    }
+                }
+                5 if clients > 1 => {
+                    let mut tx =
+                        transaction(TransactionVariant::Transfer, client, id, Some(amount));
+                    tx.destination = Some(client % clients + 1);
+                    tx
+                }
+                6 => transaction(TransactionVariant::Fee, client, id, Some(amount)),
+                1 => {
+                    disputable.push((client, id));
+                    transaction(TransactionVariant::Withdrawal, client, id, Some(amount))
+                }
+                _ => {
+                    disputable.push((client, id));
+                    transaction(TransactionVariant::Deposit, client, id, Some(amount))
+                }
+            };
+            txs.push(tx);
+        }
+        txs
+    })
+}
+
+fn transaction(
+    variant: TransactionVariant,
+    client: u16,
+    tx: u32,
+    amount: Option<Amount>,
+) -> Transaction {
+    Transaction {
+        variant,
+        client,
+        tx,
+        amount,
+        disputed: false,
+        chargeback: false,
+        destination: None,
+        currency: None,
+        to_currency: None,
+        rate: None,
+        timestamp: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::PaymentEngine;
+
+    proptest! {
+        #[test]
+        fn arbitrary_transactions_are_valid(tx in any::<Transaction>()) {
+            prop_assert!(tx.is_valid());
+        }
+
+        #[test]
+        fn sequences_keep_accounts_balanced(txs in transactions(3, 50)) {
+            let mut engine = PaymentEngine::default();
+            for tx in txs {
+                prop_assert!(tx.is_valid());
+                let _ = engine.insert(tx);
+            }
+            for account in engine.accounts_iter(true) {
+                prop_assert_eq!(
+                    account.available().checked_add(account.held()).unwrap(),
+                    account.total()
+                );
+            }
+        }
+    }
+}
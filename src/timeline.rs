@@ -0,0 +1,316 @@
+//! Queries over the `timestamp` of transactions.
+
+use std::{
+    borrow::Borrow,
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
+    str::FromStr,
+};
+
+use rust_decimal::Decimal;
+use serde::{Serialize, Serializer};
+use thiserror::Error;
+
+use crate::{account::Balance, Transaction, TransactionVariant};
+
+/// The number of seconds in a day.
+pub(crate) const DAY: u64 = 86400;
+
+/// The deposits and withdrawals of one day, see
+/// [`PaymentEngine::daily_volumes`](crate::PaymentEngine::daily_volumes).
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct DailyVolume {
+    /// The start of the day in seconds since the Unix epoch, in UTC.
+    pub day: u64,
+    pub deposits: u64,
+    pub deposited: Decimal,
+    pub withdrawals: u64,
+    pub withdrawn: Decimal,
+}
+
+/// Sums up the deposits and withdrawals with a timestamp and without a
+/// currency by day, oldest first.
+pub(crate) fn daily_volumes<I>(transactions: I) -> Vec<DailyVolume>
+where
+    I: IntoIterator,
+    I::Item: Borrow<Transaction>,
+{
+    let mut days = BTreeMap::new();
+    for tx in transactions {
+        let tx = tx.borrow();
+        let (timestamp, amount) = match (tx.timestamp, tx.amount) {
+            (Some(timestamp), Some(amount)) if tx.currency.is_none() => (timestamp, amount),
+            _ => continue,
+        };
+        let day = timestamp - timestamp % DAY;
+        let volume = days.entry(day).or_insert_with(|| DailyVolume {
+            day,
+            ..DailyVolume::default()
+        });
+        match tx.variant {
+            TransactionVariant::Deposit => {
+                volume.deposits += 1;
+                volume.deposited = volume.deposited.saturating_add(Decimal::from(amount));
+            }
+            TransactionVariant::Withdrawal => {
+                volume.withdrawals += 1;
+                volume.withdrawn = volume.withdrawn.saturating_add(Decimal::from(amount));
+            }
+            _ => (),
+        }
+    }
+    days.into_values().collect()
+}
+
+/// A calendar month in UTC, written as `2024-03`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Month {
+    pub year: u32,
+    /// From 1 for January to 12
+    pub month: u32,
+}
+
+impl Month {
+    /// The month `timestamp`, in seconds since the Unix epoch, falls in.
+    pub fn of(timestamp: u64) -> Self {
+        // Converts days to a date in the proleptic Gregorian calendar, with
+        // years starting in March so that leap days come last, see
+        // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+        let days = timestamp / DAY + 719_468;
+        let era = days / 146_097;
+        let day_of_era = days % 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+        let month = if shifted_month < 10 {
+            shifted_month + 3
+        } else {
+            shifted_month - 9
+        };
+        let year = year_of_era + era * 400 + u64::from(month <= 2);
+        Self {
+            year: year as u32,
+            month: month as u32,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Error)]
+#[error("Invalid month `{0}`, expected e.g. `2024-03`")]
+pub struct MonthError(String);
+
+impl FromStr for Month {
+    type Err = MonthError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || MonthError(s.to_string());
+        let (year, month) = s.trim().split_once('-').ok_or_else(error)?;
+        let year = year.parse().map_err(|_| error())?;
+        let month = month.parse().map_err(|_| error())?;
+        if !(1..=12).contains(&month) {
+            return Err(error());
+        }
+        Ok(Self { year, month })
+    }
+}
+
+impl Display for Month {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:04}-{:02}", self.year, self.month)
+    }
+}
+
+impl Serialize for Month {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// The money that moved into and out of the account of a client in a month,
+/// see [`PaymentEngine::monthly_flows`](crate::PaymentEngine::monthly_flows).
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MonthlyFlow {
+    pub client: u16,
+    pub month: Month,
+    /// Deposits and transfers and fees credited to the client
+    pub inflow: Decimal,
+    /// Withdrawals and transfers and fees debited from the client
+    pub outflow: Decimal,
+    /// The inflow minus the outflow
+    pub net: Decimal,
+}
+
+/// Sums up the flows of `client` per month from `from` to `to`, both
+/// included, oldest first. Only transactions with a timestamp and without a
+/// currency are included, and charged back transactions are left out as
+/// they have been reversed.
+pub(crate) fn monthly_flows<I>(
+    client: u16,
+    transactions: I,
+    from: Month,
+    to: Month,
+) -> Vec<MonthlyFlow>
+where
+    I: IntoIterator,
+    I::Item: Borrow<Transaction>,
+{
+    let mut months = BTreeMap::new();
+    for tx in transactions {
+        let tx = tx.borrow();
+        let (timestamp, amount) = match (tx.timestamp, tx.amount) {
+            (Some(timestamp), Some(amount)) if tx.currency.is_none() && !tx.chargeback => {
+                (timestamp, Decimal::from(amount))
+            }
+            _ => continue,
+        };
+        let month = Month::of(timestamp);
+        if month < from || month > to {
+            continue;
+        }
+        let flow = months.entry(month).or_insert_with(|| MonthlyFlow {
+            client,
+            month,
+            inflow: Decimal::ZERO,
+            outflow: Decimal::ZERO,
+            net: Decimal::ZERO,
+        });
+        match tx.variant {
+            TransactionVariant::Deposit => flow.inflow = flow.inflow.saturating_add(amount),
+            TransactionVariant::Withdrawal => flow.outflow = flow.outflow.saturating_add(amount),
+            TransactionVariant::Transfer | TransactionVariant::Fee if tx.client == client => {
+                flow.outflow = flow.outflow.saturating_add(amount)
+            }
+            TransactionVariant::Transfer | TransactionVariant::Fee => {
+                flow.inflow = flow.inflow.saturating_add(amount)
+            }
+            _ => (),
+        }
+        flow.net = flow.inflow.saturating_sub(flow.outflow);
+    }
+    months.into_values().collect()
+}
+
+/// The balances of every client over time, see
+/// [`PaymentEngine::balance_at`](crate::PaymentEngine::balance_at).
+#[derive(Debug, Default)]
+pub(crate) struct BalanceTimeline {
+    /// The balance after every change, oldest first
+    balances: HashMap<u16, Vec<(u64, Balance)>>,
+}
+
+impl BalanceTimeline {
+    /// Records the `balance` of `client` at `timestamp`, which replaces an
+    /// earlier balance at the same time.
+    pub(crate) fn record(&mut self, client: u16, timestamp: u64, balance: Balance) {
+        let balances = self.balances.entry(client).or_default();
+        match balances.last_mut() {
+            Some((last, previous)) if *last == timestamp => *previous = balance,
+            _ => balances.push((timestamp, balance)),
+        }
+    }
+
+    /// The balance of `client` at `timestamp`, if it was recorded by then.
+    pub(crate) fn at(&self, client: u16, timestamp: u64) -> Option<Balance> {
+        let balances = self.balances.get(&client)?;
+        let after = balances.partition_point(|(time, _)| *time <= timestamp);
+        after.checked_sub(1).map(|i| balances[i].1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Amount;
+
+    #[test]
+    fn months_of_timestamps() {
+        assert_eq!(Month::of(0).to_string(), "1970-01");
+        // 2024-02-29T23:59:59Z and 2024-03-01T00:00:00Z
+        assert_eq!(Month::of(1_709_251_199).to_string(), "2024-02");
+        assert_eq!(Month::of(1_709_251_200).to_string(), "2024-03");
+        assert_eq!(Month::of(1_735_689_599).to_string(), "2024-12");
+        assert_eq!("2024-03".parse(), Ok(Month::of(1_709_251_200)));
+        assert!("2024-13".parse::<Month>().is_err());
+    }
+
+    #[test]
+    fn sums_flows_by_month() {
+        let tx = |variant, client, amount, timestamp| Transaction {
+            tx: 1,
+            amount: Some(Amount::new(amount, 0).unwrap()),
+            client,
+            disputed: false,
+            variant,
+            chargeback: false,
+            destination: Some(2),
+            currency: None,
+            to_currency: None,
+            rate: None,
+            timestamp: Some(timestamp),
+        };
+        let march = 1_709_251_200;
+        let april = 1_711_929_600;
+        let mut charged_back = tx(TransactionVariant::Deposit, 2, 7, march);
+        charged_back.chargeback = true;
+        let transactions = vec![
+            tx(TransactionVariant::Deposit, 2, 5, march),
+            tx(TransactionVariant::Transfer, 1, 3, march + 10),
+            tx(TransactionVariant::Withdrawal, 2, 6, april),
+            tx(TransactionVariant::Deposit, 2, 1, 0),
+            charged_back,
+        ];
+        let flows = monthly_flows(
+            2,
+            &transactions,
+            "2024-01".parse().unwrap(),
+            Month::of(april),
+        );
+        assert_eq!(flows.len(), 2);
+        assert_eq!(flows[0].month.to_string(), "2024-03");
+        assert_eq!(flows[0].inflow, Decimal::from(8));
+        assert_eq!(flows[1].outflow, Decimal::from(6));
+        assert_eq!(flows[1].net, Decimal::from(-6));
+    }
+
+    #[test]
+    fn sums_volumes_by_day() {
+        let tx = |variant, amount, timestamp| Transaction {
+            tx: 1,
+            amount: Some(Amount::new(amount, 0).unwrap()),
+            client: 1,
+            disputed: false,
+            variant,
+            chargeback: false,
+            destination: None,
+            currency: None,
+            to_currency: None,
+            rate: None,
+            timestamp,
+        };
+        let volumes = daily_volumes(vec![
+            tx(TransactionVariant::Deposit, 5, Some(DAY + 10)),
+            tx(TransactionVariant::Withdrawal, 2, Some(DAY + 20)),
+            tx(TransactionVariant::Deposit, 3, Some(10)),
+            tx(TransactionVariant::Deposit, 3, None),
+        ]);
+        assert_eq!(
+            volumes,
+            vec![
+                DailyVolume {
+                    day: 0,
+                    deposits: 1,
+                    deposited: Decimal::from(3),
+                    ..DailyVolume::default()
+                },
+                DailyVolume {
+                    day: DAY,
+                    deposits: 1,
+                    deposited: Decimal::from(5),
+                    withdrawals: 1,
+                    withdrawn: Decimal::from(2),
+                },
+            ]
+        );
+    }
+}
@@ -1,8 +1,9 @@
-use serde::Deserialize;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
 
-use crate::{amount::Amount, error::TransactionError};
+use crate::{amount::Amount, currency::Currency, error::TransactionError};
 
-#[derive(Debug, PartialEq, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum TransactionVariant {
     Deposit,
@@ -10,14 +11,95 @@ pub enum TransactionVariant {
     Dispute,
     Resolve,
     Chargeback,
+    /// Reverses the chargeback of `tx` after the bank decided for the
+    /// merchant, after which the transaction is final.
+    Representment,
+    /// Unlocks an account locked by a chargeback. The `tx` column is ignored.
+    Unlock,
+    /// Closes an account without funds, after which it rejects every
+    /// transaction. The `tx` column is ignored.
+    Close,
+    /// Pauses every transaction of an account, e.g. while the risk team looks
+    /// into it, independently of the lock of a chargeback. The `tx` column is
+    /// ignored.
+    Freeze,
+    /// Lifts a [`TransactionVariant::Freeze`]. The `tx` column is ignored.
+    Unfreeze,
+    /// Moves `amount` from the account of `client` to the account of
+    /// `destination`, or neither if either side rejects it.
+    Transfer,
+    /// Charges `amount` to the account of `client`. Unlike a withdrawal it
+    /// cannot be disputed. Credited to the
+    /// [`EngineConfig::fee_account`](crate::EngineConfig::fee_account), if any.
+    Fee,
+    /// Holds `amount` of the available funds of `client` until the
+    /// authorization is captured or voided, like a card payment. Cannot be
+    /// disputed.
+    Authorize,
+    /// Takes the funds held by the authorization `tx` out of the account.
+    Capture,
+    /// Makes the funds held by the authorization `tx` available again.
+    Void,
+    /// Converts `amount` of the funds in `currency` of the account of `client`
+    /// to its funds in `to_currency` at `rate`, rounded down to the minor
+    /// units of `to_currency`. Cannot be disputed.
+    Convert,
+}
+
+impl TransactionVariant {
+    /// The name of the variant as it appears in the `type` column.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TransactionVariant::Deposit => "deposit",
+            TransactionVariant::Withdrawal => "withdrawal",
+            TransactionVariant::Dispute => "dispute",
+            TransactionVariant::Resolve => "resolve",
+            TransactionVariant::Chargeback => "chargeback",
+            TransactionVariant::Representment => "representment",
+            TransactionVariant::Unlock => "unlock",
+            TransactionVariant::Close => "close",
+            TransactionVariant::Freeze => "freeze",
+            TransactionVariant::Unfreeze => "unfreeze",
+            TransactionVariant::Transfer => "transfer",
+            TransactionVariant::Fee => "fee",
+            TransactionVariant::Convert => "convert",
+            TransactionVariant::Authorize => "authorize",
+            TransactionVariant::Capture => "capture",
+            TransactionVariant::Void => "void",
+        }
+    }
+
+    /// The inverse of [`TransactionVariant::as_str`].
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "deposit" => Some(TransactionVariant::Deposit),
+            "withdrawal" => Some(TransactionVariant::Withdrawal),
+            "dispute" => Some(TransactionVariant::Dispute),
+            "resolve" => Some(TransactionVariant::Resolve),
+            "chargeback" => Some(TransactionVariant::Chargeback),
+            "representment" => Some(TransactionVariant::Representment),
+            "unlock" => Some(TransactionVariant::Unlock),
+            "close" => Some(TransactionVariant::Close),
+            "freeze" => Some(TransactionVariant::Freeze),
+            "unfreeze" => Some(TransactionVariant::Unfreeze),
+            "transfer" => Some(TransactionVariant::Transfer),
+            "fee" => Some(TransactionVariant::Fee),
+            "convert" => Some(TransactionVariant::Convert),
+            "authorize" => Some(TransactionVariant::Authorize),
+            "capture" => Some(TransactionVariant::Capture),
+            "void" => Some(TransactionVariant::Void),
+            _ => None,
+        }
+    }
 }
 
 // Unfortunately the csv crate does not support deserializing to more complex
 // enum variants and we have to use a struct with a slightly more awkward type
 // definition.
 // This means that it is actually possible to read in a [`Transaction`]
-// that is not actaully valid, for exmaple with variant = `TransactionVariant::Dispute` and
-// amount = `Some(5.0)` which is illegal.
+// that is not actaully valid, for exmaple with variant = `TransactionVariant::Resolve` and
+// amount = `Some(5.0)` which is illegal. A dispute may have an amount, the
+// disputed portion of the transaction, see [`Transaction::is_valid`].
 //
 // It would be better if we could deserialize to something like:
 // enum RowInput {
@@ -26,7 +108,7 @@ pub enum TransactionVariant {
 // }
 //
 // Related issue: https://github.com/BurntSushi/rust-csv/issues/211
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Transaction {
     #[serde(rename = "type")]
     pub variant: TransactionVariant,
@@ -37,13 +119,64 @@ pub struct Transaction {
     pub disputed: bool,
     #[serde(skip_deserializing)]
     pub chargeback: bool,
+    /// The client receiving a [`TransactionVariant::Transfer`]. Read from the
+    /// optional `destination` column.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub destination: Option<u16>,
+    /// The currency of `amount`, read from the optional `currency` column, see
+    /// [`Account::balances`](crate::Account::balances). Disputes, resolves and
+    /// chargebacks apply to the currency of the disputed transaction.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<Currency>,
+    /// The currency a [`TransactionVariant::Convert`] converts to. Read from
+    /// the optional `to_currency` column.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to_currency: Option<Currency>,
+    /// The amount of `to_currency` per unit of `currency` of a
+    /// [`TransactionVariant::Convert`]. Read from the optional `rate` column.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate: Option<Decimal>,
+    /// When the transaction happened, in seconds since the Unix epoch. Read
+    /// from the optional `timestamp` column by [`process`](crate::process).
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
 }
 
 impl Transaction {
     pub fn is_valid(&self) -> bool {
+        // Amounts cannot be more precise than their currency
+        if let (Some(currency), Some(amount)) = (&self.currency, self.amount) {
+            if currency.check(amount).is_err() {
+                return false;
+            }
+        }
+        let conversion = self.to_currency.is_some() || self.rate.is_some();
         match self.variant {
-            TransactionVariant::Deposit | TransactionVariant::Withdrawal => self.amount.is_some(),
-            _ => self.amount.is_none(),
+            TransactionVariant::Deposit
+            | TransactionVariant::Withdrawal
+            | TransactionVariant::Fee
+            | TransactionVariant::Authorize => {
+                self.amount.is_some() && self.destination.is_none() && !conversion
+            }
+            TransactionVariant::Transfer => {
+                self.amount.is_some()
+                    && self.destination.is_some_and(|d| d != self.client)
+                    && !conversion
+            }
+            TransactionVariant::Convert => {
+                self.amount.is_some()
+                    && self.destination.is_none()
+                    && self.to_currency.is_some()
+                    && self.to_currency != self.currency
+                    && self.rate.is_some_and(|rate| rate > Decimal::ZERO)
+            }
+            // The amount of a dispute is the disputed portion of the transaction
+            TransactionVariant::Dispute => {
+                self.amount.is_none_or(|amount| !amount.is_zero())
+                    && self.destination.is_none()
+                    && !conversion
+            }
+            _ => self.amount.is_none() && self.destination.is_none() && !conversion,
         }
     }
 
@@ -0,0 +1,284 @@
+use std::{error::Error, io};
+
+use serde::{ser::SerializeStruct, Serialize, Serializer};
+
+use crate::{
+    run::{process_with_policy, RowError, RowErrorKind},
+    store::{AccountStore, TransactionStore},
+    Account, PaymentEngine, ProcessingPolicy,
+};
+
+/// The columns every input must have.
+const REQUIRED_COLUMNS: [&str; 3] = ["type", "client", "tx"];
+
+/// The outcome of [`validate`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ValidationReport {
+    /// The number of rows read, not counting the header
+    pub rows: u64,
+    /// Every row that would not be processed, with its error. A missing
+    /// column is reported on line 1 and no rows are read.
+    pub errors: Vec<RowError>,
+}
+
+impl ValidationReport {
+    /// Whether every row would be processed.
+    pub fn is_valid(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+/// Checks the transactions read as CSV from `reader` as if they were
+/// processed by `engine` after its current state, without changing it.
+///
+/// Reports missing columns, rows that cannot be parsed, duplicate
+/// transaction ids within the input or against the state of `engine`, and
+/// rows the engine would reject, such as withdrawals exceeding the funds
+/// available by then. Disputes, resolves and chargebacks referencing a
+/// transaction that does not exist are not reported, as they are ignored
+/// when processing, see [`ProcessingPolicy`].
+///
+/// The rows are applied to a copy of the accounts and transactions with the
+/// same [`EngineConfig`](crate::EngineConfig). Interest, client limits and
+/// observers of `engine` are not applied to the copy.
+///
+/// # Examples
+///
+/// ```
+/// use randomlib::{process, validate, PaymentEngine};
+///
+/// let mut engine = PaymentEngine::default();
+/// process("type,client,tx,amount\ndeposit,1,1,5.0".as_bytes(), &mut engine).unwrap();
+///
+/// let input = "type,client,tx,amount\nwithdrawal,1,2,4.0\nwithdrawal,1,3,4.0";
+/// let report = validate(input.as_bytes(), &engine).unwrap();
+/// assert_eq!(report.rows, 2);
+/// assert_eq!(report.errors[0].line, 3);
+/// // Nothing has been withdrawn
+/// assert_eq!(engine.accounts().get(&1).unwrap().available().to_string(), "5.0000");
+/// ```
+pub fn validate<R, A, T>(
+    mut reader: R,
+    engine: &PaymentEngine<A, T>,
+) -> Result<ValidationReport, Box<dyn Error>>
+where
+    R: io::Read,
+    A: AccountStore,
+    T: TransactionStore,
+{
+    let mut input = Vec::new();
+    reader.read_to_end(&mut input)?;
+    let headers = csv::Reader::from_reader(input.as_slice())
+        .headers()?
+        .clone();
+    let errors = REQUIRED_COLUMNS
+        .iter()
+        .filter(|column| !headers.iter().any(|header| header == **column))
+        .map(|column| RowError {
+            line: 1,
+            kind: RowErrorKind::Parse(format!("Missing column `{}`", column)),
+        })
+        .collect::<Vec<_>>();
+    if !errors.is_empty() {
+        return Ok(ValidationReport { rows: 0, errors });
+    }
+
+    let mut scratch = PaymentEngine::from_snapshot(engine.snapshot());
+    scratch.set_config(engine.config().clone());
+    let errors = process_with_policy(
+        input.as_slice(),
+        &mut scratch,
+        ProcessingPolicy::SkipWithLog,
+    )?;
+    Ok(ValidationReport {
+        rows: scratch.metrics().rows,
+        errors,
+    })
+}
+
+/// The outcome of [`dry_run`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DryRunReport {
+    /// Every row in order
+    pub rows: Vec<RowOutcome>,
+    /// The accounts as they would be afterwards, ordered by client id
+    pub accounts: Vec<Account>,
+}
+
+/// Whether a row of a [`DryRunReport`] would be accepted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RowOutcome {
+    /// The line of the row in the input, starting at 1 for the header
+    pub line: u64,
+    /// Why the row would be rejected, if it would
+    pub error: Option<RowErrorKind>,
+}
+
+impl RowOutcome {
+    pub fn is_accepted(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+impl Serialize for RowOutcome {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("RowOutcome", 3)?;
+        state.serialize_field("line", &self.line)?;
+        state.serialize_field("accepted", &self.is_accepted())?;
+        match &self.error {
+            Some(error) => state.serialize_field("error", &error.to_string())?,
+            None => state.skip_field("error")?,
+        }
+        state.end()
+    }
+}
+
+/// Processes the transactions read as CSV from `reader` like [`validate`],
+/// on a copy of the state of `engine`, and reports whether every row would be
+/// accepted and the resulting accounts, without changing `engine` or its
+/// storage.
+///
+/// Disputes, resolves and chargebacks referencing a transaction that does not
+/// exist are reported as accepted, as they are ignored when processing.
+///
+/// # Examples
+///
+/// ```
+/// use randomlib::{dry_run, PaymentEngine};
+///
+/// let engine = PaymentEngine::default();
+/// let input = "type,client,tx,amount\ndeposit,1,1,5.0\nwithdrawal,1,2,6.0";
+/// let report = dry_run(input.as_bytes(), &engine).unwrap();
+/// assert!(report.rows[0].is_accepted());
+/// assert!(!report.rows[1].is_accepted());
+/// assert_eq!(report.accounts[0].total().to_string(), "5.0000");
+/// assert!(engine.accounts().is_empty());
+/// ```
+pub fn dry_run<R, A, T>(
+    mut reader: R,
+    engine: &PaymentEngine<A, T>,
+) -> Result<DryRunReport, Box<dyn Error>>
+where
+    R: io::Read,
+    A: AccountStore,
+    T: TransactionStore,
+{
+    let mut input = Vec::new();
+    reader.read_to_end(&mut input)?;
+    let mut scratch = PaymentEngine::from_snapshot(engine.snapshot());
+    scratch.set_config(engine.config().clone());
+    let errors = process_with_policy(
+        input.as_slice(),
+        &mut scratch,
+        ProcessingPolicy::SkipWithLog,
+    )?;
+
+    // The errors are in the order of their rows
+    let mut errors = errors.into_iter().peekable();
+    let mut rows = Vec::new();
+    let mut rdr = csv::Reader::from_reader(input.as_slice());
+    let mut record = csv::StringRecord::new();
+    while rdr.read_record(&mut record)? {
+        let line = record.position().map_or(0, csv::Position::line);
+        let error = errors
+            .next_if(|error| error.line == line)
+            .map(|error| error.kind);
+        rows.push(RowOutcome { line, error });
+    }
+    Ok(DryRunReport {
+        rows,
+        accounts: scratch.accounts_iter(true).cloned().collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_errors_against_current_state() {
+        let mut engine = PaymentEngine::default();
+        crate::process(
+            "type,client,tx,amount\ndeposit,1,1,5.0".as_bytes(),
+            &mut engine,
+        )
+        .unwrap();
+
+        let input = "type,client,tx,amount
+deposit,1,1,1.0
+deposit,2,2,1.0
+deposit,2,2,1.0
+withdrawal,1,3,6.0
+withdrawal,2,4,x
+dispute,3,9,
+";
+        let report = validate(input.as_bytes(), &engine).unwrap();
+        assert_eq!(report.rows, 6);
+        let lines = report.errors.iter().map(|e| e.line).collect::<Vec<_>>();
+        assert_eq!(lines, vec![2, 4, 5, 6, 7]);
+        assert!(!report.is_valid());
+        assert!(engine.accounts().get(&2).is_none());
+        assert_eq!(engine.metrics().rows, 1);
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["errors"][2]["line"], 5);
+        assert!(json["errors"][2]["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("Insufficient funds"));
+    }
+
+    #[test]
+    fn dry_run_reports_rows_and_accounts() {
+        let mut engine = PaymentEngine::default();
+        crate::process(
+            "type,client,tx,amount\ndeposit,1,1,5.0".as_bytes(),
+            &mut engine,
+        )
+        .unwrap();
+
+        let input = "type,client,tx,amount
+deposit,1,1,1.0
+withdrawal,1,2,2.0
+dispute,1,9,
+deposit,2,3,x
+";
+        let report = dry_run(input.as_bytes(), &engine).unwrap();
+        let accepted = report
+            .rows
+            .iter()
+            .map(RowOutcome::is_accepted)
+            .collect::<Vec<_>>();
+        assert_eq!(accepted, vec![false, true, false, false]);
+        assert_eq!(report.rows[3].line, 5);
+        assert_eq!(report.accounts.len(), 1);
+        assert_eq!(report.accounts[0].total().to_string(), "3.0000");
+        assert_eq!(
+            engine.accounts().get(&1).unwrap().total().to_string(),
+            "5.0000"
+        );
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(
+            json["rows"][1],
+            serde_json::json!({"line": 3, "accepted": true})
+        );
+        assert_eq!(json["rows"][0]["accepted"], false);
+        assert_eq!(
+            json["rows"][0]["error"],
+            "Cannot overwrite an existing transaction"
+        );
+    }
+
+    #[test]
+    fn reports_missing_columns() {
+        let engine = PaymentEngine::default();
+        let report = validate("type,tx,amount\ndeposit,1,1.0".as_bytes(), &engine).unwrap();
+        assert_eq!(report.rows, 0);
+        assert_eq!(report.errors.len(), 1);
+        assert_eq!(
+            report.errors[0].to_string(),
+            "Line 1: Missing column `client`"
+        );
+    }
+}
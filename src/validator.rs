@@ -0,0 +1,161 @@
+//! Rules a transaction has to pass before a [`PaymentEngine`](crate::PaymentEngine)
+//! applies it.
+
+use std::collections::BTreeSet;
+use std::fmt::Debug;
+
+use crate::{Account, Amount, Transaction};
+
+/// A rule checked before every transaction is applied, registered with
+/// [`PaymentEngine::add_validator`](crate::PaymentEngine::add_validator).
+///
+/// A transaction failing any rule is rejected with
+/// [`TransactionError::Rejected`](crate::TransactionError::Rejected) and
+/// changes nothing, not even creating the account of its client.
+///
+/// # Examples
+///
+/// ```
+/// use randomlib::{Account, PaymentEngine, Transaction, Validator};
+///
+/// /// Only accepts transactions during office hours, in UTC.
+/// #[derive(Debug)]
+/// struct OfficeHours;
+///
+/// impl Validator for OfficeHours {
+///     fn validate(&self, tx: &Transaction, _account: Option<&Account>) -> Result<(), String> {
+///         match tx.timestamp.map(|timestamp| timestamp % 86400 / 3600) {
+///             Some(9..=16) | None => Ok(()),
+///             Some(hour) => Err(format!("Outside of office hours at {}h", hour)),
+///         }
+///     }
+/// }
+///
+/// let mut engine = PaymentEngine::default();
+/// engine.add_validator(Box::new(OfficeHours));
+/// ```
+pub trait Validator: Debug {
+    /// Returns why `tx` has to be rejected, if it has to be. `account` is
+    /// the account of its client as it is now, if it exists.
+    fn validate(&self, tx: &Transaction, account: Option<&Account>) -> Result<(), String>;
+}
+
+/// Rejects transactions that lack values their variant needs or have values
+/// it does not use, see [`Transaction::is_valid`].
+///
+/// [`process`](crate::process) always checks this and reports such rows as
+/// [`RowErrorKind::Invalid`](crate::RowErrorKind::Invalid), so it is only
+/// useful when inserting transactions built elsewhere.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WellFormed;
+
+impl Validator for WellFormed {
+    fn validate(&self, tx: &Transaction, _account: Option<&Account>) -> Result<(), String> {
+        if tx.is_valid() {
+            Ok(())
+        } else {
+            Err("Invalid transaction".to_string())
+        }
+    }
+}
+
+/// Rejects transactions moving more than the given amount, in any currency.
+#[derive(Debug, Clone, Copy)]
+pub struct MaxAmount(pub Amount);
+
+impl Validator for MaxAmount {
+    fn validate(&self, tx: &Transaction, _account: Option<&Account>) -> Result<(), String> {
+        match tx.amount {
+            Some(amount) if amount > self.0 => Err(format!(
+                "The amount `{}` exceeds the maximum of `{}`",
+                amount, self.0
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Rejects transactions of clients, or to destinations, that are not in the
+/// set.
+#[derive(Debug, Clone, Default)]
+pub struct AllowedClients(pub BTreeSet<u16>);
+
+impl Validator for AllowedClients {
+    fn validate(&self, tx: &Transaction, _account: Option<&Account>) -> Result<(), String> {
+        match std::iter::once(tx.client)
+            .chain(tx.destination)
+            .find(|client| !self.0.contains(client))
+        {
+            Some(client) => Err(format!("Client `{}` is not allowed", client)),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        process_with_policy, PaymentEngine, ProcessingPolicy, TransactionError, TransactionVariant,
+    };
+
+    #[test]
+    fn rejects_transactions_failing_a_rule() {
+        let mut engine = PaymentEngine::builder()
+            .validator(Box::new(MaxAmount(Amount::new(100, 0).unwrap())))
+            .validator(Box::new(AllowedClients(BTreeSet::from([1, 2]))))
+            .build();
+        let input = "type,client,tx,amount,destination\n\
+                     deposit,1,1,150.0,\n\
+                     deposit,3,2,1.0,\n\
+                     deposit,1,3,50.0,\n\
+                     transfer,1,4,10.0,3\n\
+                     transfer,1,5,10.0,2\n";
+        let errors =
+            process_with_policy(input.as_bytes(), &mut engine, ProcessingPolicy::SkipWithLog)
+                .unwrap();
+        let lines = errors.iter().map(|e| e.line).collect::<Vec<_>>();
+        assert_eq!(lines, [2, 3, 5]);
+        assert_eq!(
+            errors[1].kind.to_string(),
+            "Rejected by a validator: Client `3` is not allowed"
+        );
+        assert!(engine.accounts().get(&3).is_none());
+        assert_eq!(
+            engine.accounts().get(&1).unwrap().total(),
+            Amount::new(40, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn rejects_batches_failing_a_rule() {
+        let mut engine = PaymentEngine::builder()
+            .validator(Box::new(WellFormed))
+            .build();
+        let deposit = Transaction {
+            tx: 1,
+            amount: Some(Amount::new(5, 0).unwrap()),
+            client: 1,
+            disputed: false,
+            variant: TransactionVariant::Deposit,
+            chargeback: false,
+            destination: None,
+            currency: None,
+            to_currency: None,
+            rate: None,
+            timestamp: None,
+        };
+        let missing_amount = Transaction {
+            tx: 2,
+            amount: None,
+            ..deposit.clone()
+        };
+        let error = engine.insert_batch(&[deposit, missing_amount]).unwrap_err();
+        assert_eq!(error.index, 1);
+        assert_eq!(
+            error.error,
+            TransactionError::Rejected("Invalid transaction".to_string())
+        );
+        assert!(engine.accounts().is_empty());
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::{HashMap, VecDeque};
+
+use rust_decimal::Decimal;
+
+use crate::{Amount, Transaction, TransactionError};
+
+/// Limits on how many transactions, and how much, a client can move within a
+/// sliding window of time, to catch suspicious bursts of activity.
+///
+/// Time is taken from the `timestamp` of a transaction, or the latest
+/// timestamp seen by the engine if it has none. Every transaction with an
+/// amount counts, including those the engine goes on to reject, as repeated
+/// attempts are suspicious too. Only amounts without a currency are added up.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VelocityConfig {
+    /// The length of the window in seconds
+    pub window: u64,
+    /// The number of transactions a client can make within the window
+    pub max_transactions: Option<u64>,
+    /// The total amount a client can move within the window
+    pub max_amount: Option<Amount>,
+    pub action: VelocityAction,
+}
+
+impl Default for VelocityConfig {
+    fn default() -> Self {
+        Self {
+            window: 3600,
+            max_transactions: None,
+            max_amount: None,
+            action: VelocityAction::default(),
+        }
+    }
+}
+
+/// What happens to a transaction exceeding a [`VelocityConfig`] limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VelocityAction {
+    /// Apply the transaction and record a [`VelocityFlag`], see
+    /// [`PaymentEngine::velocity_flags`](crate::PaymentEngine::velocity_flags).
+    #[default]
+    Flag,
+    /// Fail with [`TransactionError::VelocityExceeded`]. Rejected
+    /// transactions do not count towards the limits.
+    Reject,
+}
+
+/// A transaction that exceeded a [`VelocityConfig`] limit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VelocityFlag {
+    pub client: u16,
+    pub tx: u32,
+    /// The number of transactions within the window, including this one
+    pub transactions: u64,
+    /// The amount moved within the window, including this transaction
+    pub amount: Decimal,
+}
+
+/// The recent transactions of every client, to check them against a
+/// [`VelocityConfig`].
+#[derive(Debug, Clone)]
+pub(crate) struct VelocityTracker {
+    config: VelocityConfig,
+    /// When each recent transaction of a client happened and its amount,
+    /// oldest first
+    windows: HashMap<u16, VecDeque<(u64, Decimal)>>,
+    flags: Vec<VelocityFlag>,
+}
+
+impl VelocityTracker {
+    pub(crate) fn new(config: VelocityConfig) -> Self {
+        Self {
+            config,
+            windows: HashMap::new(),
+            flags: Vec::new(),
+        }
+    }
+
+    /// Counts `tx` happening at `now`, unless it exceeds a limit and is
+    /// rejected.
+    pub(crate) fn check(&mut self, tx: &Transaction, now: u64) -> Result<(), TransactionError> {
+        let amount = match tx.amount {
+            Some(amount) if tx.currency.is_none() => Decimal::from(amount),
+            Some(_) => Decimal::ZERO,
+            None => return Ok(()),
+        };
+        let config = &self.config;
+        let window = self.windows.entry(tx.client).or_default();
+        while window
+            .front()
+            .is_some_and(|(at, _)| at.saturating_add(config.window) <= now)
+        {
+            window.pop_front();
+        }
+        let transactions = window.len() as u64 + 1;
+        let total = window
+            .iter()
+            .fold(amount, |total, (_, amount)| total.saturating_add(*amount));
+        let exceeded = config
+            .max_transactions
+            .is_some_and(|max| transactions > max)
+            || config
+                .max_amount
+                .is_some_and(|max| total > Decimal::from(max));
+        if exceeded {
+            match config.action {
+                VelocityAction::Reject => {
+                    return Err(TransactionError::VelocityExceeded(tx.client))
+                }
+                VelocityAction::Flag => {
+                    tracing::warn!(
+                        client = tx.client,
+                        tx = tx.tx,
+                        transactions,
+                        amount = %total,
+                        "Transaction velocity exceeded"
+                    );
+                    self.flags.push(VelocityFlag {
+                        client: tx.client,
+                        tx: tx.tx,
+                        transactions,
+                        amount: total,
+                    });
+                }
+            }
+        }
+        window.push_back((now, amount));
+        Ok(())
+    }
+
+    pub(crate) fn flags(&self) -> &[VelocityFlag] {
+        &self.flags
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TransactionVariant;
+
+    fn deposit(tx: u32, amount: i64) -> Transaction {
+        Transaction {
+            tx,
+            amount: Some(Amount::new(amount, 0).unwrap()),
+            client: 1,
+            disputed: false,
+            variant: TransactionVariant::Deposit,
+            chargeback: false,
+            destination: None,
+            currency: None,
+            to_currency: None,
+            rate: None,
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn flags_transactions_within_the_window() {
+        let mut tracker = VelocityTracker::new(VelocityConfig {
+            window: 60,
+            max_transactions: Some(2),
+            max_amount: Some(Amount::new(100, 0).unwrap()),
+            action: VelocityAction::Flag,
+        });
+        tracker.check(&deposit(1, 10), 0).unwrap();
+        tracker.check(&deposit(2, 10), 30).unwrap();
+        tracker.check(&deposit(3, 10), 50).unwrap();
+        // The first deposit left the window
+        tracker.check(&deposit(4, 10), 60).unwrap();
+        tracker.check(&deposit(5, 90), 200).unwrap();
+        tracker.check(&deposit(6, 20), 210).unwrap();
+        let flagged = tracker
+            .flags()
+            .iter()
+            .map(|flag| (flag.tx, flag.transactions, flag.amount))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            flagged,
+            [
+                (3, 3, Decimal::from(30)),
+                (4, 3, Decimal::from(30)),
+                (6, 2, Decimal::from(110))
+            ]
+        );
+    }
+
+    #[test]
+    fn rejected_transactions_do_not_count() {
+        let mut tracker = VelocityTracker::new(VelocityConfig {
+            max_transactions: Some(1),
+            action: VelocityAction::Reject,
+            ..VelocityConfig::default()
+        });
+        tracker.check(&deposit(1, 10), 0).unwrap();
+        assert_eq!(
+            tracker.check(&deposit(2, 10), 10),
+            Err(TransactionError::VelocityExceeded(1))
+        );
+        tracker.check(&deposit(3, 10), 3600).unwrap();
+        assert!(tracker.flags().is_empty());
+    }
+}
@@ -0,0 +1,101 @@
+//! Bindings for `wasm32-unknown-unknown` with the `wasm` feature, to run the
+//! same settlement logic in a browser, e.g. built with
+//! `wasm-pack build --target web --features wasm`.
+//!
+//! Accounts are returned as JSON in the format of [`Account`].
+
+use wasm_bindgen::prelude::*;
+
+use crate::{Account, Amount, PaymentEngine, Transaction, TransactionVariant};
+
+/// A [`PaymentEngine`] with the default configuration, exported to
+/// JavaScript as `PaymentEngine`.
+#[wasm_bindgen(js_name = PaymentEngine)]
+#[derive(Default)]
+pub struct WasmEngine {
+    engine: PaymentEngine,
+}
+
+#[wasm_bindgen(js_class = PaymentEngine)]
+impl WasmEngine {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Inserts a transaction of `variant`, named like the `type` column of
+    /// the input, e.g. `deposit` or `dispute`. Fails with the message of the
+    /// [`TransactionError`](crate::TransactionError).
+    pub fn insert(
+        &mut self,
+        variant: &str,
+        client: u16,
+        tx: u32,
+        amount: Option<String>,
+    ) -> Result<(), JsError> {
+        let variant = TransactionVariant::from_name(variant)
+            .ok_or_else(|| JsError::new(&format!("Unknown transaction type `{}`", variant)))?;
+        let amount = amount
+            .map(|amount| amount.parse::<Amount>())
+            .transpose()
+            .map_err(|e| JsError::new(&e))?;
+        let tx = Transaction {
+            variant,
+            client,
+            tx,
+            amount,
+            disputed: false,
+            chargeback: false,
+            destination: None,
+            currency: None,
+            to_currency: None,
+            rate: None,
+            timestamp: None,
+        };
+        if !tx.is_valid() {
+            return Err(JsError::new("Invalid transaction"));
+        }
+        self.engine
+            .insert(tx)
+            .map_err(|e| JsError::new(&e.to_string()))
+    }
+
+    /// Processes transactions as CSV in the input format, see
+    /// [`process`](crate::process).
+    pub fn process(&mut self, input: &str) -> Result<(), JsError> {
+        crate::process(input.as_bytes(), &mut self.engine).map_err(|e| JsError::new(&e.to_string()))
+    }
+
+    /// The account of `client` as JSON, if it has one.
+    pub fn account(&self, client: u16) -> Option<String> {
+        let account = self.engine.account(client)?;
+        serde_json::to_string(account).ok()
+    }
+
+    /// All accounts as a JSON array ordered by client id.
+    pub fn accounts(&self) -> String {
+        let accounts = self.engine.accounts_iter(true).collect::<Vec<&Account>>();
+        serde_json::to_string(&accounts).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inserts_and_queries_accounts() {
+        let mut engine = WasmEngine::new();
+        engine
+            .insert("deposit", 2, 1, Some("1.5".to_string()))
+            .unwrap();
+        engine
+            .process("type,client,tx,amount\ndeposit,1,2,2.0\n")
+            .unwrap();
+        assert_eq!(engine.account(3), None);
+        assert_eq!(
+            engine.accounts(),
+            r#"[{"client":1,"available":"2.0","held":"0","total":"2.0","locked":false,"closed":false},{"client":2,"available":"1.5","held":"0","total":"1.5","locked":false,"closed":false}]"#
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use randomlib::run;
+use randomlib::{run_with_options, OutputOptions, RunOptions};
 use std::fs::{self, File, OpenOptions};
 
 #[test]
@@ -22,7 +22,14 @@ fn compare_fixtures() {
             .open(&run_output_file_name)
             .unwrap();
 
-        run(input_file, run_output_file).unwrap();
+        let options = RunOptions {
+            output: OutputOptions {
+                sorted: true,
+                ..OutputOptions::default()
+            },
+            ..RunOptions::default()
+        };
+        run_with_options(input_file, run_output_file, &options).unwrap();
         let run_output = fs::read_to_string(&run_output_file_name).unwrap();
 
         // Expected results
@@ -30,22 +37,160 @@ fn compare_fixtures() {
         let output = fs::read_to_string(output_file_name).unwrap();
 
         // Compare expected with test output
-        assert_eq!(
-            run_output.lines().collect::<Vec<_>>().len(),
-            output.lines().collect::<Vec<_>>().len()
-        );
-
-        // Get lines and skip csv header
-        let expected_records = output.lines().skip(1);
-
-        for expected_record in expected_records {
-            // Check that there is a row that is equal to `expected_row`.
-            // Ordering can differ from run to run, so comparing by
-            // expected_record[i] = run_output[i] does not work.
-            assert!(run_output
-                .lines()
-                .skip(1)
-                .any(|output_record| { output_record == expected_record }));
-        }
+        assert_eq!(run_output, output, "{}", fixture_dir);
     }
 }
+
+/// Runs the binary in `dir` with `args`.
+fn run_cli(dir: &std::path::Path, args: &[&str]) -> std::process::Output {
+    std::process::Command::new(env!("CARGO_BIN_EXE_randomlib"))
+        .current_dir(dir)
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn config_file_settings_satisfy_and_conflict_with_flags() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(
+        dir.path().join("input.csv"),
+        "type,client,tx,amount\ndeposit,1,1,1.0\n",
+    )
+    .unwrap();
+    fs::write(
+        dir.path().join("output.toml"),
+        "output = \"accounts.csv\"\n",
+    )
+    .unwrap();
+    let output = run_cli(
+        dir.path(),
+        &[
+            "input.csv",
+            "--config",
+            "output.toml",
+            "--compression",
+            "none",
+        ],
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Rows read: 1"), "{}", stderr);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(dir.path().join("accounts.csv").exists());
+
+    fs::write(
+        dir.path().join("resident.toml"),
+        "max-resident-transactions = 10\n",
+    )
+    .unwrap();
+    let output = run_cli(
+        dir.path(),
+        &[
+            "input.csv",
+            "--config",
+            "resident.toml",
+            "--resume",
+            "state.json",
+        ],
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr).trim(),
+        "`resume` cannot be used with `max-resident-transactions`"
+    );
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn errors_exit_with_a_non_zero_status() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(
+        dir.path().join("input.csv"),
+        "type,client,tx,amount\ndeposit,1,1,1.0\nwithdrawal,1,2,5.0\n",
+    )
+    .unwrap();
+    let output = run_cli(dir.path(), &["input.csv", "-o", "accounts.csv"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Insufficient funds"), "{}", stderr);
+
+    let output = run_cli(
+        dir.path(),
+        &[
+            "input.csv",
+            "--policy",
+            "skip-invalid",
+            "-o",
+            "accounts.csv",
+        ],
+    );
+    assert_eq!(output.status.code(), Some(0));
+
+    let output = run_cli(
+        dir.path(),
+        &[
+            "input.csv",
+            "--policy",
+            "skip-invalid",
+            "--alert",
+            "rejected > 0",
+        ],
+    );
+    assert_eq!(output.status.code(), Some(3));
+
+    let output = run_cli(dir.path(), &["missing.csv"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("missing.csv: "), "{}", stderr);
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+
+    for args in [
+        &["reconcile", "input.csv", "missing.csv"][..],
+        &["query", "missing.json"],
+        &["dry-run", "input.csv", "--snapshot", "missing.json"],
+        &["serve", "--snapshot", "missing.json"],
+    ] {
+        let output = run_cli(dir.path(), args);
+        assert_eq!(output.status.code(), Some(1));
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("missing."), "{}", stderr);
+        assert!(!stderr.contains("panicked"), "{}", stderr);
+    }
+}
+
+#[test]
+fn quarantined_rows_are_reported() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(
+        dir.path().join("input.csv"),
+        "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,2,1.0\ndeposit,2,3,1.0\n",
+    )
+    .unwrap();
+    let output = run_cli(
+        dir.path(),
+        &["input.csv", "--max-transactions-per-client", "1"],
+    );
+    assert_eq!(output.status.code(), Some(1));
+
+    let output = run_cli(
+        dir.path(),
+        &[
+            "input.csv",
+            "--max-transactions-per-client",
+            "1",
+            "--policy",
+            "skip-with-log",
+            "--rejected",
+            "rejected.csv",
+        ],
+    );
+    assert_eq!(output.status.code(), Some(0));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Line 3: "), "{}", stderr);
+    let rejected = fs::read_to_string(dir.path().join("rejected.csv")).unwrap();
+    assert!(
+        rejected.contains("deposit,1,2,1.0,3,client_limit_exceeded,"),
+        "{}",
+        rejected
+    );
+}